@@ -0,0 +1,8 @@
+CREATE TABLE IF NOT EXISTS signing_keys (
+  kid VARCHAR PRIMARY KEY,
+  algorithm VARCHAR NOT NULL,
+  private_key VARCHAR NOT NULL,
+  public_key VARCHAR,
+  created_at Timestamp WITH TIME ZONE NOT NULL DEFAULT now(),
+  retire_at Timestamp WITH TIME ZONE
+);
\ No newline at end of file
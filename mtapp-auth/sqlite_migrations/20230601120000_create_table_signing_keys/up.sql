@@ -0,0 +1,8 @@
+CREATE TABLE IF NOT EXISTS signing_keys (
+  kid VARCHAR PRIMARY KEY,
+  algorithm VARCHAR NOT NULL,
+  private_key VARCHAR NOT NULL,
+  public_key VARCHAR,
+  created_at TIMESTAMP NOT NULL DEFAULT CURRENT_TIMESTAMP,
+  retire_at TIMESTAMP
+);
\ No newline at end of file
@@ -1,18 +1,50 @@
+use std::collections::HashMap;
 use std::marker::PhantomData;
+use std::path::PathBuf;
+use std::process;
 use std::time::Duration;
 
-use axum::middleware::from_fn;
-use axum::routing::post;
+use axum::http::Extensions;
+use axum::middleware::from_fn_with_state;
+use axum::routing::{delete, get, post};
 use axum::Router;
-use mtapp::{App, Configuration};
-use secrecy::{ExposeSecret, Secret};
+use axum_extra::extract::cookie::SameSite;
+use clap::{value_parser, Arg, Command};
+use jsonwebtoken::Algorithm;
+use mtapp::db::DbPool;
+use mtapp::settings::{Settings, SettingsError};
+use mtapp::{include_backend_migrations, App, Configuration, Migration, ReactorState};
+use reqwest::Client;
+use serde::Deserialize;
 
+use crate::commands;
+use crate::cookies::{parse_same_site, CookieConfig};
+use crate::errors::AuthError;
+use crate::external::{oidc_authorize, oidc_callback};
 use crate::handlers::*;
+use crate::keys::{KeyError, KeyRing, KeySealer, SigningKey};
 use crate::middleware::jwt_claims;
+use crate::oauth::{introspect, revoke, token};
+use crate::oidc::OidcProvider;
 use crate::openapi::get_open_api;
-use crate::providers::{GrantProvider, SessionProvider, UserProvider};
+use crate::passkeys::{
+    passkey_login, passkey_login_options, passkey_register, passkey_register_options,
+};
+use crate::providers::{
+    ClientProvider, GrantProvider, NoClients, NoPasskeys, PasskeyProvider, SessionProvider,
+    UserProvider,
+};
+use crate::scopes::RequireScopes;
+use crate::tasks::{load_keys, prune_blacklist, reload_keys};
+use crate::throttle::{clear_lockout, LoginThrottle};
+use crate::webauthn::RelyingParty;
 
 const TOKENEXPIRY: u64 = 24 * 60 * 60;
+const MFA_CHALLENGE_EXPIRY: u64 = 5 * 60;
+const BLACKLIST_PRUNE_INTERVAL: u64 = 10 * 60;
+const KEY_RELOAD_INTERVAL: u64 = 60;
+const OIDC_REQUEST_TIMEOUT: u64 = 10;
+const DEFAULT_DEPENDENCIES: &[&str] = &["mtapp-user", "mtapp-session", "mtapp-grant"];
 
 #[derive(Clone)]
 pub struct AuthConfig {
@@ -22,43 +54,294 @@ pub struct AuthConfig {
     // Time to live for JWT tokens
     token_expiry: Duration,
 
-    // Secret used to sign jwt tokens
-    secret: Secret<String>,
+    // Time the users with MFA have to send their second factor after the password
+    mfa_challenge_expiry: Duration,
+
+    // Keys used to sign and verify jwt tokens, an empty secret means the key should be set
+    // through the settings
+    keys: Option<KeyRing>,
+
+    // The HS256 secret of the keys, only kept for `expose_secret`
+    secret: String,
+
+    // Encrypts the keys stored by the `rotate-key` command, they can't be stored when not set
+    sealer: Option<KeySealer>,
+
+    // Attributes of the refresh token and csrf cookies
+    cookie: CookieConfig,
+
+    // Whether the refresh token is also returned in the body, or only set as a cookie
+    refresh_token_in_body: bool,
+
+    // Whether the requests authenticated by the refresh token cookie need the csrf header
+    csrf: bool,
+
+    // The site the passkeys are registered for, passkeys are disabled when not set
+    relying_party: Option<RelyingParty>,
+
+    // The OpenID Connect providers the users can log in with, by their names
+    oidc_providers: HashMap<String, OidcProvider>,
+
+    // Used to talk to the OpenID Connect providers
+    http: Client,
+
+    // Limits the failed logins, disabled when not set
+    login_throttle: Option<LoginThrottle>,
 }
 
 impl AuthConfig {
+    /// Sign the tokens with HS256 using the given secret
     pub fn new(storage_scope: &str, token_expiry: u64, secret: String) -> Self {
         AuthConfig {
             blacklist_scope: String::from(storage_scope),
             token_expiry: Duration::from_secs(token_expiry),
-            secret: Secret::new(secret),
+            mfa_challenge_expiry: Duration::from_secs(MFA_CHALLENGE_EXPIRY),
+            keys: (!secret.is_empty()).then(|| KeyRing::new(SigningKey::hmac(&secret))),
+            secret,
+            sealer: None,
+            cookie: CookieConfig::default(),
+            refresh_token_in_body: true,
+            csrf: true,
+            relying_party: None,
+            oidc_providers: HashMap::new(),
+            http: Client::builder()
+                .timeout(Duration::from_secs(OIDC_REQUEST_TIMEOUT))
+                .build()
+                .expect("Failed to build the http client"),
+            login_throttle: Some(LoginThrottle::default()),
         }
     }
 
+    /// Sign the tokens with the given key instead, ex. a RS256 or EdDSA key pair
+    pub fn with_signing_key(mut self, key: SigningKey) -> Self {
+        self.keys = Some(KeyRing::new(key));
+        self.secret = String::new();
+        self
+    }
+
+    /// Encrypt the rotated keys stored in the database with a key derived from this secret,
+    /// needed to rotate the keys. Changing it makes the stored keys unusable
+    pub fn with_sealing_key(mut self, secret: &str) -> Self {
+        self.sealer = Some(KeySealer::new(secret));
+        self
+    }
+
+    /// 5 minutes by default
+    pub fn mfa_challenge_expiry(mut self, expiry: Duration) -> Self {
+        self.mfa_challenge_expiry = expiry;
+        self
+    }
+
+    /// Send the cookies only over https, enabled by default
+    pub fn cookie_secure(mut self, secure: bool) -> Self {
+        self.cookie.secure = secure;
+        self
+    }
+
+    /// `Strict` by default, it should be `None` if the frontend is served from another site
+    pub fn cookie_same_site(mut self, same_site: SameSite) -> Self {
+        self.cookie.same_site = same_site;
+        self
+    }
+
+    /// Path of the refresh token cookie, ex. the auth app's prefix
+    pub fn cookie_path(mut self, path: &str) -> Self {
+        self.cookie.path = Some(String::from(path));
+        self
+    }
+
+    pub fn cookie_domain(mut self, domain: &str) -> Self {
+        self.cookie.domain = Some(String::from(domain));
+        self
+    }
+
+    /// The cookies are kept until the browser is closed if not set, panics if it's more than
+    /// `i64::MAX` seconds
+    pub fn cookie_max_age(mut self, max_age: Duration) -> Self {
+        self.cookie.max_age =
+            Some(time::Duration::try_from(max_age).expect("Cookie max age is out of range"));
+        self
+    }
+
+    /// Return the refresh token in the body too, disable it to keep it away from the scripts
+    pub fn refresh_token_in_body(mut self, enabled: bool) -> Self {
+        self.refresh_token_in_body = enabled;
+        self
+    }
+
+    /// Require the `X-CSRF-Token` header on the requests authenticated by the refresh token
+    /// cookie, enabled by default
+    pub fn csrf(mut self, enabled: bool) -> Self {
+        self.csrf = enabled;
+        self
+    }
+
+    /// Enable the passkeys for the site, ex. id `example.com` and origin `https://example.com`.
+    /// The name is shown by the authenticators
+    pub fn relying_party(mut self, id: &str, name: &str, origin: &str) -> Self {
+        self.relying_party = Some(RelyingParty {
+            id: String::from(id),
+            name: String::from(name),
+            origin: String::from(origin),
+        });
+        self
+    }
+
+    /// Let the users log in with the provider, replacing the one with the same name
+    pub fn oidc_provider(mut self, provider: OidcProvider) -> Self {
+        self.oidc_providers
+            .insert(provider.name().to_owned(), provider);
+        self
+    }
+
+    /// Enabled with the defaults of `LoginThrottle`, `None` disables it
+    pub fn login_throttle(mut self, throttle: Option<LoginThrottle>) -> Self {
+        self.login_throttle = throttle;
+        self
+    }
+
+    pub(crate) fn cookie(&self) -> &CookieConfig {
+        &self.cookie
+    }
+
+    pub(crate) fn is_refresh_token_in_body(&self) -> bool {
+        self.refresh_token_in_body
+    }
+
+    pub(crate) fn is_csrf_enabled(&self) -> bool {
+        self.csrf
+    }
+
     pub fn blacklist_scope(&self) -> &str {
         &self.blacklist_scope
     }
 
+    pub fn keys(&self) -> &KeyRing {
+        self.keys
+            .as_ref()
+            .expect("A signing key should be set, see AuthConfig::with_signing_key")
+    }
+
+    /// The HS256 secret, empty when the tokens are signed with another key
+    #[deprecated(note = "sign and verify the tokens with `AuthConfig::keys`")]
     pub fn expose_secret(&self) -> &str {
-        &self.secret.expose_secret()
+        &self.secret
+    }
+
+    pub(crate) fn sealer(&self) -> Result<&KeySealer, KeyError> {
+        self.sealer.as_ref().ok_or(KeyError::NoSealingKey)
     }
 
     pub fn get_token_expiry(&self) -> Duration {
         self.token_expiry
     }
+
+    pub(crate) fn get_mfa_challenge_expiry(&self) -> Duration {
+        self.mfa_challenge_expiry
+    }
+
+    pub(crate) fn get_relying_party(&self) -> Result<&RelyingParty, AuthError> {
+        self.relying_party.as_ref().ok_or_else(|| {
+            log::error!("Passkeys are used, but the relying party is not configured");
+            AuthError::Configuration
+        })
+    }
+
+    pub(crate) fn get_oidc_provider(&self, name: &str) -> Result<&OidcProvider, AuthError> {
+        self.oidc_providers
+            .get(name)
+            .ok_or(AuthError::UnknownProvider)
+    }
+
+    pub(crate) fn http(&self) -> &Client {
+        &self.http
+    }
+
+    pub(crate) fn get_login_throttle(&self) -> Option<&LoginThrottle> {
+        self.login_throttle.as_ref()
+    }
+}
+
+/// The `auth` section of the settings, overriding the values the app is built with
+#[derive(Default, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+struct AuthSettings {
+    blacklist_scope: Option<String>,
+
+    // In seconds
+    token_expiry: Option<u64>,
+    mfa_challenge_expiry: Option<u64>,
+
+    secret: Option<String>,
+
+    // Encrypts the stored keys, see `AuthConfig::with_sealing_key`
+    sealing_key: Option<String>,
+
+    // HS256(default), RS256 or EdDSA
+    algorithm: Option<Algorithm>,
+
+    // Required for RS256 and EdDSA, sent as the `kid` of the tokens
+    key_id: Option<String>,
+
+    // PEM files of the RS256 or EdDSA key pair
+    private_key: Option<PathBuf>,
+    public_key: Option<PathBuf>,
+
+    cookie_secure: Option<bool>,
+    // strict, lax or none
+    cookie_same_site: Option<String>,
+    cookie_path: Option<String>,
+    cookie_domain: Option<String>,
+    // In seconds
+    cookie_max_age: Option<u64>,
+
+    refresh_token_in_body: Option<bool>,
+    csrf: Option<bool>,
+
+    // Both are needed to enable the passkeys, the name defaults to the id
+    webauthn_rp_id: Option<String>,
+    webauthn_rp_name: Option<String>,
+    webauthn_origin: Option<String>,
+
+    // Set to false to disable the login throttle
+    login_throttle: Option<bool>,
+    login_max_failures: Option<u32>,
+    login_max_ip_failures: Option<u32>,
+    // In seconds
+    login_lockout: Option<u64>,
+    login_failure_window: Option<u64>,
+
+    // The `[auth.oidc.<name>]` tables, added to the providers of the app
+    oidc: HashMap<String, OidcSettings>,
+}
+
+#[derive(Deserialize)]
+#[serde(deny_unknown_fields)]
+struct OidcSettings {
+    issuer: String,
+    client_id: String,
+    client_secret: String,
+    redirect_uri: String,
+    scopes: Option<Vec<String>>,
 }
 
+/// `C` authenticates the service clients of the client_credentials grant and `P` stores the
+/// passkeys, none by default
 #[derive(Clone)]
-pub struct AuthApp<U, S, G> {
+pub struct AuthApp<U, S, G, C = NoClients, P = NoPasskeys> {
     config: AuthConfig,
 
-    _phantom: PhantomData<dyn Fn() -> (U, S, G) + Sync + Send>,
+    // The apps behind the providers
+    dependencies: &'static [&'static str],
+
+    _phantom: PhantomData<dyn Fn() -> (U, S, G, C, P) + Sync + Send>,
 }
 
-impl<U, S, G> AuthApp<U, S, G> {
+impl<U, S, G, C, P> AuthApp<U, S, G, C, P> {
     pub fn new(secret: String) -> Self {
         Self {
             config: AuthConfig::new("storage_scope", TOKENEXPIRY, secret),
+            dependencies: DEFAULT_DEPENDENCIES,
             _phantom: PhantomData,
         }
     }
@@ -66,55 +349,417 @@ impl<U, S, G> AuthApp<U, S, G> {
     pub fn with_config(config: AuthConfig) -> Self {
         Self {
             config,
+            dependencies: DEFAULT_DEPENDENCIES,
             _phantom: PhantomData,
         }
     }
+
+    /// The apps whose data the providers use, the user, session and grant apps by default. Set
+    /// it when the providers come from other apps
+    pub fn depends_on(mut self, apps: &'static [&'static str]) -> Self {
+        self.dependencies = apps;
+        self
+    }
 }
 
-impl<U, S, G> App for AuthApp<U, S, G>
+impl<U, S, G, C, P> App for AuthApp<U, S, G, C, P>
 where
     U: UserProvider + 'static + Send + Sync,
     S: SessionProvider + 'static + Send + Sync,
     G: GrantProvider + 'static + Send + Sync,
+    C: ClientProvider + 'static + Send + Sync,
+    P: PasskeyProvider + 'static + Send + Sync,
 {
     fn name(&self) -> &'static str {
         "auth"
     }
 
+    // Also puts the claims middleware around the middlewares of the other apps, like the ban check
+    // of the users
+    fn dependencies(&self) -> &'static [&'static str] {
+        self.dependencies
+    }
+
+    fn load_settings(&mut self, settings: &Settings) -> Result<(), SettingsError> {
+        let section: AuthSettings = settings.section(self.name())?;
+
+        if let Some(blacklist_scope) = section.blacklist_scope {
+            self.config.blacklist_scope = blacklist_scope;
+        }
+        if let Some(token_expiry) = section.token_expiry {
+            if token_expiry == 0 {
+                return Err(SettingsError::invalid(
+                    "auth.token_expiry",
+                    "should be more than 0 seconds",
+                ));
+            }
+            self.config.token_expiry = Duration::from_secs(token_expiry);
+        }
+        if let Some(expiry) = section.mfa_challenge_expiry {
+            if expiry == 0 {
+                return Err(SettingsError::invalid(
+                    "auth.mfa_challenge_expiry",
+                    "should be more than 0 seconds",
+                ));
+            }
+            self.config.mfa_challenge_expiry = Duration::from_secs(expiry);
+        }
+
+        match section.algorithm.unwrap_or(Algorithm::HS256) {
+            Algorithm::HS256 => {
+                if let Some(secret) = section.secret.filter(|secret| !secret.is_empty()) {
+                    self.config.keys = Some(KeyRing::new(SigningKey::hmac(&secret)));
+                    self.config.secret = secret;
+                }
+            }
+            algorithm @ (Algorithm::RS256 | Algorithm::EdDSA) => {
+                let key_id = section.key_id.ok_or_else(|| {
+                    SettingsError::invalid("auth.key_id", "should be set for RS256 and EdDSA")
+                })?;
+                let private_key = section.private_key.ok_or_else(|| {
+                    SettingsError::invalid("auth.private_key", "should be set for RS256 and EdDSA")
+                })?;
+                let public_key = section.public_key.ok_or_else(|| {
+                    SettingsError::invalid("auth.public_key", "should be set for RS256 and EdDSA")
+                })?;
+
+                let key = SigningKey::from_pem_files(algorithm, key_id, private_key, public_key)
+                    .map_err(|e| SettingsError::invalid("auth.private_key", e.to_string()))?;
+                self.config.keys = Some(KeyRing::new(key));
+                self.config.secret = String::new();
+            }
+            _ => {
+                return Err(SettingsError::invalid(
+                    "auth.algorithm",
+                    "should be one of HS256, RS256 or EdDSA",
+                ))
+            }
+        }
+
+        if self.config.keys.is_none() {
+            return Err(SettingsError::invalid(
+                "auth.secret",
+                "should be set, or use AuthConfig::with_signing_key",
+            ));
+        }
+        if let Some(sealing_key) = section.sealing_key.filter(|key| !key.is_empty()) {
+            self.config.sealer = Some(KeySealer::new(&sealing_key));
+        }
+
+        if let Some(secure) = section.cookie_secure {
+            self.config.cookie.secure = secure;
+        }
+        if let Some(same_site) = section.cookie_same_site {
+            self.config.cookie.same_site = parse_same_site(&same_site).ok_or_else(|| {
+                SettingsError::invalid("auth.cookie_same_site", "should be strict, lax or none")
+            })?;
+        }
+        if let Some(path) = section.cookie_path {
+            self.config.cookie.path = Some(path);
+        }
+        if let Some(domain) = section.cookie_domain {
+            self.config.cookie.domain = Some(domain);
+        }
+        if let Some(max_age) = section.cookie_max_age {
+            let max_age = i64::try_from(max_age)
+                .map_err(|_| SettingsError::invalid("auth.cookie_max_age", "is out of range"))?;
+            self.config.cookie.max_age = Some(time::Duration::seconds(max_age));
+        }
+        if let Some(enabled) = section.refresh_token_in_body {
+            self.config.refresh_token_in_body = enabled;
+        }
+        if let Some(enabled) = section.csrf {
+            self.config.csrf = enabled;
+        }
+        match (section.webauthn_rp_id, section.webauthn_origin) {
+            (Some(id), Some(origin)) => {
+                self.config.relying_party = Some(RelyingParty {
+                    name: section.webauthn_rp_name.unwrap_or_else(|| id.clone()),
+                    id,
+                    origin,
+                });
+            }
+            (None, None) => {}
+            _ => {
+                return Err(SettingsError::invalid(
+                    "auth.webauthn_origin",
+                    "webauthn_rp_id and webauthn_origin should be set together",
+                ));
+            }
+        }
+        match section.login_throttle {
+            Some(false) => self.config.login_throttle = None,
+            Some(true) => {
+                self.config
+                    .login_throttle
+                    .get_or_insert_with(LoginThrottle::default);
+            }
+            None => {}
+        }
+        if let Some(mut throttle) = self.config.login_throttle.take() {
+            if let Some(max) = section.login_max_failures {
+                if max == 0 {
+                    return Err(SettingsError::invalid(
+                        "auth.login_max_failures",
+                        "should be more than 0",
+                    ));
+                }
+                throttle = throttle.max_failures(max);
+            }
+            if let Some(max) = section.login_max_ip_failures {
+                if max == 0 {
+                    return Err(SettingsError::invalid(
+                        "auth.login_max_ip_failures",
+                        "should be more than 0",
+                    ));
+                }
+                throttle = throttle.max_ip_failures(max);
+            }
+            if let Some(lockout) = section.login_lockout {
+                if lockout == 0 {
+                    return Err(SettingsError::invalid(
+                        "auth.login_lockout",
+                        "should be more than 0 seconds",
+                    ));
+                }
+                throttle = throttle.lockout(Duration::from_secs(lockout));
+            }
+            if let Some(window) = section.login_failure_window {
+                if window == 0 {
+                    return Err(SettingsError::invalid(
+                        "auth.login_failure_window",
+                        "should be more than 0 seconds",
+                    ));
+                }
+                throttle = throttle.window(Duration::from_secs(window));
+            }
+            self.config.login_throttle = Some(throttle);
+        }
+        for (name, oidc) in section.oidc {
+            let mut provider = OidcProvider::new(
+                &name,
+                &oidc.issuer,
+                &oidc.client_id,
+                &oidc.client_secret,
+                &oidc.redirect_uri,
+            );
+            if let Some(scopes) = oidc.scopes {
+                provider = provider.scopes(&scopes.iter().map(String::as_str).collect::<Vec<_>>());
+            }
+            self.config.oidc_providers.insert(name, provider);
+        }
+
+        Ok(())
+    }
+
+    fn secret_settings(&self) -> &'static [&'static str] {
+        // The providers are hidden as a whole, since they hold the client secrets
+        &["secret", "sealing_key", "oidc"]
+    }
+
     fn configure(&mut self, cfg: &mut Configuration) {
+        // Still inserted into the requests for the handlers extracting `Extension<AuthConfig>`
         let config = self.config.clone();
-        cfg.global_state(move |ext| {
-            ext.insert(config.clone());
-        })
-        .base_router(|router| 
-            // Register auth middleware
-            router.layer(from_fn(jwt_claims))
-        );
+        cfg.state(self.config.clone())
+            .global_state(move |ext| {
+                ext.insert(config.clone());
+            })
+            .base_router_with_state(|router, state| {
+                router.layer(from_fn_with_state(state.clone(), jwt_claims::<U, G, _>))
+            });
+
+        cfg.background_task(|state, shutdown| {
+            prune_blacklist(
+                state,
+                shutdown,
+                Duration::from_secs(BLACKLIST_PRUNE_INTERVAL),
+            )
+        });
+        cfg.background_task(|state, shutdown| {
+            reload_keys(state, shutdown, Duration::from_secs(KEY_RELOAD_INTERVAL))
+        });
+    }
+
+    async fn on_startup(&mut self, state: &ReactorState) {
+        // Signing with the configured key instead would reject the tokens of the rotated keys
+        if let Err(e) = load_keys(&self.config, state.db()).await {
+            panic!("Failed to load the stored signing keys: {}", e);
+        }
+    }
+
+    fn migrations(&mut self) -> Option<Vec<Box<dyn Migration>>> {
+        include_backend_migrations!(postgres: "./migrations", sqlite: "./sqlite_migrations")
+    }
+
+    fn clap_def(&self) -> Option<clap::Command> {
+        Some(
+            Command::default()
+                .about("Management commands for Auth app")
+                .subcommand(
+                    Command::new("rotate-key")
+                        .about(
+                            "Sign the tokens with a new key, the previous keys are accepted until \
+                            the outstanding tokens expire",
+                        )
+                        .arg(
+                            Arg::new("algorithm")
+                                .long("algorithm")
+                                .value_parser(["HS256", "RS256", "EdDSA"])
+                                .help("Defaults to the algorithm of the current key"),
+                        )
+                        .arg(
+                            Arg::new("private-key")
+                                .long("private-key")
+                                .requires("public-key")
+                                .help("PEM file of the new key, required for RS256"),
+                        )
+                        .arg(
+                            Arg::new("public-key")
+                                .long("public-key")
+                                .requires("private-key"),
+                        )
+                        .arg(
+                            Arg::new("grace")
+                                .long("grace")
+                                .value_parser(value_parser!(u64))
+                                .help(
+                                    "Seconds the previous keys are accepted, defaults to the \
+                                    token expiry",
+                                ),
+                        ),
+                )
+                .subcommand_required(true),
+        )
+    }
+
+    async fn clap_run(&mut self, matches: &clap::ArgMatches, ext: &Extensions) {
+        let pool = ext
+            .get::<DbPool>()
+            .expect("Inserted into extensions by reactor")
+            .clone();
+
+        match matches.subcommand() {
+            Some(("rotate-key", sub_m)) => {
+                let algorithm = match sub_m.get_one::<String>("algorithm") {
+                    Some(algorithm) => algorithm.parse().expect("Validated by clap"),
+                    None => self.config.keys().current().algorithm(),
+                };
+                let key_files = sub_m
+                    .get_one::<String>("private-key")
+                    .zip(sub_m.get_one::<String>("public-key"))
+                    .map(|(private, public)| (PathBuf::from(private), PathBuf::from(public)));
+                let grace = sub_m
+                    .get_one::<u64>("grace")
+                    .map(|grace| Duration::from_secs(*grace))
+                    .unwrap_or(self.config.token_expiry);
+
+                let rotated =
+                    commands::rotate_key(pool, &self.config, algorithm, key_files, grace).await;
+                if let Err(e) = rotated {
+                    eprintln!("{}", e);
+                    process::exit(1);
+                }
+            }
+            _ => {
+                // Subcommand is required in clap definition
+                unreachable!()
+            }
+        }
     }
 
-    fn public_routes(&mut self, path_prefix: &str) -> Option<Router> {
+    fn public_routes(&mut self, path_prefix: &str) -> Option<Router<ReactorState>> {
         Some(
             Router::new()
                 .route(&format!("{}/login", path_prefix), post(login::<U, S, G>))
+                .route(
+                    &format!("{}/login/mfa", path_prefix),
+                    post(login_mfa::<U, S, G>),
+                )
                 .route(&format!("{}/refresh", path_prefix), post(refresh::<S, G>))
-                .route(&format!("{}/logout", path_prefix), post(logout::<U, S>)),
+                .route(&format!("{}/logout", path_prefix), post(logout::<U, S>))
+                .route(&format!("{}/token", path_prefix), post(token::<U, S, G, C>))
+                .route(&format!("{}/revoke", path_prefix), post(revoke::<S>))
+                .route(
+                    &format!("{}/webauthn/register/options", path_prefix),
+                    post(passkey_register_options::<P>),
+                )
+                .route(
+                    &format!("{}/webauthn/register", path_prefix),
+                    post(passkey_register::<P>),
+                )
+                .route(
+                    &format!("{}/webauthn/login/options", path_prefix),
+                    post(passkey_login_options),
+                )
+                .route(
+                    &format!("{}/webauthn/login", path_prefix),
+                    post(passkey_login::<P, S, G>),
+                )
+                .route(
+                    &format!("{}/oidc/:provider/authorize", path_prefix),
+                    post(oidc_authorize),
+                )
+                .route(
+                    &format!("{}/oidc/:provider/callback", path_prefix),
+                    post(oidc_callback::<U, S, G>),
+                )
+                .route(&format!("{}/.well-known/jwks.json", path_prefix), get(jwks)),
         )
     }
 
-    fn internal_routes(&mut self, path_prefix: &str) -> Option<Router> {
+    fn internal_routes(&mut self, path_prefix: &str) -> Option<Router<ReactorState>> {
         Some(
             Router::new()
                 .route(&format!("{}/login", path_prefix), post(login::<U, S, G>))
+                .route(
+                    &format!("{}/login/mfa", path_prefix),
+                    post(login_mfa::<U, S, G>),
+                )
                 .route(&format!("{}/refresh", path_prefix), post(refresh::<S, G>))
-                .route(&format!("{}/logout", path_prefix), post(logout::<U, S>)),
+                .route(&format!("{}/logout", path_prefix), post(logout::<U, S>))
+                .route(&format!("{}/token", path_prefix), post(token::<U, S, G, C>))
+                .route(&format!("{}/revoke", path_prefix), post(revoke::<S>))
+                .route(
+                    &format!("{}/webauthn/register/options", path_prefix),
+                    post(passkey_register_options::<P>),
+                )
+                .route(
+                    &format!("{}/webauthn/register", path_prefix),
+                    post(passkey_register::<P>),
+                )
+                .route(
+                    &format!("{}/webauthn/login/options", path_prefix),
+                    post(passkey_login_options),
+                )
+                .route(
+                    &format!("{}/webauthn/login", path_prefix),
+                    post(passkey_login::<P, S, G>),
+                )
+                .route(
+                    &format!("{}/oidc/:provider/authorize", path_prefix),
+                    post(oidc_authorize),
+                )
+                .route(
+                    &format!("{}/oidc/:provider/callback", path_prefix),
+                    post(oidc_callback::<U, S, G>),
+                )
+                .merge(
+                    Router::new()
+                        .route(
+                            &format!("{}/introspect", path_prefix),
+                            post(introspect::<S>),
+                        )
+                        .route(&format!("{}/lockouts", path_prefix), delete(clear_lockout))
+                        .layer(RequireScopes::admin()),
+                ),
         )
     }
 
     fn public_openapi(&mut self, path: &str) -> Option<utoipa::openapi::OpenApi> {
-        Some(get_open_api(path))
+        Some(get_open_api(path, false))
     }
 
     fn internal_openapi(&mut self, path: &str) -> Option<utoipa::openapi::OpenApi> {
-        Some(get_open_api(path))
+        Some(get_open_api(path, true))
     }
 }
@@ -0,0 +1,79 @@
+use std::fmt;
+use std::path::PathBuf;
+use std::time::Duration;
+
+use jsonwebtoken::Algorithm;
+use mtapp::db::DbPool;
+use sqlx::types::chrono::{self, Utc};
+use uuid::Uuid;
+
+use crate::keys::{KeyError, SigningKey};
+use crate::models::StoredKey;
+use crate::AuthConfig;
+
+#[derive(Debug)]
+pub(crate) enum CommandError {
+    Key(KeyError),
+    Database(sqlx::Error),
+    GraceTooLong,
+}
+
+impl fmt::Display for CommandError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::Key(e) => write!(f, "Failed to create the new key: {}", e),
+            Self::Database(e) => write!(f, "Failed to store the keys: {}", e),
+            Self::GraceTooLong => f.write_str("The grace period is too long"),
+        }
+    }
+}
+
+impl From<KeyError> for CommandError {
+    fn from(err: KeyError) -> Self {
+        Self::Key(err)
+    }
+}
+
+impl From<sqlx::Error> for CommandError {
+    fn from(err: sqlx::Error) -> Self {
+        Self::Database(err)
+    }
+}
+
+pub(crate) async fn rotate_key(
+    pool: DbPool,
+    config: &AuthConfig,
+    algorithm: Algorithm,
+    key_files: Option<(PathBuf, PathBuf)>,
+    grace: Duration,
+) -> Result<(), CommandError> {
+    let kid = Uuid::new_v4().simple().to_string();
+    let key = match key_files {
+        Some((private, public)) => SigningKey::from_pem_files(algorithm, kid, private, public),
+        None => SigningKey::generate(algorithm, kid),
+    }?;
+
+    let retire_at =
+        Utc::now() + chrono::Duration::from_std(grace).map_err(|_| CommandError::GraceTooLong)?;
+
+    let keys = config.keys();
+    let sealer = config.sealer()?;
+    let mut tx = pool.begin().await?;
+
+    // The configured key is stored the first time, so it's accepted until it's retired
+    if StoredKey::count(&mut tx).await? == 0 {
+        StoredKey::create(&keys.current().sealed(sealer)?, &mut tx).await?;
+    }
+
+    StoredKey::retire_all(retire_at, &mut tx).await?;
+    StoredKey::create(&key.sealed(sealer)?, &mut tx).await?;
+
+    tx.commit().await?;
+
+    println!(
+        "Key `{}` is now used to sign the tokens, the previous keys are accepted until {}",
+        key.kid().unwrap_or_default(),
+        retire_at
+    );
+    Ok(())
+}
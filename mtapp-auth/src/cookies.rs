@@ -0,0 +1,127 @@
+use axum::http::{HeaderMap, HeaderName};
+use axum_extra::extract::{
+    cookie::{Cookie, SameSite},
+    CookieJar,
+};
+use base64::{engine::general_purpose::URL_SAFE_NO_PAD, Engine};
+use ring::{
+    constant_time::verify_slices_are_equal,
+    rand::{SecureRandom, SystemRandom},
+};
+
+use crate::errors::AuthError;
+
+pub(crate) const REFRESH_COOKIE: &str = "refresh-token";
+pub(crate) const CSRF_COOKIE: &str = "csrf-token";
+pub(crate) const OIDC_STATE_COOKIE: &str = "oidc-state";
+
+/// Requests authenticated by the refresh token cookie should repeat the csrf cookie in this header
+pub static CSRF_HEADER: HeaderName = HeaderName::from_static("x-csrf-token");
+
+/// Attributes of the cookies set by the auth endpoints
+#[derive(Clone)]
+pub(crate) struct CookieConfig {
+    pub(crate) secure: bool,
+    pub(crate) same_site: SameSite,
+    pub(crate) path: Option<String>,
+    pub(crate) domain: Option<String>,
+
+    // Session cookies when not set, it's checked to be in range when it's configured
+    pub(crate) max_age: Option<time::Duration>,
+}
+
+impl Default for CookieConfig {
+    fn default() -> Self {
+        Self {
+            secure: true,
+            same_site: SameSite::Strict,
+            path: None,
+            domain: None,
+            max_age: None,
+        }
+    }
+}
+
+impl CookieConfig {
+    /// Value of the `Set-Cookie` header for the refresh token
+    pub(crate) fn refresh_cookie(&self, token: &str) -> String {
+        self.build(
+            REFRESH_COOKIE,
+            token,
+            self.path.as_deref(),
+            true,
+            self.max_age,
+        )
+    }
+
+    /// Value of the `Set-Cookie` header for the csrf token, it's readable by the scripts of every
+    /// page so they can send it back in the `X-CSRF-Token` header
+    pub(crate) fn csrf_cookie(&self, token: &str) -> String {
+        self.build(CSRF_COOKIE, token, Some("/"), false, self.max_age)
+    }
+
+    /// Value of the `Set-Cookie` header tying an OpenID Connect login to the browser which started
+    /// it. Without a path it's sent to the provider's callback, next to the authorize route
+    pub(crate) fn oidc_state_cookie(&self, state: &str, max_age: time::Duration) -> String {
+        self.build(OIDC_STATE_COOKIE, state, None, true, Some(max_age))
+    }
+
+    fn build(
+        &self,
+        name: &str,
+        value: &str,
+        path: Option<&str>,
+        http_only: bool,
+        max_age: Option<time::Duration>,
+    ) -> String {
+        let mut cookie = Cookie::build(name, value)
+            .http_only(http_only)
+            .secure(self.secure)
+            .same_site(self.same_site)
+            .finish();
+
+        if let Some(path) = path {
+            cookie.set_path(path);
+        }
+        if let Some(domain) = &self.domain {
+            cookie.set_domain(domain);
+        }
+        if let Some(max_age) = max_age {
+            cookie.set_max_age(max_age);
+        }
+
+        cookie.stripped().to_string()
+    }
+}
+
+pub(crate) fn generate_csrf_token() -> String {
+    let mut bytes = [0u8; 32];
+    SystemRandom::new()
+        .fill(&mut bytes)
+        .expect("Failed to generate a csrf token");
+    URL_SAFE_NO_PAD.encode(bytes)
+}
+
+/// Double submit check, the csrf cookie should be repeated in the `X-CSRF-Token` header.
+///
+/// Other sites can make the browser send the cookies, but can't read them to set the header
+pub(crate) fn check_csrf(cookies: &CookieJar, headers: &HeaderMap) -> Result<(), AuthError> {
+    let cookie = cookies
+        .get(CSRF_COOKIE)
+        .filter(|cookie| !cookie.value().is_empty())
+        .ok_or(AuthError::Csrf)?;
+    let header = headers.get(&CSRF_HEADER).ok_or(AuthError::Csrf)?;
+
+    verify_slices_are_equal(cookie.value().as_bytes(), header.as_bytes())
+        .map_err(|_| AuthError::Csrf)
+}
+
+/// Parse the `same_site` setting
+pub(crate) fn parse_same_site(value: &str) -> Option<SameSite> {
+    match value.to_ascii_lowercase().as_str() {
+        "strict" => Some(SameSite::Strict),
+        "lax" => Some(SameSite::Lax),
+        "none" => Some(SameSite::None),
+        _ => None,
+    }
+}
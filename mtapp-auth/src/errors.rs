@@ -19,6 +19,15 @@ pub enum AuthError {
     #[json_error(request, status = 403, code = "403000 not-authorized")]
     Permission,
 
+    #[json_error(request, status = 403, code = "403001 bad-csrf-token")]
+    Csrf,
+
+    #[json_error(request, status = 404, code = "404000 unknown-provider")]
+    UnknownProvider,
+
+    #[json_error(request, status = 429, code = "429000 too-many-attempts")]
+    TooManyAttempts,
+
     #[json_error(internal)]
     Configuration,
 
@@ -0,0 +1,201 @@
+use std::time::Duration;
+
+use axum::{
+    extract::{Path, State},
+    http::header::SET_COOKIE,
+    response::{AppendHeaders, IntoResponse, Response},
+    Extension,
+};
+use axum_extra::extract::CookieJar;
+use basteh::Basteh;
+use json_resp::JsonResponse;
+use ring::constant_time::verify_slices_are_equal;
+
+use mtapp::extractors::{oai, Form, Query};
+
+use crate::{
+    app::AuthConfig,
+    cookies::OIDC_STATE_COOKIE,
+    errors::AuthError,
+    errors::AuthErrorOai,
+    extract::Claims,
+    handlers::{login_response, mfa_challenge_response},
+    oidc::PendingLogin,
+    providers::{GrantProvider, SessionProvider, UserProvider},
+    schemas::{Flat, OidcAuthorization, OidcCallback, TokenData},
+};
+
+const STATE_SCOPE: &str = "oidc_states";
+const STATE_EXPIRY: Duration = Duration::from_secs(10 * 60);
+
+#[utoipa::path(
+    post,
+    tag = "Auth",
+    path = "/oidc/{provider}/authorize",
+    params(
+        ("provider" = String, Path, description = "Name of the configured provider")
+    ),
+    responses(
+        (
+            status = 200,
+            body = inline(JsonResponse<OidcAuthorization>),
+            description = "Logged in users link the identity to their account instead of \
+                logging in with it",
+            headers(
+                (
+                    "Set-Cookie" = String,
+                    description="An http only `oidc-state` cookie, the callback should be called \
+                        from the same browser"
+                )
+            )
+        ),
+        oai::PathErrors,
+        AuthErrorOai::Permission,
+        AuthErrorOai::UnknownProvider,
+        AuthErrorOai::InternalError,
+    ),
+    security((), ("jwt_token" = []))
+)]
+pub async fn oidc_authorize(
+    config: AuthConfig,
+    State(storage): State<Basteh>,
+    Path(provider): Path<String>,
+    claims: Option<Extension<Claims>>,
+) -> impl IntoResponse {
+    let provider = config.get_oidc_provider(&provider)?;
+
+    let link_to = match claims {
+        // Tokens only, same as adding a passkey
+        Some(claims) if claims.is_client() || claims.is_api_key() => {
+            return Err(AuthError::Permission)
+        }
+        Some(claims) => Some(claims.user_id),
+        None => None,
+    };
+
+    let (authorization_url, state, pending) = provider.authorize(config.http(), link_to).await?;
+    let max_age = time::Duration::try_from(STATE_EXPIRY).expect("State expiry is in range");
+    let cookie = config.cookie().oidc_state_cookie(&state, max_age);
+    storage
+        .scope(STATE_SCOPE)
+        .set_expiring(
+            state,
+            serde_json::to_string(&pending).expect("Serializable"),
+            STATE_EXPIRY,
+        )
+        .await?;
+
+    Result::<_, AuthError>::Ok((
+        AppendHeaders([(SET_COOKIE, cookie)]),
+        JsonResponse::with_content(OidcAuthorization { authorization_url }),
+    ))
+}
+
+#[utoipa::path(
+    post,
+    tag = "Auth",
+    path = "/oidc/{provider}/callback",
+    params(
+        ("provider" = String, Path, description = "Name of the configured provider")
+    ),
+    request_body(
+        content=inline(OidcCallback),
+        content_type="application/x-www-form-urlencoded",
+        description="The code and state the provider redirected back with"
+    ),
+    responses(
+        (
+            status = 200,
+            body = inline(JsonResponse<TokenData>),
+            description = "Login was successful, a new user is made on the first login. Users \
+                with MFA enabled get an `MfaChallenge` instead, and a `Message` is returned when \
+                an identity is linked",
+            headers(
+                (
+                    "Set-Cookie" = String,
+                    description="Same cookies as the `/login` endpoint"
+                )
+            )
+        ),
+        oai::AllExtErrors,
+        oai::PathErrors,
+        AuthErrorOai::BadToken,
+        AuthErrorOai::Credentials,
+        AuthErrorOai::Permission,
+        AuthErrorOai::UnknownProvider,
+        AuthErrorOai::InternalError,
+    ),
+    security((), ("jwt_token" = []))
+)]
+#[allow(clippy::too_many_arguments)]
+pub async fn oidc_callback<U, S, G>(
+    config: AuthConfig,
+    State(storage): State<Basteh>,
+    Path(provider): Path<String>,
+    query: Query<Flat>,
+    claims: Option<Extension<Claims>>,
+    cookies: CookieJar,
+    user_data: U::Data,
+    session_data: S::Data,
+    scopes_data: G::Data,
+    form: Form<OidcCallback>,
+) -> Result<Response, AuthError>
+where
+    U: UserProvider,
+    S: SessionProvider,
+    G: GrantProvider,
+{
+    let provider = config.get_oidc_provider(&provider)?;
+
+    // Otherwise a victim could be logged in as the attacker with the attacker's code and state
+    let cookie = cookies.get(OIDC_STATE_COOKIE).ok_or(AuthError::BadToken)?;
+    verify_slices_are_equal(cookie.value().as_bytes(), form.state.as_bytes())
+        .map_err(|_| AuthError::BadToken)?;
+
+    // The state can only be used once
+    let pending: Option<String> = storage.scope(STATE_SCOPE).remove(&form.state).await?;
+    let pending = pending
+        .and_then(|pending| serde_json::from_str::<PendingLogin>(&pending).ok())
+        .filter(|pending| pending.provider == provider.name())
+        .ok_or(AuthError::BadToken)?;
+
+    let identity = match provider.exchange(config.http(), &form.code, &pending).await {
+        Ok(identity) => identity,
+        Err(err) => {
+            metrics::increment_counter!(
+                "auth_oidc_logins_total",
+                "provider" => provider.name().to_owned(),
+                "result" => "failure"
+            );
+            return Err(err);
+        }
+    };
+
+    if let Some(user_id) = pending.link_to {
+        // The linking should be finished by the same user who started it
+        if claims.map(|claims| claims.user_id) != Some(user_id) {
+            return Err(AuthError::Permission);
+        }
+        U::link_identity(&user_data, user_id, &identity).await?;
+        return Ok(JsonResponse::with_content("Identity is linked").into_response());
+    }
+
+    let user_id = U::external_login(&user_data, &identity).await?;
+
+    if U::mfa_required(&user_data, user_id).await? {
+        metrics::increment_counter!(
+            "auth_oidc_logins_total",
+            "provider" => provider.name().to_owned(),
+            "result" => "mfa_required"
+        );
+        return Ok(mfa_challenge_response(&config, &query, user_id, None));
+    }
+
+    let res = login_response::<S, G>(&config, &query, &session_data, &scopes_data, user_id).await?;
+    metrics::increment_counter!(
+        "auth_oidc_logins_total",
+        "provider" => provider.name().to_owned(),
+        "result" => "success"
+    );
+    Ok(res)
+}
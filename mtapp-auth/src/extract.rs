@@ -2,36 +2,67 @@ use std::ops::Deref;
 use std::sync::Arc;
 use std::time::{Duration, SystemTime, UNIX_EPOCH};
 
-use axum::extract::FromRequestParts;
+use axum::extract::{FromRef, FromRequestParts};
 use basteh::Basteh;
-use jsonwebtoken::{decode, encode, DecodingKey, EncodingKey, Header as TokenHeader, Validation};
+use jsonwebtoken::{decode, decode_header, encode, Validation};
+use mtapp::ReactorState;
 use serde::{Deserialize, Serialize};
+use utoipa::ToSchema;
 use uuid::Uuid;
 
-use crate::{AuthConfig, AuthError};
+use crate::{AuthConfig, AuthError, KeyRing, SigningKey};
+
+/// What the `sub` of the claims refers to
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq, Serialize, Deserialize, ToSchema)]
+#[serde(rename_all = "lowercase")]
+pub enum SubjectType {
+    #[default]
+    User,
+    /// A service authenticated through the client_credentials grant
+    Client,
+}
+
+impl SubjectType {
+    fn is_user(&self) -> bool {
+        *self == SubjectType::User
+    }
+}
 
 #[derive(Debug, Serialize, Deserialize)]
 pub struct ClaimsInner {
     pub jti: Uuid,
     pub iat: u64,
     pub exp: u64,
+    /// The client's id for client tokens
     #[serde(rename = "sub")]
     pub user_id: Uuid,
+    #[serde(
+        rename = "sub_type",
+        default,
+        skip_serializing_if = "SubjectType::is_user"
+    )]
+    pub subject_type: SubjectType,
     pub scopes: Vec<String>,
+    /// Set when the request is authenticated by an api key instead of a token, the jti is the
+    /// key's id then
+    #[serde(skip)]
+    pub api_key: bool,
 }
 
-/// Get the jwt Claims from extensions, it won't work outside of jwt middleware wrapped handlers.
-///
-/// Be advised the actual user might not exist
-#[derive(Debug, Clone)]
-pub struct Claims(Arc<ClaimsInner>);
-
-impl Claims {
-    pub fn new(user_id: Uuid, jti: Uuid, scopes: Vec<String>, exp: Duration) -> Self {
-        Self(Arc::new(ClaimsInner {
+impl ClaimsInner {
+    fn new(
+        subject_type: SubjectType,
+        user_id: Uuid,
+        jti: Uuid,
+        scopes: Vec<String>,
+        exp: Duration,
+    ) -> Self {
+        ClaimsInner {
             jti,
             user_id,
+            subject_type,
             scopes,
+            api_key: false,
             iat: SystemTime::now()
                 .duration_since(UNIX_EPOCH)
                 .expect("UNIX_EPOCH is past")
@@ -40,24 +71,58 @@ impl Claims {
                 .duration_since(UNIX_EPOCH)
                 .expect("UNIX_EPOCH is past")
                 .as_secs(),
+        }
+    }
+}
+
+/// Get the jwt Claims from extensions, it won't work outside of jwt middleware wrapped handlers.
+///
+/// Be advised the actual user might not exist
+#[derive(Debug, Clone)]
+pub struct Claims(Arc<ClaimsInner>);
+
+impl Claims {
+    pub fn new(user_id: Uuid, jti: Uuid, scopes: Vec<String>, exp: Duration) -> Self {
+        Self(Arc::new(ClaimsInner::new(
+            SubjectType::User,
+            user_id,
+            jti,
+            scopes,
+            exp,
+        )))
+    }
+
+    /// Claims of a client, it's not tied to any session
+    pub fn for_client(client_id: Uuid, scopes: Vec<String>, exp: Duration) -> Self {
+        Self(Arc::new(ClaimsInner::new(
+            SubjectType::Client,
+            client_id,
+            Uuid::new_v4(),
+            scopes,
+            exp,
+        )))
+    }
+
+    /// Claims of a request authenticated by the user's api key, they're never signed
+    pub fn for_api_key(user_id: Uuid, key_id: Uuid, scopes: Vec<String>, exp: Duration) -> Self {
+        Self(Arc::new(ClaimsInner {
+            api_key: true,
+            ..ClaimsInner::new(SubjectType::User, user_id, key_id, scopes, exp)
         }))
     }
 
-    pub fn generate_token(&self, secret: &str) -> String {
-        encode(
-            &TokenHeader::default(),
-            self.0.as_ref(),
-            &EncodingKey::from_secret(secret.as_bytes()),
-        )
-        .expect("Parameters are valid.")
+    pub fn generate_token(&self, key: &SigningKey) -> String {
+        encode(&key.header(), self.0.as_ref(), key.encoding()).expect("Parameters are valid.")
     }
 
-    pub fn from_token(token: &str, secret: &str) -> Result<Self, AuthError> {
-        let inner = match decode(
-            &token,
-            &DecodingKey::from_secret(secret.as_bytes()),
-            &Validation::default(),
-        ) {
+    /// Verify the token with the key from the ring matching its `kid`
+    pub fn from_token(token: &str, keys: &KeyRing) -> Result<Self, AuthError> {
+        let header = decode_header(token).map_err(|_| AuthError::BadToken)?;
+        let key = keys
+            .find(header.kid.as_deref())
+            .ok_or(AuthError::BadToken)?;
+
+        let inner = match decode(token, key.decoding(), &Validation::new(key.algorithm())) {
             Ok(value) => Ok(value.claims),
             Err(_) => Err(AuthError::BadToken),
         }?;
@@ -68,6 +133,14 @@ impl Claims {
         self.0
     }
 
+    pub fn is_client(&self) -> bool {
+        self.0.subject_type == SubjectType::Client
+    }
+
+    pub fn is_api_key(&self) -> bool {
+        self.0.api_key
+    }
+
     pub fn has_scope(&self, scope: &str) -> bool {
         self.0.scopes.iter().any(|r| r == scope)
     }
@@ -116,24 +189,40 @@ impl TokenBlacklist {
 }
 
 #[axum::async_trait]
-impl<S: Sync> FromRequestParts<S> for TokenBlacklist {
+impl<S> FromRequestParts<S> for TokenBlacklist
+where
+    ReactorState: FromRef<S>,
+    S: Sync,
+{
     type Rejection = AuthError;
 
     async fn from_request_parts(
         parts: &mut axum::http::request::Parts,
-        _state: &S,
+        state: &S,
+    ) -> Result<Self, Self::Rejection> {
+        Ok(Self {
+            config: AuthConfig::from_request_parts(parts, state).await?,
+            storage: ReactorState::from_ref(state).storage().clone(),
+        })
+    }
+}
+
+/// Rejects with `AuthError::Configuration` when `AuthApp` isn't mounted
+#[axum::async_trait]
+impl<S> FromRequestParts<S> for AuthConfig
+where
+    ReactorState: FromRef<S>,
+    S: Sync,
+{
+    type Rejection = AuthError;
+
+    async fn from_request_parts(
+        _parts: &mut axum::http::request::Parts,
+        state: &S,
     ) -> Result<Self, Self::Rejection> {
-        let config = parts
-            .extensions
+        ReactorState::from_ref(state)
             .get::<AuthConfig>()
-            .ok_or(AuthError::Configuration)?
-            .clone();
-        let storage = parts
-            .extensions
-            .get::<Basteh>()
-            .ok_or(AuthError::Configuration)?
-            .clone();
-
-        Ok(Self { config, storage })
+            .cloned()
+            .ok_or(AuthError::Configuration)
     }
 }
@@ -1,21 +1,27 @@
 use axum::{
-    http::header::SET_COOKIE,
-    response::{AppendHeaders, IntoResponse},
+    extract::State,
+    http::{header::SET_COOKIE, HeaderMap, HeaderName},
+    response::{AppendHeaders, IntoResponse, Response},
     Extension,
 };
-use axum_extra::extract::{cookie::Cookie, CookieJar};
+use axum_extra::extract::CookieJar;
 use basteh::Basteh;
 use json_resp::JsonResponse;
+use jsonwebtoken::jwk::JwkSet;
+use uuid::Uuid;
 
 use mtapp::extractors::{oai, Form, Json, Query};
 
 use crate::{
     app::AuthConfig,
+    cookies::{check_csrf, generate_csrf_token, REFRESH_COOKIE},
     errors::AuthError,
     errors::AuthErrorOai,
     extract::Claims,
-    providers::{GrantProvider, SessionProvider, UserProvider},
-    schemas::{Credentials, Flat, Message, TokenData},
+    mfa::{complete_challenge, issue_challenge},
+    providers::{GrantProvider, RefreshOutcome, SessionProvider, UserProvider},
+    schemas::{Credentials, Flat, Message, MfaChallenge, MfaLogin, TokenData},
+    throttle::LoginGuard,
 };
 
 #[utoipa::path(
@@ -31,65 +37,125 @@ use crate::{
         (
             status = 200,
             body = inline(JsonResponse<TokenData>),
-            description = "Login was successful",
+            description = "Login was successful. Users with MFA enabled get an `MfaChallenge` \
+                instead, to be completed at `/login/mfa`",
             headers(
                 (
                     "Set-Cookie" = String,
                     description="Contains a cookie name `refresh-token` which is used \
-                        by both the `/refresh` endpoint and the `/logout` endpoint"
+                        by both the `/refresh` endpoint and the `/logout` endpoint, and a \
+                        `csrf-token` cookie which should be sent back in the `X-CSRF-Token` header"
                 )
             )
         ),
         oai::AllExtErrors,
         AuthErrorOai::Credentials,
         AuthErrorOai::Permission,
+        AuthErrorOai::TooManyAttempts,
         AuthErrorOai::InternalError,
     )
 )]
 pub async fn login<U, S, G>(
-    config: Extension<AuthConfig>,
+    config: AuthConfig,
     query: Query<Flat>,
-    user_data: U::Data<()>,
-    session_data: S::Data<()>,
-    scopes_data: G::Data<()>,
+    guard: LoginGuard,
+    user_data: U::Data,
+    session_data: S::Data,
+    scopes_data: G::Data,
     credentials: Form<Credentials>,
-) -> impl IntoResponse
+) -> Result<Response, AuthError>
 where
     U: UserProvider,
     S: SessionProvider,
     G: GrantProvider,
 {
-    let user_id = U::login(&user_data, &credentials.username, &credentials.password).await?;
-    let scopes = G::scopes(&scopes_data, user_id).await?;
+    guard.check(&credentials.username).await?;
 
-    let (jti, refresh_token) = S::make(&session_data, user_id).await?;
+    let user_id = match U::login(&user_data, &credentials.username, &credentials.password).await {
+        Ok(user_id) => user_id,
+        Err(err) => {
+            if matches!(err, AuthError::Credentials) {
+                guard.failed(&credentials.username).await?;
+            }
+            metrics::increment_counter!("auth_logins_total", "result" => "failure");
+            return Err(err);
+        }
+    };
 
-    let claims = Claims::new(user_id, jti, scopes, config.get_token_expiry());
-    let access_token = claims.generate_token(config.expose_secret());
+    // The failures are forgotten once the second factor is verified too
+    if U::mfa_required(&user_data, user_id).await? {
+        metrics::increment_counter!("auth_logins_total", "result" => "mfa_required");
+        return Ok(mfa_challenge_response(
+            &config,
+            &query,
+            user_id,
+            Some(&credentials.username),
+        ));
+    }
+    guard.succeeded(&credentials.username).await?;
 
-    let headers = AppendHeaders([(
-        SET_COOKIE,
-        Cookie::build("refresh-token", &refresh_token)
-            .http_only(true)
-            .finish()
-            .stripped()
-            .to_string(),
-    )]);
+    let res = login_response::<S, G>(&config, &query, &session_data, &scopes_data, user_id).await?;
+    metrics::increment_counter!("auth_logins_total", "result" => "success");
+    Ok(res)
+}
 
-    let token_data = TokenData {
-        access_token,
-        token_type: "bearer",
-        refresh_token,
-        expires_in: config.get_token_expiry().as_secs(),
-    };
+#[utoipa::path(
+    post,
+    tag = "Auth",
+    path = "/login/mfa",
+    request_body(
+        content=inline(MfaLogin),
+        content_type="application/x-www-form-urlencoded",
+        description="The challenge token returned by `/login` and a TOTP or recovery code"
+    ),
+    responses(
+        (
+            status = 200,
+            body = inline(JsonResponse<TokenData>),
+            description = "Login was successful",
+            headers(
+                (
+                    "Set-Cookie" = String,
+                    description="Same cookies as the `/login` endpoint"
+                )
+            )
+        ),
+        oai::AllExtErrors,
+        AuthErrorOai::BadToken,
+        AuthErrorOai::Credentials,
+        AuthErrorOai::Permission,
+        AuthErrorOai::TooManyAttempts,
+        AuthErrorOai::InternalError,
+    )
+)]
+pub async fn login_mfa<U, S, G>(
+    config: AuthConfig,
+    State(storage): State<Basteh>,
+    query: Query<Flat>,
+    guard: LoginGuard,
+    user_data: U::Data,
+    session_data: S::Data,
+    scopes_data: G::Data,
+    form: Form<MfaLogin>,
+) -> Result<Response, AuthError>
+where
+    U: UserProvider,
+    S: SessionProvider,
+    G: GrantProvider,
+{
+    let user_id = complete_challenge::<U>(
+        &config,
+        &storage,
+        &guard,
+        &user_data,
+        &form.challenge_token,
+        &form.code,
+    )
+    .await?;
 
-    if query.flat.unwrap_or_default() {
-        Result::<_, AuthError>::Ok((headers, Json(token_data)).into_response())
-    } else {
-        Result::<_, AuthError>::Ok(
-            (headers, JsonResponse::with_content(token_data)).into_response(),
-        )
-    }
+    let res = login_response::<S, G>(&config, &query, &session_data, &scopes_data, user_id).await?;
+    metrics::increment_counter!("auth_logins_total", "result" => "success");
+    Ok(res)
 }
 
 #[utoipa::path(
@@ -97,7 +163,13 @@ where
     tag = "Auth",
     path = "/refresh",
     params(
-        ("refresh-token" = Option<String>, Cookie, description = "Refresh token")
+        ("refresh-token" = Option<String>, Cookie, description = "Refresh token"),
+        ("csrf-token" = Option<String>, Cookie, description = "Csrf token"),
+        (
+            "X-CSRF-Token" = Option<String>,
+            Header,
+            description = "Same as the `csrf-token` cookie, needed when the refresh token is used"
+        )
     ),
     responses(
         (
@@ -107,59 +179,58 @@ where
                 (
                     "Set-Cookie" = String,
                     description="Contains a cookie name `refresh-token` which is used \
-                        by both the `/refresh` endpoint and the `/logout` endpoint"
+                        by both the `/refresh` endpoint and the `/logout` endpoint, and a \
+                        `csrf-token` cookie which should be sent back in the `X-CSRF-Token` header"
                 )
             )
         ),
         AuthErrorOai::BadToken,
+        AuthErrorOai::Csrf,
         AuthErrorOai::Permission,
         AuthErrorOai::InternalError,
     )
 )]
 pub async fn refresh<S, G>(
-    config: Extension<AuthConfig>,
-    storage: Extension<Basteh>,
+    config: AuthConfig,
+    State(storage): State<Basteh>,
     query: Query<Flat>,
-    session_data: S::Data<()>,
-    grants_data: G::Data<()>,
+    session_data: S::Data,
+    grants_data: G::Data,
     cookies: CookieJar,
+    headers: HeaderMap,
 ) -> impl IntoResponse
 where
     S: SessionProvider,
     G: GrantProvider,
 {
-    let refresh_token = if let Some(cookie) = cookies.get("refresh-token") {
+    let refresh_token = if let Some(cookie) = cookies.get(REFRESH_COOKIE) {
         String::from(cookie.value())
     } else {
         return Err(AuthError::BadToken);
     };
 
-    let (jti, user_id) = S::find(&session_data, &refresh_token).await?;
-
-    let scopes = G::scopes(&grants_data, user_id).await?;
+    if config.is_csrf_enabled() {
+        check_csrf(&cookies, &headers)?;
+    }
 
-    // Blacklist the previous jti
-    storage
-        .scope(config.blacklist_scope())
-        .set_expiring(jti, 0, config.get_token_expiry())
-        .await?;
+    let (user_id, jti, refresh_token) =
+        rotate_session::<S>(&config, &storage, &session_data, &refresh_token).await?;
 
-    let jti = S::reset_jti(&session_data, &refresh_token).await?;
+    let scopes = G::scopes(&grants_data, user_id).await?;
 
     let claims = Claims::new(user_id, jti, scopes, config.get_token_expiry());
-    let access_token = claims.generate_token(config.expose_secret());
+    let access_token = claims.generate_token(&config.keys().current());
 
-    let token_data = TokenData {
-        access_token,
-        token_type: "bearer",
-        refresh_token,
-        expires_in: config.get_token_expiry().as_secs(),
-    };
+    let (headers, token_data) = token_response(&config, access_token, refresh_token);
+
+    metrics::increment_counter!("auth_refreshes_total");
 
     if query.flat.unwrap_or_default() {
-        Result::<_, AuthError>::Ok(Json(token_data).into_response())
+        Result::<_, AuthError>::Ok((headers, Json(token_data)).into_response())
     } else {
-        Result::<_, AuthError>::Ok(JsonResponse::with_content(token_data).into_response())
+        Result::<_, AuthError>::Ok(
+            (headers, JsonResponse::with_content(token_data)).into_response(),
+        )
     }
 }
 
@@ -168,21 +239,29 @@ where
     tag = "Auth",
     path = "/logout",
     params(
-        ("refresh-token" = Option<String>, Cookie, description = "Refresh token")
+        ("refresh-token" = Option<String>, Cookie, description = "Refresh token"),
+        ("csrf-token" = Option<String>, Cookie, description = "Csrf token"),
+        (
+            "X-CSRF-Token" = Option<String>,
+            Header,
+            description = "Same as the `csrf-token` cookie, needed when the refresh token is used"
+        )
     ),
     responses(
         (status = 200, body=inline(JsonResponse<Message>)),
         AuthErrorOai::BadToken,
+        AuthErrorOai::Csrf,
         AuthErrorOai::Permission,
         AuthErrorOai::InternalError,
     )
 )]
 pub async fn logout<U, S>(
-    config: Extension<AuthConfig>,
-    storage: Extension<Basteh>,
+    config: AuthConfig,
+    State(storage): State<Basteh>,
     claims: Option<Extension<Claims>>,
     cookies: CookieJar,
-    session_data: S::Data<()>,
+    headers: HeaderMap,
+    session_data: S::Data,
 ) -> impl IntoResponse
 where
     U: UserProvider,
@@ -196,7 +275,11 @@ where
             .scope(config.blacklist_scope())
             .set_expiring(claims.jti, 0, config.get_token_expiry())
             .await?;
-    } else if let Some(cookie) = cookies.get("refresh-token") {
+    } else if let Some(cookie) = cookies.get(REFRESH_COOKIE) {
+        if config.is_csrf_enabled() {
+            check_csrf(&cookies, &headers)?;
+        }
+
         let (jti, _) = S::find(&session_data, cookie.value()).await?;
 
         // Blacklist the previous jti
@@ -208,5 +291,129 @@ where
         S::delete_by_jti(&session_data, jti).await?;
     }
 
+    metrics::increment_counter!("auth_logouts_total");
+
     Result::<_, AuthError>::Ok(JsonResponse::with_content("Logged out successfully"))
 }
+
+#[utoipa::path(
+    get,
+    tag = "Auth",
+    path = "/.well-known/jwks.json",
+    responses(
+        (
+            status = 200,
+            body = Object,
+            description = "Public keys of the active signing keys, HS256 keys are not published"
+        ),
+    )
+)]
+pub async fn jwks(config: AuthConfig) -> Json<JwkSet> {
+    Json(config.keys().jwks())
+}
+
+/// Rotate the refresh token and blacklist the session's previous jti, returns
+/// (user_id, jti, refresh_token)
+pub(crate) async fn rotate_session<S: SessionProvider>(
+    config: &AuthConfig,
+    storage: &Basteh,
+    session_data: &S::Data,
+    refresh_token: &str,
+) -> Result<(Uuid, Uuid, String), AuthError> {
+    let (user_id, previous_jti, jti, refresh_token) =
+        match S::rotate(session_data, refresh_token).await? {
+            RefreshOutcome::Rotated {
+                user_id,
+                previous_jti,
+                jti,
+                refresh_token,
+            } => (user_id, previous_jti, jti, refresh_token),
+            RefreshOutcome::Reused { jti } => {
+                // The token is probably stolen, the session is already revoked by the provider
+                storage
+                    .scope(config.blacklist_scope())
+                    .set_expiring(jti, 0, config.get_token_expiry())
+                    .await?;
+
+                tracing::warn!(%jti, "A rotated refresh token was reused, revoked its session");
+                metrics::increment_counter!("auth_refresh_reuses_total");
+                return Err(AuthError::BadToken);
+            }
+        };
+
+    // Blacklist the previous jti
+    storage
+        .scope(config.blacklist_scope())
+        .set_expiring(previous_jti, 0, config.get_token_expiry())
+        .await?;
+
+    Ok((user_id, jti, refresh_token))
+}
+
+/// Make a session for the user and respond with its tokens
+pub(crate) async fn login_response<S: SessionProvider, G: GrantProvider>(
+    config: &AuthConfig,
+    query: &Flat,
+    session_data: &S::Data,
+    scopes_data: &G::Data,
+    user_id: Uuid,
+) -> Result<Response, AuthError> {
+    let scopes = G::scopes(scopes_data, user_id).await?;
+
+    let (jti, refresh_token) = S::make(session_data, user_id).await?;
+
+    let claims = Claims::new(user_id, jti, scopes, config.get_token_expiry());
+    let access_token = claims.generate_token(&config.keys().current());
+
+    let (headers, token_data) = token_response(config, access_token, refresh_token);
+
+    if query.flat.unwrap_or_default() {
+        Ok((headers, Json(token_data)).into_response())
+    } else {
+        Ok((headers, JsonResponse::with_content(token_data)).into_response())
+    }
+}
+
+/// Respond with a challenge instead of the tokens, to be completed at `/login/mfa`
+pub(crate) fn mfa_challenge_response(
+    config: &AuthConfig,
+    query: &Flat,
+    user_id: Uuid,
+    username: Option<&str>,
+) -> Response {
+    let challenge = MfaChallenge {
+        mfa_required: true,
+        challenge_token: issue_challenge(config, user_id, username),
+        expires_in: config.get_mfa_challenge_expiry().as_secs(),
+    };
+
+    if query.flat.unwrap_or_default() {
+        Json(challenge).into_response()
+    } else {
+        JsonResponse::with_content(challenge).into_response()
+    }
+}
+
+/// Set the refresh token and csrf cookies, and build the body based on the config
+fn token_response(
+    config: &AuthConfig,
+    access_token: String,
+    refresh_token: String,
+) -> (AppendHeaders<Vec<(HeaderName, String)>>, TokenData) {
+    let mut cookies = vec![(SET_COOKIE, config.cookie().refresh_cookie(&refresh_token))];
+
+    let csrf_token = config.is_csrf_enabled().then(generate_csrf_token);
+    if let Some(csrf_token) = &csrf_token {
+        cookies.push((SET_COOKIE, config.cookie().csrf_cookie(csrf_token)));
+    }
+
+    let token_data = TokenData {
+        access_token,
+        token_type: "bearer",
+        refresh_token: config.is_refresh_token_in_body().then_some(refresh_token),
+        expires_in: config.get_token_expiry().as_secs(),
+        csrf_token,
+    };
+
+    (AppendHeaders(cookies), token_data)
+}
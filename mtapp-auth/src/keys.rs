@@ -0,0 +1,483 @@
+use std::{
+    error::Error,
+    fmt, fs, io,
+    path::Path,
+    sync::{Arc, RwLock},
+};
+
+use base64::{engine::general_purpose::URL_SAFE_NO_PAD, Engine};
+use jsonwebtoken::{
+    jwk::{
+        AlgorithmParameters, CommonParameters, EllipticCurve, Jwk, JwkSet, OctetKeyPairParameters,
+        OctetKeyPairType, PublicKeyUse, RSAKeyParameters, RSAKeyType,
+    },
+    Algorithm, DecodingKey, EncodingKey, Header,
+};
+use pem::Pem;
+use ring::{
+    aead::{Aad, LessSafeKey, Nonce, UnboundKey, AES_256_GCM, NONCE_LEN},
+    hkdf::{Salt, HKDF_SHA256},
+    rand::{SecureRandom, SystemRandom},
+    signature::{Ed25519KeyPair, KeyPair},
+};
+use simple_asn1::ASN1Block;
+use sqlx::types::chrono::{DateTime, Utc};
+
+/// SubjectPublicKeyInfo header of an Ed25519 public key, followed by the 32 bytes key
+const ED25519_SPKI_PREFIX: &[u8] = &[
+    0x30, 0x2a, 0x30, 0x05, 0x06, 0x03, 0x2b, 0x65, 0x70, 0x03, 0x21, 0x00,
+];
+
+const SEALING_SALT: &[u8] = b"mtapp-auth signing keys";
+
+#[derive(Debug)]
+pub enum KeyError {
+    /// The key file couldn't be read
+    Io(io::Error),
+
+    /// The key is not a valid PEM encoded key for the algorithm
+    InvalidKey(jsonwebtoken::errors::Error),
+
+    /// The public key couldn't be converted to a JWK
+    UnsupportedFormat,
+
+    /// A new key couldn't be generated
+    Generation,
+
+    /// A stored key couldn't be decrypted, ex. the sealing key has changed since it was stored
+    Sealing,
+
+    /// Keys can't be stored or loaded without a sealing key
+    NoSealingKey,
+}
+
+impl fmt::Display for KeyError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::Io(e) => write!(f, "Failed to read the key: {}", e),
+            Self::InvalidKey(e) => write!(f, "Invalid key: {}", e),
+            Self::UnsupportedFormat => f.write_str("Unsupported public key format"),
+            Self::Generation => f.write_str("Failed to generate a new key"),
+            Self::Sealing => f.write_str("Failed to decrypt a stored key"),
+            Self::NoSealingKey => f.write_str("A sealing key should be set to store the keys"),
+        }
+    }
+}
+
+impl Error for KeyError {}
+
+impl From<io::Error> for KeyError {
+    fn from(err: io::Error) -> Self {
+        Self::Io(err)
+    }
+}
+
+impl From<jsonwebtoken::errors::Error> for KeyError {
+    fn from(err: jsonwebtoken::errors::Error) -> Self {
+        Self::InvalidKey(err)
+    }
+}
+
+/// The key used to sign and verify the jwt tokens.
+///
+/// HMAC keys are shared secrets, so they are never published. RSA and Ed25519 keys are
+/// published in the JWKS route so other services can verify the tokens on their own.
+#[derive(Clone)]
+pub struct SigningKey(Arc<SigningKeyInner>);
+
+struct SigningKeyInner {
+    kid: Option<String>,
+    algorithm: Algorithm,
+    encoding: EncodingKey,
+    decoding: DecodingKey,
+    jwk: Option<Jwk>,
+
+    // Kept to store the key in the key ring
+    private: Vec<u8>,
+    public: Option<Vec<u8>>,
+}
+
+impl SigningKey {
+    /// A HS256 key, tokens signed with it don't have a `kid`
+    pub fn hmac(secret: &str) -> Self {
+        Self::hmac_with_kid(None, secret)
+    }
+
+    fn hmac_with_kid(kid: Option<String>, secret: &str) -> Self {
+        Self(Arc::new(SigningKeyInner {
+            kid,
+            algorithm: Algorithm::HS256,
+            encoding: EncodingKey::from_secret(secret.as_bytes()),
+            decoding: DecodingKey::from_secret(secret.as_bytes()),
+            jwk: None,
+            private: secret.as_bytes().to_vec(),
+            public: None,
+        }))
+    }
+
+    /// A RS256 key from PEM encoded PKCS#1 or PKCS#8 keys
+    pub fn rsa_pem(
+        kid: impl Into<String>,
+        private: &[u8],
+        public: &[u8],
+    ) -> Result<Self, KeyError> {
+        let kid = kid.into();
+        let (n, e) = rsa_components(public)?;
+
+        Ok(Self(Arc::new(SigningKeyInner {
+            jwk: Some(jwk(
+                &kid,
+                Algorithm::RS256,
+                AlgorithmParameters::RSA(RSAKeyParameters {
+                    key_type: RSAKeyType::RSA,
+                    n: URL_SAFE_NO_PAD.encode(n),
+                    e: URL_SAFE_NO_PAD.encode(e),
+                }),
+            )),
+            kid: Some(kid),
+            algorithm: Algorithm::RS256,
+            encoding: EncodingKey::from_rsa_pem(private)?,
+            decoding: DecodingKey::from_rsa_pem(public)?,
+            private: private.to_vec(),
+            public: Some(public.to_vec()),
+        })))
+    }
+
+    /// An EdDSA key from PEM encoded PKCS#8 Ed25519 keys
+    pub fn ed25519_pem(
+        kid: impl Into<String>,
+        private: &[u8],
+        public: &[u8],
+    ) -> Result<Self, KeyError> {
+        let kid = kid.into();
+        let x = spki_bitstring(public)?;
+
+        Ok(Self(Arc::new(SigningKeyInner {
+            jwk: Some(jwk(
+                &kid,
+                Algorithm::EdDSA,
+                AlgorithmParameters::OctetKeyPair(OctetKeyPairParameters {
+                    key_type: OctetKeyPairType::OctetKeyPair,
+                    curve: EllipticCurve::Ed25519,
+                    x: URL_SAFE_NO_PAD.encode(x),
+                }),
+            )),
+            kid: Some(kid),
+            algorithm: Algorithm::EdDSA,
+            encoding: EncodingKey::from_ed_pem(private)?,
+            decoding: DecodingKey::from_ed_pem(public)?,
+            private: private.to_vec(),
+            public: Some(public.to_vec()),
+        })))
+    }
+
+    /// Read a RS256 or EdDSA key pair from PEM files
+    pub fn from_pem_files(
+        algorithm: Algorithm,
+        kid: impl Into<String>,
+        private: impl AsRef<Path>,
+        public: impl AsRef<Path>,
+    ) -> Result<Self, KeyError> {
+        let private = fs::read(private)?;
+        let public = fs::read(public)?;
+
+        match algorithm {
+            Algorithm::RS256 => Self::rsa_pem(kid, &private, &public),
+            Algorithm::EdDSA => Self::ed25519_pem(kid, &private, &public),
+            _ => Err(KeyError::UnsupportedFormat),
+        }
+    }
+
+    /// Generate a new HS256 or EdDSA key, RS256 keys should be generated with openssl instead
+    pub fn generate(algorithm: Algorithm, kid: impl Into<String>) -> Result<Self, KeyError> {
+        let rng = SystemRandom::new();
+
+        match algorithm {
+            Algorithm::HS256 => {
+                let mut secret = [0u8; 64];
+                rng.fill(&mut secret).map_err(|_| KeyError::Generation)?;
+                Ok(Self::hmac_with_kid(
+                    Some(kid.into()),
+                    &URL_SAFE_NO_PAD.encode(secret),
+                ))
+            }
+            Algorithm::EdDSA => {
+                let pkcs8 =
+                    Ed25519KeyPair::generate_pkcs8(&rng).map_err(|_| KeyError::Generation)?;
+                let pair =
+                    Ed25519KeyPair::from_pkcs8(pkcs8.as_ref()).map_err(|_| KeyError::Generation)?;
+
+                let private = pem::encode(&Pem {
+                    tag: String::from("PRIVATE KEY"),
+                    contents: pkcs8.as_ref().to_vec(),
+                });
+                let public = pem::encode(&Pem {
+                    tag: String::from("PUBLIC KEY"),
+                    contents: [ED25519_SPKI_PREFIX, pair.public_key().as_ref()].concat(),
+                });
+
+                Self::ed25519_pem(kid, private.as_bytes(), public.as_bytes())
+            }
+            _ => Err(KeyError::UnsupportedFormat),
+        }
+    }
+
+    /// Rebuild a key stored in the key ring, HS256 keys are stored with an empty `kid` if they
+    /// don't have one
+    pub(crate) fn from_stored(
+        kid: String,
+        algorithm: Algorithm,
+        private: &[u8],
+        public: Option<&[u8]>,
+    ) -> Result<Self, KeyError> {
+        match (algorithm, public) {
+            (Algorithm::HS256, _) => Ok(Self::hmac_with_kid(
+                Some(kid).filter(|kid| !kid.is_empty()),
+                &String::from_utf8_lossy(private),
+            )),
+            (Algorithm::RS256, Some(public)) => Self::rsa_pem(kid, private, public),
+            (Algorithm::EdDSA, Some(public)) => Self::ed25519_pem(kid, private, public),
+            _ => Err(KeyError::UnsupportedFormat),
+        }
+    }
+
+    pub fn kid(&self) -> Option<&str> {
+        self.0.kid.as_deref()
+    }
+
+    pub fn algorithm(&self) -> Algorithm {
+        self.0.algorithm
+    }
+
+    /// The public key, `None` for HMAC keys
+    pub fn jwk(&self) -> Option<&Jwk> {
+        self.0.jwk.as_ref()
+    }
+
+    pub(crate) fn header(&self) -> Header {
+        let mut header = Header::new(self.0.algorithm);
+        header.kid = self.0.kid.clone();
+        header
+    }
+
+    pub(crate) fn encoding(&self) -> &EncodingKey {
+        &self.0.encoding
+    }
+
+    pub(crate) fn decoding(&self) -> &DecodingKey {
+        &self.0.decoding
+    }
+
+    /// The key as it's stored in the key ring, with the secret or private key encrypted
+    pub(crate) fn sealed(&self, sealer: &KeySealer) -> Result<SealedKey, KeyError> {
+        let kid = self.kid().unwrap_or_default().to_owned();
+        Ok(SealedKey {
+            algorithm: algorithm_name(self.0.algorithm)?,
+            private: sealer.seal(&kid, &self.0.private)?,
+            public: self
+                .0
+                .public
+                .as_deref()
+                .map(|public| String::from_utf8_lossy(public).into_owned()),
+            kid,
+        })
+    }
+}
+
+/// Columns of a stored key, HS256 keys without a `kid` are stored with an empty one
+pub(crate) struct SealedKey {
+    pub(crate) kid: String,
+    pub(crate) algorithm: &'static str,
+    pub(crate) private: String,
+    pub(crate) public: Option<String>,
+}
+
+/// Encrypts the secrets of the stored keys, so a copy of the database isn't enough to sign the
+/// tokens. Its key is derived from the sealing secret, which should be kept as long as the
+/// stored keys are used
+#[derive(Clone)]
+pub(crate) struct KeySealer([u8; 32]);
+
+impl KeySealer {
+    pub(crate) fn new(secret: &str) -> Self {
+        let mut bytes = [0u8; 32];
+        Salt::new(HKDF_SHA256, SEALING_SALT)
+            .extract(secret.as_bytes())
+            .expand(&[], &AES_256_GCM)
+            .and_then(|okm| okm.fill(&mut bytes))
+            .expect("The output fits the AES key");
+        Self(bytes)
+    }
+
+    fn key(&self) -> LessSafeKey {
+        LessSafeKey::new(UnboundKey::new(&AES_256_GCM, &self.0).expect("The key length is valid"))
+    }
+
+    /// Base64 of the nonce followed by the ciphertext, the kid is authenticated along with it
+    pub(crate) fn seal(&self, kid: &str, plain: &[u8]) -> Result<String, KeyError> {
+        let mut nonce = [0u8; NONCE_LEN];
+        SystemRandom::new()
+            .fill(&mut nonce)
+            .map_err(|_| KeyError::Generation)?;
+
+        let mut sealed = plain.to_vec();
+        self.key()
+            .seal_in_place_append_tag(
+                Nonce::assume_unique_for_key(nonce),
+                Aad::from(kid.as_bytes()),
+                &mut sealed,
+            )
+            .map_err(|_| KeyError::Sealing)?;
+
+        Ok(URL_SAFE_NO_PAD.encode([nonce.as_slice(), &sealed].concat()))
+    }
+
+    pub(crate) fn open(&self, kid: &str, sealed: &str) -> Result<Vec<u8>, KeyError> {
+        let mut sealed = URL_SAFE_NO_PAD
+            .decode(sealed)
+            .map_err(|_| KeyError::Sealing)?;
+        if sealed.len() < NONCE_LEN {
+            return Err(KeyError::Sealing);
+        }
+        let mut ciphertext = sealed.split_off(NONCE_LEN);
+        let nonce = Nonce::try_assume_unique_for_key(&sealed).map_err(|_| KeyError::Sealing)?;
+
+        let plain = self
+            .key()
+            .open_in_place(nonce, Aad::from(kid.as_bytes()), &mut ciphertext)
+            .map_err(|_| KeyError::Sealing)?;
+        Ok(plain.to_vec())
+    }
+}
+
+/// The current signing key, along with the previous keys which are still accepted until they
+/// are retired, so rotating the key doesn't invalidate the outstanding tokens.
+///
+/// Clones share the same keys, so reloading the ring updates every `AuthConfig`.
+#[derive(Clone)]
+pub struct KeyRing(Arc<RwLock<KeyRingInner>>);
+
+struct KeyRingInner {
+    current: SigningKey,
+    keys: Vec<(SigningKey, Option<DateTime<Utc>>)>,
+}
+
+impl KeyRing {
+    pub fn new(current: SigningKey) -> Self {
+        Self(Arc::new(RwLock::new(KeyRingInner {
+            keys: vec![(current.clone(), None)],
+            current,
+        })))
+    }
+
+    /// The key new tokens are signed with
+    pub fn current(&self) -> SigningKey {
+        self.0.read().expect("Poisoned key ring").current.clone()
+    }
+
+    /// The key a token was signed with, by its `kid` header, retired keys are not returned
+    pub fn find(&self, kid: Option<&str>) -> Option<SigningKey> {
+        let now = Utc::now();
+
+        self.0
+            .read()
+            .expect("Poisoned key ring")
+            .keys
+            .iter()
+            .filter(|(_, retire_at)| retire_at.map_or(true, |retire_at| retire_at > now))
+            .find(|(key, _)| key.kid() == kid)
+            .map(|(key, _)| key.clone())
+    }
+
+    /// Public keys of all the keys which are not retired yet
+    pub fn jwks(&self) -> JwkSet {
+        let now = Utc::now();
+
+        JwkSet {
+            keys: self
+                .0
+                .read()
+                .expect("Poisoned key ring")
+                .keys
+                .iter()
+                .filter(|(_, retire_at)| retire_at.map_or(true, |retire_at| retire_at > now))
+                .filter_map(|(key, _)| key.jwk().cloned())
+                .collect(),
+        }
+    }
+
+    pub(crate) fn replace(
+        &self,
+        current: SigningKey,
+        keys: Vec<(SigningKey, Option<DateTime<Utc>>)>,
+    ) {
+        let mut inner = self.0.write().expect("Poisoned key ring");
+        inner.current = current;
+        inner.keys = keys;
+    }
+}
+
+fn algorithm_name(algorithm: Algorithm) -> Result<&'static str, KeyError> {
+    match algorithm {
+        Algorithm::HS256 => Ok("HS256"),
+        Algorithm::RS256 => Ok("RS256"),
+        Algorithm::EdDSA => Ok("EdDSA"),
+        _ => Err(KeyError::UnsupportedFormat),
+    }
+}
+
+fn jwk(kid: &str, algorithm: Algorithm, params: AlgorithmParameters) -> Jwk {
+    Jwk {
+        common: CommonParameters {
+            public_key_use: Some(PublicKeyUse::Signature),
+            algorithm: Some(algorithm),
+            key_id: Some(kid.to_owned()),
+            ..Default::default()
+        },
+        algorithm: params,
+    }
+}
+
+fn parse_pem(key: &[u8]) -> Result<(String, Vec<ASN1Block>), KeyError> {
+    let pem = pem::parse(key).map_err(|_| KeyError::UnsupportedFormat)?;
+    let asn1 = simple_asn1::from_der(&pem.contents).map_err(|_| KeyError::UnsupportedFormat)?;
+    Ok((pem.tag, asn1))
+}
+
+/// The raw key inside a `PUBLIC KEY`(SubjectPublicKeyInfo) PEM
+fn spki_bitstring(key: &[u8]) -> Result<Vec<u8>, KeyError> {
+    match parse_pem(key)? {
+        (tag, asn1) if tag == "PUBLIC KEY" => match asn1.as_slice() {
+            [ASN1Block::Sequence(_, info)] => match info.as_slice() {
+                [_, ASN1Block::BitString(_, _, key)] => Ok(key.clone()),
+                _ => Err(KeyError::UnsupportedFormat),
+            },
+            _ => Err(KeyError::UnsupportedFormat),
+        },
+        _ => Err(KeyError::UnsupportedFormat),
+    }
+}
+
+/// Modulus and exponent of a RSA public key, big endian
+fn rsa_components(key: &[u8]) -> Result<(Vec<u8>, Vec<u8>), KeyError> {
+    let (tag, asn1) = parse_pem(key)?;
+
+    // PKCS#8 wraps the PKCS#1 key in a bitstring
+    let asn1 = match tag.as_str() {
+        "RSA PUBLIC KEY" => asn1,
+        "PUBLIC KEY" => {
+            simple_asn1::from_der(&spki_bitstring(key)?).map_err(|_| KeyError::UnsupportedFormat)?
+        }
+        _ => return Err(KeyError::UnsupportedFormat),
+    };
+
+    match asn1.as_slice() {
+        [ASN1Block::Sequence(_, parts)] => match parts.as_slice() {
+            [ASN1Block::Integer(_, n), ASN1Block::Integer(_, e)] => {
+                Ok((n.to_bytes_be().1, e.to_bytes_be().1))
+            }
+            _ => Err(KeyError::UnsupportedFormat),
+        },
+        _ => Err(KeyError::UnsupportedFormat),
+    }
+}
@@ -1,17 +1,42 @@
 mod app;
+mod commands;
+mod cookies;
 mod errors;
+mod external;
 mod extract;
 mod handlers;
+mod keys;
+mod mfa;
 mod middleware;
+mod models;
+mod oauth;
+mod oidc;
 mod openapi;
+mod passkeys;
 mod providers;
 mod schemas;
+mod scopes;
+pub mod secrets;
+mod tasks;
+mod throttle;
+mod webauthn;
+#[cfg(feature = "testing")]
+pub mod testing;
 
 pub use app::{AuthApp, AuthConfig};
+pub use axum_extra::extract::cookie::SameSite;
+pub use cookies::CSRF_HEADER;
 pub use errors::AuthError;
-pub use extract::{Claims, TokenBlacklist};
-pub use middleware::ClaimCheck;
-pub use providers::{GrantProvider, SessionProvider, UserProvider};
+pub use extract::{Claims, SubjectType, TokenBlacklist};
+pub use keys::{KeyError, KeyRing, SigningKey};
+pub use middleware::{api_key_rate_limit_key, user_rate_limit_key, ClaimCheck, API_KEY_HEADER};
+pub use oidc::OidcProvider;
+pub use providers::{
+    ClientProvider, ExternalIdentity, GrantProvider, NoClients, NoPasskeys, Passkey,
+    PasskeyProvider, RefreshOutcome, SessionProvider, UserProvider,
+};
+pub use scopes::{RequireScopes, ScopedRoutes};
+pub use throttle::LoginThrottle;
 
 #[allow(non_snake_case)]
 pub mod AuthErrorOai {
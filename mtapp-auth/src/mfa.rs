@@ -0,0 +1,94 @@
+use std::time::{SystemTime, UNIX_EPOCH};
+
+use basteh::Basteh;
+use jsonwebtoken::{decode, decode_header, encode, Validation};
+use serde::{Deserialize, Serialize};
+use uuid::Uuid;
+
+use crate::{app::AuthConfig, errors::AuthError, providers::UserProvider, throttle::LoginGuard};
+
+const CHALLENGE_TYPE: &str = "mfa";
+
+/// Claims of the challenge token `login` returns to the users with MFA enabled. It has no scopes,
+/// so it can't be used as an access token
+#[derive(Serialize, Deserialize)]
+struct ChallengeClaims {
+    jti: Uuid,
+    iat: u64,
+    exp: u64,
+    sub: Uuid,
+    typ: String,
+    // Throttled as this login, the username of password logins and the user's id otherwise
+    login: String,
+}
+
+pub(crate) fn issue_challenge(
+    config: &AuthConfig,
+    user_id: Uuid,
+    username: Option<&str>,
+) -> String {
+    let now = SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .expect("UNIX_EPOCH is past")
+        .as_secs();
+    let claims = ChallengeClaims {
+        jti: Uuid::new_v4(),
+        iat: now,
+        exp: now + config.get_mfa_challenge_expiry().as_secs(),
+        sub: user_id,
+        typ: String::from(CHALLENGE_TYPE),
+        login: username.map_or_else(|| user_id.to_string(), String::from),
+    };
+
+    let key = config.keys().current();
+    encode(&key.header(), &claims, key.encoding()).expect("Parameters are valid.")
+}
+
+/// Exchange the challenge token and the second factor for the user's id, the challenge can only
+/// be used once, even with a wrong code. The failed codes count against the login like the
+/// failed passwords
+pub(crate) async fn complete_challenge<U: UserProvider>(
+    config: &AuthConfig,
+    storage: &Basteh,
+    guard: &LoginGuard,
+    user_data: &U::Data,
+    token: &str,
+    code: &str,
+) -> Result<Uuid, AuthError> {
+    let header = decode_header(token).map_err(|_| AuthError::BadToken)?;
+    let key = config
+        .keys()
+        .find(header.kid.as_deref())
+        .ok_or(AuthError::BadToken)?;
+    let claims =
+        decode::<ChallengeClaims>(token, key.decoding(), &Validation::new(key.algorithm()))
+            .map_err(|_| AuthError::BadToken)?
+            .claims;
+    if claims.typ != CHALLENGE_TYPE {
+        return Err(AuthError::BadToken);
+    }
+
+    // Consumed before the code is checked, so concurrent requests can't reuse it
+    let blacklist = storage.scope(config.blacklist_scope());
+    let uses = blacklist.mutate(claims.jti, |value| value.incr(1)).await?;
+    if uses == 1 {
+        blacklist
+            .expire(claims.jti, config.get_mfa_challenge_expiry())
+            .await?;
+    } else {
+        return Err(AuthError::BadToken);
+    }
+
+    guard.check(&claims.login).await?;
+    if let Err(err) = U::verify_mfa(user_data, claims.sub, code).await {
+        if matches!(err, AuthError::Credentials) {
+            guard.failed(&claims.login).await?;
+        }
+        metrics::increment_counter!("auth_mfa_verifications_total", "result" => "failure");
+        return Err(err);
+    }
+    guard.succeeded(&claims.login).await?;
+    metrics::increment_counter!("auth_mfa_verifications_total", "result" => "success");
+
+    Ok(claims.sub)
+}
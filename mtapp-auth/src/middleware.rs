@@ -5,65 +5,177 @@ use std::sync::Arc;
 use std::task::{Context, Poll};
 
 use axum::body::{self, BoxBody, Bytes, HttpBody};
+use axum::extract::{FromRequestParts, State};
 use axum::headers::authorization::Bearer;
 use axum::headers::Authorization;
-use axum::http::{Request, Response};
+use axum::http::request::Parts;
+use axum::http::{HeaderName, Request, Response};
 use axum::middleware::Next;
 use axum::response::IntoResponse;
-use axum::{BoxError, Extension, TypedHeader};
-use basteh::Basteh;
+use axum::{BoxError, TypedHeader};
+use mtapp::ReactorState;
 use tower::{Layer, Service};
 
 use crate::app::AuthConfig;
 use crate::errors::AuthError;
 use crate::extract::Claims;
+use crate::providers::{GrantProvider, UserProvider};
 
-pub async fn jwt_claims<B>(
-    config: Extension<AuthConfig>,
-    storage: Extension<Basteh>,
+/// Requests can be authenticated by a user's api key in this header instead of a bearer token
+pub static API_KEY_HEADER: HeaderName = HeaderName::from_static("x-api-key");
+
+pub async fn jwt_claims<U, G, B>(
+    State(state): State<ReactorState>,
     token: Option<TypedHeader<Authorization<Bearer>>>,
-    mut request: Request<B>,
+    request: Request<B>,
     next: Next<B>,
-) -> axum::response::Response {
-    if let Some(token) = token {
-        debug_assert!(
-            request.extensions().get::<Claims>().is_none(),
-            "jwt_claims middleware is called twice"
-        );
-
-        // try to extract the claims from header token
-        let claims = match Claims::from_token(token.token(), config.expose_secret()) {
-            Ok(val) => val,
-            Err(_) => {
-                return (AuthError::BadToken).into_response();
-            }
-        };
-
-        let blacklisted = match storage
-            .scope(config.blacklist_scope())
-            .contains_key(claims.jti)
-            .await
-        {
-            Ok(a) => a,
-            // Internal error
-            Err(e) => return (AuthError::BastehError(e)).into_response(),
-        };
-
-        if blacklisted {
-            return (AuthError::BadToken).into_response();
-        }
+) -> axum::response::Response
+where
+    U: UserProvider,
+    G: GrantProvider,
+    B: Send,
+{
+    let api_key = match request.headers().get(&API_KEY_HEADER) {
+        Some(key) => match key.to_str() {
+            Ok(key) => Some(key.to_owned()),
+            Err(_) => return AuthError::BadToken.into_response(),
+        },
+        None => None,
+    };
+    if token.is_none() && api_key.is_none() {
+        return next.run(request).await;
+    }
+
+    let config = match state.get::<AuthConfig>() {
+        Some(config) => config,
+        None => return AuthError::Configuration.into_response(),
+    };
+
+    debug_assert!(
+        request.extensions().get::<Claims>().is_none(),
+        "jwt_claims middleware is called twice"
+    );
+
+    let (mut parts, body) = request.into_parts();
 
-        request.extensions_mut().insert(claims.clone());
+    let claims = match (token, api_key) {
+        (Some(token), _) => token_claims(&state, config, token.token()).await,
+        (None, Some(key)) => api_key_claims::<U, G>(&state, &mut parts, config, &key).await,
+        (None, None) => unreachable!("Checked above"),
+    };
+    let claims = match claims {
+        Ok(claims) => claims,
+        Err(res) => return res,
+    };
+
+    tracing::Span::current().record("user_id", tracing::field::display(claims.user_id));
+    parts.extensions.insert(claims);
+
+    next.run(Request::from_parts(parts, body)).await
+}
+
+async fn token_claims(
+    state: &ReactorState,
+    config: &AuthConfig,
+    token: &str,
+) -> Result<Claims, axum::response::Response> {
+    // try to extract the claims from header token
+    let claims = match Claims::from_token(token, config.keys()) {
+        Ok(val) => val,
+        Err(_) => {
+            return Err((AuthError::BadToken).into_response());
+        }
+    };
+
+    let blacklisted = match state
+        .storage()
+        .scope(config.blacklist_scope())
+        .contains_key(claims.jti)
+        .await
+    {
+        Ok(a) => a,
+        // Internal error
+        Err(e) => return Err((AuthError::BastehError(e)).into_response()),
+    };
+
+    if blacklisted {
+        metrics::increment_counter!("auth_blacklist_hits_total");
+        return Err((AuthError::BadToken).into_response());
     }
 
-    next.run(request).await
+    Ok(claims)
+}
+
+/// The providers' data is only extracted when an api key is sent
+async fn api_key_claims<U, G>(
+    state: &ReactorState,
+    parts: &mut Parts,
+    config: &AuthConfig,
+    key: &str,
+) -> Result<Claims, axum::response::Response>
+where
+    U: UserProvider,
+    G: GrantProvider,
+{
+    let user_data = U::Data::from_request_parts(parts, state)
+        .await
+        .map_err(IntoResponse::into_response)?;
+    let grants_data = G::Data::from_request_parts(parts, state)
+        .await
+        .map_err(IntoResponse::into_response)?;
+
+    let (user_id, key_id, scopes) = U::api_key(&user_data, key)
+        .await
+        .map_err(IntoResponse::into_response)?;
+
+    // Revoking a grant takes effect on the keys too
+    let granted = G::scopes(&grants_data, user_id)
+        .await
+        .map_err(IntoResponse::into_response)?;
+    let scopes = scopes
+        .into_iter()
+        .filter(|scope| granted.contains(scope))
+        .collect();
+
+    Ok(Claims::for_api_key(
+        user_id,
+        key_id,
+        scopes,
+        config.get_token_expiry(),
+    ))
+}
+
+/// Key for `mtapp::RateLimit` counting the requests of each user or client, anonymous requests
+/// aren't limited by it
+pub fn user_rate_limit_key(parts: &Parts) -> Option<String> {
+    let claims = parts.extensions.get::<Claims>()?;
+    let subject = if claims.is_client() { "client" } else { "user" };
+    Some(format!("{}:{}", subject, claims.user_id))
+}
+
+/// Key for `mtapp::RateLimit` counting the requests of each api key, other requests aren't
+/// limited by it
+pub fn api_key_rate_limit_key(parts: &Parts) -> Option<String> {
+    let claims = parts.extensions.get::<Claims>()?;
+    // The jti is the key's id
+    claims
+        .is_api_key()
+        .then(|| format!("api-key:{}", claims.jti))
 }
 
-#[derive(Clone)]
 pub struct ClaimCheck<F> {
     check_fn: Arc<F>,
 }
 
+// Not derived, the closure is shared so it doesn't need to be `Clone`
+impl<F> Clone for ClaimCheck<F> {
+    fn clone(&self) -> Self {
+        Self {
+            check_fn: self.check_fn.clone(),
+        }
+    }
+}
+
 impl<F> ClaimCheck<F> {
     pub fn new(check_fn: F) -> Self {
         Self {
@@ -83,12 +195,20 @@ impl<F, T> Layer<T> for ClaimCheck<F> {
     }
 }
 
-#[derive(Clone)]
 pub struct ClaimCheckService<F, S> {
     check_fn: Arc<F>,
     inner: S,
 }
 
+impl<F, S: Clone> Clone for ClaimCheckService<F, S> {
+    fn clone(&self) -> Self {
+        Self {
+            check_fn: self.check_fn.clone(),
+            inner: self.inner.clone(),
+        }
+    }
+}
+
 impl<ReqBody, ResBody, F, S> Service<Request<ReqBody>> for ClaimCheckService<F, S>
 where
     F: Fn(Option<Claims>) -> bool,
@@ -116,8 +236,16 @@ where
         Box::pin(async move {
             let r = !check_fn(claims.clone());
             if r && claims.is_none() {
+                metrics::increment_counter!(
+                    "auth_claim_check_denials_total",
+                    "reason" => "authentication",
+                );
                 Ok(AuthError::Authentication.into_response())
             } else if r {
+                metrics::increment_counter!(
+                    "auth_claim_check_denials_total",
+                    "reason" => "permission",
+                );
                 Ok(AuthError::Permission.into_response())
             } else {
                 Ok(fut.await?.map(body::boxed))
@@ -0,0 +1,122 @@
+use jsonwebtoken::Algorithm;
+use mtapp::db::{Db, DbQueryBuilder, DbRow};
+use sea_query::{enum_def, Cond, Expr, Iden, Order, Query};
+use sea_query_binder::SqlxBinder;
+use sqlx::types::chrono::{DateTime, Utc};
+use sqlx::{Error, Executor, FromRow, Row};
+
+use crate::keys::{KeyError, KeySealer, SealedKey, SigningKey};
+
+/// A signing key in the key ring, see `KeyRing`
+#[derive(FromRow)]
+#[enum_def]
+pub(crate) struct StoredKey {
+    kid: String,
+    algorithm: String,
+    // Encrypted by the ring's `KeySealer`
+    private_key: String,
+    public_key: Option<String>,
+    pub(crate) created_at: DateTime<Utc>,
+    pub(crate) retire_at: Option<DateTime<Utc>>,
+}
+
+#[derive(Iden)]
+struct SigningKeys;
+
+impl StoredKey {
+    /// Keys which are not retired at `now`, the oldest first
+    #[tracing::instrument(name = "StoredKey::find_active", skip_all)]
+    pub(crate) async fn find_active<'a, E>(now: DateTime<Utc>, con: E) -> Result<Vec<Self>, Error>
+    where
+        E: Executor<'a, Database = Db>,
+    {
+        let (sql, args) = Query::select()
+            .expr(Expr::asterisk())
+            .from(SigningKeys)
+            .cond_where(
+                Cond::any()
+                    .add(Expr::col(StoredKeyIden::RetireAt).is_null())
+                    .add(Expr::col(StoredKeyIden::RetireAt).gt(now)),
+            )
+            .order_by(StoredKeyIden::CreatedAt, Order::Asc)
+            .build_sqlx(DbQueryBuilder);
+
+        sqlx::query_as_with(&sql, args).fetch_all(con).await
+    }
+
+    #[tracing::instrument(name = "StoredKey::count", skip_all)]
+    pub(crate) async fn count<'a, E>(con: E) -> Result<i64, Error>
+    where
+        E: Executor<'a, Database = Db>,
+    {
+        let (sql, args) = Query::select()
+            .expr(Expr::asterisk().count())
+            .from(SigningKeys)
+            .build_sqlx(DbQueryBuilder);
+
+        sqlx::query_with(&sql, args)
+            .try_map(|row: DbRow| row.try_get_unchecked::<i64, _>(0usize))
+            .fetch_one(con)
+            .await
+    }
+
+    #[tracing::instrument(name = "StoredKey::create", skip_all)]
+    pub(crate) async fn create<'a, E>(key: &SealedKey, con: E) -> Result<Self, Error>
+    where
+        E: Executor<'a, Database = Db>,
+    {
+        let (sql, args) = Query::insert()
+            .into_table(SigningKeys)
+            .columns([
+                StoredKeyIden::Kid,
+                StoredKeyIden::Algorithm,
+                StoredKeyIden::PrivateKey,
+                StoredKeyIden::PublicKey,
+                StoredKeyIden::CreatedAt,
+            ])
+            .values_panic([
+                key.kid.as_str().into(),
+                key.algorithm.into(),
+                key.private.as_str().into(),
+                key.public.as_deref().into(),
+                Utc::now().into(),
+            ])
+            .returning(Query::returning().expr(Expr::asterisk()))
+            .build_sqlx(DbQueryBuilder);
+
+        sqlx::query_as_with(&sql, args).fetch_one(con).await
+    }
+
+    /// Schedule the retirement of every key which is not already scheduled
+    #[tracing::instrument(name = "StoredKey::retire_all", skip_all)]
+    pub(crate) async fn retire_all<'a, E>(at: DateTime<Utc>, con: E) -> Result<u64, Error>
+    where
+        E: Executor<'a, Database = Db>,
+    {
+        let (sql, args) = Query::update()
+            .table(SigningKeys)
+            .value(StoredKeyIden::RetireAt, at)
+            .and_where(Expr::col(StoredKeyIden::RetireAt).is_null())
+            .build_sqlx(DbQueryBuilder);
+
+        sqlx::query_with(&sql, args)
+            .execute(con)
+            .await
+            .map(|res| res.rows_affected())
+    }
+
+    pub(crate) fn to_signing_key(&self, sealer: &KeySealer) -> Result<SigningKey, KeyError> {
+        let algorithm = self
+            .algorithm
+            .parse::<Algorithm>()
+            .map_err(|_| KeyError::UnsupportedFormat)?;
+        let private = sealer.open(&self.kid, &self.private_key)?;
+
+        SigningKey::from_stored(
+            self.kid.clone(),
+            algorithm,
+            &private,
+            self.public_key.as_deref().map(str::as_bytes),
+        )
+    }
+}
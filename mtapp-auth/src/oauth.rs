@@ -0,0 +1,436 @@
+use axum::{
+    extract::{rejection::FormRejection, Form, State},
+    headers::{authorization::Basic, Authorization},
+    http::{header, HeaderValue, StatusCode},
+    response::{IntoResponse, Response},
+    TypedHeader,
+};
+use basteh::{Basteh, BastehError};
+use mtapp::extractors::Json;
+
+use crate::{
+    app::AuthConfig,
+    errors::{AuthError, AuthErrorOai},
+    extract::{Claims, SubjectType},
+    handlers::rotate_session,
+    mfa::{complete_challenge, issue_challenge},
+    providers::{ClientProvider, GrantProvider, SessionProvider, UserProvider},
+    schemas::{Introspection, OAuthErrorBody, OAuthToken, TokenHint, TokenRequest},
+    throttle::LoginGuard,
+};
+
+/// Errors of the OAuth2 endpoints, sent in the format of RFC 6749 instead of the usual json errors
+#[derive(Debug)]
+pub enum OAuthError {
+    InvalidRequest(&'static str),
+    InvalidClient,
+    InvalidGrant,
+    /// The password was right, but the user should complete the mfa_otp grant with this token
+    MfaRequired(String),
+    InvalidScope,
+    UnsupportedGrantType,
+    UnsupportedTokenType,
+    Internal(AuthError),
+}
+
+impl From<AuthError> for OAuthError {
+    fn from(err: AuthError) -> Self {
+        match err {
+            AuthError::Authentication
+            | AuthError::Credentials
+            | AuthError::BadToken
+            | AuthError::Permission => OAuthError::InvalidGrant,
+            // Including the throttled logins, which keep their own status
+            err => OAuthError::Internal(err),
+        }
+    }
+}
+
+impl From<BastehError> for OAuthError {
+    fn from(err: BastehError) -> Self {
+        OAuthError::Internal(err.into())
+    }
+}
+
+impl IntoResponse for OAuthError {
+    fn into_response(self) -> Response {
+        let (status, error, error_description) = match self {
+            OAuthError::InvalidRequest(description) => (
+                StatusCode::BAD_REQUEST,
+                "invalid_request",
+                Some(description),
+            ),
+            OAuthError::InvalidClient => {
+                let body = OAuthErrorBody {
+                    error: "invalid_client",
+                    error_description: None,
+                    mfa_token: None,
+                };
+                return (
+                    StatusCode::UNAUTHORIZED,
+                    [(header::WWW_AUTHENTICATE, "Basic")],
+                    Json(body),
+                )
+                    .into_response();
+            }
+            OAuthError::MfaRequired(mfa_token) => {
+                let body = OAuthErrorBody {
+                    error: "mfa_required",
+                    error_description: Some("Multi-factor authentication is required"),
+                    mfa_token: Some(mfa_token),
+                };
+                return (StatusCode::FORBIDDEN, Json(body)).into_response();
+            }
+            OAuthError::InvalidGrant => (StatusCode::BAD_REQUEST, "invalid_grant", None),
+            OAuthError::InvalidScope => (StatusCode::BAD_REQUEST, "invalid_scope", None),
+            OAuthError::UnsupportedGrantType => {
+                (StatusCode::BAD_REQUEST, "unsupported_grant_type", None)
+            }
+            OAuthError::UnsupportedTokenType => {
+                (StatusCode::BAD_REQUEST, "unsupported_token_type", None)
+            }
+            OAuthError::Internal(err) => return err.into_response(),
+        };
+
+        let body = OAuthErrorBody {
+            error,
+            error_description,
+            mfa_token: None,
+        };
+        (status, Json(body)).into_response()
+    }
+}
+
+#[utoipa::path(
+    post,
+    tag = "Auth",
+    path = "/token",
+    request_body(
+        content=TokenRequest,
+        content_type="application/x-www-form-urlencoded",
+    ),
+    responses(
+        (status = 200, body = OAuthToken, description = "See RFC 6749 section 5.1"),
+        (status = 400, body = OAuthErrorBody, description = "See RFC 6749 section 5.2"),
+        (status = 401, body = OAuthErrorBody, description = "The client failed to authenticate"),
+        (
+            status = 403,
+            body = OAuthErrorBody,
+            description = "The user has MFA enabled, the `mfa_token` should be used with the \
+                mfa_otp grant"
+        ),
+        AuthErrorOai::TooManyAttempts,
+    )
+)]
+#[allow(clippy::too_many_arguments)]
+pub async fn token<U, S, G, C>(
+    config: AuthConfig,
+    State(storage): State<Basteh>,
+    guard: LoginGuard,
+    user_data: U::Data,
+    session_data: S::Data,
+    grants_data: G::Data,
+    client_data: C::Data,
+    basic_auth: Option<TypedHeader<Authorization<Basic>>>,
+    form: Result<Form<TokenRequest>, FormRejection>,
+) -> Result<Response, OAuthError>
+where
+    U: UserProvider,
+    S: SessionProvider,
+    G: GrantProvider,
+    C: ClientProvider,
+{
+    let Form(form) = form.map_err(|_| OAuthError::InvalidRequest("Malformed request body"))?;
+
+    let (claims, refresh_token) = match form.grant_type.as_str() {
+        "password" => {
+            let username = form
+                .username
+                .as_deref()
+                .ok_or(OAuthError::InvalidRequest("username is required"))?;
+            let password = form
+                .password
+                .as_deref()
+                .ok_or(OAuthError::InvalidRequest("password is required"))?;
+
+            guard.check(username).await?;
+
+            let user_id = match U::login(&user_data, username, password).await {
+                Ok(user_id) => user_id,
+                Err(err) => {
+                    if matches!(err, AuthError::Credentials) {
+                        guard.failed(username).await?;
+                    }
+                    metrics::increment_counter!("auth_logins_total", "result" => "failure");
+                    return Err(err.into());
+                }
+            };
+            if U::mfa_required(&user_data, user_id).await? {
+                metrics::increment_counter!("auth_logins_total", "result" => "mfa_required");
+                return Err(OAuthError::MfaRequired(issue_challenge(
+                    &config,
+                    user_id,
+                    Some(username),
+                )));
+            }
+            guard.succeeded(username).await?;
+
+            // Checked before making the session, so an invalid scope doesn't leave one behind
+            let scopes = narrow_scopes(
+                G::scopes(&grants_data, user_id).await?,
+                form.scope.as_deref(),
+            )?;
+            let (jti, refresh_token) = S::make(&session_data, user_id).await?;
+
+            metrics::increment_counter!("auth_logins_total", "result" => "success");
+            let claims = Claims::new(user_id, jti, scopes, config.get_token_expiry());
+            (claims, Some(refresh_token))
+        }
+        "mfa_otp" => {
+            let mfa_token = form
+                .mfa_token
+                .as_deref()
+                .ok_or(OAuthError::InvalidRequest("mfa_token is required"))?;
+            let otp = form
+                .otp
+                .as_deref()
+                .ok_or(OAuthError::InvalidRequest("otp is required"))?;
+
+            let user_id =
+                complete_challenge::<U>(&config, &storage, &guard, &user_data, mfa_token, otp)
+                    .await?;
+
+            let scopes = narrow_scopes(
+                G::scopes(&grants_data, user_id).await?,
+                form.scope.as_deref(),
+            )?;
+            let (jti, refresh_token) = S::make(&session_data, user_id).await?;
+
+            metrics::increment_counter!("auth_logins_total", "result" => "success");
+            let claims = Claims::new(user_id, jti, scopes, config.get_token_expiry());
+            (claims, Some(refresh_token))
+        }
+        "refresh_token" => {
+            let refresh_token = form
+                .refresh_token
+                .as_deref()
+                .ok_or(OAuthError::InvalidRequest("refresh_token is required"))?;
+
+            // Rotated first, so a reused token always revokes its session
+            let (user_id, jti, refresh_token) =
+                rotate_session::<S>(&config, &storage, &session_data, refresh_token).await?;
+            let scopes = narrow_scopes(
+                G::scopes(&grants_data, user_id).await?,
+                form.scope.as_deref(),
+            )?;
+
+            metrics::increment_counter!("auth_refreshes_total");
+            let claims = Claims::new(user_id, jti, scopes, config.get_token_expiry());
+            (claims, Some(refresh_token))
+        }
+        "client_credentials" => {
+            let (client_id, client_secret) = match (&basic_auth, &form.client_id) {
+                (Some(TypedHeader(auth)), _) => (auth.username(), auth.password()),
+                (None, Some(client_id)) => (
+                    client_id.as_str(),
+                    form.client_secret.as_deref().unwrap_or_default(),
+                ),
+                (None, None) => return Err(OAuthError::InvalidClient),
+            };
+
+            let (client_id, allowed) = match C::authenticate(&client_data, client_id, client_secret)
+                .await
+            {
+                Ok(client) => client,
+                Err(AuthError::Credentials) => {
+                    metrics::increment_counter!("auth_client_logins_total", "result" => "failure");
+                    return Err(OAuthError::InvalidClient);
+                }
+                Err(err) => return Err(OAuthError::Internal(err)),
+            };
+            let scopes = narrow_scopes(allowed, form.scope.as_deref())?;
+
+            metrics::increment_counter!("auth_client_logins_total", "result" => "success");
+            // Clients can authenticate again at any time, so there is no session to refresh
+            let claims = Claims::for_client(client_id, scopes, config.get_token_expiry());
+            (claims, None)
+        }
+        _ => return Err(OAuthError::UnsupportedGrantType),
+    };
+
+    let body = OAuthToken {
+        access_token: claims.generate_token(&config.keys().current()),
+        token_type: "bearer",
+        expires_in: config.get_token_expiry().as_secs(),
+        refresh_token,
+        scope: claims.scopes.join(" "),
+    };
+
+    Ok(no_store(Json(body)))
+}
+
+#[utoipa::path(
+    post,
+    tag = "Auth",
+    path = "/revoke",
+    request_body(
+        content=TokenHint,
+        content_type="application/x-www-form-urlencoded",
+    ),
+    responses(
+        (
+            status = 200,
+            description = "The token is revoked, or it was invalid. See RFC 7009 section 2.2"
+        ),
+        (status = 400, body = OAuthErrorBody, description = "See RFC 7009 section 2.2.1"),
+    )
+)]
+pub async fn revoke<S>(
+    config: AuthConfig,
+    State(storage): State<Basteh>,
+    session_data: S::Data,
+    form: Result<Form<TokenHint>, FormRejection>,
+) -> Result<StatusCode, OAuthError>
+where
+    S: SessionProvider,
+{
+    let Form(form) = form.map_err(|_| OAuthError::InvalidRequest("Malformed request body"))?;
+    check_hint(form.token_type_hint.as_deref())?;
+
+    // The hint is only an optimization, both kinds of tokens are looked up
+    let jti = match Claims::from_token(&form.token, config.keys()) {
+        Ok(claims) => Some(claims.jti),
+        Err(_) => match S::find(&session_data, &form.token).await {
+            Ok((jti, _)) => Some(jti),
+            Err(AuthError::BadToken) => None,
+            Err(err) => return Err(OAuthError::Internal(err)),
+        },
+    };
+
+    if let Some(jti) = jti {
+        storage
+            .scope(config.blacklist_scope())
+            .set_expiring(jti, 0, config.get_token_expiry())
+            .await?;
+
+        match S::delete_by_jti(&session_data, jti).await {
+            // The session might be already gone
+            Ok(()) | Err(AuthError::BadToken) => {}
+            Err(err) => return Err(OAuthError::Internal(err)),
+        }
+
+        metrics::increment_counter!("auth_revocations_total");
+    }
+
+    Ok(StatusCode::OK)
+}
+
+#[utoipa::path(
+    post,
+    tag = "Auth",
+    path = "/introspect",
+    request_body(
+        content=TokenHint,
+        content_type="application/x-www-form-urlencoded",
+    ),
+    responses(
+        (status = 200, body = Introspection, description = "See RFC 7662 section 2.2"),
+        (status = 400, body = OAuthErrorBody),
+    ),
+    security(("jwt_token" = ["admin"]))
+)]
+pub async fn introspect<S>(
+    config: AuthConfig,
+    State(storage): State<Basteh>,
+    session_data: S::Data,
+    form: Result<Form<TokenHint>, FormRejection>,
+) -> Result<Response, OAuthError>
+where
+    S: SessionProvider,
+{
+    let Form(form) = form.map_err(|_| OAuthError::InvalidRequest("Malformed request body"))?;
+    check_hint(form.token_type_hint.as_deref())?;
+
+    let inactive = Introspection {
+        active: false,
+        scope: None,
+        sub: None,
+        sub_type: None,
+        jti: None,
+        iat: None,
+        exp: None,
+        token_type: None,
+    };
+
+    if let Ok(claims) = Claims::from_token(&form.token, config.keys()) {
+        let blacklisted = storage
+            .scope(config.blacklist_scope())
+            .contains_key(claims.jti)
+            .await?;
+        if blacklisted {
+            return Ok(no_store(Json(inactive)));
+        }
+
+        return Ok(no_store(Json(Introspection {
+            active: true,
+            scope: Some(claims.scopes.join(" ")),
+            sub: Some(claims.user_id),
+            sub_type: Some(claims.subject_type),
+            jti: Some(claims.jti),
+            iat: Some(claims.iat),
+            exp: Some(claims.exp),
+            token_type: Some("access_token"),
+        })));
+    }
+
+    match S::find(&session_data, &form.token).await {
+        Ok((jti, user_id)) => Ok(no_store(Json(Introspection {
+            active: true,
+            sub: Some(user_id),
+            // Only the users get refresh tokens
+            sub_type: Some(SubjectType::User),
+            jti: Some(jti),
+            token_type: Some("refresh_token"),
+            ..inactive
+        }))),
+        Err(AuthError::BadToken) => Ok(no_store(Json(inactive))),
+        Err(err) => Err(OAuthError::Internal(err)),
+    }
+}
+
+/// Keep only the requested scopes, all of them should be granted to the user or client
+fn narrow_scopes(granted: Vec<String>, requested: Option<&str>) -> Result<Vec<String>, OAuthError> {
+    let requested = match requested {
+        Some(requested) if !requested.trim().is_empty() => requested,
+        _ => return Ok(granted),
+    };
+
+    let requested: Vec<&str> = requested.split_whitespace().collect();
+    if requested
+        .iter()
+        .any(|scope| !granted.iter().any(|granted| granted == scope))
+    {
+        return Err(OAuthError::InvalidScope);
+    }
+
+    Ok(granted
+        .into_iter()
+        .filter(|scope| requested.contains(&scope.as_str()))
+        .collect())
+}
+
+fn check_hint(hint: Option<&str>) -> Result<(), OAuthError> {
+    match hint {
+        None | Some("access_token") | Some("refresh_token") => Ok(()),
+        Some(_) => Err(OAuthError::UnsupportedTokenType),
+    }
+}
+
+/// Responses carrying tokens shouldn't be cached
+fn no_store(res: impl IntoResponse) -> Response {
+    let mut res = res.into_response();
+    let headers = res.headers_mut();
+    headers.insert(header::CACHE_CONTROL, HeaderValue::from_static("no-store"));
+    headers.insert(header::PRAGMA, HeaderValue::from_static("no-cache"));
+    res
+}
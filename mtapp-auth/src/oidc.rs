@@ -0,0 +1,252 @@
+use base64::{engine::general_purpose::URL_SAFE_NO_PAD, Engine};
+use jsonwebtoken::{
+    decode, decode_header,
+    jwk::{Jwk, JwkSet},
+    Algorithm, DecodingKey, Validation,
+};
+use reqwest::{Client, Url};
+use ring::{
+    digest::{digest, SHA256},
+    rand::{SecureRandom, SystemRandom},
+};
+use serde::{Deserialize, Serialize};
+use uuid::Uuid;
+
+use crate::{errors::AuthError, providers::ExternalIdentity};
+
+/// An OpenID Connect provider the users can log in with, ex. `OidcProvider::new("google",
+/// "https://accounts.google.com", client_id, client_secret, redirect_uri)`.
+///
+/// The name is used in the paths of the endpoints, and the redirect uri is the frontend's page
+/// which posts the code and state back to the callback endpoint
+#[derive(Clone)]
+pub struct OidcProvider {
+    name: String,
+    issuer: String,
+    client_id: String,
+    client_secret: String,
+    redirect_uri: String,
+    scopes: Vec<String>,
+}
+
+/// Kept in the storage from the authorization until the callback
+#[derive(Serialize, Deserialize)]
+pub(crate) struct PendingLogin {
+    pub(crate) provider: String,
+    nonce: String,
+    // PKCE code verifier
+    verifier: String,
+    // Set when a logged in user is linking the identity instead
+    pub(crate) link_to: Option<Uuid>,
+}
+
+#[derive(Deserialize)]
+struct Discovery {
+    issuer: String,
+    authorization_endpoint: String,
+    token_endpoint: String,
+    jwks_uri: String,
+}
+
+#[derive(Deserialize)]
+struct TokenResponse {
+    id_token: String,
+}
+
+#[derive(Deserialize)]
+struct IdTokenClaims {
+    sub: String,
+    nonce: Option<String>,
+    email: Option<String>,
+    email_verified: Option<bool>,
+    preferred_username: Option<String>,
+}
+
+impl OidcProvider {
+    pub fn new(
+        name: &str,
+        issuer: &str,
+        client_id: &str,
+        client_secret: &str,
+        redirect_uri: &str,
+    ) -> Self {
+        Self {
+            name: String::from(name),
+            issuer: String::from(issuer),
+            client_id: String::from(client_id),
+            client_secret: String::from(client_secret),
+            redirect_uri: String::from(redirect_uri),
+            scopes: vec![
+                String::from("openid"),
+                String::from("email"),
+                String::from("profile"),
+            ],
+        }
+    }
+
+    /// `openid email profile` by default, `openid` is always requested
+    pub fn scopes(mut self, scopes: &[&str]) -> Self {
+        self.scopes = vec![String::from("openid")];
+        self.scopes.extend(
+            scopes
+                .iter()
+                .filter(|scope| **scope != "openid")
+                .map(|scope| String::from(*scope)),
+        );
+        self
+    }
+
+    pub(crate) fn name(&self) -> &str {
+        &self.name
+    }
+
+    /// Returns (the url the user should be sent to, state, the login to keep until the callback)
+    pub(crate) async fn authorize(
+        &self,
+        http: &Client,
+        link_to: Option<Uuid>,
+    ) -> Result<(String, String, PendingLogin), AuthError> {
+        let discovery = self.discover(http).await?;
+
+        let state = random_token();
+        let pending = PendingLogin {
+            provider: self.name.clone(),
+            nonce: random_token(),
+            verifier: random_token(),
+            link_to,
+        };
+        let challenge = URL_SAFE_NO_PAD.encode(digest(&SHA256, pending.verifier.as_bytes()));
+
+        let url = Url::parse_with_params(
+            &discovery.authorization_endpoint,
+            [
+                ("response_type", "code"),
+                ("client_id", self.client_id.as_str()),
+                ("redirect_uri", self.redirect_uri.as_str()),
+                ("scope", self.scopes.join(" ").as_str()),
+                ("state", state.as_str()),
+                ("nonce", pending.nonce.as_str()),
+                ("code_challenge", challenge.as_str()),
+                ("code_challenge_method", "S256"),
+            ],
+        )
+        .map_err(|e| {
+            log::error!("Bad authorization endpoint of {}: {}", self.name, e);
+            AuthError::Configuration
+        })?;
+
+        Ok((url.into(), state, pending))
+    }
+
+    /// Exchange the code for an ID token, and return the identity in it once it's validated
+    pub(crate) async fn exchange(
+        &self,
+        http: &Client,
+        code: &str,
+        pending: &PendingLogin,
+    ) -> Result<ExternalIdentity, AuthError> {
+        let discovery = self.discover(http).await?;
+
+        let res = http
+            .post(&discovery.token_endpoint)
+            .form(&[
+                ("grant_type", "authorization_code"),
+                ("code", code),
+                ("redirect_uri", self.redirect_uri.as_str()),
+                ("client_id", self.client_id.as_str()),
+                ("client_secret", self.client_secret.as_str()),
+                ("code_verifier", pending.verifier.as_str()),
+            ])
+            .send()
+            .await
+            .map_err(AuthError::other)?;
+        if res.status().is_client_error() {
+            // The code is invalid, expired or already used
+            return Err(AuthError::Credentials);
+        }
+        let token: TokenResponse = res
+            .error_for_status()
+            .map_err(AuthError::other)?
+            .json()
+            .await
+            .map_err(AuthError::other)?;
+
+        let header = decode_header(&token.id_token).map_err(|_| AuthError::BadToken)?;
+        // Only the provider's public keys are trusted, not a shared secret
+        if matches!(
+            header.alg,
+            Algorithm::HS256 | Algorithm::HS384 | Algorithm::HS512
+        ) {
+            return Err(AuthError::BadToken);
+        }
+
+        let jwks: JwkSet = http
+            .get(&discovery.jwks_uri)
+            .send()
+            .await
+            .and_then(|res| res.error_for_status())
+            .map_err(AuthError::other)?
+            .json()
+            .await
+            .map_err(AuthError::other)?;
+        let jwk: &Jwk = match &header.kid {
+            Some(kid) => jwks.find(kid),
+            None => jwks.keys.first(),
+        }
+        .ok_or(AuthError::BadToken)?;
+        let key = DecodingKey::from_jwk(jwk).map_err(|_| AuthError::BadToken)?;
+
+        let mut validation = Validation::new(header.alg);
+        validation.set_issuer(&[&self.issuer]);
+        validation.set_audience(&[&self.client_id]);
+        let claims = decode::<IdTokenClaims>(&token.id_token, &key, &validation)
+            .map_err(|_| AuthError::BadToken)?
+            .claims;
+        if claims.nonce.as_deref() != Some(pending.nonce.as_str()) {
+            return Err(AuthError::BadToken);
+        }
+
+        Ok(ExternalIdentity {
+            issuer: self.issuer.clone(),
+            subject: claims.sub,
+            email: claims.email,
+            email_verified: claims.email_verified.unwrap_or_default(),
+            preferred_username: claims.preferred_username,
+        })
+    }
+
+    async fn discover(&self, http: &Client) -> Result<Discovery, AuthError> {
+        let url = format!(
+            "{}/.well-known/openid-configuration",
+            self.issuer.trim_end_matches('/')
+        );
+        let discovery: Discovery = http
+            .get(url)
+            .send()
+            .await
+            .and_then(|res| res.error_for_status())
+            .map_err(AuthError::other)?
+            .json()
+            .await
+            .map_err(AuthError::other)?;
+
+        if discovery.issuer != self.issuer {
+            log::error!(
+                "{} is configured with the issuer {}, but the provider reports {}",
+                self.name,
+                self.issuer,
+                discovery.issuer
+            );
+            return Err(AuthError::Configuration);
+        }
+        Ok(discovery)
+    }
+}
+
+fn random_token() -> String {
+    let mut bytes = [0u8; 32];
+    SystemRandom::new()
+        .fill(&mut bytes)
+        .expect("Failed to generate a random token");
+    URL_SAFE_NO_PAD.encode(bytes)
+}
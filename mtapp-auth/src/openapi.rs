@@ -5,39 +5,99 @@ use utoipa::{
 
 use crate::{
     errors::AuthErrorOai,
+    external::*,
+    extract::SubjectType,
     handlers::*,
-    schemas::{Message, TokenData},
+    oauth::*,
+    passkeys::*,
+    schemas::{
+        AssertionOptions, AssertionResponse, AttestationResponse, AuthenticatorSelection,
+        CredentialDescriptor, CredentialParameter, Introspection, Message, MfaChallenge, MfaLogin,
+        OAuthErrorBody, OAuthToken, OidcAuthorization, OidcCallback, PasskeyAssertion,
+        PasskeyRegistration, RegistrationOptions, RelyingPartyEntity, TokenData, TokenHint,
+        TokenRequest, UserEntity,
+    },
+    scopes::RequireScopes,
+    throttle::clear_lockout,
 };
 
+pub(crate) const SECURITY_SCHEME: &str = "jwt_token";
+
 #[derive(OpenApi)]
 #[openapi(
-    paths(login, refresh, logout),
+    paths(
+        login,
+        login_mfa,
+        refresh,
+        logout,
+        token,
+        revoke,
+        jwks,
+        passkey_register_options,
+        passkey_register,
+        passkey_login_options,
+        passkey_login,
+        oidc_authorize,
+        oidc_callback
+    ),
     components(schemas(
         TokenData,
         Message,
+        MfaChallenge,
+        MfaLogin,
+        TokenRequest,
+        TokenHint,
+        OAuthToken,
+        OAuthErrorBody,
+        RegistrationOptions,
+        RelyingPartyEntity,
+        UserEntity,
+        CredentialParameter,
+        CredentialDescriptor,
+        AuthenticatorSelection,
+        AssertionOptions,
+        PasskeyRegistration,
+        AttestationResponse,
+        PasskeyAssertion,
+        AssertionResponse,
+        OidcAuthorization,
+        OidcCallback,
         AuthErrorOai::Authentication,
         AuthErrorOai::BadToken,
+        AuthErrorOai::Csrf,
         AuthErrorOai::Permission,
         AuthErrorOai::Credentials,
+        AuthErrorOai::UnknownProvider,
+        AuthErrorOai::TooManyAttempts,
         AuthErrorOai::InternalError
     ))
 )]
 pub(crate) struct AuthOpenApi;
 
-pub(crate) fn get_open_api(path: &str) -> utoipa::openapi::OpenApi {
+#[derive(OpenApi)]
+#[openapi(
+    paths(introspect, clear_lockout),
+    components(schemas(Introspection, SubjectType))
+)]
+pub(crate) struct InternalAuthOpenApi;
+
+pub(crate) fn get_open_api(path: &str, internal: bool) -> utoipa::openapi::OpenApi {
     let mut openapi = AuthOpenApi::openapi();
+    if internal {
+        openapi.merge(RequireScopes::admin().document(InternalAuthOpenApi::openapi()));
+    }
     let mut components = openapi.components.unwrap_or_default();
     components.add_security_scheme(
-        "jwt_token",
+        SECURITY_SCHEME,
         SecurityScheme::OAuth2(OAuth2::new([Flow::Password(Password::with_refresh_url(
-            format!("{}/login?flat=true", path),
+            format!("{}/token", path),
             Scopes::from_iter([
                 ("superadmin", "Super Admin"),
                 ("admin", "Admin"),
                 ("confirmed", "Confirmed"),
                 ("active", "Active"),
             ]),
-            format!("{}/refresh?flat=true", path),
+            format!("{}/token", path),
         ))])),
     );
     openapi.components = Some(components);
@@ -0,0 +1,310 @@
+use std::time::Duration;
+
+use axum::{
+    extract::State,
+    response::{IntoResponse, Response},
+};
+use base64::{engine::general_purpose::URL_SAFE_NO_PAD, Engine};
+use basteh::Basteh;
+use json_resp::JsonResponse;
+
+use mtapp::extractors::{oai, Json, Query};
+
+use crate::{
+    app::AuthConfig,
+    errors::AuthError,
+    errors::AuthErrorOai,
+    extract::Claims,
+    handlers::login_response,
+    providers::{GrantProvider, Passkey, PasskeyProvider, SessionProvider},
+    schemas::{
+        AssertionOptions, AuthenticatorSelection, CredentialDescriptor, CredentialParameter, Flat,
+        Message, PasskeyAssertion, PasskeyRegistration, RegistrationOptions, RelyingPartyEntity,
+        TokenData, UserEntity,
+    },
+    webauthn::{self, EDDSA, ES256, RS256},
+};
+
+const CHALLENGE_SCOPE: &str = "webauthn_challenges";
+const CEREMONY_TIMEOUT: Duration = Duration::from_secs(5 * 60);
+
+// Challenges of the registrations are issued for the user's id instead
+const LOGIN_CEREMONY: &str = "login";
+
+#[utoipa::path(
+    post,
+    tag = "Auth",
+    path = "/webauthn/register/options",
+    responses(
+        (
+            status = 200,
+            body = inline(JsonResponse<RegistrationOptions>),
+            description = "Should be passed to `navigator.credentials.create`, after decoding \
+                the base64url fields"
+        ),
+        AuthErrorOai::Authentication,
+        AuthErrorOai::Permission,
+        AuthErrorOai::InternalError,
+    ),
+    security(("jwt_token" = []))
+)]
+pub async fn passkey_register_options<P>(
+    config: AuthConfig,
+    State(storage): State<Basteh>,
+    claims: Claims,
+    passkey_data: P::Data,
+) -> impl IntoResponse
+where
+    P: PasskeyProvider,
+{
+    // Tokens only, a leaked api key shouldn't be able to add a way in
+    if claims.is_client() || claims.is_api_key() {
+        return Err(AuthError::Permission);
+    }
+    let rp = config.get_relying_party()?;
+
+    let (name, credential_ids) = P::passkey_user(&passkey_data, claims.user_id).await?;
+
+    let challenge = webauthn::generate_challenge();
+    storage
+        .scope(CHALLENGE_SCOPE)
+        .set_expiring(
+            challenge.clone(),
+            claims.user_id.to_string(),
+            CEREMONY_TIMEOUT,
+        )
+        .await?;
+
+    let options = RegistrationOptions {
+        challenge,
+        rp: RelyingPartyEntity {
+            id: rp.id.clone(),
+            name: rp.name.clone(),
+        },
+        user: UserEntity {
+            id: URL_SAFE_NO_PAD.encode(claims.user_id.as_bytes()),
+            display_name: name.clone(),
+            name,
+        },
+        pub_key_cred_params: [ES256, EDDSA, RS256]
+            .into_iter()
+            .map(|alg| CredentialParameter {
+                typ: "public-key",
+                alg,
+            })
+            .collect(),
+        timeout: CEREMONY_TIMEOUT.as_millis() as u64,
+        exclude_credentials: credential_ids
+            .into_iter()
+            .map(|id| CredentialDescriptor {
+                typ: "public-key",
+                id,
+            })
+            .collect(),
+        authenticator_selection: AuthenticatorSelection {
+            resident_key: "required",
+            user_verification: "required",
+        },
+        attestation: "none",
+    };
+
+    Result::<_, AuthError>::Ok(JsonResponse::with_content(options))
+}
+
+#[utoipa::path(
+    post,
+    tag = "Auth",
+    path = "/webauthn/register",
+    request_body(
+        content=PasskeyRegistration,
+        content_type="application/json",
+    ),
+    responses(
+        (status = 200, body=inline(JsonResponse<Message>)),
+        oai::AllExtErrors,
+        AuthErrorOai::Authentication,
+        AuthErrorOai::BadToken,
+        AuthErrorOai::Permission,
+        AuthErrorOai::InternalError,
+    ),
+    security(("jwt_token" = []))
+)]
+pub async fn passkey_register<P>(
+    config: AuthConfig,
+    State(storage): State<Basteh>,
+    claims: Claims,
+    passkey_data: P::Data,
+    Json(body): Json<PasskeyRegistration>,
+) -> impl IntoResponse
+where
+    P: PasskeyProvider,
+{
+    if claims.is_client() || claims.is_api_key() {
+        return Err(AuthError::Permission);
+    }
+    let rp = config.get_relying_party()?;
+
+    let client_data = webauthn::decode(&body.response.client_data_json)?;
+    let challenge = webauthn::check_client_data(rp, &client_data, "webauthn.create")?;
+    take_challenge(&storage, &challenge, &claims.user_id.to_string()).await?;
+
+    let attestation_object = webauthn::decode(&body.response.attestation_object)?;
+    let authenticator_data = webauthn::attested_data(&attestation_object)?;
+    let data = webauthn::parse_authenticator_data(rp, &authenticator_data)?;
+    let (credential_id, public_key) = data.credential.ok_or(AuthError::BadToken)?;
+
+    let passkey = Passkey {
+        credential_id: URL_SAFE_NO_PAD.encode(credential_id),
+        user_id: claims.user_id,
+        public_key,
+        sign_count: data.sign_count,
+    };
+    P::add(
+        &passkey_data,
+        passkey,
+        body.name.as_deref().unwrap_or("Passkey"),
+    )
+    .await?;
+
+    metrics::increment_counter!("auth_passkey_registrations_total");
+
+    Result::<_, AuthError>::Ok(JsonResponse::with_content("Passkey is registered"))
+}
+
+#[utoipa::path(
+    post,
+    tag = "Auth",
+    path = "/webauthn/login/options",
+    responses(
+        (
+            status = 200,
+            body = inline(JsonResponse<AssertionOptions>),
+            description = "Should be passed to `navigator.credentials.get`, after decoding \
+                the base64url fields"
+        ),
+        AuthErrorOai::InternalError,
+    )
+)]
+pub async fn passkey_login_options(
+    config: AuthConfig,
+    State(storage): State<Basteh>,
+) -> impl IntoResponse {
+    let rp = config.get_relying_party()?;
+
+    let challenge = webauthn::generate_challenge();
+    storage
+        .scope(CHALLENGE_SCOPE)
+        .set_expiring(challenge.clone(), LOGIN_CEREMONY, CEREMONY_TIMEOUT)
+        .await?;
+
+    Result::<_, AuthError>::Ok(JsonResponse::with_content(AssertionOptions {
+        challenge,
+        rp_id: rp.id.clone(),
+        timeout: CEREMONY_TIMEOUT.as_millis() as u64,
+        user_verification: "required",
+    }))
+}
+
+#[utoipa::path(
+    post,
+    tag = "Auth",
+    path = "/webauthn/login",
+    request_body(
+        content=PasskeyAssertion,
+        content_type="application/json",
+    ),
+    responses(
+        (
+            status = 200,
+            body = inline(JsonResponse<TokenData>),
+            description = "Login was successful",
+            headers(
+                (
+                    "Set-Cookie" = String,
+                    description="Same cookies as the `/login` endpoint"
+                )
+            )
+        ),
+        oai::AllExtErrors,
+        AuthErrorOai::BadToken,
+        AuthErrorOai::Credentials,
+        AuthErrorOai::InternalError,
+    )
+)]
+pub async fn passkey_login<P, S, G>(
+    config: AuthConfig,
+    State(storage): State<Basteh>,
+    query: Query<Flat>,
+    passkey_data: P::Data,
+    session_data: S::Data,
+    scopes_data: G::Data,
+    Json(body): Json<PasskeyAssertion>,
+) -> Result<Response, AuthError>
+where
+    P: PasskeyProvider,
+    S: SessionProvider,
+    G: GrantProvider,
+{
+    let rp = config.get_relying_party()?;
+
+    let client_data = webauthn::decode(&body.response.client_data_json)?;
+    let challenge = webauthn::check_client_data(rp, &client_data, "webauthn.get")?;
+    take_challenge(&storage, &challenge, LOGIN_CEREMONY).await?;
+
+    let passkey = match P::find(&passkey_data, &body.id).await {
+        Ok(passkey) => passkey,
+        Err(err) => {
+            metrics::increment_counter!("auth_passkey_logins_total", "result" => "failure");
+            return Err(err);
+        }
+    };
+
+    let authenticator_data = webauthn::decode(&body.response.authenticator_data)?;
+    let signature = webauthn::decode(&body.response.signature)?;
+    let data = webauthn::parse_authenticator_data(rp, &authenticator_data)?;
+    if let Err(err) = webauthn::verify(
+        &passkey.public_key,
+        &authenticator_data,
+        &client_data,
+        &signature,
+    ) {
+        metrics::increment_counter!("auth_passkey_logins_total", "result" => "failure");
+        return Err(err);
+    }
+
+    // Authenticators without a counter always send 0, otherwise it goes up on every use
+    if (data.sign_count != 0 || passkey.sign_count != 0) && data.sign_count <= passkey.sign_count {
+        tracing::warn!(
+            credential_id = %passkey.credential_id,
+            "The signature counter of a passkey went back, it might be cloned"
+        );
+        metrics::increment_counter!("auth_passkey_logins_total", "result" => "failure");
+        return Err(AuthError::Credentials);
+    }
+    P::update_sign_count(&passkey_data, &passkey.credential_id, data.sign_count).await?;
+
+    let res = login_response::<S, G>(
+        &config,
+        &query,
+        &session_data,
+        &scopes_data,
+        passkey.user_id,
+    )
+    .await?;
+    metrics::increment_counter!("auth_passkey_logins_total", "result" => "success");
+    Ok(res)
+}
+
+/// Consume the challenge, it should be issued to the same ceremony
+async fn take_challenge(
+    storage: &Basteh,
+    challenge: &str,
+    issued_to: &str,
+) -> Result<(), AuthError> {
+    let value: Option<String> = storage.scope(CHALLENGE_SCOPE).remove(challenge).await?;
+    if value.as_deref() == Some(issued_to) {
+        Ok(())
+    } else {
+        Err(AuthError::BadToken)
+    }
+}
@@ -1,62 +1,188 @@
 use axum::extract::FromRequestParts;
+use mtapp::ReactorState;
 use uuid::Uuid;
 
 use crate::AuthError;
 
+/// A user of an external OpenID Connect provider, taken from its verified ID token
+pub struct ExternalIdentity {
+    pub issuer: String,
+    pub subject: String,
+    pub email: Option<String>,
+    pub email_verified: bool,
+    pub preferred_username: Option<String>,
+}
+
 #[axum::async_trait]
 pub trait UserProvider {
-    type Data<S: Send + Sync + 'static>: FromRequestParts<S> + Send + Sync + 'static;
+    type Data: FromRequestParts<ReactorState> + Send + Sync + 'static;
 
     /// Given an identifier and password, init a new provider
-    async fn login<S: Send + Sync + 'static>(
-        data: &Self::Data<S>,
-        username: &str,
-        password: &str,
-    ) -> Result<Uuid, AuthError>;
+    async fn login(data: &Self::Data, username: &str, password: &str) -> Result<Uuid, AuthError>;
+
+    /// Whether the user should pass a second factor after the password, never by default
+    async fn mfa_required(_data: &Self::Data, _user_id: Uuid) -> Result<bool, AuthError> {
+        Ok(false)
+    }
+
+    /// Check the second factor of the user, ex. a TOTP or recovery code
+    async fn verify_mfa(_data: &Self::Data, _user_id: Uuid, _code: &str) -> Result<(), AuthError> {
+        Err(AuthError::Credentials)
+    }
+
+    /// Given an external identity, return the user it's linked to. Identities which aren't linked
+    /// yet should get a new user, rejected by default
+    async fn external_login(
+        _data: &Self::Data,
+        _identity: &ExternalIdentity,
+    ) -> Result<Uuid, AuthError> {
+        Err(AuthError::Credentials)
+    }
+
+    /// Link an external identity to the user, it should fail if it's linked to another user
+    async fn link_identity(
+        _data: &Self::Data,
+        _user_id: Uuid,
+        _identity: &ExternalIdentity,
+    ) -> Result<(), AuthError> {
+        Err(AuthError::Credentials)
+    }
+
+    /// Given an api key, return (user id, key id, scopes of the key)
+    async fn api_key(
+        _data: &Self::Data,
+        _key: &str,
+    ) -> Result<(Uuid, Uuid, Vec<String>), AuthError> {
+        Err(AuthError::Credentials)
+    }
+}
+
+#[axum::async_trait]
+pub trait ClientProvider {
+    type Data: FromRequestParts<ReactorState> + Send + Sync + 'static;
+
+    /// Given a client id and secret, authenticate the client and return (id, allowed scopes)
+    async fn authenticate(
+        data: &Self::Data,
+        client_id: &str,
+        client_secret: &str,
+    ) -> Result<(Uuid, Vec<String>), AuthError>;
+}
+
+/// The default `ClientProvider` of `AuthApp`, rejecting every client
+pub struct NoClients;
+
+#[axum::async_trait]
+impl ClientProvider for NoClients {
+    type Data = ();
+
+    async fn authenticate(_: &(), _: &str, _: &str) -> Result<(Uuid, Vec<String>), AuthError> {
+        Err(AuthError::Credentials)
+    }
+}
+
+/// A WebAuthn credential of a user
+pub struct Passkey {
+    /// Base64url encoded, as sent by the browsers
+    pub credential_id: String,
+    pub user_id: Uuid,
+    /// The COSE encoded public key
+    pub public_key: Vec<u8>,
+    pub sign_count: u32,
+}
+
+#[axum::async_trait]
+pub trait PasskeyProvider {
+    type Data: FromRequestParts<ReactorState> + Send + Sync + 'static;
+
+    /// Given a user id, return (name shown by the authenticators, credential ids of the user)
+    async fn passkey_user(
+        data: &Self::Data,
+        user_id: Uuid,
+    ) -> Result<(String, Vec<String>), AuthError>;
+
+    /// Store a newly registered passkey
+    async fn add(data: &Self::Data, passkey: Passkey, name: &str) -> Result<(), AuthError>;
+
+    /// Given a credential id, find the passkey
+    async fn find(data: &Self::Data, credential_id: &str) -> Result<Passkey, AuthError>;
+
+    /// Store the signature counter of the last login
+    async fn update_sign_count(
+        data: &Self::Data,
+        credential_id: &str,
+        sign_count: u32,
+    ) -> Result<(), AuthError>;
+}
+
+/// The default `PasskeyProvider` of `AuthApp`, no passkey can be registered
+pub struct NoPasskeys;
+
+#[axum::async_trait]
+impl PasskeyProvider for NoPasskeys {
+    type Data = ();
+
+    async fn passkey_user(_: &(), _: Uuid) -> Result<(String, Vec<String>), AuthError> {
+        Err(AuthError::Permission)
+    }
+
+    async fn add(_: &(), _: Passkey, _: &str) -> Result<(), AuthError> {
+        Err(AuthError::Permission)
+    }
+
+    async fn find(_: &(), _: &str) -> Result<Passkey, AuthError> {
+        Err(AuthError::Credentials)
+    }
+
+    async fn update_sign_count(_: &(), _: &str, _: u32) -> Result<(), AuthError> {
+        Err(AuthError::Credentials)
+    }
 }
 
 #[axum::async_trait]
 pub trait GrantProvider {
-    type Data<S: Send + Sync + 'static>: FromRequestParts<S> + Send + Sync + 'static;
+    type Data: FromRequestParts<ReactorState> + Send + Sync + 'static;
 
     /// Return all the scopes for this user
-    async fn scopes<S: Send + Sync + 'static>(
-        data: &Self::Data<S>,
+    async fn scopes(data: &Self::Data, user_id: Uuid) -> Result<Vec<String>, AuthError>;
+}
+
+/// Result of rotating a refresh token, see `SessionProvider::rotate`
+pub enum RefreshOutcome {
+    Rotated {
         user_id: Uuid,
-    ) -> Result<Vec<String>, AuthError>;
+        previous_jti: Uuid,
+        jti: Uuid,
+        refresh_token: String,
+    },
+    /// The token was already rotated and the session is revoked
+    Reused { jti: Uuid },
 }
 
 #[axum::async_trait]
 pub trait SessionProvider {
-    type Data<S: Send + Sync + 'static>: FromRequestParts<S> + Send + Sync + 'static;
+    type Data: FromRequestParts<ReactorState> + Send + Sync + 'static;
 
     /// Given the refresh_token, find the session and return (jti, user_id)
-    async fn find<S: Send + Sync + 'static>(
-        data: &Self::Data<S>,
-        refresh_token: &str,
-    ) -> Result<(Uuid, Uuid), AuthError>
+    async fn find(data: &Self::Data, refresh_token: &str) -> Result<(Uuid, Uuid), AuthError>
     where
         Self: Sized;
 
     /// Given a user id, make a new session and return (jti, refresh_token)
-    async fn make<S: Send + Sync + 'static>(
-        data: &Self::Data<S>,
-        user_id: Uuid,
-    ) -> Result<(Uuid, String), AuthError>
+    async fn make(data: &Self::Data, user_id: Uuid) -> Result<(Uuid, String), AuthError>
     where
         Self: Sized;
 
-    /// return a new unique identifier that will be used as jti for this session, deleting the previous one
-    async fn reset_jti<S: Send + Sync + 'static>(
-        data: &Self::Data<S>,
-        refresh_token: &str,
-    ) -> Result<Uuid, AuthError>;
+    /// Rotate the refresh token, issuing a new jti and refresh token for the session.
+    ///
+    /// A refresh token which is already rotated means it's leaked, so the session should be
+    /// revoked and `RefreshOutcome::Reused` returned
+    async fn rotate(data: &Self::Data, refresh_token: &str) -> Result<RefreshOutcome, AuthError>
+    where
+        Self: Sized;
 
     /// Given a jti, invalidate the session in a sense that it can't be used to get the user based on it
-    async fn delete_by_jti<S: Send + Sync + 'static>(
-        data: &Self::Data<S>,
-        jti: Uuid,
-    ) -> Result<(), AuthError>
+    async fn delete_by_jti(data: &Self::Data, jti: Uuid) -> Result<(), AuthError>
     where
         Self: Sized;
 }
@@ -1,12 +1,21 @@
+use std::net::IpAddr;
+
 use serde::{Deserialize, Serialize};
 use utoipa::{IntoParams, ToSchema};
+use uuid::Uuid;
+
+use crate::extract::SubjectType;
 
 #[derive(Serialize, ToSchema)]
 pub struct TokenData {
     pub access_token: String,
     pub token_type: &'static str,
-    pub refresh_token: String,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub refresh_token: Option<String>,
     pub expires_in: u64,
+    /// Should be sent back in the `X-CSRF-Token` header, when the refresh token cookie is used
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub csrf_token: Option<String>,
 }
 
 #[derive(ToSchema)]
@@ -18,7 +27,208 @@ pub struct Credentials {
     pub password: String,
 }
 
+/// Returned by `/login` instead of the tokens when the user has MFA enabled
+#[derive(Serialize, ToSchema)]
+pub struct MfaChallenge {
+    pub mfa_required: bool,
+    /// Should be sent to `/login/mfa` along with the code
+    pub challenge_token: String,
+    pub expires_in: u64,
+}
+
+#[derive(Deserialize, ToSchema, IntoParams)]
+pub struct MfaLogin {
+    pub challenge_token: String,
+    /// A TOTP code or one of the recovery codes
+    pub code: String,
+}
+
 #[derive(Deserialize, ToSchema, IntoParams)]
 pub struct Flat {
     pub flat: Option<bool>,
 }
+
+#[derive(Deserialize, IntoParams)]
+pub struct LockoutQuery {
+    pub username: Option<String>,
+    #[param(value_type = Option<String>)]
+    pub ip: Option<IpAddr>,
+}
+
+#[derive(Serialize, ToSchema)]
+pub struct OidcAuthorization {
+    /// The user should be redirected to this page of the provider
+    pub authorization_url: String,
+}
+
+#[derive(Deserialize, ToSchema, IntoParams)]
+pub struct OidcCallback {
+    /// Both are passed to the redirect uri by the provider
+    pub code: String,
+    pub state: String,
+}
+
+/// Body of the `/token` endpoint, see RFC 6749
+#[derive(Deserialize, ToSchema)]
+pub struct TokenRequest {
+    /// password, refresh_token, client_credentials or mfa_otp
+    pub grant_type: String,
+    pub username: Option<String>,
+    pub password: Option<String>,
+    pub refresh_token: Option<String>,
+    /// The client credentials can be sent in a basic `Authorization` header instead
+    pub client_id: Option<String>,
+    pub client_secret: Option<String>,
+    /// The `mfa_token` of the `mfa_required` error, used by the mfa_otp grant
+    pub mfa_token: Option<String>,
+    /// A TOTP code or one of the recovery codes, used by the mfa_otp grant
+    pub otp: Option<String>,
+    /// Space separated scopes, a subset of the granted scopes
+    pub scope: Option<String>,
+}
+
+#[derive(Serialize, ToSchema)]
+pub struct OAuthToken {
+    pub access_token: String,
+    pub token_type: &'static str,
+    pub expires_in: u64,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub refresh_token: Option<String>,
+    pub scope: String,
+}
+
+/// Body of the `/revoke` and `/introspect` endpoints, see RFC 7009 and RFC 7662
+#[derive(Deserialize, ToSchema)]
+pub struct TokenHint {
+    pub token: String,
+    /// access_token or refresh_token
+    pub token_type_hint: Option<String>,
+}
+
+#[derive(Serialize, ToSchema)]
+pub struct Introspection {
+    pub active: bool,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub scope: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub sub: Option<Uuid>,
+    /// Whether the `sub` is a user or a client
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub sub_type: Option<SubjectType>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub jti: Option<Uuid>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub iat: Option<u64>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub exp: Option<u64>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub token_type: Option<&'static str>,
+}
+
+/// Error body of the OAuth2 endpoints, see RFC 6749 section 5.2
+#[derive(Serialize, ToSchema)]
+pub struct OAuthErrorBody {
+    pub error: &'static str,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub error_description: Option<&'static str>,
+    /// Only for the `mfa_required` error, should be sent back with the mfa_otp grant
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub mfa_token: Option<String>,
+}
+
+/// Options of `navigator.credentials.create`, the binary fields are base64url encoded
+#[derive(Serialize, ToSchema)]
+#[serde(rename_all = "camelCase")]
+pub struct RegistrationOptions {
+    pub challenge: String,
+    pub rp: RelyingPartyEntity,
+    pub user: UserEntity,
+    pub pub_key_cred_params: Vec<CredentialParameter>,
+    /// In milliseconds
+    pub timeout: u64,
+    pub exclude_credentials: Vec<CredentialDescriptor>,
+    pub authenticator_selection: AuthenticatorSelection,
+    pub attestation: &'static str,
+}
+
+#[derive(Serialize, ToSchema)]
+pub struct RelyingPartyEntity {
+    pub id: String,
+    pub name: String,
+}
+
+#[derive(Serialize, ToSchema)]
+#[serde(rename_all = "camelCase")]
+pub struct UserEntity {
+    pub id: String,
+    pub name: String,
+    pub display_name: String,
+}
+
+#[derive(Serialize, ToSchema)]
+pub struct CredentialParameter {
+    #[serde(rename = "type")]
+    pub typ: &'static str,
+    pub alg: i64,
+}
+
+#[derive(Serialize, ToSchema)]
+pub struct CredentialDescriptor {
+    #[serde(rename = "type")]
+    pub typ: &'static str,
+    pub id: String,
+}
+
+#[derive(Serialize, ToSchema)]
+#[serde(rename_all = "camelCase")]
+pub struct AuthenticatorSelection {
+    pub resident_key: &'static str,
+    pub user_verification: &'static str,
+}
+
+/// Options of `navigator.credentials.get`, no credentials are listed so any passkey of the site
+/// can be picked
+#[derive(Serialize, ToSchema)]
+#[serde(rename_all = "camelCase")]
+pub struct AssertionOptions {
+    pub challenge: String,
+    pub rp_id: String,
+    /// In milliseconds
+    pub timeout: u64,
+    pub user_verification: &'static str,
+}
+
+/// The credential returned by `navigator.credentials.create`, with the binary fields base64url
+/// encoded
+#[derive(Deserialize, ToSchema)]
+pub struct PasskeyRegistration {
+    pub id: String,
+    pub response: AttestationResponse,
+    /// To tell the user's passkeys apart
+    pub name: Option<String>,
+}
+
+#[derive(Deserialize, ToSchema)]
+#[serde(rename_all = "camelCase")]
+pub struct AttestationResponse {
+    #[serde(rename = "clientDataJSON")]
+    pub client_data_json: String,
+    pub attestation_object: String,
+}
+
+/// The credential returned by `navigator.credentials.get`, with the binary fields base64url
+/// encoded
+#[derive(Deserialize, ToSchema)]
+pub struct PasskeyAssertion {
+    pub id: String,
+    pub response: AssertionResponse,
+}
+
+#[derive(Deserialize, ToSchema)]
+#[serde(rename_all = "camelCase")]
+pub struct AssertionResponse {
+    #[serde(rename = "clientDataJSON")]
+    pub client_data_json: String,
+    pub authenticator_data: String,
+    pub signature: String,
+}
@@ -0,0 +1,216 @@
+use std::sync::Arc;
+
+use axum::{routing::MethodRouter, Router};
+use mtapp::ReactorState;
+use tower::Layer;
+use utoipa::openapi::{security::SecurityRequirement, OpenApi, PathItem};
+
+use crate::{
+    extract::Claims,
+    middleware::{ClaimCheck, ClaimCheckService},
+    openapi::SECURITY_SCHEME,
+};
+
+type CheckFn = Box<dyn Fn(Option<Claims>) -> bool + Send + Sync>;
+
+/// Scopes a route needs, checked as a layer like `ClaimCheck` and shown in the OpenAPI docs of the
+/// route with `RequireScopes::document`.
+///
+/// It's a list of alternatives, and the claims should have all the scopes of one of them, ex.
+/// `RequireScopes::admin().and(RequireScopes::all_of(&["active"]))`. Anonymous requests are
+/// rejected with a 401 and the ones missing the scopes with a 403.
+#[derive(Clone, Debug)]
+pub struct RequireScopes {
+    alternatives: Arc<Vec<Vec<String>>>,
+    clients: bool,
+    api_keys: bool,
+}
+
+impl RequireScopes {
+    /// Any authenticated request, no scopes are needed
+    pub fn authenticated() -> Self {
+        Self::new(vec![Vec::new()])
+    }
+
+    /// One of the scopes is enough
+    pub fn any_of(scopes: &[&str]) -> Self {
+        Self::new(
+            scopes
+                .iter()
+                .map(|scope| vec![String::from(*scope)])
+                .collect(),
+        )
+    }
+
+    /// All the scopes are needed
+    pub fn all_of(scopes: &[&str]) -> Self {
+        Self::new(vec![scopes
+            .iter()
+            .map(|scope| String::from(*scope))
+            .collect()])
+    }
+
+    /// `superadmin` or `admin`, needed by the internal routes of the apps
+    pub fn admin() -> Self {
+        Self::any_of(&["superadmin", "admin"])
+    }
+
+    /// Reject the client tokens, for the routes acting on the user's own data
+    pub fn users_only(mut self) -> Self {
+        self.clients = false;
+        self
+    }
+
+    /// Reject the api keys, ex. so a key can't make more keys to outlive itself
+    pub fn tokens_only(mut self) -> Self {
+        self.api_keys = false;
+        self
+    }
+
+    pub fn or(self, other: Self) -> Self {
+        Self {
+            clients: self.clients || other.clients,
+            api_keys: self.api_keys || other.api_keys,
+            ..Self::new(
+                self.alternatives
+                    .iter()
+                    .chain(other.alternatives.iter())
+                    .cloned()
+                    .collect(),
+            )
+        }
+    }
+
+    pub fn and(self, other: Self) -> Self {
+        let mut alternatives = Vec::new();
+        for left in self.alternatives.iter() {
+            for right in other.alternatives.iter() {
+                let mut scopes = left.clone();
+                scopes.extend(right.iter().filter(|scope| !left.contains(scope)).cloned());
+                alternatives.push(scopes);
+            }
+        }
+        Self {
+            clients: self.clients && other.clients,
+            api_keys: self.api_keys && other.api_keys,
+            ..Self::new(alternatives)
+        }
+    }
+
+    pub fn is_satisfied_by(&self, claims: &Claims) -> bool {
+        (self.clients || !claims.is_client())
+            && (self.api_keys || !claims.is_api_key())
+            && self
+                .alternatives
+                .iter()
+                .any(|scopes| scopes.iter().all(|scope| claims.has_scope(scope)))
+    }
+
+    /// Show the requirement as the security of all the operations in the docs
+    pub fn document(&self, mut openapi: OpenApi) -> OpenApi {
+        for item in openapi.paths.paths.values_mut() {
+            self.secure(item);
+        }
+        openapi
+    }
+
+    /// Show the requirement as the security of the operations of a path, as it's written in the
+    /// docs(ex. `/{user_id}`)
+    pub fn document_path(&self, mut openapi: OpenApi, path: &str) -> OpenApi {
+        if let Some(item) = openapi.paths.paths.get_mut(path) {
+            self.secure(item);
+        }
+        openapi
+    }
+
+    fn new(alternatives: Vec<Vec<String>>) -> Self {
+        Self {
+            alternatives: Arc::new(alternatives),
+            clients: true,
+            api_keys: true,
+        }
+    }
+
+    // The requirements of a list are alternatives, and the scopes of each one are all needed
+    fn secure(&self, item: &mut PathItem) {
+        let security: Vec<_> = self
+            .alternatives
+            .iter()
+            .map(|scopes| SecurityRequirement::new(SECURITY_SCHEME, scopes))
+            .collect();
+        for operation in item.operations.values_mut() {
+            operation.security = Some(security.clone());
+        }
+    }
+}
+
+impl<T> Layer<T> for RequireScopes {
+    type Service = ClaimCheckService<CheckFn, T>;
+
+    fn layer(&self, inner: T) -> Self::Service {
+        let scopes = self.clone();
+        let check: CheckFn = Box::new(move |claims: Option<Claims>| {
+            claims.map_or(false, |claims| scopes.is_satisfied_by(&claims))
+        });
+        ClaimCheck::new(check).layer(inner)
+    }
+}
+
+/// Routes with the scopes they need, so the layer and the docs are written in one place. Build
+/// them in a function called by both `App::*_routes` and `App::*_openapi`, ex.
+///
+/// ```ignore
+/// fn internal_routes(prefix: &str) -> ScopedRoutes {
+///     ScopedRoutes::new(prefix).route("/{user_id}", get(admin::get), RequireScopes::admin())
+/// }
+///
+/// // App::internal_routes
+/// Some(internal_routes(path_prefix).into_router())
+/// // App::internal_openapi
+/// Some(internal_routes("").document(InternalUserOpenApi::openapi()))
+/// ```
+pub struct ScopedRoutes<S = ReactorState> {
+    prefix: String,
+    router: Router<S>,
+    paths: Vec<(String, RequireScopes)>,
+}
+
+impl<S> ScopedRoutes<S>
+where
+    S: Clone + Send + Sync + 'static,
+{
+    pub fn new(prefix: &str) -> Self {
+        Self {
+            prefix: String::from(prefix),
+            router: Router::new(),
+            paths: Vec::new(),
+        }
+    }
+
+    /// Add a route needing `scopes`. The path is written as in the docs, ex. `/{user_id}`, and
+    /// is relative to the prefix
+    pub fn route(
+        mut self,
+        path: &str,
+        method_router: MethodRouter<S>,
+        scopes: RequireScopes,
+    ) -> Self {
+        let route = format!("{}{}", self.prefix, path.replace('{', ":").replace('}', ""));
+        self.router = self
+            .router
+            .route(&route, method_router.layer(scopes.clone()));
+        self.paths.push((String::from(path), scopes));
+        self
+    }
+
+    pub fn into_router(self) -> Router<S> {
+        self.router
+    }
+
+    /// Show the scopes of the routes as the security of their operations in the docs
+    pub fn document(&self, openapi: OpenApi) -> OpenApi {
+        self.paths.iter().fold(openapi, |openapi, (path, scopes)| {
+            scopes.document_path(openapi, path)
+        })
+    }
+}
@@ -0,0 +1,31 @@
+use base64::{engine::general_purpose::URL_SAFE_NO_PAD, Engine};
+use ring::{
+    constant_time::verify_slices_are_equal,
+    digest,
+    rand::{SecureRandom, SystemRandom},
+};
+
+/// A new random secret with 256 bits, ex. an api key or a client secret
+pub fn generate() -> String {
+    let mut bytes = [0u8; 32];
+    SystemRandom::new()
+        .fill(&mut bytes)
+        .expect("Failed to generate a secret");
+    URL_SAFE_NO_PAD.encode(bytes)
+}
+
+/// Hash of a secret to store instead of it
+///
+/// It's a plain SHA-256, so it's only for the random secrets with at least 80 bits which can't be
+/// guessed from a leaked hash, the passwords need a slow hash
+pub fn hash(secret: &str) -> String {
+    URL_SAFE_NO_PAD.encode(digest::digest(&digest::SHA256, secret.as_bytes()))
+}
+
+pub fn verify(secret: &str, hash: &str) -> bool {
+    constant_time_eq(self::hash(secret).as_bytes(), hash.as_bytes())
+}
+
+pub fn constant_time_eq(a: &[u8], b: &[u8]) -> bool {
+    verify_slices_are_equal(a, b).is_ok()
+}
@@ -0,0 +1,96 @@
+use std::error::Error;
+use std::time::Duration;
+
+use basteh::{Basteh, BastehError};
+use mtapp::{db::DbPool, ReactorState, Shutdown};
+use sqlx::types::chrono::Utc;
+
+use crate::keys::KeyError;
+use crate::models::StoredKey;
+use crate::AuthConfig;
+
+/// Periodically drop the expired tokens from the blacklist
+pub(crate) async fn prune_blacklist(state: ReactorState, mut shutdown: Shutdown, every: Duration) {
+    let blacklist = match state.get::<AuthConfig>() {
+        Some(config) => state.storage().scope(config.blacklist_scope()),
+        None => {
+            log::error!("AuthConfig is not registered, blacklist won't be pruned");
+            return;
+        }
+    };
+    let mut interval = tokio::time::interval(every);
+
+    loop {
+        tokio::select! {
+            _ = interval.tick() => {}
+            _ = shutdown.wait() => break,
+        }
+
+        if let Err(e) = prune(&blacklist).await {
+            log::error!("Failed to prune the token blacklist: {}", e);
+        }
+    }
+}
+
+// Some backends only evict the expired keys when they're read, so touch every key
+async fn prune(blacklist: &Basteh) -> Result<(), BastehError> {
+    for key in blacklist.keys().await? {
+        blacklist.contains_key(key).await?;
+    }
+    Ok(())
+}
+
+/// Periodically reload the key ring, so the keys rotated through the `rotate-key` command are
+/// picked up by every running instance
+pub(crate) async fn reload_keys(state: ReactorState, mut shutdown: Shutdown, every: Duration) {
+    let config = match state.get::<AuthConfig>() {
+        Some(config) => config.clone(),
+        None => {
+            log::error!("AuthConfig is not registered, signing keys won't be reloaded");
+            return;
+        }
+    };
+    let mut interval = tokio::time::interval(every);
+
+    // The first tick is immediate and the keys are already loaded on startup
+    interval.tick().await;
+
+    loop {
+        tokio::select! {
+            _ = interval.tick() => {}
+            _ = shutdown.wait() => break,
+        }
+
+        if let Err(e) = load_keys(&config, state.db()).await {
+            log::error!("Failed to reload the signing keys: {}", e);
+        }
+    }
+}
+
+/// Replace the ring's keys with the stored ones, the configured key is kept until a key is
+/// rotated in
+pub(crate) async fn load_keys(
+    config: &AuthConfig,
+    db: &DbPool,
+) -> Result<(), Box<dyn Error + Send + Sync>> {
+    let stored = StoredKey::find_active(Utc::now(), db).await?;
+
+    let current = match stored
+        .iter()
+        .filter(|key| key.retire_at.is_none())
+        .max_by_key(|key| key.created_at)
+    {
+        Some(current) => current,
+        None => return Ok(()),
+    };
+    let sealer = config.sealer()?;
+    let current = current.to_signing_key(sealer)?;
+
+    let active = stored
+        .iter()
+        .map(|key| Ok((key.to_signing_key(sealer)?, key.retire_at)))
+        .collect::<Result<Vec<_>, KeyError>>()?;
+
+    config.keys().replace(current, active);
+    Ok(())
+}
@@ -0,0 +1,32 @@
+use mtapp::testing::TestClient;
+use uuid::Uuid;
+
+use crate::{AuthConfig, Claims};
+
+pub trait AuthTestExt {
+    /// Authenticate the client's requests with a freshly signed token for the given user.
+    ///
+    /// No session is created for the token, so it can't be refreshed.
+    fn as_user(self, user_id: Uuid, scopes: &[&str]) -> Self;
+}
+
+impl AuthTestExt for TestClient {
+    fn as_user(self, user_id: Uuid, scopes: &[&str]) -> Self {
+        let token = {
+            let config = self
+                .state()
+                .get::<AuthConfig>()
+                .expect("AuthApp should be mounted to authenticate requests");
+
+            Claims::new(
+                user_id,
+                Uuid::new_v4(),
+                scopes.iter().map(|scope| scope.to_string()).collect(),
+                config.get_token_expiry(),
+            )
+            .generate_token(&config.keys().current())
+        };
+
+        self.bearer(&token)
+    }
+}
@@ -0,0 +1,253 @@
+use std::net::IpAddr;
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+
+use axum::extract::{FromRef, FromRequestParts, State};
+use axum::response::IntoResponse;
+use axum_client_ip::InsecureClientIp;
+use basteh::Basteh;
+use json_resp::JsonResponse;
+use mtapp::extractors::{oai, Query};
+use mtapp::ReactorState;
+
+use crate::{
+    app::AuthConfig,
+    errors::{AuthError, AuthErrorOai},
+    schemas::{LockoutQuery, Message},
+};
+
+const THROTTLE_SCOPE: &str = "login_throttle";
+
+/// Limits the failed logins of each username and ip address. After a few failures every attempt
+/// of a username is delayed a bit more, and too many failures lock it out for a while
+#[derive(Clone)]
+pub struct LoginThrottle {
+    // Failures of a username before the delays start
+    free_failures: u32,
+    max_failures: u32,
+    max_ip_failures: u32,
+    // Doubled on every failure after the free ones
+    base_delay: Duration,
+    lockout: Duration,
+    window: Duration,
+}
+
+impl Default for LoginThrottle {
+    fn default() -> Self {
+        Self {
+            free_failures: 3,
+            max_failures: 10,
+            max_ip_failures: 100,
+            base_delay: Duration::from_secs(1),
+            lockout: Duration::from_secs(15 * 60),
+            window: Duration::from_secs(15 * 60),
+        }
+    }
+}
+
+impl LoginThrottle {
+    /// Failures of a username before it's locked out, 10 by default
+    pub fn max_failures(mut self, max: u32) -> Self {
+        self.max_failures = max;
+        self
+    }
+
+    /// Failures from an ip address before it's locked out, 100 by default. It's higher since many
+    /// users may share an address, and it's not delayed before the lockout
+    pub fn max_ip_failures(mut self, max: u32) -> Self {
+        self.max_ip_failures = max;
+        self
+    }
+
+    /// 15 minutes by default
+    pub fn lockout(mut self, lockout: Duration) -> Self {
+        self.lockout = lockout;
+        self
+    }
+
+    /// The failures are forgotten after this long without a new one, 15 minutes by default
+    pub fn window(mut self, window: Duration) -> Self {
+        self.window = window;
+        self
+    }
+
+    fn delay(&self, failures: u32) -> Option<Duration> {
+        let delayed = failures.checked_sub(self.free_failures + 1)?;
+        Some(
+            self.base_delay
+                .saturating_mul(1 << delayed.min(16))
+                .min(self.lockout),
+        )
+    }
+}
+
+/// Throttles the login attempts of a request, does nothing when the throttle is disabled
+pub(crate) struct LoginGuard {
+    throttle: Option<LoginThrottle>,
+    storage: Basteh,
+    ip: Option<IpAddr>,
+}
+
+impl LoginGuard {
+    /// Reject the attempt while the username or the ip address is blocked
+    pub(crate) async fn check(&self, username: &str) -> Result<(), AuthError> {
+        if self.throttle.is_none() {
+            return Ok(());
+        }
+
+        let storage = self.storage.scope(THROTTLE_SCOPE);
+        for key in self.keys(username) {
+            // Some backends only evict the expired keys when they're read, so the time is checked
+            let until: Option<i64> = storage.get(block_key(&key)).await?;
+            if until.map_or(false, |until| until > now()) {
+                metrics::increment_counter!("auth_logins_total", "result" => "throttled");
+                return Err(AuthError::TooManyAttempts);
+            }
+        }
+        Ok(())
+    }
+
+    pub(crate) async fn failed(&self, username: &str) -> Result<(), AuthError> {
+        let throttle = match &self.throttle {
+            Some(throttle) => throttle,
+            None => return Ok(()),
+        };
+
+        let storage = self.storage.scope(THROTTLE_SCOPE);
+        for key in self.keys(username) {
+            let failures = storage.mutate(&key, |value| value.incr(1)).await?;
+            storage.expire(&key, throttle.window).await?;
+            let failures = u32::try_from(failures).unwrap_or(u32::MAX);
+
+            let is_ip = key.starts_with("ip:");
+            let max_failures = if is_ip {
+                throttle.max_ip_failures
+            } else {
+                throttle.max_failures
+            };
+
+            let block = if failures >= max_failures {
+                tracing::warn!(%key, failures, "Locked out the logins after too many failures");
+                metrics::increment_counter!(
+                    "auth_login_lockouts_total",
+                    "by" => if is_ip { "ip" } else { "username" }
+                );
+                Some(throttle.lockout)
+            } else if is_ip {
+                None
+            } else {
+                throttle.delay(failures)
+            };
+
+            if let Some(block) = block {
+                storage
+                    .set_expiring(
+                        block_key(&key),
+                        now() + block.as_secs().max(1) as i64,
+                        block,
+                    )
+                    .await?;
+            }
+        }
+        Ok(())
+    }
+
+    /// Forget the failures of the username, the ones of the ip address are kept so a known
+    /// password can't be used to reset them
+    pub(crate) async fn succeeded(&self, username: &str) -> Result<(), AuthError> {
+        if self.throttle.is_none() {
+            return Ok(());
+        }
+
+        let _: Option<i64> = self
+            .storage
+            .scope(THROTTLE_SCOPE)
+            .remove(username_key(username))
+            .await?;
+        Ok(())
+    }
+
+    fn keys(&self, username: &str) -> Vec<String> {
+        let mut keys = vec![username_key(username)];
+        keys.extend(self.ip.map(ip_key));
+        keys
+    }
+}
+
+#[axum::async_trait]
+impl<S> FromRequestParts<S> for LoginGuard
+where
+    ReactorState: FromRef<S>,
+    S: Sync,
+{
+    type Rejection = AuthError;
+
+    async fn from_request_parts(
+        parts: &mut axum::http::request::Parts,
+        state: &S,
+    ) -> Result<Self, Self::Rejection> {
+        let config = AuthConfig::from_request_parts(parts, state).await?;
+        let ip = InsecureClientIp::from_request_parts(parts, state)
+            .await
+            .ok()
+            .map(|ip| ip.0);
+
+        Ok(Self {
+            throttle: config.get_login_throttle().cloned(),
+            storage: ReactorState::from_ref(state).storage().clone(),
+            ip,
+        })
+    }
+}
+
+#[utoipa::path(
+    delete,
+    tag = "Auth",
+    path = "/lockouts",
+    params(LockoutQuery),
+    responses(
+        (status = 200, body=inline(JsonResponse<Message>)),
+        oai::QueryErrors,
+        AuthErrorOai::Authentication,
+        AuthErrorOai::Permission,
+        AuthErrorOai::InternalError,
+    ),
+    security(("jwt_token" = ["admin"]))
+)]
+pub async fn clear_lockout(
+    State(storage): State<Basteh>,
+    Query(query): Query<LockoutQuery>,
+) -> impl IntoResponse {
+    let storage = storage.scope(THROTTLE_SCOPE);
+
+    let keys = query
+        .username
+        .as_deref()
+        .map(username_key)
+        .into_iter()
+        .chain(query.ip.map(ip_key));
+    for key in keys {
+        let _: Option<i64> = storage.remove(block_key(&key)).await?;
+        let _: Option<i64> = storage.remove(&key).await?;
+    }
+
+    Result::<_, AuthError>::Ok(JsonResponse::with_content("Lockout is cleared"))
+}
+
+fn username_key(username: &str) -> String {
+    format!("user:{}", username.to_lowercase())
+}
+
+fn ip_key(ip: IpAddr) -> String {
+    format!("ip:{}", ip)
+}
+
+fn block_key(key: &str) -> String {
+    format!("{}:until", key)
+}
+
+fn now() -> i64 {
+    SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .expect("Time went backwards")
+        .as_secs() as i64
+}
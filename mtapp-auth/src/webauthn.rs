@@ -0,0 +1,202 @@
+use base64::{engine::general_purpose::URL_SAFE_NO_PAD, Engine};
+use ciborium::value::Value;
+use ring::{
+    digest::{digest, SHA256},
+    rand::{SecureRandom, SystemRandom},
+    signature::{self, RsaPublicKeyComponents, UnparsedPublicKey},
+};
+use serde::Deserialize;
+
+use crate::errors::AuthError;
+
+const FLAG_USER_PRESENT: u8 = 0x01;
+const FLAG_USER_VERIFIED: u8 = 0x04;
+const FLAG_ATTESTED_CREDENTIAL: u8 = 0x40;
+
+// COSE algorithms of the supported keys
+pub(crate) const ES256: i64 = -7;
+pub(crate) const EDDSA: i64 = -8;
+pub(crate) const RS256: i64 = -257;
+
+/// The site the passkeys are bound to, ex. id `example.com` and origin `https://example.com`
+#[derive(Clone)]
+pub(crate) struct RelyingParty {
+    pub(crate) id: String,
+    pub(crate) name: String,
+    pub(crate) origin: String,
+}
+
+#[derive(Deserialize)]
+struct ClientData {
+    #[serde(rename = "type")]
+    typ: String,
+    challenge: String,
+    origin: String,
+}
+
+/// Parsed authenticator data, the credential is only present in the registration
+pub(crate) struct AuthenticatorData {
+    pub(crate) sign_count: u32,
+    // (credential id, COSE public key)
+    pub(crate) credential: Option<(Vec<u8>, Vec<u8>)>,
+}
+
+enum PublicKey {
+    Es256(Vec<u8>),
+    EdDsa(Vec<u8>),
+    Rs256 { n: Vec<u8>, e: Vec<u8> },
+}
+
+pub(crate) fn generate_challenge() -> String {
+    let mut bytes = [0u8; 32];
+    SystemRandom::new()
+        .fill(&mut bytes)
+        .expect("Failed to generate a challenge");
+    URL_SAFE_NO_PAD.encode(bytes)
+}
+
+pub(crate) fn decode(value: &str) -> Result<Vec<u8>, AuthError> {
+    URL_SAFE_NO_PAD
+        .decode(value.trim_end_matches('='))
+        .map_err(|_| AuthError::BadToken)
+}
+
+/// Check the type and origin of the client data, and return its challenge
+pub(crate) fn check_client_data(
+    rp: &RelyingParty,
+    client_data_json: &[u8],
+    typ: &str,
+) -> Result<String, AuthError> {
+    let data: ClientData =
+        serde_json::from_slice(client_data_json).map_err(|_| AuthError::BadToken)?;
+    if data.typ != typ || data.origin != rp.origin {
+        return Err(AuthError::BadToken);
+    }
+    Ok(data.challenge)
+}
+
+/// Return the authenticator data of an attestation object. The attestation statement isn't
+/// verified, since `none` is requested
+pub(crate) fn attested_data(attestation_object: &[u8]) -> Result<Vec<u8>, AuthError> {
+    let object: Value =
+        ciborium::de::from_reader(attestation_object).map_err(|_| AuthError::BadToken)?;
+
+    object
+        .into_map()
+        .map_err(|_| AuthError::BadToken)?
+        .into_iter()
+        .find(|(key, _)| key.as_text() == Some("authData"))
+        .and_then(|(_, value)| value.into_bytes().ok())
+        .ok_or(AuthError::BadToken)
+}
+
+pub(crate) fn parse_authenticator_data(
+    rp: &RelyingParty,
+    data: &[u8],
+) -> Result<AuthenticatorData, AuthError> {
+    // rp id hash, flags and the signature counter
+    if data.len() < 37 {
+        return Err(AuthError::BadToken);
+    }
+    if data[..32] != *digest(&SHA256, rp.id.as_bytes()).as_ref() {
+        return Err(AuthError::BadToken);
+    }
+    // Passkeys replace the password, so the user should be verified(ex. a PIN or biometrics)
+    // and not just present
+    let flags = data[32];
+    if flags & FLAG_USER_PRESENT == 0 || flags & FLAG_USER_VERIFIED == 0 {
+        return Err(AuthError::Credentials);
+    }
+    let sign_count = u32::from_be_bytes(data[33..37].try_into().expect("Length is checked"));
+
+    let credential = if flags & FLAG_ATTESTED_CREDENTIAL != 0 {
+        // 16 bytes of aaguid, then the length of the credential id
+        let rest = data.get(37..).filter(|rest| rest.len() >= 18);
+        let rest = rest.ok_or(AuthError::BadToken)?;
+        let id_len = u16::from_be_bytes([rest[16], rest[17]]) as usize;
+        let rest = &rest[18..];
+        if rest.len() < id_len {
+            return Err(AuthError::BadToken);
+        }
+        let (id, key) = rest.split_at(id_len);
+
+        // The key may be followed by the extensions, so only the first item is kept
+        let key: Value = ciborium::de::from_reader(key).map_err(|_| AuthError::BadToken)?;
+        let mut public_key = Vec::new();
+        ciborium::ser::into_writer(&key, &mut public_key).expect("Writing to a vec can't fail");
+        parse_public_key(&public_key)?;
+
+        Some((id.to_vec(), public_key))
+    } else {
+        None
+    };
+
+    Ok(AuthenticatorData {
+        sign_count,
+        credential,
+    })
+}
+
+/// Verify the signature of an assertion, made over the authenticator data and the hash of the
+/// client data
+pub(crate) fn verify(
+    public_key: &[u8],
+    authenticator_data: &[u8],
+    client_data_json: &[u8],
+    sig: &[u8],
+) -> Result<(), AuthError> {
+    let mut message = authenticator_data.to_vec();
+    message.extend_from_slice(digest(&SHA256, client_data_json).as_ref());
+
+    let result = match parse_public_key(public_key)? {
+        PublicKey::Es256(point) => {
+            UnparsedPublicKey::new(&signature::ECDSA_P256_SHA256_ASN1, point).verify(&message, sig)
+        }
+        PublicKey::EdDsa(key) => {
+            UnparsedPublicKey::new(&signature::ED25519, key).verify(&message, sig)
+        }
+        PublicKey::Rs256 { n, e } => RsaPublicKeyComponents { n, e }.verify(
+            &signature::RSA_PKCS1_2048_8192_SHA256,
+            &message,
+            sig,
+        ),
+    };
+    result.map_err(|_| AuthError::Credentials)
+}
+
+fn parse_public_key(public_key: &[u8]) -> Result<PublicKey, AuthError> {
+    let key: Value = ciborium::de::from_reader(public_key).map_err(|_| AuthError::BadToken)?;
+    let key = key.as_map().ok_or(AuthError::BadToken)?;
+
+    let field = |label: i64| {
+        key.iter()
+            .find(|(key, _)| key.as_integer().map(i128::from) == Some(label.into()))
+            .map(|(_, value)| value)
+    };
+    let integer = |label: i64| field(label).and_then(Value::as_integer).map(i128::from);
+    let bytes = |label: i64| {
+        field(label)
+            .and_then(Value::as_bytes)
+            .cloned()
+            .ok_or(AuthError::BadToken)
+    };
+
+    // 3 is the algorithm and -1 the curve, the rest of the labels depend on the key type
+    match integer(3).ok_or(AuthError::BadToken)? {
+        alg if alg == i128::from(ES256) && integer(-1) == Some(1) => {
+            let mut point = vec![0x04];
+            point.extend(bytes(-2)?);
+            point.extend(bytes(-3)?);
+            Ok(PublicKey::Es256(point))
+        }
+        alg if alg == i128::from(EDDSA) && integer(-1) == Some(6) => {
+            Ok(PublicKey::EdDsa(bytes(-2)?))
+        }
+        alg if alg == i128::from(RS256) => Ok(PublicKey::Rs256 {
+            n: bytes(-1)?,
+            e: bytes(-2)?,
+        }),
+        // Not supported
+        _ => Err(AuthError::BadToken),
+    }
+}
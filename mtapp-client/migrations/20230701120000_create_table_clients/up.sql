@@ -0,0 +1,8 @@
+CREATE TABLE IF NOT EXISTS clients (
+  id UUID PRIMARY KEY,
+  name VARCHAR NOT NULL,
+  secret_hash VARCHAR NOT NULL,
+  scopes VARCHAR NOT NULL DEFAULT '',
+  created_at Timestamp WITH TIME ZONE NOT NULL DEFAULT now(),
+  CONSTRAINT client_name_uniq UNIQUE (name)
+);
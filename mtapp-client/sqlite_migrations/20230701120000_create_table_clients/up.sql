@@ -0,0 +1,8 @@
+CREATE TABLE IF NOT EXISTS clients (
+  id BLOB PRIMARY KEY,
+  name VARCHAR NOT NULL,
+  secret_hash VARCHAR NOT NULL,
+  scopes VARCHAR NOT NULL DEFAULT '',
+  created_at TIMESTAMP NOT NULL DEFAULT CURRENT_TIMESTAMP,
+  CONSTRAINT client_name_uniq UNIQUE (name)
+);
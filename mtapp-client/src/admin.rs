@@ -0,0 +1,183 @@
+use axum::extract::{Path, State};
+use axum::response::IntoResponse;
+use json_resp::{JsonListMeta, JsonResponse};
+use seaqs::QueryFilter;
+use sqlx::types::Uuid;
+
+use mtapp::db::DbPool;
+use mtapp::extractors::{oai, Json, Query};
+use mtapp_auth::{secrets, AuthErrorOai};
+
+use crate::errors::{ClientError, ClientErrorOai};
+use crate::filters::{ClientDeleteFilter, ClientLookupFilter};
+use crate::models::Client;
+use crate::schemas::{ClientCreate, ClientList, ClientWithSecret};
+
+type QueryClientLookupFilter = QueryFilter<ClientLookupFilter<'static>>;
+
+#[utoipa::path(
+    get,
+    tag = "Client",
+    path = "/",
+    params(
+        QueryClientLookupFilter
+    ),
+    responses(
+        (status = 200, body=inline(JsonResponse<ClientList>)),
+        oai::QueryErrors,
+        AuthErrorOai::Authentication,
+        AuthErrorOai::Permission,
+        ClientErrorOai::InternalError
+    ),
+    security(
+        ("jwt_token" = [])
+    )
+)]
+pub async fn list(
+    State(pool): State<DbPool>,
+    Query(query): Query<QueryFilter<ClientLookupFilter<'_>>>,
+) -> impl IntoResponse {
+    let clients = Client::find(&query, &pool).await?;
+    let total = Client::count(&query, &pool).await?;
+    Result::<_, ClientError>::Ok(
+        JsonResponse::with_content(clients).meta(JsonListMeta::default().total(total as usize)),
+    )
+}
+
+#[utoipa::path(
+    post,
+    tag = "Client",
+    path = "/",
+    request_body(
+        content=inline(ClientCreate),
+        content_type="application/json",
+        description="Client create"
+    ),
+    responses(
+        (status = 200, body=inline(JsonResponse<ClientWithSecret>)),
+        oai::AllExtErrors,
+        AuthErrorOai::Authentication,
+        AuthErrorOai::Permission,
+        ClientErrorOai::DuplicateField,
+        ClientErrorOai::InternalError
+    ),
+    security(
+        ("jwt_token" = [])
+    )
+)]
+pub async fn create(
+    State(pool): State<DbPool>,
+    Json(client): Json<ClientCreate>,
+) -> impl IntoResponse {
+    let client_secret = secrets::generate();
+    let hash = secrets::hash(&client_secret);
+    let client = Client::create(client.name, &hash, &client.scopes, &pool).await?;
+    Result::<_, ClientError>::Ok(JsonResponse::with_content(ClientWithSecret {
+        client,
+        client_secret,
+    }))
+}
+
+#[utoipa::path(
+    delete,
+    tag = "Client",
+    path = "/",
+    params(
+        ClientDeleteFilter
+    ),
+    responses(
+        (status = 200, body=inline(JsonResponse<ClientList>)),
+        oai::QueryErrors,
+        AuthErrorOai::Authentication,
+        AuthErrorOai::Permission,
+        ClientErrorOai::InternalError
+    ),
+    security(
+        ("jwt_token" = [])
+    )
+)]
+pub async fn batch_delete(
+    State(pool): State<DbPool>,
+    Query(query): Query<ClientDeleteFilter>,
+) -> impl IntoResponse {
+    let clients = Client::delete(&query, &pool).await?;
+    Result::<_, ClientError>::Ok(JsonResponse::with_content(clients))
+}
+
+#[utoipa::path(
+    get,
+    tag = "Client",
+    path = "/{client_id}",
+    params(
+        ("client_id" = Uuid, Path,)
+    ),
+    responses(
+        (status = 200, body=inline(JsonResponse<Client>)),
+        oai::PathErrors,
+        AuthErrorOai::Authentication,
+        AuthErrorOai::Permission,
+        ClientErrorOai::NotFound,
+        ClientErrorOai::InternalError
+    ),
+    security(
+        ("jwt_token" = [])
+    )
+)]
+pub async fn get(id: Path<Uuid>, State(pool): State<DbPool>) -> impl IntoResponse {
+    let client = Client::get_by_id(*id, &pool).await?;
+    Result::<_, ClientError>::Ok(JsonResponse::with_content(client))
+}
+
+#[utoipa::path(
+    delete,
+    tag = "Client",
+    path = "/{client_id}",
+    params(
+        ("client_id" = Uuid, Path,)
+    ),
+    responses(
+        (status = 200, body=inline(JsonResponse<Client>)),
+        oai::PathErrors,
+        AuthErrorOai::Authentication,
+        AuthErrorOai::Permission,
+        ClientErrorOai::NotFound,
+        ClientErrorOai::InternalError
+    ),
+    security(
+        ("jwt_token" = [])
+    )
+)]
+pub async fn delete(id: Path<Uuid>, State(pool): State<DbPool>) -> impl IntoResponse {
+    let client = Client::delete_by_id(*id, &pool).await?;
+    Result::<_, ClientError>::Ok(JsonResponse::with_content(client))
+}
+
+#[utoipa::path(
+    post,
+    tag = "Client",
+    path = "/{client_id}/secret",
+    params(
+        ("client_id" = Uuid, Path,)
+    ),
+    responses(
+        (status = 200, body=inline(JsonResponse<ClientWithSecret>)),
+        oai::PathErrors,
+        AuthErrorOai::Authentication,
+        AuthErrorOai::Permission,
+        ClientErrorOai::NotFound,
+        ClientErrorOai::InternalError
+    ),
+    security(
+        ("jwt_token" = [])
+    )
+)]
+pub async fn reset_secret(id: Path<Uuid>, State(pool): State<DbPool>) -> impl IntoResponse {
+    // The tokens issued with the old secret are valid until they expire
+    let client_secret = secrets::generate();
+    let hash = secrets::hash(&client_secret);
+    let client = Client::update_secret(*id, &hash, &pool).await?;
+    Result::<_, ClientError>::Ok(JsonResponse::with_content(ClientWithSecret {
+        client,
+        client_secret,
+    }))
+}
@@ -0,0 +1,59 @@
+use axum::{
+    routing::{get, post},
+    Router,
+};
+use mtapp::{include_backend_migrations, App, ReactorState};
+use mtapp_auth::RequireScopes;
+use utoipa::OpenApi;
+
+use crate::{admin, openapi::InternalClientOpenApi};
+
+/// Service clients authenticating through the client_credentials grant, see `Provider`
+#[derive(Default)]
+pub struct ClientApp {}
+
+impl ClientApp {
+    pub fn new() -> Self {
+        ClientApp {}
+    }
+}
+
+impl App for ClientApp {
+    fn name(&self) -> &'static str {
+        "mtapp-client"
+    }
+
+    // The clients are given the scope app's scopes
+    fn dependencies(&self) -> &'static [&'static str] {
+        &["mtapp-scope"]
+    }
+
+    fn internal_routes(&mut self, path_prefix: &str) -> Option<Router<ReactorState>> {
+        Some(
+            Router::new()
+                .route(
+                    &format!("{}/", path_prefix),
+                    get(admin::list)
+                        .post(admin::create)
+                        .delete(admin::batch_delete),
+                )
+                .route(
+                    &format!("{}/:client_id", path_prefix),
+                    get(admin::get).delete(admin::delete),
+                )
+                .route(
+                    &format!("{}/:client_id/secret", path_prefix),
+                    post(admin::reset_secret),
+                )
+                .layer(RequireScopes::admin()),
+        )
+    }
+
+    fn migrations(&mut self) -> Option<Vec<Box<dyn mtapp::Migration>>> {
+        include_backend_migrations!(postgres: "./migrations", sqlite: "./sqlite_migrations")
+    }
+
+    fn internal_openapi(&mut self, _: &str) -> Option<utoipa::openapi::OpenApi> {
+        Some(RequireScopes::admin().document(InternalClientOpenApi::openapi()))
+    }
+}
@@ -0,0 +1,39 @@
+use std::fmt;
+
+use axum::http::StatusCode;
+use json_resp::JsonError;
+
+#[derive(Debug, JsonError)]
+#[json_error(internal_code = "500000 internal-error")]
+pub enum ClientError {
+    #[json_error(request, status = 404, code = "404001 resource-not-found")]
+    NotFound,
+
+    #[json_error(request, status = 409, code = "409001 already-exist")]
+    DuplicateField(&'static str),
+
+    #[json_error(internal)]
+    DatabaseError(sqlx::Error),
+
+    #[json_error(internal)]
+    UnknownConstaintError(Box<dyn sqlx::error::DatabaseError>),
+}
+
+impl fmt::Display for ClientError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str("ClientError")
+    }
+}
+
+impl From<sqlx::Error> for ClientError {
+    fn from(err: sqlx::Error) -> Self {
+        match err {
+            sqlx::Error::RowNotFound => ClientError::NotFound,
+            sqlx::Error::Database(db_err) => match mtapp::db::constraint(db_err.as_ref()) {
+                Some("client_name_uniq" | "clients.name") => ClientError::DuplicateField("name"),
+                _ => ClientError::UnknownConstaintError(db_err),
+            },
+            _ => ClientError::DatabaseError(err),
+        }
+    }
+}
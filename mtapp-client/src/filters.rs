@@ -0,0 +1,55 @@
+use sea_query::Cond;
+use seaqs::{
+    filters::{DateTimeTzFilterSet, StringFilterSet, UuidFilterSet},
+    Filter, ToCond, ToFieldCond,
+};
+use serde::Deserialize;
+use utoipa::{IntoParams, ToSchema};
+
+use crate::models::ClientIden;
+
+#[derive(Debug, Default, Deserialize, ToSchema)]
+pub struct ClientLookupFilter<'a> {
+    name: Option<StringFilterSet<'a>>,
+    created_at: Option<DateTimeTzFilterSet>,
+}
+
+impl<'a> ToCond for ClientLookupFilter<'a> {
+    fn to_cond(&self) -> Cond {
+        let mut cond = Cond::all();
+        if let Some(name) = self.name.to_cond(ClientIden::Name) {
+            cond = cond.add(name);
+        }
+        if let Some(created_at) = self.created_at.to_cond(ClientIden::CreatedAt) {
+            cond = cond.add(created_at);
+        }
+        cond
+    }
+}
+
+impl<'a> Filter for ClientLookupFilter<'a> {
+    const SORTABLE_FIELDS: &'static [&'static str] = &["name", "created_at"];
+}
+
+#[derive(Debug, Deserialize, IntoParams)]
+#[into_params(parameter_in = Query)]
+pub struct ClientDeleteFilter {
+    #[param(style = DeepObject, inline, explode)]
+    id: UuidFilterSet,
+}
+
+impl ToCond for ClientDeleteFilter {
+    fn to_cond(&self) -> Cond {
+        let mut cond = Cond::all();
+        if let Some(ids) = self.id.to_cond(ClientIden::Id) {
+            cond = cond.add(ids);
+        }
+        cond
+    }
+}
+
+impl ClientDeleteFilter {
+    pub fn is_empty(&self) -> bool {
+        self.id.is_empty()
+    }
+}
@@ -0,0 +1,12 @@
+mod admin;
+mod app;
+mod errors;
+mod filters;
+mod models;
+mod openapi;
+mod provider;
+mod schemas;
+
+pub use app::ClientApp;
+pub use models::Client;
+pub use provider::Provider;
@@ -0,0 +1,174 @@
+use mtapp::db::{Db, DbQueryBuilder, DbRow};
+use sea_query::{enum_def, Iden};
+use sea_query::{Expr, Query};
+use sea_query_binder::SqlxBinder;
+use seaqs::{ApplyConds, ApplyFilters, QueryFilter};
+use serde::Serialize;
+use sqlx::types::{
+    chrono::{DateTime, Utc},
+    Uuid,
+};
+use sqlx::{Error, Executor, FromRow, Row};
+use utoipa::ToSchema;
+
+use crate::filters::{ClientDeleteFilter, ClientLookupFilter};
+
+#[derive(Debug, FromRow, Serialize, ToSchema)]
+#[enum_def]
+pub struct Client {
+    pub id: Uuid,
+    pub name: String,
+    #[serde(skip)]
+    pub secret_hash: String,
+    /// Space separated scopes the client is allowed to request
+    pub scopes: String,
+    pub created_at: DateTime<Utc>,
+}
+
+#[derive(Iden)]
+struct Clients;
+
+impl Client {
+    pub fn scope_list(&self) -> Vec<String> {
+        self.scopes.split_whitespace().map(String::from).collect()
+    }
+
+    #[tracing::instrument(name = "Client::count", skip_all)]
+    pub async fn count<'a, E>(
+        filters: &QueryFilter<ClientLookupFilter<'_>>,
+        con: E,
+    ) -> Result<i64, Error>
+    where
+        E: Executor<'a, Database = Db>,
+    {
+        let mut q = Query::select()
+            .expr(Expr::asterisk().count())
+            .from(Clients)
+            .to_owned();
+
+        if let Some(filter) = &filters.filter {
+            q = q.apply_conds(filter).to_owned();
+        };
+
+        let (sql, args) = q.build_sqlx(DbQueryBuilder);
+
+        sqlx::query_with(&sql, args)
+            .try_map(|row: DbRow| {
+                let count = row.try_get_unchecked::<Option<i64>, _>(0usize)?;
+                Ok(count)
+            })
+            .fetch_one(con)
+            .await
+            .map(|v| v.unwrap_or(0))
+    }
+
+    #[tracing::instrument(name = "Client::find", skip_all)]
+    pub async fn find<'a, E>(
+        filters: &QueryFilter<ClientLookupFilter<'_>>,
+        con: E,
+    ) -> Result<Vec<Self>, Error>
+    where
+        E: Executor<'a, Database = Db>,
+    {
+        let (sql, args) = Query::select()
+            .expr(Expr::asterisk())
+            .from(Clients)
+            .to_owned()
+            .apply_filters(filters)
+            .build_sqlx(DbQueryBuilder);
+
+        sqlx::query_as_with(&sql, args).fetch_all(con).await
+    }
+
+    #[tracing::instrument(name = "Client::get_by_id", skip_all)]
+    pub async fn get_by_id<'a, E>(id: Uuid, con: E) -> Result<Self, Error>
+    where
+        E: Executor<'a, Database = Db>,
+    {
+        let (sql, args) = Query::select()
+            .expr(Expr::asterisk())
+            .from(Clients)
+            .and_where(Expr::col(ClientIden::Id).eq(id))
+            .build_sqlx(DbQueryBuilder);
+
+        sqlx::query_as_with(&sql, args).fetch_one(con).await
+    }
+
+    #[tracing::instrument(name = "Client::create", skip_all)]
+    pub async fn create<'a, E>(
+        name: String,
+        secret_hash: &str,
+        scopes: &[String],
+        con: E,
+    ) -> Result<Self, Error>
+    where
+        E: Executor<'a, Database = Db>,
+    {
+        let (sql, args) = Query::insert()
+            .into_table(Clients)
+            .columns([
+                ClientIden::Id,
+                ClientIden::Name,
+                ClientIden::SecretHash,
+                ClientIden::Scopes,
+            ])
+            .values_panic([
+                Uuid::new_v4().into(),
+                name.into(),
+                secret_hash.into(),
+                scopes.join(" ").into(),
+            ])
+            .returning(Query::returning().expr(Expr::asterisk()))
+            .build_sqlx(DbQueryBuilder);
+
+        sqlx::query_as_with(&sql, args).fetch_one(con).await
+    }
+
+    #[tracing::instrument(name = "Client::update_secret", skip_all)]
+    pub async fn update_secret<'a, E>(id: Uuid, secret_hash: &str, con: E) -> Result<Self, Error>
+    where
+        E: Executor<'a, Database = Db>,
+    {
+        let (sql, args) = Query::update()
+            .table(Clients)
+            .value(ClientIden::SecretHash, secret_hash)
+            .and_where(Expr::col(ClientIden::Id).eq(id))
+            .returning(Query::returning().expr(Expr::asterisk()))
+            .build_sqlx(DbQueryBuilder);
+
+        sqlx::query_as_with(&sql, args).fetch_one(con).await
+    }
+
+    #[tracing::instrument(name = "Client::delete", skip_all)]
+    pub async fn delete<'a, E>(filters: &ClientDeleteFilter, con: E) -> Result<Vec<Self>, Error>
+    where
+        E: Executor<'a, Database = Db>,
+    {
+        if filters.is_empty() {
+            return Err(Error::RowNotFound);
+        }
+
+        let (sql, args) = Query::delete()
+            .from_table(Clients)
+            .to_owned()
+            .apply_conds(filters)
+            .returning(Query::returning().expr(Expr::asterisk()))
+            .build_sqlx(DbQueryBuilder);
+
+        sqlx::query_as_with(&sql, args).fetch_all(con).await
+    }
+
+    #[tracing::instrument(name = "Client::delete_by_id", skip_all)]
+    pub async fn delete_by_id<'a, E>(id: Uuid, con: E) -> Result<Self, Error>
+    where
+        E: Executor<'a, Database = Db>,
+    {
+        let (sql, args) = Query::delete()
+            .from_table(Clients)
+            .and_where(Expr::col(ClientIden::Id).eq(id))
+            .returning(Query::returning().expr(Expr::asterisk()))
+            .build_sqlx(DbQueryBuilder);
+
+        sqlx::query_as_with(&sql, args).fetch_one(con).await
+    }
+}
@@ -0,0 +1,38 @@
+use seaqs::filters::{DateTimeTzFilterSet, StringFilterSet, UuidFilterSet};
+use utoipa::OpenApi;
+
+use crate::{
+    admin,
+    errors::ClientErrorOai,
+    models::Client,
+    schemas::{ClientList, ClientWithSecret},
+};
+
+#[derive(OpenApi)]
+#[openapi(
+    info(description = "Service client management endpoints"),
+    paths(
+        admin::list,
+        admin::create,
+        admin::batch_delete,
+        admin::get,
+        admin::delete,
+        admin::reset_secret
+    ),
+    components(schemas(
+        // Responses
+        Client,
+        ClientList,
+        ClientWithSecret,
+
+        // Params
+        UuidFilterSet,
+        DateTimeTzFilterSet,
+        StringFilterSet,
+
+        // Errors
+        ClientErrorOai::NotFound,
+        ClientErrorOai::DuplicateField
+    ),)
+)]
+pub(crate) struct InternalClientOpenApi;
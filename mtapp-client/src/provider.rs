@@ -0,0 +1,33 @@
+use axum::extract::State;
+use mtapp::db::DbPool;
+use mtapp_auth::{secrets, AuthError, ClientProvider};
+use sqlx::types::Uuid;
+
+use crate::models::Client;
+
+pub struct Provider;
+
+#[axum::async_trait]
+impl ClientProvider for Provider {
+    type Data = State<DbPool>;
+
+    async fn authenticate(
+        State(pool): &State<DbPool>,
+        client_id: &str,
+        client_secret: &str,
+    ) -> Result<(Uuid, Vec<String>), AuthError> {
+        let id: Uuid = client_id.parse().map_err(|_| AuthError::Credentials)?;
+
+        let client = match Client::get_by_id(id, pool).await {
+            Ok(client) => client,
+            Err(sqlx::Error::RowNotFound) => return Err(AuthError::Credentials),
+            Err(err) => return Err(AuthError::DatabaseError(err)),
+        };
+
+        if !secrets::verify(client_secret, &client.secret_hash) {
+            return Err(AuthError::Credentials);
+        }
+
+        Ok((client.id, client.scope_list()))
+    }
+}
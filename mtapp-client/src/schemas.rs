@@ -0,0 +1,35 @@
+use serde::{Deserialize, Serialize};
+use utoipa::{
+    openapi::{ArrayBuilder, RefOr, Schema},
+    ToSchema,
+};
+
+use crate::Client;
+
+#[derive(Debug, Deserialize, ToSchema)]
+pub struct ClientCreate {
+    pub name: String,
+    /// Scopes the client is allowed to request
+    #[serde(default)]
+    pub scopes: Vec<String>,
+}
+
+/// The secret is only shown once, when the client is created or its secret is reset
+#[derive(Serialize, ToSchema)]
+pub struct ClientWithSecret {
+    #[serde(flatten)]
+    pub client: Client,
+    pub client_secret: String,
+}
+
+#[derive(utoipa::ToResponse)]
+pub(crate) struct ClientList(Vec<Client>);
+
+impl ToSchema<'static> for ClientList {
+    fn schema() -> (&'static str, RefOr<Schema>) {
+        (
+            "ClientList",
+            ArrayBuilder::new().items(Client::schema().1).build().into(),
+        )
+    }
+}
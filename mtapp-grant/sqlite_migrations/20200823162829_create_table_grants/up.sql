@@ -0,0 +1,9 @@
+CREATE TABLE IF NOT EXISTS grants (
+    id BLOB PRIMARY KEY,
+    user_id BLOB NOT NULL,
+    scope_id BLOB NOT NULL,
+    created_at TIMESTAMP NOT NULL DEFAULT CURRENT_TIMESTAMP,
+    CONSTRAINT grants_user_id FOREIGN KEY (user_id) REFERENCES users (id) ON DELETE CASCADE,
+    CONSTRAINT grants_scope_id FOREIGN KEY (scope_id) REFERENCES scopes (id) ON DELETE CASCADE,
+    CONSTRAINT grants_uniq UNIQUE (user_id, scope_id)
+);
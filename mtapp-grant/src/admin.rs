@@ -1,9 +1,9 @@
-use axum::{response::IntoResponse, Extension};
+use axum::{extract::State, response::IntoResponse};
 use json_resp::{JsonListMeta, JsonResponse};
 use seaqs::QueryFilter;
-use sqlx::PgPool;
 
 use mtapp::{
+    db::DbPool,
     extractors::{oai, Json, Path, Query},
     Uuid,
 };
@@ -41,7 +41,7 @@ type QueryGrantLookupFilter = QueryFilter<GrantLookupFilter>;
 )]
 pub async fn list(
     Query(query): Query<QueryFilter<GrantLookupFilter>>,
-    Extension(pool): Extension<PgPool>,
+    State(pool): State<DbPool>,
 ) -> impl IntoResponse {
     let grants = Grant::find(&query, &pool).await?;
     let count = Grant::count(&query, &pool).await?;
@@ -73,7 +73,7 @@ pub async fn list(
     )
 )]
 pub async fn create(
-    Extension(pool): Extension<PgPool>,
+    State(pool): State<DbPool>,
     Json(scope): Json<GrantCreate>,
 ) -> impl IntoResponse {
     let grant = Grant::create(scope, &pool).await?;
@@ -99,7 +99,7 @@ pub async fn create(
     )
 )]
 pub async fn batch_delete(
-    Extension(pool): Extension<PgPool>,
+    State(pool): State<DbPool>,
     Query(query): Query<GrantDeleteFilter>,
 ) -> impl IntoResponse {
     let scopes = Grant::delete(&query, &pool).await?;
@@ -125,10 +125,7 @@ pub async fn batch_delete(
         ("jwt_token" = [])
     )
 )]
-pub async fn delete(
-    Path(grant_id): Path<Uuid>,
-    Extension(pool): Extension<PgPool>,
-) -> impl IntoResponse {
+pub async fn delete(Path(grant_id): Path<Uuid>, State(pool): State<DbPool>) -> impl IntoResponse {
     let grant = Grant::delete_by_id(grant_id, &pool)
         .await
         .map(JsonResponse::with_content)?;
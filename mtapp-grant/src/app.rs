@@ -4,9 +4,8 @@ use axum::{
     Router,
 };
 use clap::{Arg, ArgAction, ArgMatches, Command};
-use mtapp::App;
-use mtapp_auth::{ClaimCheck, Claims};
-use sqlx::PgPool;
+use mtapp::{db::DbPool, include_backend_migrations, App, Migration, ReactorState};
+use mtapp_auth::RequireScopes;
 use utoipa::OpenApi;
 
 use crate::{admin, commands::manage_grants, openapi::InternalGrantOpenApi};
@@ -26,7 +25,11 @@ impl App for GrantApp {
         "mtapp-grant"
     }
 
-    fn internal_routes(&mut self, path_prefix: &str) -> Option<Router> {
+    fn dependencies(&self) -> &'static [&'static str] {
+        &["mtapp-user", "mtapp-scope"]
+    }
+
+    fn internal_routes(&mut self, path_prefix: &str) -> Option<Router<ReactorState>> {
         Some(
             Router::new()
                 .route(
@@ -36,16 +39,14 @@ impl App for GrantApp {
                         .delete(admin::batch_delete),
                 )
                 .route(&format!("{}/:grant_id", path_prefix), delete(admin::delete))
-                .layer(ClaimCheck::new(|claims: Option<Claims>| {
-                    if let Some(claims) = claims {
-                        claims.has_scope("superadmin") || claims.has_scope("admin")
-                    } else {
-                        false
-                    }
-                })),
+                .layer(RequireScopes::admin()),
         )
     }
 
+    fn migrations(&mut self) -> Option<Vec<Box<dyn Migration>>> {
+        include_backend_migrations!(postgres: "./migrations", sqlite: "./sqlite_migrations")
+    }
+
     fn clap_def(&self) -> Option<Command> {
         Some(
             Command::default()
@@ -60,7 +61,7 @@ impl App for GrantApp {
     }
 
     async fn clap_run(&mut self, matches: &ArgMatches, ext: &Extensions) {
-        let pool = ext.get::<PgPool>().unwrap().clone();
+        let pool = ext.get::<DbPool>().unwrap().clone();
         let recv_username = matches
             .subcommand()
             .expect("Subcommand is required")
@@ -72,6 +73,6 @@ impl App for GrantApp {
     }
 
     fn internal_openapi(&mut self, _: &str) -> Option<utoipa::openapi::OpenApi> {
-        Some(InternalGrantOpenApi::openapi())
+        Some(RequireScopes::admin().document(InternalGrantOpenApi::openapi()))
     }
 }
@@ -1,12 +1,12 @@
 use dialoguer::MultiSelect;
-use sqlx::PgPool;
 
+use mtapp::db::DbPool;
 use mtapp_scope::Scope;
 use mtapp_user::User;
 
 use crate::{models::Grant, schemas::GrantCreate};
 
-pub async fn manage_grants(pool: PgPool, recv_username: String) {
+pub async fn manage_grants(pool: DbPool, recv_username: String) {
     let user = User::get_by_username(&recv_username, &pool)
         .await
         .expect("Failed to retrieve user.");
@@ -16,7 +16,7 @@ pub enum GrantError {
     DatabaseError(sqlx::Error),
 
     #[json_error(internal)]
-    UnknownConstaintError(Box<sqlx::postgres::PgDatabaseError>),
+    UnknownConstaintError(Box<dyn sqlx::error::DatabaseError>),
 }
 
 impl fmt::Display for GrantError {
@@ -31,10 +31,11 @@ impl From<sqlx::Error> for GrantError {
             sqlx::Error::RowNotFound => GrantError::NotFound,
             sqlx::Error::Database(db_err) => {
                 // It's hacky and should be converted into a more general way possiblity converted to ValidationError
-                let pg_error = db_err.downcast::<sqlx::postgres::PgDatabaseError>();
-                match pg_error.constraint() {
-                    Some("grants_uniq") => GrantError::AlreadyExist,
-                    _ => GrantError::UnknownConstaintError(pg_error),
+                match mtapp::db::constraint(db_err.as_ref()) {
+                    Some("grants_uniq" | "grants.user_id, grants.scope_id") => {
+                        GrantError::AlreadyExist
+                    }
+                    _ => GrantError::UnknownConstaintError(db_err),
                 }
             }
             _ => GrantError::DatabaseError(err),
@@ -1,5 +1,6 @@
+use mtapp::db::{Db, DbQueryBuilder, DbRow};
 use sea_query::{enum_def, Iden};
-use sea_query::{Expr, PostgresQueryBuilder, Query};
+use sea_query::{Expr, Query};
 use sea_query_binder::SqlxBinder;
 use seaqs::{ApplyConds, ApplyFilters, QueryFilter};
 use serde::Serialize;
@@ -7,7 +8,7 @@ use sqlx::types::{
     chrono::{DateTime, Utc},
     Uuid,
 };
-use sqlx::{Error, Executor, FromRow, Postgres, Row};
+use sqlx::{Error, Executor, FromRow, Row};
 use utoipa::ToSchema;
 
 use crate::filters::{GrantDeleteFilter, GrantLookupFilter};
@@ -25,13 +26,22 @@ pub struct Grant {
 #[derive(Iden)]
 struct Grants;
 
+// Owned by mtapp-scope, only the columns needed for joining
+#[derive(Iden)]
+enum Scopes {
+    Table,
+    Id,
+    Name,
+}
+
 impl Grant {
+    #[tracing::instrument(name = "Grant::count", skip_all)]
     pub async fn count<'a, E>(
         filters: &QueryFilter<GrantLookupFilter>,
         con: E,
     ) -> Result<i64, Error>
     where
-        E: Executor<'a, Database = Postgres>,
+        E: Executor<'a, Database = Db>,
     {
         let mut q = Query::select()
             .expr(Expr::asterisk().count())
@@ -42,10 +52,10 @@ impl Grant {
             q = q.apply_conds(filter).to_owned();
         };
 
-        let (sql, args) = q.build_sqlx(PostgresQueryBuilder);
+        let (sql, args) = q.build_sqlx(DbQueryBuilder);
 
         sqlx::query_with(&sql, args)
-            .try_map(|row: sqlx::postgres::PgRow| {
+            .try_map(|row: DbRow| {
                 let count = row.try_get_unchecked::<Option<i64>, _>(0usize)?;
                 Ok(count)
             })
@@ -54,82 +64,97 @@ impl Grant {
             .map(|v| v.unwrap_or(0))
     }
 
+    #[tracing::instrument(name = "Grant::find", skip_all)]
     pub async fn find<'a, E>(
         filters: &QueryFilter<GrantLookupFilter>,
         con: E,
     ) -> Result<Vec<Self>, Error>
     where
-        E: Executor<'a, Database = Postgres>,
+        E: Executor<'a, Database = Db>,
     {
         let (sql, args) = Query::select()
             .expr(Expr::asterisk())
             .from(Grants)
             .to_owned()
             .apply_filters(filters)
-            .build_sqlx(PostgresQueryBuilder);
+            .build_sqlx(DbQueryBuilder);
 
         sqlx::query_as_with(&sql, args).fetch_all(con).await
     }
 
+    #[tracing::instrument(name = "Grant::find_for_user", skip_all)]
     pub(crate) async fn find_for_user<'a, E>(user_id: Uuid, con: E) -> Result<Vec<String>, Error>
     where
-        E: Executor<'a, Database = Postgres>,
+        E: Executor<'a, Database = Db>,
     {
-        Ok(sqlx::query!(
-            "SELECT s.name as scope_name \
-                        FROM scopes s INNER JOIN grants g ON g.scope_id = s.id \
-                        WHERE g.user_id=$1",
-            user_id
-        )
-        .fetch_all(con)
-        .await?
-        .into_iter()
-        .map(|r| r.scope_name)
-        .collect())
+        let (sql, args) = Query::select()
+            .column((Scopes::Table, Scopes::Name))
+            .from(Scopes::Table)
+            .inner_join(
+                Grants,
+                Expr::col((Grants, GrantIden::ScopeId)).equals((Scopes::Table, Scopes::Id)),
+            )
+            .and_where(Expr::col((Grants, GrantIden::UserId)).eq(user_id))
+            .build_sqlx(DbQueryBuilder);
+
+        sqlx::query_with(&sql, args)
+            .try_map(|row: DbRow| row.try_get::<String, _>(0usize))
+            .fetch_all(con)
+            .await
     }
 
+    #[tracing::instrument(name = "Grant::create", skip_all)]
     pub async fn create<'a, E>(grant: GrantCreate, con: E) -> Result<Self, Error>
     where
-        E: Executor<'a, Database = Postgres>,
+        E: Executor<'a, Database = Db>,
     {
-        let id = Uuid::new_v4();
-        sqlx::query_as!(
-            Self,
-            "INSERT INTO grants (id, user_id, scope_id) VALUES ($1, $2, $3) RETURNING *",
-            id,
-            grant.user_id,
-            grant.scope_id
-        )
-        .fetch_one(con)
-        .await
+        let (sql, args) = Query::insert()
+            .into_table(Grants)
+            .columns([GrantIden::Id, GrantIden::UserId, GrantIden::ScopeId])
+            .values_panic([
+                Uuid::new_v4().into(),
+                grant.user_id.into(),
+                grant.scope_id.into(),
+            ])
+            .returning(Query::returning().expr(Expr::asterisk()))
+            .build_sqlx(DbQueryBuilder);
+
+        sqlx::query_as_with(&sql, args).fetch_one(con).await
     }
 
+    #[tracing::instrument(name = "Grant::delete_by_id", skip_all)]
     pub async fn delete_by_id<'a, E>(id: Uuid, con: E) -> Result<Self, Error>
     where
-        E: Executor<'a, Database = Postgres>,
+        E: Executor<'a, Database = Db>,
     {
-        sqlx::query_as!(Self, "DELETE FROM grants WHERE id=$1 RETURNING *", id)
-            .fetch_one(con)
-            .await
+        let (sql, args) = Query::delete()
+            .from_table(Grants)
+            .and_where(Expr::col(GrantIden::Id).eq(id))
+            .returning(Query::returning().expr(Expr::asterisk()))
+            .build_sqlx(DbQueryBuilder);
+
+        sqlx::query_as_with(&sql, args).fetch_one(con).await
     }
 
+    #[tracing::instrument(name = "Grant::delete_by_ids", skip_all)]
     pub async fn delete_by_ids<'a, E>(user_id: Uuid, scope_id: Uuid, con: E) -> Result<Self, Error>
     where
-        E: Executor<'a, Database = Postgres>,
+        E: Executor<'a, Database = Db>,
     {
-        sqlx::query_as!(
-            Self,
-            "DELETE FROM grants WHERE user_id=$1 AND scope_id=$2 RETURNING *",
-            user_id,
-            scope_id
-        )
-        .fetch_one(con)
-        .await
+        let (sql, args) = Query::delete()
+            .from_table(Grants)
+            .and_where(Expr::col(GrantIden::UserId).eq(user_id))
+            .and_where(Expr::col(GrantIden::ScopeId).eq(scope_id))
+            .returning(Query::returning().expr(Expr::asterisk()))
+            .build_sqlx(DbQueryBuilder);
+
+        sqlx::query_as_with(&sql, args).fetch_one(con).await
     }
 
+    #[tracing::instrument(name = "Grant::delete", skip_all)]
     pub async fn delete<'a, E>(filters: &GrantDeleteFilter, con: E) -> Result<Vec<Self>, Error>
     where
-        E: Executor<'a, Database = Postgres>,
+        E: Executor<'a, Database = Db>,
     {
         if filters.is_empty() {
             return Err(Error::RowNotFound);
@@ -140,7 +165,7 @@ impl Grant {
             .to_owned()
             .apply_conds(filters)
             .returning(Query::returning().expr(Expr::asterisk()))
-            .build_sqlx(PostgresQueryBuilder);
+            .build_sqlx(DbQueryBuilder);
 
         sqlx::query_as_with(&sql, args).fetch_all(con).await
     }
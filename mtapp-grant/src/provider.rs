@@ -1,6 +1,7 @@
-use axum::Extension;
+use axum::extract::State;
+use mtapp::db::DbPool;
 use mtapp_auth::{AuthError, GrantProvider};
-use sqlx::{types::Uuid, PgPool};
+use sqlx::types::Uuid;
 
 use crate::models::Grant;
 
@@ -8,12 +9,9 @@ pub struct Provider;
 
 #[axum::async_trait]
 impl GrantProvider for Provider {
-    type Data<S: Sync + Send + 'static> = Extension<PgPool>;
+    type Data = State<DbPool>;
 
-    async fn scopes<S: Send + Sync + 'static>(
-        Extension(pool): &Extension<PgPool>,
-        user_id: Uuid,
-    ) -> Result<Vec<String>, AuthError> {
+    async fn scopes(State(pool): &State<DbPool>, user_id: Uuid) -> Result<Vec<String>, AuthError> {
         Ok(Grant::find_for_user(user_id, pool)
             .await
             .map_err(AuthError::other)?)
@@ -0,0 +1,14 @@
+CREATE TABLE IF NOT EXISTS scopes (
+  id BLOB PRIMARY KEY,
+  name VARCHAR NOT NULL,
+  created_at TIMESTAMP NOT NULL DEFAULT CURRENT_TIMESTAMP,
+  CONSTRAINT name_uniq UNIQUE (name)
+);
+
+INSERT INTO
+  scopes (id, name)
+VALUES
+  (randomblob(16), 'superuser'),
+  (randomblob(16), 'admin'),
+  (randomblob(16), 'confirmed'),
+  (randomblob(16), 'active');
@@ -1,11 +1,10 @@
-use axum::extract::Path;
+use axum::extract::{Path, State};
 use axum::response::IntoResponse;
-use axum::Extension;
 use json_resp::{JsonListMeta, JsonResponse};
 use seaqs::QueryFilter;
 use sqlx::types::Uuid;
-use sqlx::PgPool;
 
+use mtapp::db::DbPool;
 use mtapp::extractors::{oai, Json, Query};
 use mtapp_auth::AuthErrorOai;
 
@@ -35,7 +34,7 @@ type QueryScopeLookupFilter = QueryFilter<ScopeLookupFilter<'static>>;
     )
 )]
 pub async fn list(
-    Extension(pool): Extension<PgPool>,
+    State(pool): State<DbPool>,
     Query(query): Query<QueryFilter<ScopeLookupFilter<'_>>>,
 ) -> impl IntoResponse {
     let scopes = Scope::find(&query, &pool).await?;
@@ -67,7 +66,7 @@ pub async fn list(
     )
 )]
 pub async fn create(
-    Extension(pool): Extension<PgPool>,
+    State(pool): State<DbPool>,
     Json(scope): Json<ScopeCreate>,
 ) -> impl IntoResponse {
     let scope = Scope::create(scope.name, &pool).await?;
@@ -93,7 +92,7 @@ pub async fn create(
     )
 )]
 pub async fn batch_delete(
-    Extension(pool): Extension<PgPool>,
+    State(pool): State<DbPool>,
     Query(query): Query<ScopeDeleteFilter>,
 ) -> impl IntoResponse {
     let scopes = Scope::delete(&query, &pool).await?;
@@ -119,7 +118,7 @@ pub async fn batch_delete(
         ("jwt_token" = [])
     )
 )]
-pub async fn get(id: Path<Uuid>, Extension(pool): Extension<PgPool>) -> impl IntoResponse {
+pub async fn get(id: Path<Uuid>, State(pool): State<DbPool>) -> impl IntoResponse {
     let scope = Scope::get_by_id(*id, &pool).await?;
     Result::<_, ScopeError>::Ok(JsonResponse::with_content(scope))
 }
@@ -143,7 +142,7 @@ pub async fn get(id: Path<Uuid>, Extension(pool): Extension<PgPool>) -> impl Int
         ("jwt_token" = [])
     )
 )]
-pub async fn delete(id: Path<Uuid>, Extension(pool): Extension<PgPool>) -> impl IntoResponse {
+pub async fn delete(id: Path<Uuid>, State(pool): State<DbPool>) -> impl IntoResponse {
     let scope = Scope::delete_by_id(*id, &pool).await?;
     Result::<_, ScopeError>::Ok(JsonResponse::with_content(scope))
 }
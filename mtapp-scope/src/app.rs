@@ -1,6 +1,6 @@
 use axum::{routing::get, Router};
-use mtapp::{include_migrations_dir, App};
-use mtapp_auth::{ClaimCheck, Claims};
+use mtapp::{include_backend_migrations, App, ReactorState};
+use mtapp_auth::RequireScopes;
 use utoipa::OpenApi;
 
 use crate::{admin, openapi::InternalScopeOpenApi};
@@ -19,7 +19,7 @@ impl App for ScopeApp {
         "mtapp-scope"
     }
 
-    fn internal_routes(&mut self, path_prefix: &str) -> Option<Router> {
+    fn internal_routes(&mut self, path_prefix: &str) -> Option<Router<ReactorState>> {
         Some(
             Router::new()
                 .route(
@@ -32,21 +32,15 @@ impl App for ScopeApp {
                     &format!("{}/:scope_id", path_prefix),
                     get(admin::get).delete(admin::delete),
                 )
-                .layer(ClaimCheck::new(|claims: Option<Claims>| {
-                    if let Some(claims) = claims {
-                        claims.has_scope("superadmin") || claims.has_scope("admin")
-                    } else {
-                        false
-                    }
-                })),
+                .layer(RequireScopes::admin()),
         )
     }
 
     fn migrations(&mut self) -> Option<Vec<Box<dyn mtapp::Migration>>> {
-        include_migrations_dir!("./migrations")
+        include_backend_migrations!(postgres: "./migrations", sqlite: "./sqlite_migrations")
     }
 
     fn internal_openapi(&mut self, _: &str) -> Option<utoipa::openapi::OpenApi> {
-        Some(InternalScopeOpenApi::openapi())
+        Some(RequireScopes::admin().document(InternalScopeOpenApi::openapi()))
     }
 }
@@ -16,7 +16,7 @@ pub enum ScopeError {
     DatabaseError(sqlx::Error),
 
     #[json_error(internal)]
-    UnknownConstaintError(Box<sqlx::postgres::PgDatabaseError>),
+    UnknownConstaintError(Box<dyn sqlx::error::DatabaseError>),
 }
 
 impl fmt::Display for ScopeError {
@@ -31,10 +31,9 @@ impl From<sqlx::Error> for ScopeError {
             sqlx::Error::RowNotFound => ScopeError::NotFound,
             sqlx::Error::Database(db_err) => {
                 // It's hacky and should be converted into a more general way possiblity converted to ValidationError
-                let pg_error = db_err.downcast::<sqlx::postgres::PgDatabaseError>();
-                match pg_error.constraint() {
-                    Some("name_uniq") => ScopeError::DuplicateField("name"),
-                    _ => ScopeError::UnknownConstaintError(pg_error),
+                match mtapp::db::constraint(db_err.as_ref()) {
+                    Some("name_uniq" | "scopes.name") => ScopeError::DuplicateField("name"),
+                    _ => ScopeError::UnknownConstaintError(db_err),
                 }
             }
             _ => ScopeError::DatabaseError(err),
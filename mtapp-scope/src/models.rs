@@ -1,5 +1,6 @@
+use mtapp::db::{Db, DbQueryBuilder, DbRow};
 use sea_query::{enum_def, Iden};
-use sea_query::{Expr, PostgresQueryBuilder, Query};
+use sea_query::{Expr, Query};
 use sea_query_binder::SqlxBinder;
 use seaqs::{ApplyConds, ApplyFilters, QueryFilter};
 use serde::Serialize;
@@ -7,7 +8,7 @@ use sqlx::types::{
     chrono::{DateTime, Utc},
     Uuid,
 };
-use sqlx::{Error, Executor, FromRow, Postgres, Row};
+use sqlx::{Error, Executor, FromRow, Row};
 use utoipa::ToSchema;
 
 use crate::filters::{ScopeDeleteFilter, ScopeLookupFilter};
@@ -24,12 +25,13 @@ pub struct Scope {
 struct Scopes;
 
 impl Scope {
+    #[tracing::instrument(name = "Scope::count", skip_all)]
     pub async fn count<'a, E>(
         filters: &QueryFilter<ScopeLookupFilter<'_>>,
         con: E,
     ) -> Result<i64, Error>
     where
-        E: Executor<'a, Database = Postgres>,
+        E: Executor<'a, Database = Db>,
     {
         let mut q = Query::select()
             .expr(Expr::asterisk().count())
@@ -40,10 +42,10 @@ impl Scope {
             q = q.apply_conds(filter).to_owned();
         };
 
-        let (sql, args) = q.build_sqlx(PostgresQueryBuilder);
+        let (sql, args) = q.build_sqlx(DbQueryBuilder);
 
         sqlx::query_with(&sql, args)
-            .try_map(|row: sqlx::postgres::PgRow| {
+            .try_map(|row: DbRow| {
                 let count = row.try_get_unchecked::<Option<i64>, _>(0usize)?;
                 Ok(count)
             })
@@ -52,59 +54,71 @@ impl Scope {
             .map(|v| v.unwrap_or(0))
     }
 
+    #[tracing::instrument(name = "Scope::find", skip_all)]
     pub async fn find<'a, E>(
         filters: &QueryFilter<ScopeLookupFilter<'_>>,
         con: E,
     ) -> Result<Vec<Self>, Error>
     where
-        E: Executor<'a, Database = Postgres>,
+        E: Executor<'a, Database = Db>,
     {
         let (sql, args) = Query::select()
             .expr(Expr::asterisk())
             .from(Scopes)
             .to_owned()
             .apply_filters(filters)
-            .build_sqlx(PostgresQueryBuilder);
+            .build_sqlx(DbQueryBuilder);
 
         sqlx::query_as_with(&sql, args).fetch_all(con).await
     }
 
+    #[tracing::instrument(name = "Scope::get_by_id", skip_all)]
     pub async fn get_by_id<'a, E>(id: Uuid, con: E) -> Result<Self, Error>
     where
-        E: Executor<'a, Database = Postgres>,
+        E: Executor<'a, Database = Db>,
     {
-        sqlx::query_as!(Self, "SELECT * FROM scopes WHERE id=$1", id)
-            .fetch_one(con)
-            .await
+        let (sql, args) = Query::select()
+            .expr(Expr::asterisk())
+            .from(Scopes)
+            .and_where(Expr::col(ScopeIden::Id).eq(id))
+            .build_sqlx(DbQueryBuilder);
+
+        sqlx::query_as_with(&sql, args).fetch_one(con).await
     }
 
+    #[tracing::instrument(name = "Scope::get_by_name", skip_all)]
     pub async fn get_by_name<'a, E>(name: &str, con: E) -> Result<Self, Error>
     where
-        E: Executor<'a, Database = Postgres>,
+        E: Executor<'a, Database = Db>,
     {
-        sqlx::query_as!(Self, "SELECT * FROM scopes WHERE name=$1", name)
-            .fetch_one(con)
-            .await
+        let (sql, args) = Query::select()
+            .expr(Expr::asterisk())
+            .from(Scopes)
+            .and_where(Expr::col(ScopeIden::Name).eq(name))
+            .build_sqlx(DbQueryBuilder);
+
+        sqlx::query_as_with(&sql, args).fetch_one(con).await
     }
 
+    #[tracing::instrument(name = "Scope::create", skip_all)]
     pub async fn create<'a, E>(name: String, con: E) -> Result<Self, Error>
     where
-        E: Executor<'a, Database = Postgres>,
+        E: Executor<'a, Database = Db>,
     {
-        let id = Uuid::new_v4();
-        sqlx::query_as!(
-            Self,
-            "INSERT INTO scopes (id, name) VALUES ($1, $2) RETURNING *",
-            id,
-            name
-        )
-        .fetch_one(con)
-        .await
+        let (sql, args) = Query::insert()
+            .into_table(Scopes)
+            .columns([ScopeIden::Id, ScopeIden::Name])
+            .values_panic([Uuid::new_v4().into(), name.into()])
+            .returning(Query::returning().expr(Expr::asterisk()))
+            .build_sqlx(DbQueryBuilder);
+
+        sqlx::query_as_with(&sql, args).fetch_one(con).await
     }
 
+    #[tracing::instrument(name = "Scope::delete", skip_all)]
     pub async fn delete<'a, E>(filters: &ScopeDeleteFilter, con: E) -> Result<Vec<Self>, Error>
     where
-        E: Executor<'a, Database = Postgres>,
+        E: Executor<'a, Database = Db>,
     {
         if filters.is_empty() {
             return Err(Error::RowNotFound);
@@ -115,26 +129,36 @@ impl Scope {
             .to_owned()
             .apply_conds(filters)
             .returning(Query::returning().expr(Expr::asterisk()))
-            .build_sqlx(PostgresQueryBuilder);
+            .build_sqlx(DbQueryBuilder);
 
         sqlx::query_as_with(&sql, args).fetch_all(con).await
     }
 
+    #[tracing::instrument(name = "Scope::delete_by_id", skip_all)]
     pub async fn delete_by_id<'a, E>(id: Uuid, con: E) -> Result<Self, Error>
     where
-        E: Executor<'a, Database = Postgres>,
+        E: Executor<'a, Database = Db>,
     {
-        sqlx::query_as!(Self, "DELETE FROM scopes WHERE id=$1 RETURNING *", id)
-            .fetch_one(con)
-            .await
+        let (sql, args) = Query::delete()
+            .from_table(Scopes)
+            .and_where(Expr::col(ScopeIden::Id).eq(id))
+            .returning(Query::returning().expr(Expr::asterisk()))
+            .build_sqlx(DbQueryBuilder);
+
+        sqlx::query_as_with(&sql, args).fetch_one(con).await
     }
 
+    #[tracing::instrument(name = "Scope::delete_by_name", skip_all)]
     pub async fn delete_by_name<'a, E>(name: &str, con: E) -> Result<Self, Error>
     where
-        E: Executor<'a, Database = Postgres>,
+        E: Executor<'a, Database = Db>,
     {
-        sqlx::query_as!(Self, "DELETE FROM scopes WHERE name=$1 RETURNING *", name)
-            .fetch_one(con)
-            .await
+        let (sql, args) = Query::delete()
+            .from_table(Scopes)
+            .and_where(Expr::col(ScopeIden::Name).eq(name))
+            .returning(Query::returning().expr(Expr::asterisk()))
+            .build_sqlx(DbQueryBuilder);
+
+        sqlx::query_as_with(&sql, args).fetch_one(con).await
     }
 }
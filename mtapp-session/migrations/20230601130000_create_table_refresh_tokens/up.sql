@@ -0,0 +1,11 @@
+CREATE TABLE IF NOT EXISTS refresh_tokens (
+  token UUID PRIMARY KEY,
+  session_id UUID NOT NULL,
+  parent UUID,
+  rotated_at Timestamp WITH TIME ZONE,
+  created_at Timestamp WITH TIME ZONE NOT NULL DEFAULT now(),
+  CONSTRAINT refresh_tokens_session_id FOREIGN KEY (session_id) REFERENCES sessions (id) ON DELETE CASCADE
+);
+
+INSERT INTO refresh_tokens (token, session_id, created_at)
+SELECT refresh_token, id, created_at FROM sessions;
\ No newline at end of file
@@ -0,0 +1,9 @@
+DELETE FROM sessions;
+
+ALTER TABLE refresh_tokens ALTER COLUMN parent_hash TYPE UUID USING NULL;
+ALTER TABLE refresh_tokens RENAME COLUMN parent_hash TO parent;
+ALTER TABLE refresh_tokens ALTER COLUMN token_hash TYPE UUID USING NULL;
+ALTER TABLE refresh_tokens RENAME COLUMN token_hash TO token;
+
+ALTER TABLE sessions ALTER COLUMN refresh_token_hash TYPE UUID USING NULL;
+ALTER TABLE sessions RENAME COLUMN refresh_token_hash TO refresh_token;
\ No newline at end of file
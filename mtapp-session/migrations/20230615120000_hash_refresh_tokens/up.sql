@@ -0,0 +1,9 @@
+-- The tokens can't be hashed without the app's key, so the existing ones are marked and hashed
+-- by SessionApp on startup
+ALTER TABLE sessions RENAME COLUMN refresh_token TO refresh_token_hash;
+ALTER TABLE sessions ALTER COLUMN refresh_token_hash TYPE VARCHAR USING 'legacy:' || refresh_token_hash::text;
+
+ALTER TABLE refresh_tokens RENAME COLUMN token TO token_hash;
+ALTER TABLE refresh_tokens ALTER COLUMN token_hash TYPE VARCHAR USING 'legacy:' || token_hash::text;
+ALTER TABLE refresh_tokens RENAME COLUMN parent TO parent_hash;
+ALTER TABLE refresh_tokens ALTER COLUMN parent_hash TYPE VARCHAR USING 'legacy:' || parent_hash::text;
\ No newline at end of file
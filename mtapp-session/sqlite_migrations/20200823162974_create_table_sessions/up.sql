@@ -0,0 +1,13 @@
+CREATE TABLE IF NOT EXISTS sessions (
+  id BLOB PRIMARY KEY,
+  user_id BLOB NOT NULL,
+  ip VARCHAR NOT NULL,
+  user_agent VARCHAR NOT NULL,
+  jti BLOB NOT NULL,
+  refresh_token BLOB NOT NULL,
+  last_access_at TIMESTAMP NOT NULL DEFAULT CURRENT_TIMESTAMP,
+  created_at TIMESTAMP NOT NULL DEFAULT CURRENT_TIMESTAMP,
+  CONSTRAINT refresh_token UNIQUE (refresh_token),
+  CONSTRAINT jti_uniq UNIQUE (jti),
+  CONSTRAINT sessions_user_id FOREIGN KEY (user_id) REFERENCES users (id) ON DELETE CASCADE
+);
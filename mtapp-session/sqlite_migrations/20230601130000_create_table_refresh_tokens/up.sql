@@ -0,0 +1,11 @@
+CREATE TABLE IF NOT EXISTS refresh_tokens (
+  token BLOB PRIMARY KEY,
+  session_id BLOB NOT NULL,
+  parent BLOB,
+  rotated_at TIMESTAMP,
+  created_at TIMESTAMP NOT NULL DEFAULT CURRENT_TIMESTAMP,
+  CONSTRAINT refresh_tokens_session_id FOREIGN KEY (session_id) REFERENCES sessions (id) ON DELETE CASCADE
+);
+
+INSERT INTO refresh_tokens (token, session_id, created_at)
+SELECT refresh_token, id, created_at FROM sessions;
\ No newline at end of file
@@ -0,0 +1,53 @@
+-- The tokens can't be hashed without the app's key, so the existing ones are marked and hashed
+-- by SessionApp on startup. The tables are recreated to change the column types
+CREATE TABLE sessions_hashed (
+  id BLOB PRIMARY KEY,
+  user_id BLOB NOT NULL,
+  ip VARCHAR NOT NULL,
+  user_agent VARCHAR NOT NULL,
+  jti BLOB NOT NULL,
+  refresh_token_hash VARCHAR NOT NULL,
+  last_access_at TIMESTAMP NOT NULL DEFAULT CURRENT_TIMESTAMP,
+  created_at TIMESTAMP NOT NULL DEFAULT CURRENT_TIMESTAMP,
+  CONSTRAINT refresh_token UNIQUE (refresh_token_hash),
+  CONSTRAINT jti_uniq UNIQUE (jti),
+  CONSTRAINT sessions_user_id FOREIGN KEY (user_id) REFERENCES users (id) ON DELETE CASCADE
+);
+
+CREATE TABLE refresh_tokens_hashed (
+  token_hash VARCHAR PRIMARY KEY,
+  session_id BLOB NOT NULL,
+  parent_hash VARCHAR,
+  rotated_at TIMESTAMP,
+  created_at TIMESTAMP NOT NULL DEFAULT CURRENT_TIMESTAMP,
+  CONSTRAINT refresh_tokens_session_id FOREIGN KEY (session_id) REFERENCES sessions_hashed (id) ON DELETE CASCADE
+);
+
+-- The uuids are stored as blobs, the clients have them in the hyphenated form
+INSERT INTO sessions_hashed
+SELECT id, user_id, ip, user_agent, jti,
+  'legacy:' || substr(lower(hex(refresh_token)), 1, 8) || '-' || substr(lower(hex(refresh_token)), 9, 4) || '-'
+    || substr(lower(hex(refresh_token)), 13, 4) || '-' || substr(lower(hex(refresh_token)), 17, 4) || '-'
+    || substr(lower(hex(refresh_token)), 21),
+  last_access_at, created_at
+FROM sessions;
+
+INSERT INTO refresh_tokens_hashed
+SELECT
+  'legacy:' || substr(lower(hex(token)), 1, 8) || '-' || substr(lower(hex(token)), 9, 4) || '-'
+    || substr(lower(hex(token)), 13, 4) || '-' || substr(lower(hex(token)), 17, 4) || '-'
+    || substr(lower(hex(token)), 21),
+  session_id,
+  CASE WHEN parent IS NULL THEN NULL ELSE
+    'legacy:' || substr(lower(hex(parent)), 1, 8) || '-' || substr(lower(hex(parent)), 9, 4) || '-'
+      || substr(lower(hex(parent)), 13, 4) || '-' || substr(lower(hex(parent)), 17, 4) || '-'
+      || substr(lower(hex(parent)), 21)
+  END,
+  rotated_at, created_at
+FROM refresh_tokens;
+
+DROP TABLE refresh_tokens;
+DROP TABLE sessions;
+
+ALTER TABLE sessions_hashed RENAME TO sessions;
+ALTER TABLE refresh_tokens_hashed RENAME TO refresh_tokens;
\ No newline at end of file
@@ -1,9 +1,9 @@
-use axum::{response::IntoResponse, Extension};
+use axum::{extract::State, response::IntoResponse};
 use json_resp::{JsonListMeta, JsonResponse};
 use seaqs::QueryFilter;
 use sqlx::types::Uuid;
-use sqlx::PgPool;
 
+use mtapp::db::DbPool;
 use mtapp::extractors::{oai, Path, Query};
 use mtapp_auth::{AuthErrorOai, TokenBlacklist};
 
@@ -34,7 +34,7 @@ type QuerySessionLookupFilter = QueryFilter<SessionLookupFilter>;
 )]
 pub async fn list(
     Query(query): Query<QueryFilter<SessionLookupFilter>>,
-    Extension(pool): Extension<PgPool>,
+    State(pool): State<DbPool>,
 ) -> Result<impl IntoResponse, SessionError> {
     let users = Session::find(&query, &pool).await?;
     let total = Session::count(&query, &pool).await?;
@@ -61,7 +61,7 @@ pub async fn list(
 )]
 pub async fn batch_delete(
     Query(query): Query<SessionDeleteFilter>,
-    Extension(pool): Extension<PgPool>,
+    State(pool): State<DbPool>,
     blacklist: TokenBlacklist,
 ) -> Result<impl IntoResponse, SessionError> {
     let sessions = Session::delete(&query, &pool).await?;
@@ -97,7 +97,7 @@ pub async fn batch_delete(
 )]
 pub async fn get(
     id: Path<Uuid>,
-    Extension(pool): Extension<PgPool>,
+    State(pool): State<DbPool>,
 ) -> Result<impl IntoResponse, SessionError> {
     let user = Session::get_by_id(*id, &pool).await?;
     Ok(JsonResponse::with_content(user))
@@ -125,7 +125,7 @@ pub async fn get(
 pub async fn delete(
     id: Path<Uuid>,
     blacklist: TokenBlacklist,
-    Extension(pool): Extension<PgPool>,
+    State(pool): State<DbPool>,
 ) -> Result<impl IntoResponse, SessionError> {
     let session = Session::delete_by_id(*id, &pool).await?;
 
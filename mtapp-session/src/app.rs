@@ -1,73 +1,209 @@
 // use mtapp_auth::JwtMiddleware;
 
+use std::time::Duration;
+
 use axum::{routing::get, Router};
-use mtapp::include_migrations_dir;
-use mtapp::App;
-use mtapp_auth::ClaimCheck;
-use mtapp_auth::Claims;
+use mtapp::include_backend_migrations;
+use mtapp::settings::{Settings, SettingsError};
+use mtapp::{App, Configuration, ReactorState};
+use mtapp_auth::{RequireScopes, ScopedRoutes};
+use serde::Deserialize;
 use utoipa::OpenApi;
 
 use crate::admin;
 use crate::handlers;
 use crate::openapi::{InternalSessionOpenApi, PublicSessionOpenApi};
+use crate::tasks::{hash_legacy_tokens, purge_sessions};
+use crate::tokens::TokenHasher;
+
+const SESSION_MAX_IDLE: u64 = 30 * 24 * 60 * 60;
+const PURGE_INTERVAL: u64 = 60 * 60;
 
-#[derive(Default, Clone)]
-pub struct SessionApp {}
+/// Registered in the `ReactorState`, so the provider can reject expired sessions
+#[derive(Clone, Copy)]
+pub(crate) struct SessionLifetime {
+    // Sliding, a session not refreshed for this long is expired
+    pub(crate) max_idle: Duration,
+
+    // Absolute, counted from the login
+    pub(crate) max_lifetime: Option<Duration>,
+}
+
+impl Default for SessionLifetime {
+    fn default() -> Self {
+        Self {
+            max_idle: Duration::from_secs(SESSION_MAX_IDLE),
+            max_lifetime: None,
+        }
+    }
+}
+
+/// The `mtapp-session` section of the settings, overriding the values the app is built with
+#[derive(Default, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+struct SessionSettings {
+    token_key: Option<String>,
+
+    // In seconds
+    max_idle: Option<u64>,
+    max_lifetime: Option<u64>,
+}
+
+#[derive(Clone)]
+pub struct SessionApp {
+    lifetime: SessionLifetime,
+
+    // Key used to hash the refresh tokens before storing them
+    token_key: Option<String>,
+
+    // How often to look for stale sessions
+    purge_interval: Duration,
+}
+
+impl Default for SessionApp {
+    fn default() -> Self {
+        Self::new()
+    }
+}
 
 impl SessionApp {
     pub fn new() -> Self {
-        SessionApp {}
+        SessionApp {
+            lifetime: SessionLifetime::default(),
+            token_key: None,
+            purge_interval: Duration::from_secs(PURGE_INTERVAL),
+        }
+    }
+
+    /// Refresh tokens are stored as their HMAC with this key, changing it logs everyone out
+    pub fn token_key(mut self, token_key: &str) -> Self {
+        self.token_key = Some(String::from(token_key));
+        self
+    }
+
+    /// Sessions not refreshed for this long are expired and purged
+    pub fn max_idle(mut self, max_idle: Duration) -> Self {
+        self.lifetime.max_idle = max_idle;
+        self
+    }
+
+    /// Sessions older than this are expired and purged, no matter how often they're refreshed
+    pub fn max_lifetime(mut self, max_lifetime: Duration) -> Self {
+        self.lifetime.max_lifetime = Some(max_lifetime);
+        self
+    }
+
+    pub fn purge_interval(mut self, purge_interval: Duration) -> Self {
+        self.purge_interval = purge_interval;
+        self
     }
 }
 
+#[axum::async_trait(?Send)]
 impl App for SessionApp {
     fn name(&self) -> &'static str {
         "mtapp-session"
     }
 
-    fn public_routes(&mut self, path_prefix: &str) -> Option<Router> {
-        Some(
-            Router::new()
-                .route(&format!("{}/", path_prefix), get(handlers::list))
-                .route(&format!("{}/current", path_prefix), get(handlers::get))
-                .route(
-                    &format!("{}/:session_id", path_prefix),
-                    get(handlers::get).delete(handlers::delete),
-                )
-                .layer(ClaimCheck::new(|claims: Option<Claims>| claims.is_some())),
-        )
+    fn dependencies(&self) -> &'static [&'static str] {
+        &["mtapp-user"]
     }
 
-    fn internal_routes(&mut self, path_prefix: &str) -> Option<Router> {
-        Some(
-            Router::new()
-                .route(
-                    &format!("{}/", path_prefix),
-                    get(admin::list).delete(admin::batch_delete),
-                )
-                .route(
-                    &format!("{}/:session_id", path_prefix),
-                    get(admin::get).delete(admin::delete),
-                )
-                .layer(ClaimCheck::new(|claims: Option<Claims>| {
-                    if let Some(claims) = claims {
-                        claims.has_scope("superadmin") || claims.has_scope("admin")
-                    } else {
-                        false
-                    }
-                })),
-        )
+    fn load_settings(&mut self, settings: &Settings) -> Result<(), SettingsError> {
+        let section: SessionSettings = settings.section(self.name())?;
+
+        if let Some(token_key) = section.token_key.filter(|key| !key.is_empty()) {
+            self.token_key = Some(token_key);
+        }
+        if let Some(max_idle) = section.max_idle {
+            self.lifetime.max_idle = Duration::from_secs(max_idle);
+        }
+        if let Some(max_lifetime) = section.max_lifetime {
+            self.lifetime.max_lifetime = Some(Duration::from_secs(max_lifetime));
+        }
+
+        if self.token_key.is_none() {
+            return Err(SettingsError::invalid(
+                "mtapp-session.token_key",
+                "should be set, or use SessionApp::token_key",
+            ));
+        }
+
+        Ok(())
+    }
+
+    fn secret_settings(&self) -> &'static [&'static str] {
+        &["token_key"]
+    }
+
+    fn configure(&mut self, cfg: &mut Configuration) {
+        // Checked in load_settings, without a hasher the provider rejects every login
+        if let Some(token_key) = &self.token_key {
+            cfg.state(TokenHasher::new(token_key));
+        }
+
+        let lifetime = self.lifetime;
+        let purge_interval = self.purge_interval;
+        cfg.state(lifetime).background_task(move |state, shutdown| {
+            purge_sessions(state, shutdown, lifetime, purge_interval)
+        });
+    }
+
+    async fn on_startup(&mut self, state: &ReactorState) {
+        if let Some(hasher) = state.get::<TokenHasher>() {
+            match hash_legacy_tokens(hasher, state.db()).await {
+                Ok(0) => {}
+                Ok(count) => log::info!("Hashed {} refresh tokens stored in plain", count),
+                Err(e) => log::error!("Failed to hash the stored refresh tokens: {}", e),
+            }
+        }
+    }
+
+    fn public_routes(&mut self, path_prefix: &str) -> Option<Router<ReactorState>> {
+        Some(public_scoped_routes(path_prefix).into_router())
+    }
+
+    fn internal_routes(&mut self, path_prefix: &str) -> Option<Router<ReactorState>> {
+        Some(internal_scoped_routes(path_prefix).into_router())
     }
 
     fn migrations(&mut self) -> Option<Vec<Box<dyn mtapp::Migration>>> {
-        include_migrations_dir!("./migrations")
+        include_backend_migrations!(postgres: "./migrations", sqlite: "./sqlite_migrations")
     }
 
     fn public_openapi(&mut self, _: &str) -> Option<utoipa::openapi::OpenApi> {
-        Some(PublicSessionOpenApi::openapi())
+        Some(public_scoped_routes("").document(PublicSessionOpenApi::openapi()))
     }
 
     fn internal_openapi(&mut self, _: &str) -> Option<utoipa::openapi::OpenApi> {
-        Some(InternalSessionOpenApi::openapi())
+        Some(internal_scoped_routes("").document(InternalSessionOpenApi::openapi()))
     }
 }
+
+fn public_scoped_routes(path_prefix: &str) -> ScopedRoutes {
+    // The clients don't have sessions
+    let user = RequireScopes::authenticated().users_only();
+
+    ScopedRoutes::new(path_prefix)
+        .route("/", get(handlers::list), user.clone())
+        .route("/current", get(handlers::get), user.clone())
+        .route(
+            "/{session_id}",
+            get(handlers::get).delete(handlers::delete),
+            user,
+        )
+}
+
+fn internal_scoped_routes(path_prefix: &str) -> ScopedRoutes {
+    ScopedRoutes::new(path_prefix)
+        .route(
+            "/",
+            get(admin::list).delete(admin::batch_delete),
+            RequireScopes::admin(),
+        )
+        .route(
+            "/{session_id}",
+            get(admin::get).delete(admin::delete),
+            RequireScopes::admin(),
+        )
+}
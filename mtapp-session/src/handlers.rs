@@ -1,7 +1,8 @@
-use axum::{response::IntoResponse, Extension};
+use axum::{extract::State, response::IntoResponse};
 use json_resp::{JsonListMeta, JsonResponse};
-use sqlx::{types::Uuid, PgPool};
+use sqlx::types::Uuid;
 
+use mtapp::db::DbPool;
 use mtapp::extractors::{oai, Path};
 use mtapp_auth::{AuthErrorOai, Claims, TokenBlacklist};
 
@@ -26,7 +27,7 @@ use crate::{
 )]
 pub async fn list(
     claims: Claims,
-    Extension(pool): Extension<PgPool>,
+    State(pool): State<DbPool>,
 ) -> Result<impl IntoResponse, SessionError> {
     let user_id = claims.user_id;
 
@@ -56,7 +57,7 @@ pub async fn list(
 pub async fn get(
     session_id: Option<Path<Uuid>>,
     claims: Claims,
-    Extension(pool): Extension<PgPool>,
+    State(pool): State<DbPool>,
 ) -> Result<impl IntoResponse, SessionError> {
     let user_id = claims.user_id;
 
@@ -92,7 +93,7 @@ pub async fn delete(
     session_id: Path<Uuid>,
     claims: Claims,
     blacklist: TokenBlacklist,
-    Extension(pool): Extension<PgPool>,
+    State(pool): State<DbPool>,
 ) -> Result<impl IntoResponse, SessionError> {
     blacklist
         .blacklist(claims.jti)
@@ -7,6 +7,8 @@ mod models;
 mod openapi;
 mod provider;
 mod schemas;
+mod tasks;
+mod tokens;
 
 pub use app::SessionApp;
 pub use provider::Provider;
@@ -1,4 +1,5 @@
-use sea_query::{enum_def, Expr, Iden, PostgresQueryBuilder, Query};
+use mtapp::db::{Db, DbQueryBuilder, DbRow};
+use sea_query::{enum_def, Cond, Expr, Iden, Query, UnionType};
 use sea_query_binder::SqlxBinder;
 use seaqs::{ApplyConds, ApplyFilters, QueryFilter};
 use serde::Serialize;
@@ -6,9 +7,10 @@ use sqlx::types::{
     chrono::{DateTime, Utc},
     Uuid,
 };
-use sqlx::{Error, Executor, FromRow, Postgres, Row};
+use sqlx::{Error, Executor, FromRow, Row};
 use utoipa::ToSchema;
 
+use crate::app::SessionLifetime;
 use crate::filters::{SessionDeleteFilter, SessionLookupFilter};
 
 #[derive(Serialize, FromRow, ToSchema)]
@@ -21,7 +23,7 @@ pub struct Session {
     #[serde(skip)]
     pub(crate) jti: Uuid,
     #[serde(skip)]
-    pub(crate) refresh_token: Uuid,
+    pub(crate) refresh_token_hash: String,
     last_access_at: DateTime<Utc>,
     created_at: DateTime<Utc>,
 }
@@ -30,12 +32,23 @@ pub struct Session {
 struct Sessions;
 
 impl Session {
+    /// Whether the session is idle or lived longer than allowed
+    pub(crate) fn is_expired(&self, lifetime: &SessionLifetime) -> bool {
+        let elapsed = |since: DateTime<Utc>| (Utc::now() - since).to_std().unwrap_or_default();
+
+        elapsed(self.last_access_at) > lifetime.max_idle
+            || lifetime.max_lifetime.map_or(false, |max_lifetime| {
+                elapsed(self.created_at) > max_lifetime
+            })
+    }
+
+    #[tracing::instrument(name = "Session::count", skip_all)]
     pub async fn count<'a, E>(
         filters: &QueryFilter<SessionLookupFilter>,
         con: E,
     ) -> Result<i64, Error>
     where
-        E: Executor<'a, Database = Postgres>,
+        E: Executor<'a, Database = Db>,
     {
         let mut q = Query::select()
             .expr(Expr::asterisk().count())
@@ -46,10 +59,10 @@ impl Session {
             q = q.apply_conds(filter).to_owned();
         };
 
-        let (sql, args) = q.build_sqlx(PostgresQueryBuilder);
+        let (sql, args) = q.build_sqlx(DbQueryBuilder);
 
         sqlx::query_with(&sql, args)
-            .try_map(|row: sqlx::postgres::PgRow| {
+            .try_map(|row: DbRow| {
                 let count = row.try_get_unchecked::<Option<i64>, _>(0usize)?;
                 Ok(count)
             })
@@ -58,145 +71,187 @@ impl Session {
             .map(|v| v.unwrap_or(0))
     }
 
+    #[tracing::instrument(name = "Session::count_by_user", skip_all)]
     pub(crate) async fn count_by_user<'a, E>(user_id: Uuid, con: E) -> Result<i64, Error>
     where
-        E: Executor<'a, Database = Postgres>,
+        E: Executor<'a, Database = Db>,
     {
-        Ok(
-            sqlx::query!("SELECT COUNT(*) FROM sessions WHERE user_id = $1", user_id)
-                .fetch_one(con)
-                .await?
-                .count
-                .unwrap_or(0),
-        )
+        let (sql, args) = Query::select()
+            .expr(Expr::asterisk().count())
+            .from(Sessions)
+            .and_where(Expr::col(SessionIden::UserId).eq(user_id))
+            .build_sqlx(DbQueryBuilder);
+
+        sqlx::query_with(&sql, args)
+            .try_map(|row: DbRow| {
+                let count = row.try_get_unchecked::<Option<i64>, _>(0usize)?;
+                Ok(count)
+            })
+            .fetch_one(con)
+            .await
+            .map(|v| v.unwrap_or(0))
     }
 
+    #[tracing::instrument(name = "Session::find", skip_all)]
     pub async fn find<'a, E>(
         filters: &QueryFilter<SessionLookupFilter>,
         con: E,
     ) -> Result<Vec<Self>, Error>
     where
-        E: Executor<'a, Database = Postgres>,
+        E: Executor<'a, Database = Db>,
     {
         let (sql, args) = Query::select()
             .expr(Expr::asterisk())
             .from(Sessions)
             .to_owned()
             .apply_filters(filters)
-            .build_sqlx(PostgresQueryBuilder);
+            .build_sqlx(DbQueryBuilder);
 
         sqlx::query_as_with(&sql, args).fetch_all(con).await
     }
 
+    #[tracing::instrument(name = "Session::find_by_user", skip_all)]
     pub(crate) async fn find_by_user<'a, E>(user_id: Uuid, con: E) -> Result<Vec<Self>, Error>
     where
-        E: Executor<'a, Database = Postgres>,
+        E: Executor<'a, Database = Db>,
     {
-        sqlx::query_as!(Self, "SELECT * FROM sessions WHERE user_id=$1", user_id)
-            .fetch_all(con)
-            .await
+        let (sql, args) = Query::select()
+            .expr(Expr::asterisk())
+            .from(Sessions)
+            .and_where(Expr::col(SessionIden::UserId).eq(user_id))
+            .build_sqlx(DbQueryBuilder);
+
+        sqlx::query_as_with(&sql, args).fetch_all(con).await
     }
 
+    #[tracing::instrument(name = "Session::get_by_id", skip_all)]
     pub(crate) async fn get_by_id<'a, E>(id: Uuid, con: E) -> Result<Self, Error>
     where
-        E: Executor<'a, Database = Postgres>,
+        E: Executor<'a, Database = Db>,
     {
-        sqlx::query_as!(Self, "SELECT * FROM sessions WHERE id = $1", id)
-            .fetch_one(con)
-            .await
+        let (sql, args) = Query::select()
+            .expr(Expr::asterisk())
+            .from(Sessions)
+            .and_where(Expr::col(SessionIden::Id).eq(id))
+            .build_sqlx(DbQueryBuilder);
+
+        sqlx::query_as_with(&sql, args).fetch_one(con).await
     }
 
+    #[tracing::instrument(name = "Session::get_by_id_for_user", skip_all)]
     pub(crate) async fn get_by_id_for_user<'a, E>(
         user_id: Uuid,
         id: Uuid,
         con: E,
     ) -> Result<Self, Error>
     where
-        E: Executor<'a, Database = Postgres>,
+        E: Executor<'a, Database = Db>,
     {
-        sqlx::query_as!(
-            Self,
-            "SELECT * FROM sessions WHERE user_id = $1 AND id = $2",
-            user_id,
-            id
-        )
-        .fetch_one(con)
-        .await
+        let (sql, args) = Query::select()
+            .expr(Expr::asterisk())
+            .from(Sessions)
+            .and_where(Expr::col(SessionIden::UserId).eq(user_id))
+            .and_where(Expr::col(SessionIden::Id).eq(id))
+            .build_sqlx(DbQueryBuilder);
+
+        sqlx::query_as_with(&sql, args).fetch_one(con).await
     }
 
+    #[tracing::instrument(name = "Session::get_by_jti", skip_all)]
     pub(crate) async fn get_by_jti<'a, E>(jti: Uuid, con: E) -> Result<Self, Error>
     where
-        E: Executor<'a, Database = Postgres>,
+        E: Executor<'a, Database = Db>,
     {
-        sqlx::query_as!(Self, "SELECT * FROM sessions WHERE jti = $1", jti)
-            .fetch_one(con)
-            .await
+        let (sql, args) = Query::select()
+            .expr(Expr::asterisk())
+            .from(Sessions)
+            .and_where(Expr::col(SessionIden::Jti).eq(jti))
+            .build_sqlx(DbQueryBuilder);
+
+        sqlx::query_as_with(&sql, args).fetch_one(con).await
     }
 
+    #[tracing::instrument(name = "Session::get_by_refresh_token", skip_all)]
     pub(crate) async fn get_by_refresh_token<'a, E>(
-        refresh_token: Uuid,
+        refresh_token_hash: &str,
         con: E,
     ) -> Result<Self, Error>
     where
-        E: Executor<'a, Database = Postgres>,
+        E: Executor<'a, Database = Db>,
     {
-        sqlx::query_as!(
-            Self,
-            "SELECT * FROM sessions WHERE refresh_token = $1",
-            refresh_token
-        )
-        .fetch_one(con)
-        .await
+        let (sql, args) = Query::select()
+            .expr(Expr::asterisk())
+            .from(Sessions)
+            .and_where(Expr::col(SessionIden::RefreshTokenHash).eq(refresh_token_hash))
+            .build_sqlx(DbQueryBuilder);
+
+        sqlx::query_as_with(&sql, args).fetch_one(con).await
     }
 
+    #[tracing::instrument(name = "Session::create", skip_all)]
     pub(crate) async fn create<'a, E>(
         user_id: Uuid,
         ip: String,
         user_agent: String,
         jti: Uuid,
-        refresh_token: Uuid,
+        refresh_token_hash: &str,
         con: E,
     ) -> Result<Self, Error>
     where
-        E: Executor<'a, Database = Postgres>,
-    {
-        sqlx::query_as!(
-            Self,
-            "INSERT INTO sessions (id, user_id, ip, user_agent, jti, refresh_token) VALUES ($1, $2, $3, $4, $5, $6)
-                RETURNING *",
-            Uuid::new_v4(),
-            user_id,
-            ip,
-            user_agent,
-            jti,
-            refresh_token
-        )
-        .fetch_one(con)
-        .await
-    }
-
-    pub(crate) async fn set_jti<'a, E>(
-        refresh_token: Uuid,
+        E: Executor<'a, Database = Db>,
+    {
+        let (sql, args) = Query::insert()
+            .into_table(Sessions)
+            .columns([
+                SessionIden::Id,
+                SessionIden::UserId,
+                SessionIden::Ip,
+                SessionIden::UserAgent,
+                SessionIden::Jti,
+                SessionIden::RefreshTokenHash,
+            ])
+            .values_panic([
+                Uuid::new_v4().into(),
+                user_id.into(),
+                ip.into(),
+                user_agent.into(),
+                jti.into(),
+                refresh_token_hash.into(),
+            ])
+            .returning(Query::returning().expr(Expr::asterisk()))
+            .build_sqlx(DbQueryBuilder);
+
+        sqlx::query_as_with(&sql, args).fetch_one(con).await
+    }
+
+    /// Replace the session's jti and refresh token once its refresh token is rotated, which
+    /// also counts as an access
+    #[tracing::instrument(name = "Session::rotate", skip_all)]
+    pub(crate) async fn rotate<'a, E>(
+        id: Uuid,
         jti: Uuid,
+        refresh_token_hash: &str,
         con: E,
     ) -> Result<Self, Error>
     where
-        E: Executor<'a, Database = Postgres>,
+        E: Executor<'a, Database = Db>,
     {
-        sqlx::query_as!(
-            Self,
-            "UPDATE sessions SET jti=$1 WHERE refresh_token=$2
-                RETURNING *",
-            jti,
-            refresh_token
-        )
-        .fetch_one(con)
-        .await
+        let (sql, args) = Query::update()
+            .table(Sessions)
+            .value(SessionIden::Jti, jti)
+            .value(SessionIden::RefreshTokenHash, refresh_token_hash)
+            .value(SessionIden::LastAccessAt, Utc::now())
+            .and_where(Expr::col(SessionIden::Id).eq(id))
+            .returning(Query::returning().expr(Expr::asterisk()))
+            .build_sqlx(DbQueryBuilder);
+
+        sqlx::query_as_with(&sql, args).fetch_one(con).await
     }
 
+    #[tracing::instrument(name = "Session::delete", skip_all)]
     pub async fn delete<'a, E>(filters: &SessionDeleteFilter, con: E) -> Result<Vec<Self>, Error>
     where
-        E: Executor<'a, Database = Postgres>,
+        E: Executor<'a, Database = Db>,
     {
         if filters.is_empty() {
             return Err(Error::RowNotFound);
@@ -207,45 +262,222 @@ impl Session {
             .to_owned()
             .apply_conds(filters)
             .returning(Query::returning().expr(Expr::asterisk()))
-            .build_sqlx(PostgresQueryBuilder);
+            .build_sqlx(DbQueryBuilder);
 
         sqlx::query_as_with(&sql, args).fetch_all(con).await
     }
 
+    #[tracing::instrument(name = "Session::delete_by_id", skip_all)]
     pub(crate) async fn delete_by_id<'a, E>(id: Uuid, con: E) -> Result<Self, Error>
     where
-        E: Executor<'a, Database = Postgres>,
+        E: Executor<'a, Database = Db>,
     {
-        sqlx::query_as!(Self, "DELETE FROM sessions WHERE id = $1 RETURNING *", id)
-            .fetch_one(con)
-            .await
+        let (sql, args) = Query::delete()
+            .from_table(Sessions)
+            .and_where(Expr::col(SessionIden::Id).eq(id))
+            .returning(Query::returning().expr(Expr::asterisk()))
+            .build_sqlx(DbQueryBuilder);
+
+        sqlx::query_as_with(&sql, args).fetch_one(con).await
     }
 
+    #[tracing::instrument(name = "Session::delete_by_id_for_user", skip_all)]
     pub(crate) async fn delete_by_id_for_user<'a, E>(
         user_id: Uuid,
         id: Uuid,
         con: E,
     ) -> Result<Self, Error>
     where
-        E: Executor<'a, Database = Postgres>,
+        E: Executor<'a, Database = Db>,
     {
-        sqlx::query_as!(
-            Self,
-            "DELETE FROM sessions WHERE user_id = $1 AND id = $2 RETURNING *",
-            user_id,
-            id
-        )
-        .fetch_one(con)
-        .await
+        let (sql, args) = Query::delete()
+            .from_table(Sessions)
+            .and_where(Expr::col(SessionIden::UserId).eq(user_id))
+            .and_where(Expr::col(SessionIden::Id).eq(id))
+            .returning(Query::returning().expr(Expr::asterisk()))
+            .build_sqlx(DbQueryBuilder);
+
+        sqlx::query_as_with(&sql, args).fetch_one(con).await
     }
 
+    /// Delete the sessions not accessed since `before` or created before `created_before`,
+    /// returning how many were deleted
+    #[tracing::instrument(name = "Session::delete_stale", skip_all)]
+    pub(crate) async fn delete_stale<'a, E>(
+        before: DateTime<Utc>,
+        created_before: Option<DateTime<Utc>>,
+        con: E,
+    ) -> Result<u64, Error>
+    where
+        E: Executor<'a, Database = Db>,
+    {
+        let (sql, args) = Query::delete()
+            .from_table(Sessions)
+            .cond_where(
+                Cond::any()
+                    .add(Expr::col(SessionIden::LastAccessAt).lt(before))
+                    .add_option(created_before.map(|created_before| {
+                        Expr::col(SessionIden::CreatedAt).lt(created_before)
+                    })),
+            )
+            .build_sqlx(DbQueryBuilder);
+
+        sqlx::query_with(&sql, args)
+            .execute(con)
+            .await
+            .map(|res| res.rows_affected())
+    }
+
+    #[tracing::instrument(name = "Session::delete_by_jti", skip_all)]
     pub(crate) async fn delete_by_jti<'a, E>(jti: Uuid, con: E) -> Result<Uuid, Error>
     where
-        E: Executor<'a, Database = Postgres>,
+        E: Executor<'a, Database = Db>,
     {
-        sqlx::query!("DELETE FROM sessions WHERE jti = $1 RETURNING id", jti)
+        let (sql, args) = Query::delete()
+            .from_table(Sessions)
+            .and_where(Expr::col(SessionIden::Jti).eq(jti))
+            .returning(Query::returning().column(SessionIden::Id))
+            .build_sqlx(DbQueryBuilder);
+
+        sqlx::query_with(&sql, args)
+            .try_map(|row: DbRow| row.try_get::<Uuid, _>(0usize))
             .fetch_one(con)
             .await
-            .map(|r| r.id)
+    }
+}
+
+/// Hashes of every refresh token issued for a session, each one pointing to the token it was
+/// rotated from
+#[derive(FromRow)]
+pub(crate) struct RefreshToken {
+    pub(crate) session_id: Uuid,
+}
+
+/// Marks the tokens stored before they were hashed, see `hash_legacy_tokens`
+pub(crate) const LEGACY_PREFIX: &str = "legacy:";
+
+#[derive(Iden)]
+enum RefreshTokens {
+    Table,
+    TokenHash,
+    SessionId,
+    ParentHash,
+    RotatedAt,
+}
+
+impl RefreshToken {
+    #[tracing::instrument(name = "RefreshToken::get", skip_all)]
+    pub(crate) async fn get<'a, E>(token_hash: &str, con: E) -> Result<Self, Error>
+    where
+        E: Executor<'a, Database = Db>,
+    {
+        let (sql, args) = Query::select()
+            .column(RefreshTokens::SessionId)
+            .from(RefreshTokens::Table)
+            .and_where(Expr::col(RefreshTokens::TokenHash).eq(token_hash))
+            .build_sqlx(DbQueryBuilder);
+
+        sqlx::query_as_with(&sql, args).fetch_one(con).await
+    }
+
+    #[tracing::instrument(name = "RefreshToken::create", skip_all)]
+    pub(crate) async fn create<'a, E>(
+        token_hash: &str,
+        session_id: Uuid,
+        parent_hash: Option<&str>,
+        con: E,
+    ) -> Result<(), Error>
+    where
+        E: Executor<'a, Database = Db>,
+    {
+        let (sql, args) = Query::insert()
+            .into_table(RefreshTokens::Table)
+            .columns([
+                RefreshTokens::TokenHash,
+                RefreshTokens::SessionId,
+                RefreshTokens::ParentHash,
+            ])
+            .values_panic([token_hash.into(), session_id.into(), parent_hash.into()])
+            .build_sqlx(DbQueryBuilder);
+
+        sqlx::query_with(&sql, args).execute(con).await.map(|_| ())
+    }
+
+    /// Mark the token as rotated, returns false if it was already rotated
+    #[tracing::instrument(name = "RefreshToken::mark_rotated", skip_all)]
+    pub(crate) async fn mark_rotated<'a, E>(token_hash: &str, con: E) -> Result<bool, Error>
+    where
+        E: Executor<'a, Database = Db>,
+    {
+        let (sql, args) = Query::update()
+            .table(RefreshTokens::Table)
+            .value(RefreshTokens::RotatedAt, Utc::now())
+            .and_where(Expr::col(RefreshTokens::TokenHash).eq(token_hash))
+            .and_where(Expr::col(RefreshTokens::RotatedAt).is_null())
+            .build_sqlx(DbQueryBuilder);
+
+        sqlx::query_with(&sql, args)
+            .execute(con)
+            .await
+            .map(|res| res.rows_affected() == 1)
+    }
+
+    /// Tokens kept from before they were hashed, with the `LEGACY_PREFIX`. Sessions created
+    /// before the tokens were tracked only have theirs in the sessions table
+    #[tracing::instrument(name = "RefreshToken::find_legacy", skip_all)]
+    pub(crate) async fn find_legacy<'a, E>(con: E) -> Result<Vec<String>, Error>
+    where
+        E: Executor<'a, Database = Db>,
+    {
+        let legacy = format!("{}%", LEGACY_PREFIX);
+        let (sql, args) = Query::select()
+            .column(RefreshTokens::TokenHash)
+            .from(RefreshTokens::Table)
+            .and_where(Expr::col(RefreshTokens::TokenHash).like(&legacy))
+            .union(
+                UnionType::Distinct,
+                Query::select()
+                    .column(SessionIden::RefreshTokenHash)
+                    .from(Sessions)
+                    .and_where(Expr::col(SessionIden::RefreshTokenHash).like(&legacy))
+                    .to_owned(),
+            )
+            .build_sqlx(DbQueryBuilder);
+
+        sqlx::query_with(&sql, args)
+            .try_map(|row: DbRow| row.try_get::<String, _>(0usize))
+            .fetch_all(con)
+            .await
+    }
+
+    /// Replace a stored token everywhere it's referenced
+    #[tracing::instrument(name = "RefreshToken::replace_hash", skip_all)]
+    pub(crate) async fn replace_hash(
+        old_hash: &str,
+        new_hash: &str,
+        con: &mut <Db as sqlx::Database>::Connection,
+    ) -> Result<(), Error> {
+        let (sql, args) = Query::update()
+            .table(Sessions)
+            .value(SessionIden::RefreshTokenHash, new_hash)
+            .and_where(Expr::col(SessionIden::RefreshTokenHash).eq(old_hash))
+            .build_sqlx(DbQueryBuilder);
+        sqlx::query_with(&sql, args).execute(&mut *con).await?;
+
+        let (sql, args) = Query::update()
+            .table(RefreshTokens::Table)
+            .value(RefreshTokens::TokenHash, new_hash)
+            .and_where(Expr::col(RefreshTokens::TokenHash).eq(old_hash))
+            .build_sqlx(DbQueryBuilder);
+        sqlx::query_with(&sql, args).execute(&mut *con).await?;
+
+        let (sql, args) = Query::update()
+            .table(RefreshTokens::Table)
+            .value(RefreshTokens::ParentHash, new_hash)
+            .and_where(Expr::col(RefreshTokens::ParentHash).eq(old_hash))
+            .build_sqlx(DbQueryBuilder);
+        sqlx::query_with(&sql, args).execute(&mut *con).await?;
+
+        Ok(())
     }
 }
@@ -1,9 +1,12 @@
-use axum::{headers::UserAgent, Extension, TypedHeader};
+use axum::{extract::State, headers::UserAgent, TypedHeader};
 use axum_client_ip::InsecureClientIp;
-use mtapp_auth::{AuthError, SessionProvider};
-use sqlx::{types::Uuid, PgPool};
+use mtapp::ReactorState;
+use mtapp_auth::{AuthError, RefreshOutcome, SessionProvider};
+use sqlx::types::Uuid;
 
-use crate::models::Session;
+use crate::app::SessionLifetime;
+use crate::models::{RefreshToken, Session};
+use crate::tokens::TokenHasher;
 
 fn extract_error(err: sqlx::Error) -> AuthError {
     match err {
@@ -11,73 +14,129 @@ fn extract_error(err: sqlx::Error) -> AuthError {
         _ => AuthError::DatabaseError(err),
     }
 }
+
+fn lifetime(state: &ReactorState) -> SessionLifetime {
+    // Missing when the `SessionApp` isn't mounted
+    state.get::<SessionLifetime>().copied().unwrap_or_default()
+}
+
+fn hasher(state: &ReactorState) -> Result<&TokenHasher, AuthError> {
+    // Missing when the `SessionApp` isn't mounted
+    state.get::<TokenHasher>().ok_or(AuthError::Configuration)
+}
+
 pub struct Provider;
 
 #[axum::async_trait]
 impl SessionProvider for Provider {
-    type Data<S: Send + Sync + 'static> = (
-        Extension<PgPool>,
+    type Data = (
+        State<ReactorState>,
         TypedHeader<UserAgent>,
         Option<InsecureClientIp>,
     );
 
-    async fn make<S: Send + Sync + 'static>(
-        (Extension(pool), TypedHeader(user_agent), ip): &Self::Data<S>,
+    async fn make(
+        (State(state), TypedHeader(user_agent), ip): &Self::Data,
         user_id: Uuid,
     ) -> Result<(Uuid, String), AuthError> {
+        let (refresh_token, hash) = hasher(state)?.generate();
+
+        let mut tx = state.db().begin().await.map_err(AuthError::DatabaseError)?;
+
         let session = Session::create(
             user_id,
             ip.as_ref().map(|v| v.0.to_string()).unwrap_or_default(),
             user_agent.to_string(),
             Uuid::new_v4(),
-            Uuid::new_v4(),
-            pool,
+            &hash,
+            &mut tx,
         )
         .await
         .map_err(extract_error)?;
-        Ok((session.jti, session.refresh_token.to_string()))
+        RefreshToken::create(&hash, session.id, None, &mut tx)
+            .await
+            .map_err(extract_error)?;
+
+        tx.commit().await.map_err(AuthError::DatabaseError)?;
+        Ok((session.jti, refresh_token))
     }
 
-    async fn find<S: Send + Sync + 'static>(
-        (Extension(pool), _, _): &Self::Data<S>,
+    async fn find(
+        (State(state), _, _): &Self::Data,
         refresh_token: &str,
     ) -> Result<(Uuid, Uuid), AuthError>
     where
         Self: Sized,
     {
-        let session = Session::get_by_refresh_token(
-            refresh_token.parse().map_err(|_| AuthError::BadToken)?,
-            pool,
-        )
-        .await
-        .map_err(extract_error)?;
+        let hash = hasher(state)?.hash(refresh_token);
+        let session = Session::get_by_refresh_token(&hash, state.db())
+            .await
+            .map_err(extract_error)?;
+
+        if session.is_expired(&lifetime(state)) {
+            return Err(AuthError::BadToken);
+        }
 
         Ok((session.jti, session.user_id))
     }
 
-    async fn reset_jti<S: Send + Sync + 'static>(
-        (Extension(pool), _, _): &Self::Data<S>,
+    async fn rotate(
+        (State(state), _, _): &Self::Data,
         refresh_token: &str,
-    ) -> Result<Uuid, AuthError> {
-        let new_jti = Uuid::new_v4();
-        Session::set_jti(
-            refresh_token.parse().map_err(|_| AuthError::BadToken)?,
-            new_jti,
-            pool,
-        )
-        .await
-        .map_err(extract_error)?;
-        Ok(new_jti)
+    ) -> Result<RefreshOutcome, AuthError> {
+        let hasher = hasher(state)?;
+        let hash = hasher.hash(refresh_token);
+
+        let mut tx = state.db().begin().await.map_err(AuthError::DatabaseError)?;
+
+        let stored = RefreshToken::get(&hash, &mut tx)
+            .await
+            .map_err(extract_error)?;
+        let session = Session::get_by_id(stored.session_id, &mut tx)
+            .await
+            .map_err(extract_error)?;
+
+        if session.is_expired(&lifetime(state)) {
+            return Err(AuthError::BadToken);
+        }
+
+        // Only the first one to mark the token wins, any other use of it is a reuse
+        let first_use = session.refresh_token_hash == hash
+            && RefreshToken::mark_rotated(&hash, &mut tx)
+                .await
+                .map_err(extract_error)?;
+        if !first_use {
+            // Deleting the session removes every token in its family too
+            Session::delete_by_jti(session.jti, &mut tx)
+                .await
+                .map_err(extract_error)?;
+            tx.commit().await.map_err(AuthError::DatabaseError)?;
+            return Ok(RefreshOutcome::Reused { jti: session.jti });
+        }
+
+        let (new_token, new_hash) = hasher.generate();
+        RefreshToken::create(&new_hash, session.id, Some(&hash), &mut tx)
+            .await
+            .map_err(extract_error)?;
+        let rotated = Session::rotate(session.id, Uuid::new_v4(), &new_hash, &mut tx)
+            .await
+            .map_err(extract_error)?;
+
+        tx.commit().await.map_err(AuthError::DatabaseError)?;
+
+        Ok(RefreshOutcome::Rotated {
+            user_id: rotated.user_id,
+            previous_jti: session.jti,
+            jti: rotated.jti,
+            refresh_token: new_token,
+        })
     }
 
-    async fn delete_by_jti<S: Send + Sync + 'static>(
-        (Extension(pool), _, _): &Self::Data<S>,
-        jti: Uuid,
-    ) -> Result<(), AuthError>
+    async fn delete_by_jti((State(state), _, _): &Self::Data, jti: Uuid) -> Result<(), AuthError>
     where
         Self: Sized,
     {
-        Session::delete_by_jti(jti, pool)
+        Session::delete_by_jti(jti, state.db())
             .await
             .map_err(extract_error)?;
         Ok(())
@@ -0,0 +1,57 @@
+use std::time::Duration;
+
+use mtapp::db::DbPool;
+use mtapp::{ReactorState, Shutdown};
+use sqlx::types::chrono::{self, Utc};
+
+use crate::app::SessionLifetime;
+use crate::models::{RefreshToken, Session, LEGACY_PREFIX};
+use crate::tokens::TokenHasher;
+
+/// Periodically delete the expired sessions, see `SessionLifetime`
+pub(crate) async fn purge_sessions(
+    state: ReactorState,
+    mut shutdown: Shutdown,
+    lifetime: SessionLifetime,
+    every: Duration,
+) {
+    let max_idle =
+        chrono::Duration::from_std(lifetime.max_idle).expect("Max idle time is out of range");
+    let max_lifetime = lifetime.max_lifetime.map(|max_lifetime| {
+        chrono::Duration::from_std(max_lifetime).expect("Max lifetime is out of range")
+    });
+    let mut interval = tokio::time::interval(every);
+
+    loop {
+        tokio::select! {
+            _ = interval.tick() => {}
+            _ = shutdown.wait() => break,
+        }
+
+        let now = Utc::now();
+        let created_before = max_lifetime.map(|max_lifetime| now - max_lifetime);
+        match Session::delete_stale(now - max_idle, created_before, state.db()).await {
+            Ok(0) => {}
+            Ok(count) => log::info!("Purged {} stale sessions", count),
+            Err(e) => log::error!("Failed to purge stale sessions: {}", e),
+        }
+    }
+}
+
+/// Hash the refresh tokens stored in plain before the hashes were introduced, returns how many
+/// were hashed
+pub(crate) async fn hash_legacy_tokens(
+    hasher: &TokenHasher,
+    pool: &DbPool,
+) -> Result<usize, sqlx::Error> {
+    let mut tx = pool.begin().await?;
+
+    let tokens = RefreshToken::find_legacy(&mut tx).await?;
+    for stored in &tokens {
+        let token = stored.trim_start_matches(LEGACY_PREFIX);
+        RefreshToken::replace_hash(stored, &hasher.hash(token), &mut tx).await?;
+    }
+
+    tx.commit().await?;
+    Ok(tokens.len())
+}
@@ -0,0 +1,33 @@
+use base64::{engine::general_purpose::URL_SAFE_NO_PAD, Engine};
+use ring::{
+    hmac,
+    rand::{SecureRandom, SystemRandom},
+};
+
+/// Refresh tokens are random strings handed to the client once, only their keyed hash is stored.
+///
+/// Registered in the `ReactorState` by `SessionApp`
+#[derive(Clone)]
+pub(crate) struct TokenHasher(hmac::Key);
+
+impl TokenHasher {
+    pub(crate) fn new(key: &str) -> Self {
+        Self(hmac::Key::new(hmac::HMAC_SHA256, key.as_bytes()))
+    }
+
+    /// Generate a new token, returns (token, hash)
+    pub(crate) fn generate(&self) -> (String, String) {
+        let mut bytes = [0u8; 32];
+        SystemRandom::new()
+            .fill(&mut bytes)
+            .expect("Failed to generate a refresh token");
+
+        let token = URL_SAFE_NO_PAD.encode(bytes);
+        let hash = self.hash(&token);
+        (token, hash)
+    }
+
+    pub(crate) fn hash(&self, token: &str) -> String {
+        URL_SAFE_NO_PAD.encode(hmac::sign(&self.0, token.as_bytes()))
+    }
+}
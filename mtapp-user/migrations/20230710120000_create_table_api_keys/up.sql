@@ -0,0 +1,15 @@
+CREATE TABLE IF NOT EXISTS api_keys (
+  id UUID PRIMARY KEY,
+  user_id UUID NOT NULL,
+  name VARCHAR NOT NULL,
+  prefix VARCHAR NOT NULL,
+  key_hash VARCHAR NOT NULL,
+  scopes VARCHAR NOT NULL DEFAULT '',
+  last_used_at Timestamp WITH TIME ZONE,
+  expires_at Timestamp WITH TIME ZONE,
+  created_at Timestamp WITH TIME ZONE NOT NULL DEFAULT now(),
+  CONSTRAINT api_key_prefix_uniq UNIQUE (prefix),
+  CONSTRAINT api_keys_user_id FOREIGN KEY (user_id) REFERENCES users (id) ON DELETE CASCADE
+);
+
+CREATE INDEX IF NOT EXISTS api_keys_user_id_idx ON api_keys (user_id);
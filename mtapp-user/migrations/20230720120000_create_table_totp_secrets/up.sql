@@ -0,0 +1,19 @@
+CREATE TABLE IF NOT EXISTS totp_secrets (
+  user_id UUID PRIMARY KEY,
+  secret VARCHAR NOT NULL,
+  last_used_step BIGINT,
+  confirmed_at Timestamp WITH TIME ZONE,
+  created_at Timestamp WITH TIME ZONE NOT NULL DEFAULT now(),
+  CONSTRAINT totp_secrets_user_id FOREIGN KEY (user_id) REFERENCES users (id) ON DELETE CASCADE
+);
+
+CREATE TABLE IF NOT EXISTS recovery_codes (
+  id UUID PRIMARY KEY,
+  user_id UUID NOT NULL,
+  code_hash VARCHAR NOT NULL,
+  used_at Timestamp WITH TIME ZONE,
+  created_at Timestamp WITH TIME ZONE NOT NULL DEFAULT now(),
+  CONSTRAINT recovery_codes_user_id FOREIGN KEY (user_id) REFERENCES users (id) ON DELETE CASCADE
+);
+
+CREATE INDEX IF NOT EXISTS recovery_codes_user_id_idx ON recovery_codes (user_id);
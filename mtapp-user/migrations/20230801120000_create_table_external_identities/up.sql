@@ -0,0 +1,13 @@
+CREATE TABLE IF NOT EXISTS external_identities (
+  id UUID PRIMARY KEY,
+  user_id UUID NOT NULL,
+  issuer VARCHAR NOT NULL,
+  subject VARCHAR NOT NULL,
+  email VARCHAR,
+  last_used_at Timestamp WITH TIME ZONE,
+  created_at Timestamp WITH TIME ZONE NOT NULL DEFAULT now(),
+  CONSTRAINT external_identity_issuer_subject_uniq UNIQUE (issuer, subject),
+  CONSTRAINT external_identities_user_id FOREIGN KEY (user_id) REFERENCES users (id) ON DELETE CASCADE
+);
+
+CREATE INDEX IF NOT EXISTS external_identities_user_id_idx ON external_identities (user_id);
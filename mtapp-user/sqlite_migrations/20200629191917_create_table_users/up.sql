@@ -0,0 +1,17 @@
+CREATE TABLE IF NOT EXISTS users (
+  id BLOB PRIMARY KEY,
+  username VARCHAR NOT NULL,
+  email VARCHAR,
+  password VARCHAR NOT NULL,
+  last_logged_in_at TIMESTAMP,
+  created_at TIMESTAMP NOT NULL DEFAULT CURRENT_TIMESTAMP,
+  updated_at TIMESTAMP NOT NULL DEFAULT CURRENT_TIMESTAMP,
+  CONSTRAINT username_uniq UNIQUE (username),
+  CONSTRAINT email_uniq UNIQUE (email)
+);
+
+CREATE TRIGGER IF NOT EXISTS user_updated AFTER UPDATE ON users
+FOR EACH ROW WHEN NEW.updated_at = OLD.updated_at
+BEGIN
+  UPDATE users SET updated_at = CURRENT_TIMESTAMP WHERE id = NEW.id;
+END;
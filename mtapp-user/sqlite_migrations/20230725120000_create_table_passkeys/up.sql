@@ -0,0 +1,14 @@
+CREATE TABLE IF NOT EXISTS passkeys (
+  id BLOB PRIMARY KEY,
+  user_id BLOB NOT NULL,
+  name VARCHAR NOT NULL,
+  credential_id VARCHAR NOT NULL,
+  public_key BLOB NOT NULL,
+  sign_count BIGINT NOT NULL DEFAULT 0,
+  last_used_at TIMESTAMP,
+  created_at TIMESTAMP NOT NULL DEFAULT CURRENT_TIMESTAMP,
+  CONSTRAINT passkey_credential_id_uniq UNIQUE (credential_id),
+  CONSTRAINT passkeys_user_id FOREIGN KEY (user_id) REFERENCES users (id) ON DELETE CASCADE
+);
+
+CREATE INDEX IF NOT EXISTS passkeys_user_id_idx ON passkeys (user_id);
@@ -0,0 +1,13 @@
+CREATE TABLE IF NOT EXISTS external_identities (
+  id BLOB PRIMARY KEY,
+  user_id BLOB NOT NULL,
+  issuer VARCHAR NOT NULL,
+  subject VARCHAR NOT NULL,
+  email VARCHAR,
+  last_used_at TIMESTAMP,
+  created_at TIMESTAMP NOT NULL DEFAULT CURRENT_TIMESTAMP,
+  CONSTRAINT external_identity_issuer_subject_uniq UNIQUE (issuer, subject),
+  CONSTRAINT external_identities_user_id FOREIGN KEY (user_id) REFERENCES users (id) ON DELETE CASCADE
+);
+
+CREATE INDEX IF NOT EXISTS external_identities_user_id_idx ON external_identities (user_id);
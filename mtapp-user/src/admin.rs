@@ -1,12 +1,12 @@
-use axum::extract::Path;
+use axum::extract::{Path, State};
 use axum::response::IntoResponse;
-use axum::Extension;
 use basteh::Basteh;
 use json_resp::{CombineErrors, JsonListMeta, JsonResponse};
 use seaqs::QueryFilter;
-use sqlx::{types::Uuid, PgPool};
+use sqlx::types::Uuid;
 use validator::Validate;
 
+use mtapp::db::DbPool;
 use mtapp::extractors::{oai, Json, Query};
 use mtapp_auth::AuthErrorOai;
 
@@ -37,7 +37,7 @@ type QueryUserLookupFilter = QueryFilter<UserLookupFilter<'static>>;
 )]
 pub async fn list(
     Query(query): Query<QueryFilter<UserLookupFilter<'_>>>,
-    Extension(pool): Extension<PgPool>,
+    State(pool): State<DbPool>,
 ) -> impl IntoResponse {
     let users = User::find(&query, &pool).await?;
     let total = User::count(&query, &pool).await?;
@@ -67,10 +67,7 @@ pub async fn list(
         ("jwt_token" = [])
     )
 )]
-pub async fn create(
-    Extension(pool): Extension<PgPool>,
-    Json(user): Json<UserCreate>,
-) -> impl IntoResponse {
+pub async fn create(State(pool): State<DbPool>, Json(user): Json<UserCreate>) -> impl IntoResponse {
     user.validate()?;
     let user = User::create(user, &pool).await?;
     Result::<_, UserError>::Ok(JsonResponse::with_content(user))
@@ -96,8 +93,8 @@ pub async fn create(
 )]
 pub async fn batch_delete(
     Query(query): Query<UserDeleteFilter>,
-    Extension(storage): Extension<Basteh>,
-    Extension(pool): Extension<PgPool>,
+    State(storage): State<Basteh>,
+    State(pool): State<DbPool>,
 ) -> impl IntoResponse {
     let users = User::delete(&query, &pool).await?;
     for user in users.iter() {
@@ -125,7 +122,7 @@ pub async fn batch_delete(
         ("jwt_token" = [])
     )
 )]
-pub async fn get(id: Path<Uuid>, Extension(pool): Extension<PgPool>) -> impl IntoResponse {
+pub async fn get(id: Path<Uuid>, State(pool): State<DbPool>) -> impl IntoResponse {
     let user = User::get_by_id(*id, &pool).await?;
     Result::<_, UserError>::Ok(JsonResponse::with_content(user))
 }
@@ -158,7 +155,7 @@ pub async fn get(id: Path<Uuid>, Extension(pool): Extension<PgPool>) -> impl Int
 )]
 pub async fn update(
     id: Path<Uuid>,
-    Extension(pool): Extension<PgPool>,
+    State(pool): State<DbPool>,
     Json(user): Json<UserUpdate>,
 ) -> impl IntoResponse {
     user.validate()?;
@@ -187,8 +184,8 @@ pub async fn update(
 )]
 pub async fn delete(
     id: Path<Uuid>,
-    Extension(storage): Extension<Basteh>,
-    Extension(pool): Extension<PgPool>,
+    State(storage): State<Basteh>,
+    State(pool): State<DbPool>,
 ) -> impl IntoResponse {
     let user = User::delete_by_id(*id, &pool).await?;
     storage.scope("banned_user_ids").set(user.id, 0).await?;
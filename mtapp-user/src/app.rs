@@ -1,13 +1,17 @@
+use std::time::Duration;
+
 use axum::{
+    extract::FromRef,
     http::Extensions,
     middleware::from_fn,
-    routing::{get, post},
+    routing::{delete, get, post},
     Router,
 };
 use clap::{Arg, Command};
-use mtapp::{include_migrations_dir, App, Configuration, Migration};
-use mtapp_auth::{ClaimCheck, Claims};
-use sqlx::PgPool;
+use mtapp::{
+    db::DbPool, include_backend_migrations, App, Configuration, Migration, RateLimit, ReactorState,
+};
+use mtapp_auth::{RequireScopes, ScopedRoutes};
 use utoipa::OpenApi;
 
 use crate::{
@@ -16,13 +20,50 @@ use crate::{
     openapi::{InternalUserOpenApi, PublicUserOpenApi},
 };
 
-#[derive(Default, Clone)]
-pub struct UserApp {}
+/// Shown by the authenticator apps along with the username
+#[derive(Clone)]
+pub(crate) struct TotpIssuer(pub(crate) String);
+
+impl FromRef<ReactorState> for TotpIssuer {
+    fn from_ref(state: &ReactorState) -> Self {
+        state
+            .get::<TotpIssuer>()
+            .cloned()
+            .expect("TotpIssuer is registered by UserApp")
+    }
+}
+
+#[derive(Clone)]
+pub struct UserApp {
+    totp_issuer: String,
+    signup_rate_limit: Option<RateLimit>,
+}
+
+impl Default for UserApp {
+    fn default() -> Self {
+        Self::new()
+    }
+}
 
 impl UserApp {
     pub fn new() -> Self {
         sodiumoxide::init().expect("Libsodium init failed");
-        UserApp {}
+        UserApp {
+            totp_issuer: String::from("mtapp"),
+            signup_rate_limit: Some(RateLimit::new("signup", 10, Duration::from_secs(60 * 60))),
+        }
+    }
+
+    /// Name of the service in the authenticator apps, `mtapp` by default
+    pub fn totp_issuer(mut self, issuer: &str) -> Self {
+        self.totp_issuer = String::from(issuer);
+        self
+    }
+
+    /// Signups from each ip address, 10 an hour by default. `None` disables the limit
+    pub fn signup_rate_limit(mut self, limit: Option<RateLimit>) -> Self {
+        self.signup_rate_limit = limit;
+        self
     }
 }
 
@@ -32,50 +73,35 @@ impl App for UserApp {
         "mtapp-user"
     }
 
+    // The scopes in the users' claims are the scope app's
+    fn dependencies(&self) -> &'static [&'static str] {
+        &["mtapp-scope"]
+    }
+
     fn configure(&mut self, cfg: &mut Configuration) {
-        cfg.base_router(|router| router.layer(from_fn(user_ban_check)));
+        cfg.state(TotpIssuer(self.totp_issuer.clone()))
+            .base_router(|router| router.layer(from_fn(user_ban_check)));
     }
 
-    fn public_routes(&mut self, path_prefix: &str) -> Option<Router> {
+    fn public_routes(&mut self, path_prefix: &str) -> Option<Router<ReactorState>> {
+        let signup = match self.signup_rate_limit.clone() {
+            Some(limit) => post(handlers::signup).layer(limit),
+            None => post(handlers::signup),
+        };
+
         Some(
             Router::new()
-                .route(&format!("{}/", path_prefix), post(handlers::signup))
-                .merge(
-                    Router::new()
-                        .route(
-                            &format!("{}/me", path_prefix),
-                            get(handlers::get_me).post(handlers::update),
-                        )
-                        .layer(ClaimCheck::new(|claims: Option<Claims>| claims.is_some())),
-                ),
+                .route(&format!("{}/", path_prefix), signup)
+                .merge(public_scoped_routes(path_prefix).into_router()),
         )
     }
 
-    fn internal_routes(&mut self, path_prefix: &str) -> Option<Router> {
-        Some(
-            Router::new()
-                .route(
-                    &format!("{}/", path_prefix),
-                    get(admin::list)
-                        .post(admin::create)
-                        .delete(admin::batch_delete),
-                )
-                .route(
-                    &format!("{}/:user_id", path_prefix),
-                    get(admin::get).post(admin::update).delete(admin::delete),
-                )
-                .layer(ClaimCheck::new(|claims: Option<Claims>| {
-                    if let Some(claims) = claims {
-                        claims.has_scope("superadmin") || claims.has_scope("admin")
-                    } else {
-                        false
-                    }
-                })),
-        )
+    fn internal_routes(&mut self, path_prefix: &str) -> Option<Router<ReactorState>> {
+        Some(internal_scoped_routes(path_prefix).into_router())
     }
 
     fn migrations(&mut self) -> Option<Vec<Box<dyn Migration>>> {
-        include_migrations_dir!("./migrations")
+        include_backend_migrations!(postgres: "./migrations", sqlite: "./sqlite_migrations")
     }
 
     fn clap_def(&self) -> Option<clap::Command> {
@@ -94,7 +120,7 @@ impl App for UserApp {
 
     async fn clap_run(&mut self, matches: &clap::ArgMatches, ext: &Extensions) {
         let pool = ext
-            .get::<PgPool>()
+            .get::<DbPool>()
             .expect("Inserted into extensions by reactor")
             .clone();
 
@@ -112,10 +138,68 @@ impl App for UserApp {
     }
 
     fn public_openapi(&mut self, _: &str) -> Option<utoipa::openapi::OpenApi> {
-        Some(PublicUserOpenApi::openapi())
+        Some(public_scoped_routes("").document(PublicUserOpenApi::openapi()))
     }
 
     fn internal_openapi(&mut self, _: &str) -> Option<utoipa::openapi::OpenApi> {
-        Some(InternalUserOpenApi::openapi())
+        Some(internal_scoped_routes("").document(InternalUserOpenApi::openapi()))
     }
 }
+
+fn public_scoped_routes(path_prefix: &str) -> ScopedRoutes {
+    // The clients aren't users
+    let user = RequireScopes::authenticated().users_only();
+    // Otherwise a key could make more keys to outlive itself
+    let token = user.clone().tokens_only();
+
+    ScopedRoutes::new(path_prefix)
+        .route("/me", get(handlers::get_me).post(handlers::update), user)
+        .route(
+            "/me/api-keys",
+            get(handlers::list_api_keys).post(handlers::create_api_key),
+            token.clone(),
+        )
+        .route(
+            "/me/api-keys/{key_id}",
+            delete(handlers::delete_api_key),
+            token.clone(),
+        )
+        .route("/me/passkeys", get(handlers::list_passkeys), token.clone())
+        .route(
+            "/me/passkeys/{passkey_id}",
+            delete(handlers::delete_passkey),
+            token.clone(),
+        )
+        .route(
+            "/me/identities",
+            get(handlers::list_identities),
+            token.clone(),
+        )
+        .route(
+            "/me/identities/{identity_id}",
+            delete(handlers::delete_identity),
+            token.clone(),
+        )
+        .route(
+            "/me/totp",
+            post(handlers::enroll_totp).delete(handlers::disable_totp),
+            token.clone(),
+        )
+        .route("/me/totp/confirm", post(handlers::confirm_totp), token)
+}
+
+fn internal_scoped_routes(path_prefix: &str) -> ScopedRoutes {
+    ScopedRoutes::new(path_prefix)
+        .route(
+            "/",
+            get(admin::list)
+                .post(admin::create)
+                .delete(admin::batch_delete),
+            RequireScopes::admin(),
+        )
+        .route(
+            "/{user_id}",
+            get(admin::get).post(admin::update).delete(admin::delete),
+            RequireScopes::admin(),
+        )
+}
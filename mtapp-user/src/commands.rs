@@ -1,10 +1,10 @@
 use dialoguer::{Input, Password};
-use sqlx::PgPool;
+use mtapp::db::DbPool;
 
 use crate::{models::User, schemas::UserCreate};
 
 pub async fn create_user(
-    pool: PgPool,
+    pool: DbPool,
     recv_username: Option<String>,
     recv_password: Option<String>,
 ) {
@@ -18,20 +18,10 @@ pub async fn create_user(
                 .expect("Failed IO")
         };
 
-        let exist = sqlx::query!(
-            "SELECT COUNT(*) FROM users WHERE username = $1",
-            recv_username
-        )
-        .fetch_one(&pool)
-        .await
-        .expect("Database connection failed!")
-        .count
-        .unwrap_or(0);
-
-        if exist == 0 {
-            break username;
-        } else {
-            println!("Username already exist");
+        match User::get_by_username(&username, &pool).await {
+            Ok(_) => println!("Username already exist"),
+            Err(sqlx::Error::RowNotFound) => break username,
+            Err(err) => panic!("Database connection failed! {}", err),
         }
     };
 
@@ -16,11 +16,20 @@ pub enum UserError {
     #[json_error(request, status = 409, code = "409002 validation-error")]
     ValidationError(validator::ValidationErrors),
 
+    #[json_error(request, status = 403, code = "403002 scope-not-granted")]
+    ScopeNotGranted,
+
+    #[json_error(request, status = 403, code = "403003 invalid-mfa-code")]
+    InvalidMfaCode,
+
+    #[json_error(request, status = 409, code = "409003 mfa-already-enabled")]
+    MfaAlreadyEnabled,
+
     #[json_error(internal)]
     DatabaseError(sqlx::Error),
 
     #[json_error(internal)]
-    UnknownConstaintError(Box<sqlx::postgres::PgDatabaseError>),
+    UnknownConstaintError(Box<dyn sqlx::error::DatabaseError>),
 
     #[json_error(internal)]
     Other(Box<dyn Error + Send>),
@@ -38,11 +47,12 @@ impl From<sqlx::Error> for UserError {
             sqlx::Error::RowNotFound => UserError::NotFound,
             sqlx::Error::Database(db_err) => {
                 // It's hacky and should be converted into a more general way possiblity converted to ValidationError
-                let pg_error = db_err.downcast::<sqlx::postgres::PgDatabaseError>();
-                match pg_error.constraint() {
-                    Some("username_uniq") => UserError::DuplicateField("username"),
-                    Some("email_uniq") => UserError::DuplicateField("email"),
-                    _ => UserError::UnknownConstaintError(pg_error),
+                match mtapp::db::constraint(db_err.as_ref()) {
+                    Some("username_uniq" | "users.username") => {
+                        UserError::DuplicateField("username")
+                    }
+                    Some("email_uniq" | "users.email") => UserError::DuplicateField("email"),
+                    _ => UserError::UnknownConstaintError(db_err),
                 }
             }
             _ => UserError::DatabaseError(err),
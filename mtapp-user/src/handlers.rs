@@ -1,15 +1,27 @@
-use axum::{response::IntoResponse, Extension};
+use axum::{
+    extract::{Path, State},
+    response::IntoResponse,
+};
 use json_resp::{CombineErrors, JsonResponse};
-use sqlx::PgPool;
 use validator::Validate;
 
+use mtapp::db::DbPool;
 use mtapp::extractors::{oai, Json};
+use mtapp::RateLimitErrorOai;
 use mtapp_auth::{AuthErrorOai, Claims};
 
+use sqlx::types::Uuid;
+
 use crate::{
+    app::TotpIssuer,
     errors::{UserError, UserErrorOai},
-    models::User,
-    schemas::{SelfUpdate, UserRegister},
+    helpers,
+    models::{ApiKey, ExternalIdentity, Passkey, RecoveryCode, TotpSecret, User},
+    schemas::{
+        ApiKeyCreate, ApiKeyList, ApiKeyWithSecret, ExternalIdentityList, MfaCode, PasskeyList,
+        RecoveryCodes, SelfUpdate, TotpEnrollment, UserRegister,
+    },
+    totp,
 };
 
 #[utoipa::path(
@@ -25,6 +37,7 @@ use crate::{
         (status = 200, body=inline(JsonResponse<User>)),
         oai::AllExtErrors,
         CombineErrors::<UserErrorOai::DuplicateField, UserErrorOai::ValidationError>,
+        RateLimitErrorOai::TooManyRequests,
         UserErrorOai::InternalError
     ),
     security(
@@ -32,7 +45,7 @@ use crate::{
     )
 )]
 pub async fn signup(
-    Extension(pool): Extension<PgPool>,
+    State(pool): State<DbPool>,
     Json(user): Json<UserRegister>,
 ) -> impl IntoResponse {
     user.validate()?;
@@ -53,7 +66,7 @@ pub async fn signup(
         ("jwt_token" = [])
     )
 )]
-pub async fn get_me(claims: Claims, Extension(pool): Extension<PgPool>) -> impl IntoResponse {
+pub async fn get_me(claims: Claims, State(pool): State<DbPool>) -> impl IntoResponse {
     let user = User::get_by_id(claims.user_id, &pool).await?;
     Result::<_, UserError>::Ok(JsonResponse::with_content(user))
 }
@@ -80,10 +93,313 @@ pub async fn get_me(claims: Claims, Extension(pool): Extension<PgPool>) -> impl
 )]
 pub async fn update(
     claims: Claims,
-    Extension(pool): Extension<PgPool>,
+    State(pool): State<DbPool>,
     Json(user): Json<SelfUpdate>,
 ) -> impl IntoResponse {
     user.validate()?;
     let user = User::update(claims.user_id, user, &pool).await?;
     Result::<_, UserError>::Ok(JsonResponse::with_content(user))
 }
+
+#[utoipa::path(
+    get,
+    tag = "User",
+    path = "/me/api-keys",
+    responses(
+        (status = 200, body=inline(JsonResponse<ApiKeyList>)),
+        AuthErrorOai::Authentication,
+        AuthErrorOai::Permission,
+        UserErrorOai::InternalError
+    ),
+    security(
+        ("jwt_token" = [])
+    )
+)]
+pub async fn list_api_keys(claims: Claims, State(pool): State<DbPool>) -> impl IntoResponse {
+    let keys = ApiKey::find_for_user(claims.user_id, &pool).await?;
+    Result::<_, UserError>::Ok(JsonResponse::with_content(keys))
+}
+
+#[utoipa::path(
+    post,
+    tag = "User",
+    path = "/me/api-keys",
+    request_body(
+        content=inline(ApiKeyCreate),
+        content_type="application/json",
+        description="Api key create"
+    ),
+    responses(
+        (status = 200, body=inline(JsonResponse<ApiKeyWithSecret>)),
+        oai::AllExtErrors,
+        AuthErrorOai::Authentication,
+        AuthErrorOai::Permission,
+        CombineErrors::<UserErrorOai::ScopeNotGranted, UserErrorOai::ValidationError>,
+        UserErrorOai::InternalError
+    ),
+    security(
+        ("jwt_token" = [])
+    )
+)]
+pub async fn create_api_key(
+    claims: Claims,
+    State(pool): State<DbPool>,
+    Json(api_key): Json<ApiKeyCreate>,
+) -> impl IntoResponse {
+    api_key.validate()?;
+    if api_key.scopes.iter().any(|scope| !claims.has_scope(scope)) {
+        return Err(UserError::ScopeNotGranted);
+    }
+
+    let (prefix, key, hash) = helpers::generate_api_key();
+    let api_key = ApiKey::create(claims.user_id, api_key, &prefix, &hash, &pool).await?;
+    Result::<_, UserError>::Ok(JsonResponse::with_content(ApiKeyWithSecret {
+        api_key,
+        key,
+    }))
+}
+
+#[utoipa::path(
+    delete,
+    tag = "User",
+    path = "/me/api-keys/{key_id}",
+    params(
+        ("key_id" = Uuid, Path,)
+    ),
+    responses(
+        (status = 200, body=inline(JsonResponse<ApiKey>)),
+        oai::PathErrors,
+        AuthErrorOai::Authentication,
+        AuthErrorOai::Permission,
+        UserErrorOai::NotFound,
+        UserErrorOai::InternalError
+    ),
+    security(
+        ("jwt_token" = [])
+    )
+)]
+pub async fn delete_api_key(
+    claims: Claims,
+    id: Path<Uuid>,
+    State(pool): State<DbPool>,
+) -> impl IntoResponse {
+    let api_key = ApiKey::delete_for_user(*id, claims.user_id, &pool).await?;
+    Result::<_, UserError>::Ok(JsonResponse::with_content(api_key))
+}
+
+#[utoipa::path(
+    get,
+    tag = "User",
+    path = "/me/passkeys",
+    responses(
+        (status = 200, body=inline(JsonResponse<PasskeyList>)),
+        AuthErrorOai::Authentication,
+        AuthErrorOai::Permission,
+        UserErrorOai::InternalError
+    ),
+    security(
+        ("jwt_token" = [])
+    )
+)]
+pub async fn list_passkeys(claims: Claims, State(pool): State<DbPool>) -> impl IntoResponse {
+    let passkeys = Passkey::find_for_user(claims.user_id, &pool).await?;
+    Result::<_, UserError>::Ok(JsonResponse::with_content(passkeys))
+}
+
+#[utoipa::path(
+    delete,
+    tag = "User",
+    path = "/me/passkeys/{passkey_id}",
+    params(
+        ("passkey_id" = Uuid, Path,)
+    ),
+    responses(
+        (status = 200, body=inline(JsonResponse<Passkey>)),
+        oai::PathErrors,
+        AuthErrorOai::Authentication,
+        AuthErrorOai::Permission,
+        UserErrorOai::NotFound,
+        UserErrorOai::InternalError
+    ),
+    security(
+        ("jwt_token" = [])
+    )
+)]
+pub async fn delete_passkey(
+    claims: Claims,
+    id: Path<Uuid>,
+    State(pool): State<DbPool>,
+) -> impl IntoResponse {
+    let passkey = Passkey::delete_for_user(*id, claims.user_id, &pool).await?;
+    Result::<_, UserError>::Ok(JsonResponse::with_content(passkey))
+}
+
+#[utoipa::path(
+    get,
+    tag = "User",
+    path = "/me/identities",
+    responses(
+        (status = 200, body=inline(JsonResponse<ExternalIdentityList>)),
+        AuthErrorOai::Authentication,
+        AuthErrorOai::Permission,
+        UserErrorOai::InternalError
+    ),
+    security(
+        ("jwt_token" = [])
+    )
+)]
+pub async fn list_identities(claims: Claims, State(pool): State<DbPool>) -> impl IntoResponse {
+    let identities = ExternalIdentity::find_for_user(claims.user_id, &pool).await?;
+    Result::<_, UserError>::Ok(JsonResponse::with_content(identities))
+}
+
+#[utoipa::path(
+    delete,
+    tag = "User",
+    path = "/me/identities/{identity_id}",
+    params(
+        ("identity_id" = Uuid, Path,)
+    ),
+    responses(
+        (status = 200, body=inline(JsonResponse<ExternalIdentity>)),
+        oai::PathErrors,
+        AuthErrorOai::Authentication,
+        AuthErrorOai::Permission,
+        UserErrorOai::NotFound,
+        UserErrorOai::InternalError
+    ),
+    security(
+        ("jwt_token" = [])
+    )
+)]
+pub async fn delete_identity(
+    claims: Claims,
+    id: Path<Uuid>,
+    State(pool): State<DbPool>,
+) -> impl IntoResponse {
+    let identity = ExternalIdentity::delete_for_user(*id, claims.user_id, &pool).await?;
+    Result::<_, UserError>::Ok(JsonResponse::with_content(identity))
+}
+
+#[utoipa::path(
+    post,
+    tag = "User",
+    path = "/me/totp",
+    responses(
+        (status = 200, body=inline(JsonResponse<TotpEnrollment>)),
+        AuthErrorOai::Authentication,
+        AuthErrorOai::Permission,
+        UserErrorOai::MfaAlreadyEnabled,
+        UserErrorOai::InternalError
+    ),
+    security(
+        ("jwt_token" = [])
+    )
+)]
+pub async fn enroll_totp(
+    claims: Claims,
+    State(issuer): State<TotpIssuer>,
+    State(pool): State<DbPool>,
+) -> impl IntoResponse {
+    let user = User::get_by_id(claims.user_id, &pool).await?;
+
+    let mut tx = pool.begin().await?;
+    match TotpSecret::get(user.id, &mut tx).await {
+        Ok(secret) if secret.is_confirmed() => return Err(UserError::MfaAlreadyEnabled),
+        // Start over if it's not confirmed yet
+        Ok(_) => TotpSecret::delete(user.id, &mut tx).await?,
+        Err(sqlx::Error::RowNotFound) => {}
+        Err(err) => return Err(err.into()),
+    }
+    let secret = TotpSecret::create(user.id, &totp::generate_secret(), &mut tx).await?;
+    tx.commit().await?;
+
+    Result::<_, UserError>::Ok(JsonResponse::with_content(TotpEnrollment {
+        otpauth_uri: totp::otpauth_uri(&issuer.0, &user.username, &secret.secret),
+        secret: secret.secret,
+    }))
+}
+
+#[utoipa::path(
+    post,
+    tag = "User",
+    path = "/me/totp/confirm",
+    request_body(
+        content=inline(MfaCode),
+        content_type="application/json",
+        description="A code generated with the new secret"
+    ),
+    responses(
+        (status = 200, body=inline(JsonResponse<RecoveryCodes>)),
+        oai::AllExtErrors,
+        AuthErrorOai::Authentication,
+        AuthErrorOai::Permission,
+        UserErrorOai::NotFound,
+        CombineErrors::<UserErrorOai::InvalidMfaCode, UserErrorOai::MfaAlreadyEnabled>,
+        UserErrorOai::InternalError
+    ),
+    security(
+        ("jwt_token" = [])
+    )
+)]
+pub async fn confirm_totp(
+    claims: Claims,
+    State(pool): State<DbPool>,
+    Json(body): Json<MfaCode>,
+) -> impl IntoResponse {
+    let secret = TotpSecret::get(claims.user_id, &pool).await?;
+    if secret.is_confirmed() {
+        return Err(UserError::MfaAlreadyEnabled);
+    }
+    // Only a TOTP code, there are no recovery codes yet
+    if !totp::is_totp_code(&body.code) || !secret.check_code(&body.code, &pool).await? {
+        return Err(UserError::InvalidMfaCode);
+    }
+
+    let mut tx = pool.begin().await?;
+    TotpSecret::confirm(claims.user_id, &mut tx).await?;
+    let recovery_codes = RecoveryCode::regenerate(claims.user_id, &mut tx).await?;
+    tx.commit().await?;
+
+    Result::<_, UserError>::Ok(JsonResponse::with_content(RecoveryCodes { recovery_codes }))
+}
+
+#[utoipa::path(
+    delete,
+    tag = "User",
+    path = "/me/totp",
+    request_body(
+        content=inline(MfaCode),
+        content_type="application/json",
+        description="A TOTP or recovery code, not needed if MFA isn't confirmed yet"
+    ),
+    responses(
+        (status = 200, body=inline(JsonResponse<String>)),
+        oai::AllExtErrors,
+        AuthErrorOai::Authentication,
+        AuthErrorOai::Permission,
+        UserErrorOai::NotFound,
+        UserErrorOai::InvalidMfaCode,
+        UserErrorOai::InternalError
+    ),
+    security(
+        ("jwt_token" = [])
+    )
+)]
+pub async fn disable_totp(
+    claims: Claims,
+    State(pool): State<DbPool>,
+    Json(body): Json<MfaCode>,
+) -> impl IntoResponse {
+    let secret = TotpSecret::get(claims.user_id, &pool).await?;
+    if secret.is_confirmed() && !secret.check_code(&body.code, &pool).await? {
+        return Err(UserError::InvalidMfaCode);
+    }
+
+    let mut tx = pool.begin().await?;
+    TotpSecret::delete(claims.user_id, &mut tx).await?;
+    RecoveryCode::delete_for_user(claims.user_id, &mut tx).await?;
+    tx.commit().await?;
+
+    Result::<_, UserError>::Ok(JsonResponse::with_content("MFA is disabled"))
+}
@@ -1,5 +1,7 @@
 // Credit: https://blue42.net/code/rust/examples/sodiumoxide-password-hashing/post/
+use mtapp_auth::secrets;
 use sodiumoxide::crypto::pwhash::argon2id13;
+use sodiumoxide::{base64, hex, randombytes};
 
 pub(crate) fn hash(password: &str) -> String {
     let hash = argon2id13::pwhash(
@@ -25,3 +27,55 @@ pub(crate) fn verify(password: &str, hash: &str) -> bool {
         None => false,
     }
 }
+
+/// Generate a new api key, returns (prefix, key, hash)
+///
+/// The prefix is stored in plain to find the key and to tell the keys apart in listings
+pub(crate) fn generate_api_key() -> (String, String, String) {
+    // Long enough to not collide with the unique prefixes of the other keys
+    let prefix = hex::encode(randombytes::randombytes(8));
+    let key = format!("{}.{}", prefix, secrets::generate());
+    let hash = secrets::hash(&key);
+    (prefix, key, hash)
+}
+
+pub(crate) fn api_key_prefix(key: &str) -> Option<&str> {
+    key.split_once('.').map(|(prefix, _)| prefix)
+}
+
+pub(crate) fn verify_api_key(key: &str, hash: &str) -> bool {
+    secrets::verify(key, hash)
+}
+
+/// A username for the users made on their first login with an external identity, taken from
+/// the preferred username or the email of the identity
+pub(crate) fn external_username(preferred: Option<&str>, email: Option<&str>) -> String {
+    let name = preferred
+        .or_else(|| email.and_then(|email| email.split('@').next()))
+        .unwrap_or_default();
+    let name: String = name
+        .to_lowercase()
+        .chars()
+        .filter(|c| c.is_ascii_alphanumeric() || matches!(c, '.' | '_' | '-'))
+        .take(40)
+        .collect();
+
+    if name.len() < 4 {
+        String::from("user")
+    } else {
+        name
+    }
+}
+
+/// Appended to the usernames which are taken
+pub(crate) fn username_suffix() -> String {
+    hex::encode(randombytes::randombytes(3))
+}
+
+/// A password nobody knows, for the users made from an external identity
+pub(crate) fn random_password() -> String {
+    base64::encode(
+        randombytes::randombytes(32),
+        base64::Variant::UrlSafeNoPadding,
+    )
+}
@@ -10,6 +10,7 @@ mod models;
 mod openapi;
 mod provider;
 mod schemas;
+mod totp;
 
 pub use app::UserApp;
 pub use models::User;
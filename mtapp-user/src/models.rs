@@ -1,17 +1,22 @@
-use sea_query::{enum_def, Expr, Iden, PostgresQueryBuilder, Query};
+use mtapp::db::{Db, DbPool, DbQueryBuilder, DbRow};
+use sea_query::{enum_def, Cond, Expr, Iden, Order, Query, SimpleExpr};
 use sea_query_binder::SqlxBinder;
 use seaqs::{ApplyConds, ApplyFilters, QueryFilter};
 use serde::Serialize;
 use sqlx::types::{
-    chrono::{DateTime, Utc},
+    chrono::{self, DateTime, Utc},
     Uuid,
 };
-use sqlx::{Error, Executor, FromRow, Postgres, Row};
+use sqlx::{Error, Executor, FromRow, Row};
 use utoipa::ToSchema;
 
 use crate::filters::{UserDeleteFilter, UserLookupFilter};
 use crate::helpers;
-use crate::schemas::{UserCreate, UserUpdate};
+use crate::schemas::{ApiKeyCreate, UserCreate, UserUpdate};
+use crate::totp;
+
+// In seconds, `last_used_at` of the api keys isn't written more often than this
+const API_KEY_LAST_USED_INTERVAL: i64 = 60;
 
 #[derive(Serialize, FromRow, ToSchema)]
 #[enum_def]
@@ -45,12 +50,13 @@ impl Default for User {
 }
 
 impl User {
+    #[tracing::instrument(name = "User::count", skip_all)]
     pub async fn count<'a, E>(
         filters: &QueryFilter<UserLookupFilter<'_>>,
         con: E,
     ) -> Result<i64, Error>
     where
-        E: Executor<'a, Database = Postgres>,
+        E: Executor<'a, Database = Db>,
     {
         let mut q = Query::select()
             .expr(Expr::asterisk().count())
@@ -61,10 +67,10 @@ impl User {
             q = q.apply_conds(filter).to_owned();
         };
 
-        let (sql, args) = q.build_sqlx(PostgresQueryBuilder);
+        let (sql, args) = q.build_sqlx(DbQueryBuilder);
 
         sqlx::query_with(&sql, args)
-            .try_map(|row: sqlx::postgres::PgRow| {
+            .try_map(|row: DbRow| {
                 let count = row.try_get_unchecked::<Option<i64>, _>(0usize)?;
                 Ok(count)
             })
@@ -73,94 +79,135 @@ impl User {
             .map(|v| v.unwrap_or(0))
     }
 
+    #[tracing::instrument(name = "User::find", skip_all)]
     pub async fn find<'a, E>(
         filters: &QueryFilter<UserLookupFilter<'_>>,
         con: E,
     ) -> Result<Vec<Self>, Error>
     where
-        E: Executor<'a, Database = Postgres>,
+        E: Executor<'a, Database = Db>,
     {
         let (sql, args) = Query::select()
             .expr(Expr::asterisk())
             .from(Users)
             .to_owned()
             .apply_filters(filters)
-            .build_sqlx(PostgresQueryBuilder);
+            .build_sqlx(DbQueryBuilder);
 
         sqlx::query_as_with(&sql, args).fetch_all(con).await
     }
 
+    #[tracing::instrument(name = "User::get_by_id", skip_all)]
     pub async fn get_by_id<'a, E>(id: Uuid, con: E) -> Result<Self, Error>
     where
-        E: Executor<'a, Database = Postgres>,
+        E: Executor<'a, Database = Db>,
     {
-        sqlx::query_as!(Self, "SELECT * FROM users WHERE id = $1", id)
-            .fetch_one(con)
-            .await
+        let (sql, args) = Query::select()
+            .expr(Expr::asterisk())
+            .from(Users)
+            .and_where(Expr::col(UserIden::Id).eq(id))
+            .build_sqlx(DbQueryBuilder);
+
+        sqlx::query_as_with(&sql, args).fetch_one(con).await
     }
 
+    #[tracing::instrument(name = "User::get_by_username", skip_all)]
     pub async fn get_by_username<'a, E>(username: &str, con: E) -> Result<Self, Error>
     where
-        E: Executor<'a, Database = Postgres>,
+        E: Executor<'a, Database = Db>,
     {
-        sqlx::query_as!(Self, "SELECT * FROM users WHERE username = $1", username)
-            .fetch_one(con)
-            .await
+        let (sql, args) = Query::select()
+            .expr(Expr::asterisk())
+            .from(Users)
+            .and_where(Expr::col(UserIden::Username).eq(username))
+            .build_sqlx(DbQueryBuilder);
+
+        sqlx::query_as_with(&sql, args).fetch_one(con).await
     }
 
+    #[tracing::instrument(name = "User::get_by_email", skip_all)]
+    pub async fn get_by_email<'a, E>(email: &str, con: E) -> Result<Self, Error>
+    where
+        E: Executor<'a, Database = Db>,
+    {
+        let (sql, args) = Query::select()
+            .expr(Expr::asterisk())
+            .from(Users)
+            .and_where(Expr::col(UserIden::Email).eq(email.to_lowercase()))
+            .build_sqlx(DbQueryBuilder);
+
+        sqlx::query_as_with(&sql, args).fetch_one(con).await
+    }
+
+    #[tracing::instrument(name = "User::create", skip_all)]
     pub async fn create<'a, E>(user: impl Into<UserCreate>, con: E) -> Result<Self, Error>
     where
-        E: Executor<'a, Database = Postgres>,
+        E: Executor<'a, Database = Db>,
     {
         let user = user.into();
         let username = user.username.to_lowercase();
         let email = user.email.map(|val| val.to_lowercase());
         let hashed_password = helpers::hash(&user.password);
 
-        sqlx::query_as!(
-            Self,
-            "INSERT INTO users (id, username, password, email) VALUES ($1, $2, $3, $4)
-                RETURNING *",
-            Uuid::new_v4(),
-            username,
-            hashed_password,
-            email,
-        )
-        .fetch_one(con)
-        .await
+        let (sql, args) = Query::insert()
+            .into_table(Users)
+            .columns([
+                UserIden::Id,
+                UserIden::Username,
+                UserIden::Password,
+                UserIden::Email,
+            ])
+            .values_panic([
+                Uuid::new_v4().into(),
+                username.into(),
+                hashed_password.into(),
+                email.into(),
+            ])
+            .returning(Query::returning().expr(Expr::asterisk()))
+            .build_sqlx(DbQueryBuilder);
+
+        sqlx::query_as_with(&sql, args).fetch_one(con).await
     }
 
+    #[tracing::instrument(name = "User::update", skip_all)]
     pub async fn update<'a, E>(id: Uuid, user: impl Into<UserUpdate>, con: E) -> Result<Self, Error>
     where
-        E: Executor<'a, Database = Postgres>,
+        E: Executor<'a, Database = Db>,
     {
         let user = user.into();
         let username = user.username.map(|val| val.to_lowercase());
         let hashed_password = user.password.map(|val| helpers::hash(&val));
         let email = user.email.map(|val| val.to_lowercase());
 
-        sqlx::query_as!(
-            Self,
-            r#"
-                UPDATE users
-                SET username = COALESCE($1, username),
-                    password = COALESCE($2, password),
-                    email = COALESCE($3, email)
-                WHERE id = $4
-                RETURNING *
-            "#,
-            username,
-            hashed_password,
-            email,
-            id
-        )
-        .fetch_one(con)
-        .await
+        let mut values: Vec<(UserIden, SimpleExpr)> = Vec::new();
+        if let Some(username) = username {
+            values.push((UserIden::Username, username.into()));
+        }
+        if let Some(hashed_password) = hashed_password {
+            values.push((UserIden::Password, hashed_password.into()));
+        }
+        if let Some(email) = email {
+            values.push((UserIden::Email, email.into()));
+        }
+
+        if values.is_empty() {
+            return Self::get_by_id(id, con).await;
+        }
+
+        let (sql, args) = Query::update()
+            .table(Users)
+            .values(values)
+            .and_where(Expr::col(UserIden::Id).eq(id))
+            .returning(Query::returning().expr(Expr::asterisk()))
+            .build_sqlx(DbQueryBuilder);
+
+        sqlx::query_as_with(&sql, args).fetch_one(con).await
     }
 
+    #[tracing::instrument(name = "User::delete", skip_all)]
     pub async fn delete<'a, E>(filters: &UserDeleteFilter, con: E) -> Result<Vec<User>, Error>
     where
-        E: Executor<'a, Database = Postgres>,
+        E: Executor<'a, Database = Db>,
     {
         if filters.is_empty() {
             return Err(Error::RowNotFound);
@@ -171,46 +218,626 @@ impl User {
             .to_owned()
             .apply_conds(filters)
             .returning(Query::returning().expr(Expr::asterisk()))
-            .build_sqlx(PostgresQueryBuilder);
+            .build_sqlx(DbQueryBuilder);
 
         sqlx::query_as_with(&sql, args).fetch_all(con).await
     }
 
+    #[tracing::instrument(name = "User::delete_by_id", skip_all)]
     pub async fn delete_by_id<'a, E>(id: Uuid, con: E) -> Result<User, Error>
     where
-        E: Executor<'a, Database = Postgres>,
+        E: Executor<'a, Database = Db>,
     {
-        sqlx::query_as!(Self, "DELETE FROM users WHERE id = $1 RETURNING *", id)
-            .fetch_one(con)
-            .await
+        let (sql, args) = Query::delete()
+            .from_table(Users)
+            .and_where(Expr::col(UserIden::Id).eq(id))
+            .returning(Query::returning().expr(Expr::asterisk()))
+            .build_sqlx(DbQueryBuilder);
+
+        sqlx::query_as_with(&sql, args).fetch_one(con).await
     }
 
+    #[tracing::instrument(name = "User::update_login_timestamp", skip_all)]
     pub async fn update_login_timestamp<'a, E>(
         id: Uuid,
         con: E,
     ) -> Result<(Uuid, DateTime<Utc>), Error>
     where
-        E: Executor<'a, Database = Postgres>,
+        E: Executor<'a, Database = Db>,
     {
         let now = Utc::now();
 
-        let row = sqlx::query!(
-            r#"
-                UPDATE users SET last_logged_in_at = $1
-                WHERE id = $2
-                RETURNING
-                    id
-            "#,
-            now,
-            id
-        )
-        .fetch_one(con)
-        .await;
+        let (sql, args) = Query::update()
+            .table(Users)
+            .value(UserIden::LastLoggedInAt, now)
+            .and_where(Expr::col(UserIden::Id).eq(id))
+            .returning(Query::returning().column(UserIden::Id))
+            .build_sqlx(DbQueryBuilder);
+
+        let id = sqlx::query_with(&sql, args)
+            .try_map(|row: DbRow| row.try_get::<Uuid, _>(0usize))
+            .fetch_one(con)
+            .await?;
 
-        Ok((row?.id, now))
+        Ok((id, now))
     }
 
     pub fn check_password(&self, password: &str) -> bool {
         helpers::verify(password, &self.password)
     }
 }
+
+/// A long lived key the user can authenticate with instead of a token
+#[derive(Serialize, FromRow, ToSchema)]
+#[enum_def]
+pub struct ApiKey {
+    pub id: Uuid,
+    pub user_id: Uuid,
+    pub name: String,
+    /// The first part of the key, to tell the keys apart
+    pub prefix: String,
+    /// Space separated scopes, limited to the user's grants when the key is used
+    pub scopes: String,
+    pub last_used_at: Option<DateTime<Utc>>,
+    pub expires_at: Option<DateTime<Utc>>,
+    pub created_at: DateTime<Utc>,
+
+    #[serde(skip_serializing)]
+    key_hash: String,
+}
+
+#[derive(Iden)]
+pub struct ApiKeys;
+
+impl ApiKey {
+    #[tracing::instrument(name = "ApiKey::find_for_user", skip_all)]
+    pub async fn find_for_user<'a, E>(user_id: Uuid, con: E) -> Result<Vec<Self>, Error>
+    where
+        E: Executor<'a, Database = Db>,
+    {
+        let (sql, args) = Query::select()
+            .expr(Expr::asterisk())
+            .from(ApiKeys)
+            .and_where(Expr::col(ApiKeyIden::UserId).eq(user_id))
+            .order_by(ApiKeyIden::CreatedAt, Order::Asc)
+            .build_sqlx(DbQueryBuilder);
+
+        sqlx::query_as_with(&sql, args).fetch_all(con).await
+    }
+
+    #[tracing::instrument(name = "ApiKey::get_by_prefix", skip_all)]
+    pub async fn get_by_prefix<'a, E>(prefix: &str, con: E) -> Result<Self, Error>
+    where
+        E: Executor<'a, Database = Db>,
+    {
+        let (sql, args) = Query::select()
+            .expr(Expr::asterisk())
+            .from(ApiKeys)
+            .and_where(Expr::col(ApiKeyIden::Prefix).eq(prefix))
+            .build_sqlx(DbQueryBuilder);
+
+        sqlx::query_as_with(&sql, args).fetch_one(con).await
+    }
+
+    #[tracing::instrument(name = "ApiKey::create", skip_all)]
+    pub async fn create<'a, E>(
+        user_id: Uuid,
+        key: ApiKeyCreate,
+        prefix: &str,
+        key_hash: &str,
+        con: E,
+    ) -> Result<Self, Error>
+    where
+        E: Executor<'a, Database = Db>,
+    {
+        let (sql, args) = Query::insert()
+            .into_table(ApiKeys)
+            .columns([
+                ApiKeyIden::Id,
+                ApiKeyIden::UserId,
+                ApiKeyIden::Name,
+                ApiKeyIden::Prefix,
+                ApiKeyIden::KeyHash,
+                ApiKeyIden::Scopes,
+                ApiKeyIden::ExpiresAt,
+            ])
+            .values_panic([
+                Uuid::new_v4().into(),
+                user_id.into(),
+                key.name.into(),
+                prefix.into(),
+                key_hash.into(),
+                key.scopes.join(" ").into(),
+                key.expires_at.into(),
+            ])
+            .returning(Query::returning().expr(Expr::asterisk()))
+            .build_sqlx(DbQueryBuilder);
+
+        sqlx::query_as_with(&sql, args).fetch_one(con).await
+    }
+
+    #[tracing::instrument(name = "ApiKey::update_last_used", skip_all)]
+    pub async fn update_last_used<'a, E>(id: Uuid, con: E) -> Result<(), Error>
+    where
+        E: Executor<'a, Database = Db>,
+    {
+        let (sql, args) = Query::update()
+            .table(ApiKeys)
+            .value(ApiKeyIden::LastUsedAt, Utc::now())
+            .and_where(Expr::col(ApiKeyIden::Id).eq(id))
+            .build_sqlx(DbQueryBuilder);
+
+        sqlx::query_with(&sql, args).execute(con).await?;
+        Ok(())
+    }
+
+    /// Only the owner can delete the key
+    #[tracing::instrument(name = "ApiKey::delete_for_user", skip_all)]
+    pub async fn delete_for_user<'a, E>(id: Uuid, user_id: Uuid, con: E) -> Result<Self, Error>
+    where
+        E: Executor<'a, Database = Db>,
+    {
+        let (sql, args) = Query::delete()
+            .from_table(ApiKeys)
+            .and_where(Expr::col(ApiKeyIden::Id).eq(id))
+            .and_where(Expr::col(ApiKeyIden::UserId).eq(user_id))
+            .returning(Query::returning().expr(Expr::asterisk()))
+            .build_sqlx(DbQueryBuilder);
+
+        sqlx::query_as_with(&sql, args).fetch_one(con).await
+    }
+
+    pub fn check_key(&self, key: &str) -> bool {
+        helpers::verify_api_key(key, &self.key_hash)
+    }
+
+    pub fn is_expired(&self) -> bool {
+        self.expires_at
+            .map_or(false, |expires_at| expires_at <= Utc::now())
+    }
+
+    /// Whether `last_used_at` should be updated, so every request doesn't write to the database
+    pub fn is_last_use_stale(&self) -> bool {
+        self.last_used_at.map_or(true, |last_used_at| {
+            Utc::now() - last_used_at >= chrono::Duration::seconds(API_KEY_LAST_USED_INTERVAL)
+        })
+    }
+
+    pub fn scope_list(&self) -> Vec<String> {
+        self.scopes.split_whitespace().map(String::from).collect()
+    }
+}
+
+/// The user's TOTP secret, MFA is enabled once it's confirmed with a code
+#[derive(FromRow)]
+pub struct TotpSecret {
+    pub user_id: Uuid,
+    pub secret: String,
+    pub confirmed_at: Option<DateTime<Utc>>,
+}
+
+#[derive(Iden)]
+enum TotpSecretIden {
+    #[iden = "totp_secrets"]
+    Table,
+    UserId,
+    Secret,
+    // The last accepted time step, so a code can't be replayed
+    LastUsedStep,
+    ConfirmedAt,
+}
+
+impl TotpSecret {
+    #[tracing::instrument(name = "TotpSecret::get", skip_all)]
+    pub async fn get<'a, E>(user_id: Uuid, con: E) -> Result<Self, Error>
+    where
+        E: Executor<'a, Database = Db>,
+    {
+        let (sql, args) = Query::select()
+            .expr(Expr::asterisk())
+            .from(TotpSecretIden::Table)
+            .and_where(Expr::col(TotpSecretIden::UserId).eq(user_id))
+            .build_sqlx(DbQueryBuilder);
+
+        sqlx::query_as_with(&sql, args).fetch_one(con).await
+    }
+
+    #[tracing::instrument(name = "TotpSecret::create", skip_all)]
+    pub async fn create<'a, E>(user_id: Uuid, secret: &str, con: E) -> Result<Self, Error>
+    where
+        E: Executor<'a, Database = Db>,
+    {
+        let (sql, args) = Query::insert()
+            .into_table(TotpSecretIden::Table)
+            .columns([TotpSecretIden::UserId, TotpSecretIden::Secret])
+            .values_panic([user_id.into(), secret.into()])
+            .returning(Query::returning().expr(Expr::asterisk()))
+            .build_sqlx(DbQueryBuilder);
+
+        sqlx::query_as_with(&sql, args).fetch_one(con).await
+    }
+
+    #[tracing::instrument(name = "TotpSecret::confirm", skip_all)]
+    pub async fn confirm<'a, E>(user_id: Uuid, con: E) -> Result<(), Error>
+    where
+        E: Executor<'a, Database = Db>,
+    {
+        let (sql, args) = Query::update()
+            .table(TotpSecretIden::Table)
+            .value(TotpSecretIden::ConfirmedAt, Utc::now())
+            .and_where(Expr::col(TotpSecretIden::UserId).eq(user_id))
+            .build_sqlx(DbQueryBuilder);
+
+        sqlx::query_with(&sql, args).execute(con).await?;
+        Ok(())
+    }
+
+    /// Mark the time step as used, returns false if it or a later one is already used
+    #[tracing::instrument(name = "TotpSecret::use_step", skip_all)]
+    pub async fn use_step<'a, E>(user_id: Uuid, step: i64, con: E) -> Result<bool, Error>
+    where
+        E: Executor<'a, Database = Db>,
+    {
+        let (sql, args) = Query::update()
+            .table(TotpSecretIden::Table)
+            .value(TotpSecretIden::LastUsedStep, step)
+            .and_where(Expr::col(TotpSecretIden::UserId).eq(user_id))
+            .cond_where(
+                Cond::any()
+                    .add(Expr::col(TotpSecretIden::LastUsedStep).is_null())
+                    .add(Expr::col(TotpSecretIden::LastUsedStep).lt(step)),
+            )
+            .build_sqlx(DbQueryBuilder);
+
+        let res = sqlx::query_with(&sql, args).execute(con).await?;
+        Ok(res.rows_affected() == 1)
+    }
+
+    #[tracing::instrument(name = "TotpSecret::delete", skip_all)]
+    pub async fn delete<'a, E>(user_id: Uuid, con: E) -> Result<(), Error>
+    where
+        E: Executor<'a, Database = Db>,
+    {
+        let (sql, args) = Query::delete()
+            .from_table(TotpSecretIden::Table)
+            .and_where(Expr::col(TotpSecretIden::UserId).eq(user_id))
+            .build_sqlx(DbQueryBuilder);
+
+        sqlx::query_with(&sql, args).execute(con).await?;
+        Ok(())
+    }
+
+    pub fn is_confirmed(&self) -> bool {
+        self.confirmed_at.is_some()
+    }
+
+    /// Check a TOTP or a recovery code, the recovery codes are used up
+    pub async fn check_code(&self, code: &str, pool: &DbPool) -> Result<bool, Error> {
+        if totp::is_totp_code(code) {
+            match totp::verify(&self.secret, code) {
+                Some(step) => TotpSecret::use_step(self.user_id, step, pool).await,
+                None => Ok(false),
+            }
+        } else {
+            RecoveryCode::use_code(self.user_id, code, pool).await
+        }
+    }
+}
+
+/// Only the hashes of the recovery codes are stored, they're shown once when MFA is enabled
+pub struct RecoveryCode;
+
+#[derive(Iden)]
+enum RecoveryCodeIden {
+    #[iden = "recovery_codes"]
+    Table,
+    Id,
+    UserId,
+    CodeHash,
+    UsedAt,
+}
+
+impl RecoveryCode {
+    /// Replace the user's codes with new ones, returns the codes
+    #[tracing::instrument(name = "RecoveryCode::regenerate", skip_all)]
+    pub async fn regenerate(
+        user_id: Uuid,
+        con: &mut <Db as sqlx::Database>::Connection,
+    ) -> Result<Vec<String>, Error> {
+        RecoveryCode::delete_for_user(user_id, &mut *con).await?;
+
+        let codes = totp::generate_recovery_codes();
+
+        let mut q = Query::insert()
+            .into_table(RecoveryCodeIden::Table)
+            .columns([
+                RecoveryCodeIden::Id,
+                RecoveryCodeIden::UserId,
+                RecoveryCodeIden::CodeHash,
+            ])
+            .to_owned();
+        for code in codes.iter() {
+            q.values_panic([
+                Uuid::new_v4().into(),
+                user_id.into(),
+                totp::hash_recovery_code(code).into(),
+            ]);
+        }
+        let (sql, args) = q.build_sqlx(DbQueryBuilder);
+
+        sqlx::query_with(&sql, args).execute(&mut *con).await?;
+        Ok(codes)
+    }
+
+    /// Returns false if the code doesn't exist or it's already used
+    #[tracing::instrument(name = "RecoveryCode::use_code", skip_all)]
+    pub async fn use_code<'a, E>(user_id: Uuid, code: &str, con: E) -> Result<bool, Error>
+    where
+        E: Executor<'a, Database = Db>,
+    {
+        let (sql, args) = Query::update()
+            .table(RecoveryCodeIden::Table)
+            .value(RecoveryCodeIden::UsedAt, Utc::now())
+            .and_where(Expr::col(RecoveryCodeIden::UserId).eq(user_id))
+            .and_where(Expr::col(RecoveryCodeIden::CodeHash).eq(totp::hash_recovery_code(code)))
+            .and_where(Expr::col(RecoveryCodeIden::UsedAt).is_null())
+            .build_sqlx(DbQueryBuilder);
+
+        let res = sqlx::query_with(&sql, args).execute(con).await?;
+        Ok(res.rows_affected() == 1)
+    }
+
+    #[tracing::instrument(name = "RecoveryCode::delete_for_user", skip_all)]
+    pub async fn delete_for_user<'a, E>(user_id: Uuid, con: E) -> Result<(), Error>
+    where
+        E: Executor<'a, Database = Db>,
+    {
+        let (sql, args) = Query::delete()
+            .from_table(RecoveryCodeIden::Table)
+            .and_where(Expr::col(RecoveryCodeIden::UserId).eq(user_id))
+            .build_sqlx(DbQueryBuilder);
+
+        sqlx::query_with(&sql, args).execute(con).await?;
+        Ok(())
+    }
+}
+
+/// A WebAuthn credential the user can login with instead of the password
+#[derive(Serialize, FromRow, ToSchema)]
+#[enum_def]
+pub struct Passkey {
+    pub id: Uuid,
+    pub user_id: Uuid,
+    pub name: String,
+    /// Base64url encoded id of the credential
+    pub credential_id: String,
+    pub last_used_at: Option<DateTime<Utc>>,
+    pub created_at: DateTime<Utc>,
+
+    #[serde(skip_serializing)]
+    pub public_key: Vec<u8>,
+    #[serde(skip_serializing)]
+    pub sign_count: i64,
+}
+
+#[derive(Iden)]
+pub struct Passkeys;
+
+impl Passkey {
+    #[tracing::instrument(name = "Passkey::find_for_user", skip_all)]
+    pub async fn find_for_user<'a, E>(user_id: Uuid, con: E) -> Result<Vec<Self>, Error>
+    where
+        E: Executor<'a, Database = Db>,
+    {
+        let (sql, args) = Query::select()
+            .expr(Expr::asterisk())
+            .from(Passkeys)
+            .and_where(Expr::col(PasskeyIden::UserId).eq(user_id))
+            .order_by(PasskeyIden::CreatedAt, Order::Asc)
+            .build_sqlx(DbQueryBuilder);
+
+        sqlx::query_as_with(&sql, args).fetch_all(con).await
+    }
+
+    #[tracing::instrument(name = "Passkey::get_by_credential_id", skip_all)]
+    pub async fn get_by_credential_id<'a, E>(credential_id: &str, con: E) -> Result<Self, Error>
+    where
+        E: Executor<'a, Database = Db>,
+    {
+        let (sql, args) = Query::select()
+            .expr(Expr::asterisk())
+            .from(Passkeys)
+            .and_where(Expr::col(PasskeyIden::CredentialId).eq(credential_id))
+            .build_sqlx(DbQueryBuilder);
+
+        sqlx::query_as_with(&sql, args).fetch_one(con).await
+    }
+
+    #[tracing::instrument(name = "Passkey::create", skip_all)]
+    pub async fn create<'a, E>(
+        user_id: Uuid,
+        name: &str,
+        credential_id: &str,
+        public_key: Vec<u8>,
+        sign_count: u32,
+        con: E,
+    ) -> Result<Self, Error>
+    where
+        E: Executor<'a, Database = Db>,
+    {
+        let (sql, args) = Query::insert()
+            .into_table(Passkeys)
+            .columns([
+                PasskeyIden::Id,
+                PasskeyIden::UserId,
+                PasskeyIden::Name,
+                PasskeyIden::CredentialId,
+                PasskeyIden::PublicKey,
+                PasskeyIden::SignCount,
+            ])
+            .values_panic([
+                Uuid::new_v4().into(),
+                user_id.into(),
+                name.into(),
+                credential_id.into(),
+                public_key.into(),
+                i64::from(sign_count).into(),
+            ])
+            .returning(Query::returning().expr(Expr::asterisk()))
+            .build_sqlx(DbQueryBuilder);
+
+        sqlx::query_as_with(&sql, args).fetch_one(con).await
+    }
+
+    #[tracing::instrument(name = "Passkey::update_sign_count", skip_all)]
+    pub async fn update_sign_count<'a, E>(
+        credential_id: &str,
+        sign_count: u32,
+        con: E,
+    ) -> Result<(), Error>
+    where
+        E: Executor<'a, Database = Db>,
+    {
+        let (sql, args) = Query::update()
+            .table(Passkeys)
+            .value(PasskeyIden::SignCount, i64::from(sign_count))
+            .value(PasskeyIden::LastUsedAt, Utc::now())
+            .and_where(Expr::col(PasskeyIden::CredentialId).eq(credential_id))
+            .build_sqlx(DbQueryBuilder);
+
+        sqlx::query_with(&sql, args).execute(con).await?;
+        Ok(())
+    }
+
+    /// Only the owner can delete the passkey
+    #[tracing::instrument(name = "Passkey::delete_for_user", skip_all)]
+    pub async fn delete_for_user<'a, E>(id: Uuid, user_id: Uuid, con: E) -> Result<Self, Error>
+    where
+        E: Executor<'a, Database = Db>,
+    {
+        let (sql, args) = Query::delete()
+            .from_table(Passkeys)
+            .and_where(Expr::col(PasskeyIden::Id).eq(id))
+            .and_where(Expr::col(PasskeyIden::UserId).eq(user_id))
+            .returning(Query::returning().expr(Expr::asterisk()))
+            .build_sqlx(DbQueryBuilder);
+
+        sqlx::query_as_with(&sql, args).fetch_one(con).await
+    }
+}
+
+/// An account of an OpenID Connect provider the user can login with
+#[derive(Serialize, FromRow, ToSchema)]
+#[enum_def]
+pub struct ExternalIdentity {
+    pub id: Uuid,
+    pub user_id: Uuid,
+    /// Issuer of the provider, ex. `https://accounts.google.com`
+    pub issuer: String,
+    /// Id of the user at the provider
+    pub subject: String,
+    pub email: Option<String>,
+    pub last_used_at: Option<DateTime<Utc>>,
+    pub created_at: DateTime<Utc>,
+}
+
+#[derive(Iden)]
+pub struct ExternalIdentities;
+
+impl ExternalIdentity {
+    #[tracing::instrument(name = "ExternalIdentity::find_for_user", skip_all)]
+    pub async fn find_for_user<'a, E>(user_id: Uuid, con: E) -> Result<Vec<Self>, Error>
+    where
+        E: Executor<'a, Database = Db>,
+    {
+        let (sql, args) = Query::select()
+            .expr(Expr::asterisk())
+            .from(ExternalIdentities)
+            .and_where(Expr::col(ExternalIdentityIden::UserId).eq(user_id))
+            .order_by(ExternalIdentityIden::CreatedAt, Order::Asc)
+            .build_sqlx(DbQueryBuilder);
+
+        sqlx::query_as_with(&sql, args).fetch_all(con).await
+    }
+
+    #[tracing::instrument(name = "ExternalIdentity::get", skip_all)]
+    pub async fn get<'a, E>(issuer: &str, subject: &str, con: E) -> Result<Self, Error>
+    where
+        E: Executor<'a, Database = Db>,
+    {
+        let (sql, args) = Query::select()
+            .expr(Expr::asterisk())
+            .from(ExternalIdentities)
+            .and_where(Expr::col(ExternalIdentityIden::Issuer).eq(issuer))
+            .and_where(Expr::col(ExternalIdentityIden::Subject).eq(subject))
+            .build_sqlx(DbQueryBuilder);
+
+        sqlx::query_as_with(&sql, args).fetch_one(con).await
+    }
+
+    #[tracing::instrument(name = "ExternalIdentity::create", skip_all)]
+    pub async fn create<'a, E>(
+        user_id: Uuid,
+        issuer: &str,
+        subject: &str,
+        email: Option<&str>,
+        con: E,
+    ) -> Result<Self, Error>
+    where
+        E: Executor<'a, Database = Db>,
+    {
+        let (sql, args) = Query::insert()
+            .into_table(ExternalIdentities)
+            .columns([
+                ExternalIdentityIden::Id,
+                ExternalIdentityIden::UserId,
+                ExternalIdentityIden::Issuer,
+                ExternalIdentityIden::Subject,
+                ExternalIdentityIden::Email,
+                ExternalIdentityIden::LastUsedAt,
+            ])
+            .values_panic([
+                Uuid::new_v4().into(),
+                user_id.into(),
+                issuer.into(),
+                subject.into(),
+                email.into(),
+                Utc::now().into(),
+            ])
+            .returning(Query::returning().expr(Expr::asterisk()))
+            .build_sqlx(DbQueryBuilder);
+
+        sqlx::query_as_with(&sql, args).fetch_one(con).await
+    }
+
+    #[tracing::instrument(name = "ExternalIdentity::update_last_used", skip_all)]
+    pub async fn update_last_used<'a, E>(id: Uuid, con: E) -> Result<(), Error>
+    where
+        E: Executor<'a, Database = Db>,
+    {
+        let (sql, args) = Query::update()
+            .table(ExternalIdentities)
+            .value(ExternalIdentityIden::LastUsedAt, Utc::now())
+            .and_where(Expr::col(ExternalIdentityIden::Id).eq(id))
+            .build_sqlx(DbQueryBuilder);
+
+        sqlx::query_with(&sql, args).execute(con).await?;
+        Ok(())
+    }
+
+    /// Only the owner can unlink the identity
+    #[tracing::instrument(name = "ExternalIdentity::delete_for_user", skip_all)]
+    pub async fn delete_for_user<'a, E>(id: Uuid, user_id: Uuid, con: E) -> Result<Self, Error>
+    where
+        E: Executor<'a, Database = Db>,
+    {
+        let (sql, args) = Query::delete()
+            .from_table(ExternalIdentities)
+            .and_where(Expr::col(ExternalIdentityIden::Id).eq(id))
+            .and_where(Expr::col(ExternalIdentityIden::UserId).eq(user_id))
+            .returning(Query::returning().expr(Expr::asterisk()))
+            .build_sqlx(DbQueryBuilder);
+
+        sqlx::query_as_with(&sql, args).fetch_one(con).await
+    }
+}
@@ -1,20 +1,56 @@
 use seaqs::filters::{DateTimeTzFilterSet, StringFilterSet, UuidFilterSet};
 use utoipa::OpenApi;
 
-use crate::{admin, errors::UserErrorOai, handlers, models::User, schemas::UserList};
+use crate::{
+    admin,
+    errors::UserErrorOai,
+    handlers,
+    models::{ApiKey, ExternalIdentity, Passkey, User},
+    schemas::{
+        ApiKeyList, ApiKeyWithSecret, ExternalIdentityList, MfaCode, PasskeyList, RecoveryCodes,
+        TotpEnrollment, UserList,
+    },
+};
 
 #[derive(OpenApi)]
 #[openapi(
     info(description = "User management endpoints"),
-    paths(handlers::signup, handlers::get_me, handlers::update),
+    paths(
+        handlers::signup,
+        handlers::get_me,
+        handlers::update,
+        handlers::list_api_keys,
+        handlers::create_api_key,
+        handlers::delete_api_key,
+        handlers::list_passkeys,
+        handlers::delete_passkey,
+        handlers::list_identities,
+        handlers::delete_identity,
+        handlers::enroll_totp,
+        handlers::confirm_totp,
+        handlers::disable_totp
+    ),
     components(schemas(
         // Response
         User,
+        ApiKey,
+        ApiKeyList,
+        ApiKeyWithSecret,
+        Passkey,
+        PasskeyList,
+        ExternalIdentity,
+        ExternalIdentityList,
+        TotpEnrollment,
+        MfaCode,
+        RecoveryCodes,
 
         // Errors
         UserErrorOai::NotFound,
         UserErrorOai::ValidationError,
-        UserErrorOai::DuplicateField
+        UserErrorOai::DuplicateField,
+        UserErrorOai::ScopeNotGranted,
+        UserErrorOai::InvalidMfaCode,
+        UserErrorOai::MfaAlreadyEnabled
     ))
 )]
 pub(crate) struct PublicUserOpenApi;
@@ -1,8 +1,11 @@
-use axum::Extension;
-use mtapp_auth::{AuthError, UserProvider};
-use sqlx::{types::Uuid, PgPool};
+use axum::extract::State;
+use mtapp::db::DbPool;
+use mtapp_auth::{AuthError, PasskeyProvider, UserProvider};
+use sqlx::types::Uuid;
 
-use crate::models::User;
+use crate::helpers;
+use crate::models::{ApiKey, ExternalIdentity, Passkey, TotpSecret, User};
+use crate::schemas::UserCreate;
 
 fn extract_error(err: sqlx::Error) -> AuthError {
     match err {
@@ -15,10 +18,10 @@ pub struct Provider;
 
 #[axum::async_trait]
 impl UserProvider for Provider {
-    type Data<S: Sync + Send + 'static> = Extension<PgPool>;
+    type Data = State<DbPool>;
 
-    async fn login<S: Send + Sync + 'static>(
-        Extension(pool): &Extension<PgPool>,
+    async fn login(
+        State(pool): &State<DbPool>,
         username: &str,
         password: &str,
     ) -> Result<Uuid, AuthError> {
@@ -35,4 +38,218 @@ impl UserProvider for Provider {
             Err(AuthError::Credentials)
         }
     }
+
+    async fn external_login(
+        State(pool): &State<DbPool>,
+        identity: &mtapp_auth::ExternalIdentity,
+    ) -> Result<Uuid, AuthError> {
+        match ExternalIdentity::get(&identity.issuer, &identity.subject, pool).await {
+            Ok(linked) => {
+                ExternalIdentity::update_last_used(linked.id, pool)
+                    .await
+                    .map_err(AuthError::DatabaseError)?;
+                User::update_login_timestamp(linked.user_id, pool)
+                    .await
+                    .map_err(extract_error)?;
+                return Ok(linked.user_id);
+            }
+            Err(sqlx::Error::RowNotFound) => {}
+            Err(err) => return Err(AuthError::DatabaseError(err)),
+        }
+
+        // First login with the identity, make a new user for it. Existing users with the same
+        // email aren't matched, they should link the identity themselves
+        let mut username = helpers::external_username(
+            identity.preferred_username.as_deref(),
+            identity.email.as_deref(),
+        );
+        match User::get_by_username(&username, pool).await {
+            Ok(_) => username = format!("{}-{}", username, helpers::username_suffix()),
+            Err(sqlx::Error::RowNotFound) => {}
+            Err(err) => return Err(AuthError::DatabaseError(err)),
+        }
+
+        // Only a verified email which isn't used by another user is kept
+        let email = match identity.email.as_deref() {
+            Some(email) if identity.email_verified => match User::get_by_email(email, pool).await {
+                Ok(_) => None,
+                Err(sqlx::Error::RowNotFound) => Some(email.to_owned()),
+                Err(err) => return Err(AuthError::DatabaseError(err)),
+            },
+            _ => None,
+        };
+
+        let mut tx = pool.begin().await.map_err(AuthError::DatabaseError)?;
+        let user = User::create(
+            UserCreate {
+                username,
+                password: helpers::random_password(),
+                email,
+            },
+            &mut tx,
+        )
+        .await
+        .map_err(AuthError::DatabaseError)?;
+        ExternalIdentity::create(
+            user.id,
+            &identity.issuer,
+            &identity.subject,
+            identity.email.as_deref(),
+            &mut tx,
+        )
+        .await
+        .map_err(AuthError::DatabaseError)?;
+        User::update_login_timestamp(user.id, &mut tx)
+            .await
+            .map_err(AuthError::DatabaseError)?;
+        tx.commit().await.map_err(AuthError::DatabaseError)?;
+
+        Ok(user.id)
+    }
+
+    async fn link_identity(
+        State(pool): &State<DbPool>,
+        user_id: Uuid,
+        identity: &mtapp_auth::ExternalIdentity,
+    ) -> Result<(), AuthError> {
+        match ExternalIdentity::get(&identity.issuer, &identity.subject, pool).await {
+            Ok(linked) if linked.user_id == user_id => return Ok(()),
+            Ok(_) => return Err(AuthError::Permission),
+            Err(sqlx::Error::RowNotFound) => {}
+            Err(err) => return Err(AuthError::DatabaseError(err)),
+        }
+
+        ExternalIdentity::create(
+            user_id,
+            &identity.issuer,
+            &identity.subject,
+            identity.email.as_deref(),
+            pool,
+        )
+        .await
+        .map_err(AuthError::DatabaseError)?;
+        Ok(())
+    }
+
+    async fn api_key(
+        State(pool): &State<DbPool>,
+        key: &str,
+    ) -> Result<(Uuid, Uuid, Vec<String>), AuthError> {
+        let prefix = helpers::api_key_prefix(key).ok_or(AuthError::BadToken)?;
+        let api_key = match ApiKey::get_by_prefix(prefix, pool).await {
+            Ok(api_key) => api_key,
+            Err(sqlx::Error::RowNotFound) => return Err(AuthError::BadToken),
+            Err(err) => return Err(AuthError::DatabaseError(err)),
+        };
+
+        if !api_key.check_key(key) || api_key.is_expired() {
+            return Err(AuthError::BadToken);
+        }
+
+        if api_key.is_last_use_stale() {
+            ApiKey::update_last_used(api_key.id, pool)
+                .await
+                .map_err(AuthError::DatabaseError)?;
+        }
+        Ok((api_key.user_id, api_key.id, api_key.scope_list()))
+    }
+
+    async fn mfa_required(State(pool): &State<DbPool>, user_id: Uuid) -> Result<bool, AuthError> {
+        match TotpSecret::get(user_id, pool).await {
+            Ok(secret) => Ok(secret.is_confirmed()),
+            Err(sqlx::Error::RowNotFound) => Ok(false),
+            Err(err) => Err(AuthError::DatabaseError(err)),
+        }
+    }
+
+    async fn verify_mfa(
+        State(pool): &State<DbPool>,
+        user_id: Uuid,
+        code: &str,
+    ) -> Result<(), AuthError> {
+        let secret = TotpSecret::get(user_id, pool)
+            .await
+            .map_err(extract_error)?;
+
+        if secret.is_confirmed()
+            && secret
+                .check_code(code, pool)
+                .await
+                .map_err(AuthError::DatabaseError)?
+        {
+            Ok(())
+        } else {
+            Err(AuthError::Credentials)
+        }
+    }
+}
+
+#[axum::async_trait]
+impl PasskeyProvider for Provider {
+    type Data = State<DbPool>;
+
+    async fn passkey_user(
+        State(pool): &State<DbPool>,
+        user_id: Uuid,
+    ) -> Result<(String, Vec<String>), AuthError> {
+        let user = User::get_by_id(user_id, pool)
+            .await
+            .map_err(extract_error)?;
+        let passkeys = Passkey::find_for_user(user_id, pool)
+            .await
+            .map_err(AuthError::DatabaseError)?;
+
+        Ok((
+            user.username,
+            passkeys
+                .into_iter()
+                .map(|passkey| passkey.credential_id)
+                .collect(),
+        ))
+    }
+
+    async fn add(
+        State(pool): &State<DbPool>,
+        passkey: mtapp_auth::Passkey,
+        name: &str,
+    ) -> Result<(), AuthError> {
+        Passkey::create(
+            passkey.user_id,
+            name,
+            &passkey.credential_id,
+            passkey.public_key,
+            passkey.sign_count,
+            pool,
+        )
+        .await
+        .map_err(AuthError::DatabaseError)?;
+        Ok(())
+    }
+
+    async fn find(
+        State(pool): &State<DbPool>,
+        credential_id: &str,
+    ) -> Result<mtapp_auth::Passkey, AuthError> {
+        let passkey = Passkey::get_by_credential_id(credential_id, pool)
+            .await
+            .map_err(extract_error)?;
+
+        Ok(mtapp_auth::Passkey {
+            credential_id: passkey.credential_id,
+            user_id: passkey.user_id,
+            public_key: passkey.public_key,
+            // Stored from a u32
+            sign_count: passkey.sign_count as u32,
+        })
+    }
+
+    async fn update_sign_count(
+        State(pool): &State<DbPool>,
+        credential_id: &str,
+        sign_count: u32,
+    ) -> Result<(), AuthError> {
+        Passkey::update_sign_count(credential_id, sign_count, pool)
+            .await
+            .map_err(AuthError::DatabaseError)
+    }
 }
@@ -1,11 +1,12 @@
-use serde::Deserialize;
+use serde::{Deserialize, Serialize};
+use sqlx::types::chrono::{DateTime, Utc};
 use utoipa::{
     openapi::{ArrayBuilder, RefOr, Schema},
     ToSchema,
 };
 use validator::Validate;
 
-use crate::User;
+use crate::models::{ApiKey, ExternalIdentity, Passkey, User};
 
 #[derive(Validate, Deserialize, ToSchema)]
 pub struct UserCreate {
@@ -73,3 +74,81 @@ impl ToSchema<'static> for UserList {
         )
     }
 }
+
+#[derive(Validate, Deserialize, ToSchema)]
+pub struct ApiKeyCreate {
+    #[validate(length(min = 1, max = 64))]
+    pub name: String,
+    /// A subset of the scopes granted to the user
+    #[serde(default)]
+    pub scopes: Vec<String>,
+    /// The key never expires if not set
+    pub expires_at: Option<DateTime<Utc>>,
+}
+
+/// The key is only shown once, when it's created
+#[derive(Serialize, ToSchema)]
+pub struct ApiKeyWithSecret {
+    #[serde(flatten)]
+    pub api_key: ApiKey,
+    pub key: String,
+}
+
+pub(crate) struct ApiKeyList(Vec<ApiKey>);
+
+impl ToSchema<'static> for ApiKeyList {
+    fn schema() -> (&'static str, RefOr<Schema>) {
+        (
+            "ApiKeyList",
+            ArrayBuilder::new().items(ApiKey::schema().1).build().into(),
+        )
+    }
+}
+
+pub(crate) struct PasskeyList(Vec<Passkey>);
+
+impl ToSchema<'static> for PasskeyList {
+    fn schema() -> (&'static str, RefOr<Schema>) {
+        (
+            "PasskeyList",
+            ArrayBuilder::new()
+                .items(Passkey::schema().1)
+                .build()
+                .into(),
+        )
+    }
+}
+
+pub(crate) struct ExternalIdentityList(Vec<ExternalIdentity>);
+
+impl ToSchema<'static> for ExternalIdentityList {
+    fn schema() -> (&'static str, RefOr<Schema>) {
+        (
+            "ExternalIdentityList",
+            ArrayBuilder::new()
+                .items(ExternalIdentity::schema().1)
+                .build()
+                .into(),
+        )
+    }
+}
+
+#[derive(Serialize, ToSchema)]
+pub struct TotpEnrollment {
+    /// Base32 encoded, for the apps which can't scan the uri
+    pub secret: String,
+    /// Should be shown as a QR code
+    pub otpauth_uri: String,
+}
+
+#[derive(Deserialize, ToSchema)]
+pub struct MfaCode {
+    /// A TOTP code, or a recovery code when disabling MFA
+    pub code: String,
+}
+
+/// Each code can be used once instead of a TOTP code, they're only shown once
+#[derive(Serialize, ToSchema)]
+pub struct RecoveryCodes {
+    pub recovery_codes: Vec<String>,
+}
@@ -0,0 +1,127 @@
+// TOTP codes of RFC 6238, with the defaults the authenticator apps expect(SHA1, 6 digits and
+// 30 seconds steps)
+use std::time::{SystemTime, UNIX_EPOCH};
+
+use base32::Alphabet;
+use mtapp_auth::secrets;
+use percent_encoding::{utf8_percent_encode, NON_ALPHANUMERIC};
+use ring::hmac;
+use sodiumoxide::{hex, randombytes};
+
+const STEP: u64 = 30;
+const DIGITS: u32 = 6;
+const RECOVERY_CODES: usize = 10;
+// 80 bits of each code, too many to guess offline from a leaked hash
+const RECOVERY_CODE_BYTES: usize = 10;
+
+const BASE32: Alphabet = Alphabet::RFC4648 { padding: false };
+
+/// A new base32 encoded secret
+pub(crate) fn generate_secret() -> String {
+    base32::encode(BASE32, &randombytes::randombytes(20))
+}
+
+/// The uri authenticator apps read from the QR code
+pub(crate) fn otpauth_uri(issuer: &str, account: &str, secret: &str) -> String {
+    let issuer = utf8_percent_encode(issuer, NON_ALPHANUMERIC);
+    format!(
+        "otpauth://totp/{}:{}?secret={}&issuer={}&algorithm=SHA1&digits={}&period={}",
+        issuer,
+        utf8_percent_encode(account, NON_ALPHANUMERIC),
+        secret,
+        issuer,
+        DIGITS,
+        STEP
+    )
+}
+
+fn code_at(key: &hmac::Key, step: u64) -> String {
+    let digest = hmac::sign(key, &step.to_be_bytes());
+    let digest = digest.as_ref();
+
+    // Dynamic truncation
+    let offset = (digest[digest.len() - 1] & 0x0f) as usize;
+    let value = u32::from_be_bytes([
+        digest[offset] & 0x7f,
+        digest[offset + 1],
+        digest[offset + 2],
+        digest[offset + 3],
+    ]);
+
+    format!(
+        "{:0width$}",
+        value % 10u32.pow(DIGITS),
+        width = DIGITS as usize
+    )
+}
+
+/// Returns the time step the code belongs to, the steps next to the current one are accepted to
+/// tolerate clock drifts
+pub(crate) fn verify(secret: &str, code: &str) -> Option<i64> {
+    let secret = base32::decode(BASE32, secret)?;
+    let key = hmac::Key::new(hmac::HMAC_SHA1_FOR_LEGACY_USE_ONLY, &secret);
+
+    let now = SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .expect("UNIX_EPOCH is past")
+        .as_secs()
+        / STEP;
+
+    [now - 1, now, now + 1]
+        .into_iter()
+        .find(|step| secrets::constant_time_eq(code_at(&key, *step).as_bytes(), code.as_bytes()))
+        .map(|step| step as i64)
+}
+
+pub(crate) fn is_totp_code(code: &str) -> bool {
+    code.len() == DIGITS as usize && code.bytes().all(|c| c.is_ascii_digit())
+}
+
+/// New recovery codes, formatted as `xxxxx-xxxxx-xxxxx-xxxxx`
+pub(crate) fn generate_recovery_codes() -> Vec<String> {
+    (0..RECOVERY_CODES)
+        .map(|_| {
+            let code = hex::encode(randombytes::randombytes(RECOVERY_CODE_BYTES));
+            code.as_bytes()
+                .chunks(5)
+                .map(|group| std::str::from_utf8(group).expect("Hex is ascii"))
+                .collect::<Vec<_>>()
+                .join("-")
+        })
+        .collect()
+}
+
+/// The dashes and the case of the code are ignored
+pub(crate) fn hash_recovery_code(code: &str) -> String {
+    let code: String = code
+        .chars()
+        .filter(char::is_ascii_alphanumeric)
+        .map(|c| c.to_ascii_lowercase())
+        .collect();
+    secrets::hash(&code)
+}
+
+#[cfg(test)]
+mod tests {
+    use ring::hmac;
+
+    use super::{code_at, STEP};
+
+    #[test]
+    fn rfc6238_sha1_vectors() {
+        // The RFC's codes have 8 digits, so only their last 6 are compared
+        let key = hmac::Key::new(hmac::HMAC_SHA1_FOR_LEGACY_USE_ONLY, b"12345678901234567890");
+        let vectors = [
+            (59, "94287082"),
+            (1111111109, "07081804"),
+            (1111111111, "14050471"),
+            (1234567890, "89005924"),
+            (2000000000, "69279037"),
+            (20000000000, "65353130"),
+        ];
+
+        for (time, code) in vectors {
+            assert_eq!(code_at(&key, time / STEP), code[2..], "at {}", time);
+        }
+    }
+}
@@ -0,0 +1,2 @@
+-- Sqlite has no stored functions, updated_at columns are kept up to date by per table triggers
+SELECT 1;
@@ -1,20 +1,45 @@
+use std::future::Future;
+
 use axum::{http::Extensions, Router};
 use smig_lib::Migration;
 use utoipa::openapi::OpenApi;
 
+use crate::{
+    probes::ReadinessCheck,
+    settings::{Settings, SettingsError},
+    state::ReactorState,
+    task::{BackgroundTask, Shutdown},
+};
+
 #[axum::async_trait(?Send)]
 pub trait App {
     fn name(&self) -> &'static str;
 
+    /// Names of the apps this app depends on, they are configured and migrated before this app
+    fn dependencies(&self) -> &'static [&'static str] {
+        &[]
+    }
+
     /// Public routes
-    fn public_routes(&mut self, _path_prefix: &str) -> Option<Router> {
+    fn public_routes(&mut self, _path_prefix: &str) -> Option<Router<ReactorState>> {
         None
     }
     /// Internal routes
-    fn internal_routes(&mut self, _path_prefix: &str) -> Option<Router> {
+    fn internal_routes(&mut self, _path_prefix: &str) -> Option<Router<ReactorState>> {
         None
     }
 
+    /// Load the app's own section of the settings, named after the app. Called before
+    /// `configure`, errors stop the reactor from starting
+    fn load_settings(&mut self, _settings: &Settings) -> Result<(), SettingsError> {
+        Ok(())
+    }
+
+    /// Keys in the app's section which are redacted when showing the settings
+    fn secret_settings(&self) -> &'static [&'static str] {
+        &[]
+    }
+
     /// Migrations
     fn migrations(&mut self) -> Option<Vec<Box<dyn Migration>>> {
         None
@@ -28,6 +53,18 @@ pub trait App {
 
     fn configure(&mut self, _cfg: &mut Configuration) {}
 
+    /// Checks run by the readiness endpoint, along with the database, storage and migrations
+    fn readiness_checks(&mut self) -> Vec<ReadinessCheck> {
+        Vec::new()
+    }
+
+    /// Called once before the reactor starts serving, after the apps it depends on
+    async fn on_startup(&mut self, _state: &ReactorState) {}
+
+    /// Called once the server has stopped and the background tasks are finished, in reverse
+    /// startup order
+    async fn on_shutdown(&mut self, _state: &ReactorState) {}
+
     fn public_openapi(&mut self, _path: &str) -> Option<OpenApi> {
         None
     }
@@ -37,23 +74,44 @@ pub trait App {
     }
 }
 
+type RouterFn = Box<dyn Fn(Router<ReactorState>) -> Router<ReactorState> + Send + Sync>;
+type BaseRouterFn =
+    Box<dyn Fn(Router<ReactorState>, &ReactorState) -> Router<ReactorState> + Send + Sync>;
+
 pub struct Configuration {
+    state: Extensions,
     global_state: Option<Box<dyn Fn(&mut Extensions) + Send + Sync>>,
-    base_router: Option<Box<dyn Fn(Router) -> Router + Send + Sync>>,
-    public_router: Option<Box<dyn Fn(Router) -> Router + Send + Sync>>,
-    internal_router: Option<Box<dyn Fn(Router) -> Router + Send + Sync>>,
+    base_router: Option<BaseRouterFn>,
+    public_router: Option<RouterFn>,
+    internal_router: Option<RouterFn>,
+    tasks: Vec<BackgroundTask>,
 }
 
 impl Configuration {
     pub(crate) fn new() -> Self {
         Self {
+            state: Extensions::new(),
             global_state: None,
             base_router: None,
             public_router: None,
             internal_router: None,
+            tasks: Vec::new(),
         }
     }
 
+    /// Register a value in the shared `ReactorState`, it is built once and can be extracted
+    /// in handlers through `State<T>` if `T: FromRef<ReactorState>`
+    pub fn state<T>(&mut self, value: T) -> &mut Self
+    where
+        T: Send + Sync + 'static,
+    {
+        self.state.insert(value);
+        self
+    }
+
+    /// Insert values into every request's extensions.
+    ///
+    /// The closure runs on each request, prefer `Configuration::state` for new code.
     pub fn global_state<F>(&mut self, f: F) -> &mut Self
     where
         F: Fn(&mut Extensions) + Send + Sync + 'static,
@@ -62,9 +120,20 @@ impl Configuration {
         self
     }
 
+    /// Wrap the whole router, ex. with a middleware
     pub fn base_router<F>(&mut self, f: F) -> &mut Self
     where
-        F: Fn(Router) -> Router + Send + Sync + 'static,
+        F: Fn(Router<ReactorState>) -> Router<ReactorState> + Send + Sync + 'static,
+    {
+        self.base_router = Some(Box::new(move |router, _| f(router)));
+        self
+    }
+
+    /// Same as `base_router`, with the built state for the middlewares which need it, through
+    /// `middleware::from_fn_with_state`
+    pub fn base_router_with_state<F>(&mut self, f: F) -> &mut Self
+    where
+        F: Fn(Router<ReactorState>, &ReactorState) -> Router<ReactorState> + Send + Sync + 'static,
     {
         self.base_router = Some(Box::new(f));
         self
@@ -72,7 +141,7 @@ impl Configuration {
 
     pub fn public_router<F>(&mut self, f: F) -> &mut Self
     where
-        F: Fn(Router) -> Router + Send + Sync + 'static,
+        F: Fn(Router<ReactorState>) -> Router<ReactorState> + Send + Sync + 'static,
     {
         self.public_router = Some(Box::new(f));
         self
@@ -80,12 +149,34 @@ impl Configuration {
 
     pub fn internal_router<F>(&mut self, f: F) -> &mut Self
     where
-        F: Fn(Router) -> Router + Send + Sync + 'static,
+        F: Fn(Router<ReactorState>) -> Router<ReactorState> + Send + Sync + 'static,
     {
         self.internal_router = Some(Box::new(f));
         self
     }
 
+    /// Register a long running task, spawned by the reactor on startup.
+    ///
+    /// The task should return soon after `Shutdown` is triggered.
+    pub fn background_task<F, Fut>(&mut self, task: F) -> &mut Self
+    where
+        F: FnOnce(ReactorState, Shutdown) -> Fut + Send + 'static,
+        Fut: Future<Output = ()> + Send + 'static,
+    {
+        self.tasks.push(Box::new(move |state, shutdown| {
+            Box::pin(task(state, shutdown))
+        }));
+        self
+    }
+
+    pub(crate) fn take_tasks(&mut self) -> Vec<BackgroundTask> {
+        std::mem::take(&mut self.tasks)
+    }
+
+    pub(crate) fn take_state(&mut self) -> Extensions {
+        std::mem::take(&mut self.state)
+    }
+
     pub(crate) fn into_global_state(self) -> Option<Box<dyn Fn(&mut Extensions) + Send + Sync>> {
         self.global_state
     }
@@ -96,15 +187,22 @@ impl Configuration {
         }
     }
 
-    pub(crate) fn configure_base_router(&self, router: Router) -> Router {
+    pub(crate) fn configure_base_router(
+        &self,
+        router: Router<ReactorState>,
+        state: &ReactorState,
+    ) -> Router<ReactorState> {
         if let Some(f) = &self.base_router {
-            f(router)
+            f(router, state)
         } else {
             router
         }
     }
 
-    pub(crate) fn configure_public_router(&self, router: Router) -> Router {
+    pub(crate) fn configure_public_router(
+        &self,
+        router: Router<ReactorState>,
+    ) -> Router<ReactorState> {
         if let Some(f) = &self.public_router {
             f(router)
         } else {
@@ -112,7 +210,10 @@ impl Configuration {
         }
     }
 
-    pub(crate) fn configure_internal_router(&self, router: Router) -> Router {
+    pub(crate) fn configure_internal_router(
+        &self,
+        router: Router<ReactorState>,
+    ) -> Router<ReactorState> {
         if let Some(f) = &self.internal_router {
             f(router)
         } else {
@@ -0,0 +1,70 @@
+//! The database backend used by the reactor and the apps, selected at compile time
+//! through the `postgres`(default) and `sqlite` features.
+//!
+//! The features are additive, postgres is used when both are enabled(ex. `--all-features`), so
+//! sqlite is only checked with `--no-default-features --features sqlite`, see the `clippy-*`
+//! and `test-*` cargo aliases.
+
+#[cfg(not(any(feature = "postgres", feature = "sqlite")))]
+compile_error!("One of `postgres` or `sqlite` features should be enabled");
+
+use sqlx::error::DatabaseError;
+
+#[cfg(feature = "postgres")]
+pub use sea_query::PostgresQueryBuilder as DbQueryBuilder;
+#[cfg(feature = "postgres")]
+pub type Db = sqlx::Postgres;
+
+#[cfg(all(feature = "sqlite", not(feature = "postgres")))]
+pub use sea_query::SqliteQueryBuilder as DbQueryBuilder;
+#[cfg(all(feature = "sqlite", not(feature = "postgres")))]
+pub type Db = sqlx::Sqlite;
+
+pub type DbPool = sqlx::Pool<Db>;
+pub type DbRow = <Db as sqlx::Database>::Row;
+
+/// Name of the unique constraint violated by a query, if any.
+///
+/// Postgres reports the constraint name(ex. `username_uniq`), while sqlite only reports the
+/// columns involved(ex. `users.username`), so apps should match on both.
+#[cfg(feature = "postgres")]
+pub fn constraint(err: &dyn DatabaseError) -> Option<&str> {
+    err.constraint()
+}
+
+/// Name of the unique constraint violated by a query, if any.
+///
+/// Postgres reports the constraint name(ex. `username_uniq`), while sqlite only reports the
+/// columns involved(ex. `users.username`), so apps should match on both.
+#[cfg(all(feature = "sqlite", not(feature = "postgres")))]
+pub fn constraint(err: &dyn DatabaseError) -> Option<&str> {
+    err.message()
+        .strip_prefix("UNIQUE constraint failed: ")
+        .map(|columns| columns.trim())
+}
+
+/// Include the migrations directory matching the enabled backend
+///
+/// ```ignore
+/// include_backend_migrations!(postgres: "./migrations", sqlite: "./sqlite_migrations")
+/// ```
+#[cfg(feature = "postgres")]
+#[macro_export]
+macro_rules! include_backend_migrations {
+    (postgres: $postgres:literal, sqlite: $sqlite:literal) => {
+        $crate::include_migrations_dir!($postgres)
+    };
+}
+
+/// Include the migrations directory matching the enabled backend
+///
+/// ```ignore
+/// include_backend_migrations!(postgres: "./migrations", sqlite: "./sqlite_migrations")
+/// ```
+#[cfg(all(feature = "sqlite", not(feature = "postgres")))]
+#[macro_export]
+macro_rules! include_backend_migrations {
+    (postgres: $postgres:literal, sqlite: $sqlite:literal) => {
+        $crate::include_migrations_dir!($sqlite)
+    };
+}
@@ -1,11 +1,35 @@
 mod app;
 mod migration;
+mod probes;
+mod prometheus;
+mod rate_limit;
 mod reactor;
+mod request_id;
+mod state;
+mod task;
+mod trace;
 
+pub mod db;
 pub mod extractors;
+pub mod settings;
+#[cfg(feature = "testing")]
+pub mod testing;
 mod openapi;
 
 pub use app::{App, Configuration};
-pub use reactor::Reactor;
+pub use probes::{CheckResult, ReadinessCheck};
+pub use rate_limit::{
+    RateLimit, RateLimitError, RateLimitService, RATE_LIMIT_LIMIT_HEADER,
+    RATE_LIMIT_REMAINING_HEADER, RATE_LIMIT_RESET_HEADER,
+};
+pub use reactor::{Reactor, ReactorError, ReactorHandle};
+pub use request_id::{RequestId, REQUEST_ID_HEADER};
 pub use smig_lib::{include_migrations_dir, Migration, MigrationId};
 pub use sqlx::types::Uuid;
+pub use state::ReactorState;
+pub use task::Shutdown;
+
+#[allow(non_snake_case)]
+pub mod RateLimitErrorOai {
+    pub use crate::rate_limit::RateLimitErrorOai::*;
+}
@@ -0,0 +1,112 @@
+use std::{borrow::Cow, collections::HashMap};
+
+use schemer::AsyncAdapter;
+#[cfg(feature = "postgres")]
+use smig_lib::PgAdapter;
+use smig_lib::{AppMigration, Migration, MigrationId, Migrator};
+
+#[cfg(all(feature = "sqlite", not(feature = "postgres")))]
+mod sqlite;
+
+#[cfg(all(feature = "sqlite", not(feature = "postgres")))]
+use sqlite::SqliteAdapter;
+
+use crate::{db::DbPool, App};
+
+#[cfg(feature = "postgres")]
+pub(crate) fn get_local_migrations() -> Option<Vec<Box<dyn Migration>>> {
+    crate::include_migrations_dir!("./migrations" => "crate")
+}
+
+#[cfg(all(feature = "sqlite", not(feature = "postgres")))]
+pub(crate) fn get_local_migrations() -> Option<Vec<Box<dyn Migration>>> {
+    crate::include_migrations_dir!("./sqlite_migrations" => "crate")
+}
+
+#[cfg(feature = "postgres")]
+fn get_adapter(db: DbPool) -> PgAdapter {
+    PgAdapter::new(db)
+}
+
+#[cfg(all(feature = "sqlite", not(feature = "postgres")))]
+fn get_adapter(db: DbPool) -> SqliteAdapter {
+    SqliteAdapter::new(db)
+}
+
+/// Ids of the migrations registered by mtapp itself and all the apps
+pub(crate) fn migration_ids<'a>(
+    apps: impl Iterator<Item = &'a mut Box<dyn App>>,
+) -> Vec<MigrationId> {
+    let mut ids: Vec<MigrationId> = get_local_migrations()
+        .unwrap_or_default()
+        .into_iter()
+        .map(|migration| AppMigration::new(Cow::Borrowed("mtapp"), migration).id())
+        .collect();
+
+    for app in apps {
+        if let Some(app_migrations) = app.migrations() {
+            ids.extend(
+                app_migrations
+                    .into_iter()
+                    .map(|migration| AppMigration::new(Cow::Borrowed(app.name()), migration).id()),
+            );
+        }
+    }
+
+    ids
+}
+
+/// The given migrations which are not applied to the database yet
+pub(crate) async fn pending_migrations(
+    db: DbPool,
+    ids: &[MigrationId],
+) -> Result<Vec<MigrationId>, String> {
+    let applied = get_adapter(db)
+        .applied_migrations()
+        .await
+        .map_err(|e| e.to_string())?;
+
+    Ok(ids
+        .iter()
+        .filter(|id| !applied.contains(id))
+        .cloned()
+        .collect())
+}
+
+pub(crate) async fn run_migrations(db: DbPool, apps: impl Iterator<Item = &mut Box<dyn App>>) {
+    let adapter = get_adapter(db);
+
+    adapter
+        .create_migration_table()
+        .await
+        .expect("Couldn't create the migration table");
+
+    let mut migrator = Migrator::new_async(adapter);
+    let mut id_name_map = HashMap::new();
+    let mut migrations = Vec::new();
+
+    for m in get_local_migrations()
+        .unwrap()
+        .into_iter()
+        .map(|migration| AppMigration::new(Cow::Borrowed("mtapp"), migration))
+    {
+        id_name_map.insert((m.app(), m.name()), m.id());
+        migrations.push(m.clone());
+    }
+
+    for app in apps {
+        if let Some(app_migrations) = app.migrations() {
+            for m in app_migrations
+                .into_iter()
+                .map(|migration| AppMigration::new(Cow::Borrowed(app.name()), migration))
+            {
+                id_name_map.insert((m.app(), m.name()), m.id());
+                migrations.push(m.clone());
+            }
+        }
+    }
+
+    migrator.register_multiple(migrations.into_iter()).unwrap();
+
+    migrator.up_async(None).await.unwrap();
+}
@@ -0,0 +1,85 @@
+use std::collections::HashSet;
+
+use schemer::AsyncAdapter;
+use smig_lib::{AppMigration, MigrationId};
+use sqlx::{Executor, SqlitePool};
+
+const CREATE_TABLE: &str = r#"
+CREATE TABLE IF NOT EXISTS _schemer_migrations_ (
+    name varchar(255) NOT NULL,
+    app varchar(255) NOT NULL,
+    description text NOT NULL,
+    created_at timestamp DEFAULT CURRENT_TIMESTAMP NOT NULL
+);
+"#;
+
+const SELECT_MIGRATIONS: &str = r#"
+SELECT name, app FROM _schemer_migrations_;
+"#;
+
+const INSERT_MIGRATION: &str = r#"
+INSERT INTO _schemer_migrations_ (name, app, description) VALUES (?1, ?2, ?3);
+"#;
+
+const DELETE_MIGRATION: &str = r#"
+DELETE FROM _schemer_migrations_ WHERE name = ?1 and app = ?2;
+"#;
+
+/// Sqlite counterpart of `smig_lib::PgAdapter`, using the same migrations table layout
+pub struct SqliteAdapter {
+    pool: SqlitePool,
+}
+
+impl SqliteAdapter {
+    pub fn new(pool: SqlitePool) -> Self {
+        Self { pool }
+    }
+
+    pub async fn create_migration_table(&self) -> Result<(), sqlx::Error> {
+        self.pool.acquire().await?.execute(CREATE_TABLE).await?;
+        Ok(())
+    }
+}
+
+#[async_trait::async_trait]
+impl AsyncAdapter<MigrationId> for SqliteAdapter {
+    type MigrationType = AppMigration;
+
+    type Error = sqlx::Error;
+
+    async fn applied_migrations(&mut self) -> Result<HashSet<MigrationId>, Self::Error> {
+        Ok(sqlx::query_as::<_, (String, String)>(SELECT_MIGRATIONS)
+            .fetch_all(&self.pool)
+            .await?
+            .into_iter()
+            .map(|(name, app)| MigrationId::new(app, name))
+            .collect())
+    }
+
+    async fn apply_migration(&mut self, m: &Self::MigrationType) -> Result<(), Self::Error> {
+        log::info!("Applying migration {}::{}...", m.app(), m.name());
+
+        let mut trans = self.pool.begin().await?;
+        sqlx::query(INSERT_MIGRATION)
+            .bind(m.name())
+            .bind(m.app())
+            .bind(m.description())
+            .execute(&mut trans)
+            .await?;
+        trans.execute(m.up().as_ref()).await?;
+        trans.commit().await
+    }
+
+    async fn revert_migration(&mut self, m: &Self::MigrationType) -> Result<(), Self::Error> {
+        log::info!("Reverting migration {}::{}...", m.app(), m.name());
+
+        let mut trans = self.pool.begin().await?;
+        sqlx::query(DELETE_MIGRATION)
+            .bind(m.name())
+            .bind(m.app())
+            .execute(&mut trans)
+            .await?;
+        trans.execute(m.down().as_ref()).await?;
+        trans.commit().await
+    }
+}
@@ -0,0 +1,182 @@
+use std::{
+    borrow::Cow, collections::BTreeMap, future::Future, pin::Pin, sync::Arc, time::Duration,
+};
+
+use axum::{extract::State, http::StatusCode, routing::get, Json, Router};
+use serde::Serialize;
+use smig_lib::MigrationId;
+
+use crate::{reactor::ReactorError, state::ReactorState};
+
+// A hanging check(ex. an unreachable database) fails instead of hanging the probe
+const CHECK_TIMEOUT: Duration = Duration::from_secs(5);
+
+pub type CheckResult = Result<(), Cow<'static, str>>;
+
+type CheckFn =
+    Box<dyn Fn(ReactorState) -> Pin<Box<dyn Future<Output = CheckResult> + Send>> + Send + Sync>;
+
+/// A named check run by the readiness endpoint, see `App::readiness_checks`
+pub struct ReadinessCheck {
+    name: Cow<'static, str>,
+    check: CheckFn,
+}
+
+impl ReadinessCheck {
+    pub fn new<F, Fut>(name: impl Into<Cow<'static, str>>, check: F) -> Self
+    where
+        F: Fn(ReactorState) -> Fut + Send + Sync + 'static,
+        Fut: Future<Output = CheckResult> + Send + 'static,
+    {
+        Self {
+            name: name.into(),
+            check: Box::new(move |state| Box::pin(check(state))),
+        }
+    }
+}
+
+/// Paths of the probe and metrics endpoints, each one is disabled unless its path is set
+#[derive(Default)]
+pub(crate) struct Probes {
+    pub(crate) health_path: Option<Cow<'static, str>>,
+    pub(crate) ready_path: Option<Cow<'static, str>>,
+    pub(crate) version_path: Option<Cow<'static, str>>,
+    pub(crate) version: Option<Cow<'static, str>>,
+    pub(crate) metrics_path: Option<Cow<'static, str>>,
+}
+
+impl Probes {
+    pub(crate) fn router(
+        &self,
+        checks: Vec<ReadinessCheck>,
+        migrations: Vec<MigrationId>,
+    ) -> Result<Router<ReactorState>, ReactorError> {
+        let mut router = Router::new();
+
+        if let Some(path) = &self.health_path {
+            router = router.route(path, get(health));
+        }
+
+        if let Some(path) = &self.ready_path {
+            let checks: Arc<Vec<_>> = Arc::new(
+                builtin_checks(migrations)
+                    .into_iter()
+                    .chain(checks)
+                    .collect(),
+            );
+            router = router.route(
+                path,
+                get(move |state: State<ReactorState>| ready(state, checks)),
+            );
+        }
+
+        if let Some(path) = &self.version_path {
+            let version = VersionInfo {
+                version: self.version.clone().unwrap_or(Cow::Borrowed("unknown")),
+            };
+            router = router.route(path, get(move || async move { Json(version) }));
+        }
+
+        if let Some(path) = &self.metrics_path {
+            router = router.merge(crate::prometheus::router(path)?);
+        }
+
+        Ok(router)
+    }
+}
+
+#[derive(Serialize)]
+struct Health {
+    status: &'static str,
+}
+
+async fn health() -> Json<Health> {
+    Json(Health { status: "ok" })
+}
+
+#[derive(Clone, Serialize)]
+struct VersionInfo {
+    version: Cow<'static, str>,
+}
+
+#[derive(Serialize)]
+#[serde(tag = "status", rename_all = "lowercase")]
+enum CheckStatus {
+    Ok,
+    Failed { error: Cow<'static, str> },
+}
+
+#[derive(Serialize)]
+struct Readiness {
+    ready: bool,
+    checks: BTreeMap<Cow<'static, str>, CheckStatus>,
+}
+
+async fn ready(
+    State(state): State<ReactorState>,
+    checks: Arc<Vec<ReadinessCheck>>,
+) -> (StatusCode, Json<Readiness>) {
+    let mut readiness = Readiness {
+        ready: true,
+        checks: BTreeMap::new(),
+    };
+
+    for check in checks.iter() {
+        let result = tokio::time::timeout(CHECK_TIMEOUT, (check.check)(state.clone()))
+            .await
+            .unwrap_or(Err(Cow::Borrowed("timed out")));
+        let status = match result {
+            Ok(()) => CheckStatus::Ok,
+            Err(error) => {
+                readiness.ready = false;
+                CheckStatus::Failed { error }
+            }
+        };
+        readiness.checks.insert(check.name.clone(), status);
+    }
+
+    let status = if readiness.ready {
+        StatusCode::OK
+    } else {
+        StatusCode::SERVICE_UNAVAILABLE
+    };
+
+    (status, Json(readiness))
+}
+
+fn builtin_checks(migrations: Vec<MigrationId>) -> Vec<ReadinessCheck> {
+    let migrations = Arc::new(migrations);
+
+    vec![
+        ReadinessCheck::new("database", |state: ReactorState| async move {
+            sqlx::query("SELECT 1")
+                .execute(state.db())
+                .await
+                .map(|_| ())
+                .map_err(|e| e.to_string().into())
+        }),
+        ReadinessCheck::new("storage", |state: ReactorState| async move {
+            state
+                .storage()
+                .scope("mtapp_probes")
+                .contains_key("ready")
+                .await
+                .map(|_| ())
+                .map_err(|e| e.to_string().into())
+        }),
+        ReadinessCheck::new("migrations", move |state: ReactorState| {
+            let migrations = migrations.clone();
+            async move {
+                let pending = crate::migration::pending_migrations(state.db().clone(), &migrations)
+                    .await
+                    .map_err(Cow::Owned)?;
+
+                if pending.is_empty() {
+                    Ok(())
+                } else {
+                    Err(format!("{} pending migrations", pending.len()).into())
+                }
+            }
+        }),
+    ]
+}
@@ -0,0 +1,107 @@
+use std::{
+    borrow::Cow,
+    sync::{Arc, Mutex, OnceLock, PoisonError},
+    time::Instant,
+};
+
+use axum::{
+    extract::{MatchedPath, State},
+    http::Request,
+    middleware::Next,
+    response::Response,
+    routing::get,
+    Router,
+};
+use metrics_exporter_prometheus::{Matcher, PrometheusBuilder, PrometheusHandle};
+
+use crate::{reactor::ReactorError, state::ReactorState};
+
+const LATENCY_BUCKETS: &[f64] = &[
+    0.005, 0.01, 0.025, 0.05, 0.1, 0.25, 0.5, 1.0, 2.5, 5.0, 10.0,
+];
+
+static HANDLE: OnceLock<PrometheusHandle> = OnceLock::new();
+
+/// The recorder is global, so it's installed once and shared by all the reactors in the process
+fn install() -> Result<&'static PrometheusHandle, ReactorError> {
+    // Otherwise the reactors starting together would try to install it twice
+    static INSTALL: Mutex<()> = Mutex::new(());
+    let _guard = INSTALL.lock().unwrap_or_else(PoisonError::into_inner);
+
+    if let Some(handle) = HANDLE.get() {
+        return Ok(handle);
+    }
+
+    let handle = PrometheusBuilder::new()
+        .set_buckets_for_metric(
+            Matcher::Full(String::from("http_request_duration_seconds")),
+            LATENCY_BUCKETS,
+        )
+        .expect("Buckets are not empty")
+        .install_recorder()
+        .map_err(|e| ReactorError::MetricsRecorder(e.to_string()))?;
+    Ok(HANDLE.get_or_init(|| handle))
+}
+
+/// Path prefixes of the mounted apps(ex. `/api/users`), used to label the requests
+pub(crate) type AppPrefixes = Arc<Vec<(String, &'static str)>>;
+
+pub(crate) fn router(path: &str) -> Result<Router<ReactorState>, ReactorError> {
+    // Install the recorder before any request is tracked
+    install()?;
+    Ok(Router::new().route(path, get(render)))
+}
+
+async fn render(State(state): State<ReactorState>) -> String {
+    let db = state.db();
+    metrics::gauge!("db_pool_connections", db.size() as f64);
+    metrics::gauge!("db_pool_idle_connections", db.num_idle() as f64);
+
+    HANDLE.get().expect("Installed by router").render()
+}
+
+/// Count the requests and record their latency per route and app
+pub(crate) async fn track<B>(
+    State(apps): State<AppPrefixes>,
+    request: Request<B>,
+    next: Next<B>,
+) -> Response {
+    let method = request.method().to_string();
+    let route = request
+        .extensions()
+        .get::<MatchedPath>()
+        .map(|path| path.as_str().to_owned())
+        .unwrap_or_default();
+    let app = app_for(&apps, &route);
+
+    let start = Instant::now();
+    let response = next.run(request).await;
+    let latency = start.elapsed().as_secs_f64();
+
+    let status = response.status().as_u16().to_string();
+    metrics::increment_counter!(
+        "http_requests_total",
+        "method" => method.clone(),
+        "route" => route.clone(),
+        "app" => app.clone(),
+        "status" => status,
+    );
+    metrics::histogram!(
+        "http_request_duration_seconds",
+        latency,
+        "method" => method,
+        "route" => route,
+        "app" => app,
+    );
+
+    response
+}
+
+/// The app with the longest prefix matching the route, empty for the routes of the reactor
+fn app_for(apps: &[(String, &'static str)], route: &str) -> Cow<'static, str> {
+    apps.iter()
+        .filter(|(prefix, _)| route.starts_with(prefix.as_str()))
+        .max_by_key(|(prefix, _)| prefix.len())
+        .map(|(_, name)| Cow::Borrowed(*name))
+        .unwrap_or_default()
+}
@@ -0,0 +1,216 @@
+use std::{
+    convert::Infallible,
+    future::Future,
+    net::SocketAddr,
+    pin::Pin,
+    sync::Arc,
+    task::{Context, Poll},
+    time::{Duration, SystemTime, UNIX_EPOCH},
+};
+
+use axum::{
+    body::{self, BoxBody, Bytes, HttpBody},
+    extract::ConnectInfo,
+    http::{header, request::Parts, HeaderMap, HeaderName, Request, Response, StatusCode},
+    response::IntoResponse,
+    BoxError,
+};
+use axum_client_ip::InsecureClientIp;
+use basteh::{Basteh, BastehError};
+use json_resp::JsonError;
+use tower::{Layer, Service};
+
+const RATE_LIMIT_SCOPE: &str = "rate_limits";
+
+pub static RATE_LIMIT_LIMIT_HEADER: HeaderName = HeaderName::from_static("x-ratelimit-limit");
+pub static RATE_LIMIT_REMAINING_HEADER: HeaderName =
+    HeaderName::from_static("x-ratelimit-remaining");
+pub static RATE_LIMIT_RESET_HEADER: HeaderName = HeaderName::from_static("x-ratelimit-reset");
+
+type KeyFn = dyn Fn(&Parts) -> Option<String> + Send + Sync;
+
+#[derive(Debug, JsonError)]
+#[json_error(internal_code = "500000 internal-error")]
+pub enum RateLimitError {
+    #[json_error(request, status = 429, code = "429001 too-many-requests")]
+    TooManyRequests,
+
+    #[json_error(internal)]
+    BastehError(BastehError),
+}
+
+/// Limits the requests of each client to `limit` per `period`, counted in the reactor's storage
+/// so the limit is shared between the instances using the same backend.
+///
+/// Requests are keyed by the peer's ip by default(see `trust_proxy_headers` behind a proxy),
+/// `mtapp_auth` has keys for the user and the api key. Use it on the routes of an app with
+/// `Router::route_layer`, or on all of them through `Configuration::public_router` and
+/// `Configuration::internal_router`, ex.
+/// `cfg.public_router(|router| router.layer(RateLimit::new("public", 600, minute)))`.
+///
+/// The responses get `X-RateLimit-Limit`, `X-RateLimit-Remaining` and `X-RateLimit-Reset`(seconds
+/// until the counter is reset) headers, and the limited requests a 429 with `Retry-After`.
+#[derive(Clone)]
+pub struct RateLimit {
+    // Keeps the counters of different limits apart
+    name: Arc<str>,
+    limit: u64,
+    period: u64,
+    key_fn: Arc<KeyFn>,
+}
+
+impl RateLimit {
+    pub fn new(name: &str, limit: u64, period: Duration) -> Self {
+        assert!(
+            period.as_secs() > 0,
+            "The period of a rate limit should be at least a second"
+        );
+
+        Self {
+            name: name.into(),
+            limit,
+            period: period.as_secs(),
+            key_fn: Arc::new(peer_ip),
+        }
+    }
+
+    /// Key the requests by the client ip from the `X-Forwarded-For` like headers, falling back to
+    /// the peer's ip. Only use it behind a proxy setting them, the clients can send any value
+    pub fn trust_proxy_headers(self) -> Self {
+        self.key(client_ip)
+    }
+
+    /// Count the requests by another key, requests without a key aren't limited by this layer
+    pub fn key<F>(mut self, key_fn: F) -> Self
+    where
+        F: Fn(&Parts) -> Option<String> + Send + Sync + 'static,
+    {
+        self.key_fn = Arc::new(key_fn);
+        self
+    }
+
+    /// Count the request in its window, returns (the requests so far, seconds until the reset)
+    async fn hit(&self, storage: &Basteh, key: &str) -> Result<(u64, u64), BastehError> {
+        let now = SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .expect("Time went backwards")
+            .as_secs();
+        let window = now / self.period;
+
+        let storage = storage.scope(RATE_LIMIT_SCOPE);
+        let key = format!("{}:{}:{}", self.name, key, window);
+        let count = storage.mutate(&key, |value| value.incr(1)).await?;
+        if count == 1 {
+            storage
+                .expire(&key, Duration::from_secs(self.period))
+                .await?;
+        }
+
+        Ok((
+            u64::try_from(count).unwrap_or_default(),
+            (window + 1) * self.period - now,
+        ))
+    }
+
+    fn add_headers(&self, headers: &mut HeaderMap, count: u64, reset: u64) {
+        headers.insert(RATE_LIMIT_LIMIT_HEADER.clone(), self.limit.into());
+        headers.insert(
+            RATE_LIMIT_REMAINING_HEADER.clone(),
+            self.limit.saturating_sub(count).into(),
+        );
+        headers.insert(RATE_LIMIT_RESET_HEADER.clone(), reset.into());
+    }
+}
+
+impl<S> Layer<S> for RateLimit {
+    type Service = RateLimitService<S>;
+
+    fn layer(&self, inner: S) -> Self::Service {
+        RateLimitService {
+            limit: self.clone(),
+            inner,
+        }
+    }
+}
+
+#[derive(Clone)]
+pub struct RateLimitService<S> {
+    limit: RateLimit,
+    inner: S,
+}
+
+impl<ReqBody, ResBody, S> Service<Request<ReqBody>> for RateLimitService<S>
+where
+    S: Service<Request<ReqBody>, Response = Response<ResBody>, Error = Infallible>
+        + Clone
+        + Send
+        + 'static,
+    S::Future: Send + 'static,
+    ReqBody: Send + 'static,
+    ResBody: HttpBody<Data = Bytes> + Send + 'static,
+    ResBody::Error: Into<BoxError>,
+{
+    type Response = Response<BoxBody>;
+    type Error = Infallible;
+    type Future = Pin<Box<dyn Future<Output = Result<Self::Response, Self::Error>> + Send>>;
+
+    #[inline]
+    fn poll_ready(&mut self, cx: &mut Context<'_>) -> Poll<Result<(), Self::Error>> {
+        self.inner.poll_ready(cx)
+    }
+
+    fn call(&mut self, req: Request<ReqBody>) -> Self::Future {
+        let limit = self.limit.clone();
+
+        let (parts, body) = req.into_parts();
+        let key = (limit.key_fn)(&parts);
+        // Only missing when the router isn't built by the reactor
+        let storage = parts.extensions.get::<Basteh>().cloned();
+
+        let req = Request::from_parts(parts, body);
+        // The ready service is the one polled by poll_ready, keep a clone in its place
+        let clone = self.inner.clone();
+        let mut inner = std::mem::replace(&mut self.inner, clone);
+
+        Box::pin(async move {
+            let (key, storage) = match (key, storage) {
+                (Some(key), Some(storage)) => (key, storage),
+                _ => return Ok(inner.call(req).await?.map(body::boxed)),
+            };
+
+            let (count, reset) = match limit.hit(&storage, &key).await {
+                Ok(hit) => hit,
+                Err(e) => return Ok(RateLimitError::BastehError(e).into_response()),
+            };
+
+            // The inner service is only called for the allowed requests
+            let mut res = if count > limit.limit {
+                metrics::increment_counter!(
+                    "rate_limited_requests_total",
+                    "limit" => limit.name.to_string()
+                );
+                let mut res = RateLimitError::TooManyRequests.into_response();
+                res.headers_mut().insert(header::RETRY_AFTER, reset.into());
+                res
+            } else {
+                inner.call(req).await?.map(body::boxed)
+            };
+            limit.add_headers(res.headers_mut(), count, reset);
+
+            Ok(res)
+        })
+    }
+}
+
+fn peer_ip(parts: &Parts) -> Option<String> {
+    parts
+        .extensions
+        .get::<ConnectInfo<SocketAddr>>()
+        .map(|info| info.0.ip().to_string())
+}
+
+fn client_ip(parts: &Parts) -> Option<String> {
+    InsecureClientIp::from(&parts.headers, &parts.extensions)
+        .ok()
+        .map(|ip| ip.0.to_string())
+}
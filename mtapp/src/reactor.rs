@@ -1,31 +1,90 @@
 use std::{
     borrow::Cow,
+    collections::HashMap,
+    error::Error,
+    fmt,
     sync::Arc,
     task::{Context, Poll},
 };
 
 use axum::{
     http::{Extensions, Request},
-    Router,
+    middleware, Router,
 };
 use basteh::Basteh;
 use clap::{ArgMatches, Command};
 use indexmap::IndexMap;
-use sqlx::PgPool;
+use tokio::{sync::watch, task::JoinHandle};
 use tower::Service;
 use utoipa::openapi::{OpenApi, PathsBuilder};
 
 use crate::{
     app::{App, Configuration},
+    db::DbPool,
     openapi::generate_openapi,
+    probes::Probes,
+    settings::{Settings, SettingsError},
+    state::ReactorState,
+    task::{BackgroundTask, Shutdown},
 };
 
+#[derive(Debug, Clone)]
+pub enum ReactorError {
+    /// An app depends on another app which is not mounted
+    MissingDependency {
+        app: &'static str,
+        dependency: &'static str,
+    },
+
+    /// The apps in the cycle, the first one is repeated at the end
+    CircularDependency(Vec<&'static str>),
+
+    /// An app is mounted twice, the dependencies couldn't tell them apart
+    DuplicateApp {
+        app: &'static str,
+        paths: [&'static str; 2],
+    },
+
+    /// Two apps are mounted on the same path
+    DuplicatePath(&'static str),
+
+    /// The prometheus recorder couldn't be installed, ex. another metrics recorder is installed
+    MetricsRecorder(String),
+}
+
+impl fmt::Display for ReactorError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::MissingDependency { app, dependency } => write!(
+                f,
+                "App `{}` depends on `{}`, which is not mounted on the reactor",
+                app, dependency
+            ),
+            Self::CircularDependency(apps) => {
+                write!(f, "Circular app dependencies: {}", apps.join(" -> "))
+            }
+            Self::DuplicateApp { app, paths } => write!(
+                f,
+                "App `{}` is mounted on both `{}` and `{}`",
+                app, paths[0], paths[1]
+            ),
+            Self::DuplicatePath(path) => write!(f, "More than one app is mounted on `{}`", path),
+            Self::MetricsRecorder(e) => write!(f, "Failed to install the metrics recorder: {}", e),
+        }
+    }
+}
+
+impl Error for ReactorError {}
+
 pub struct Reactor<D, S> {
     map: IndexMap<&'static str, Box<dyn App>>,
     cfgs: Vec<Configuration>,
+    // Reported by the methods using the apps, so `mount_on` can be chained
+    mount_error: Option<ReactorError>,
 
     public_path: Option<Cow<'static, str>>,
     internal_path: Option<Cow<'static, str>>,
+    probes: Probes,
 
     db: D,
     storage: S,
@@ -36,9 +95,11 @@ impl Reactor<(), ()> {
         Self {
             map: IndexMap::new(),
             cfgs: Vec::new(),
+            mount_error: None,
 
             public_path: None,
             internal_path: None,
+            probes: Probes::default(),
 
             db: (),
             storage: (),
@@ -51,6 +112,18 @@ impl<D, S> Reactor<D, S> {
     where
         A: App + 'static,
     {
+        if self.mount_error.is_none() {
+            let name = app.name();
+            if let Some((mounted_on, _)) = self.map.iter().find(|(_, app)| app.name() == name) {
+                self.mount_error = Some(ReactorError::DuplicateApp {
+                    app: name,
+                    paths: [*mounted_on, path],
+                });
+            } else if self.map.contains_key(path) {
+                self.mount_error = Some(ReactorError::DuplicatePath(path));
+            }
+        }
+
         self.map.insert(path, Box::new(app));
         self
     }
@@ -65,13 +138,46 @@ impl<D, S> Reactor<D, S> {
         self
     }
 
-    pub fn db(self, db: PgPool) -> Reactor<PgPool, S> {
+    /// Serve a liveness probe on the given path, it responds as long as the process is up
+    pub fn health_path(mut self, path: impl Into<Cow<'static, str>>) -> Self {
+        self.probes.health_path = Some(path.into());
+        self
+    }
+
+    /// Serve a readiness probe on the given path, it checks the database, storage, pending
+    /// migrations and the apps' own readiness checks
+    pub fn ready_path(mut self, path: impl Into<Cow<'static, str>>) -> Self {
+        self.probes.ready_path = Some(path.into());
+        self
+    }
+
+    /// Serve the given version on the given path
+    pub fn version_path(
+        mut self,
+        path: impl Into<Cow<'static, str>>,
+        version: impl Into<Cow<'static, str>>,
+    ) -> Self {
+        self.probes.version_path = Some(path.into());
+        self.probes.version = Some(version.into());
+        self
+    }
+
+    /// Serve the metrics in the prometheus text format on the given path, it isn't protected so
+    /// it should only be reachable from the internal network
+    pub fn metrics_path(mut self, path: impl Into<Cow<'static, str>>) -> Self {
+        self.probes.metrics_path = Some(path.into());
+        self
+    }
+
+    pub fn db(self, db: DbPool) -> Reactor<DbPool, S> {
         Reactor {
             map: self.map,
             cfgs: self.cfgs,
+            mount_error: self.mount_error,
 
             public_path: self.public_path,
             internal_path: self.internal_path,
+            probes: self.probes,
 
             db,
             storage: self.storage,
@@ -82,15 +188,37 @@ impl<D, S> Reactor<D, S> {
         Reactor {
             map: self.map,
             cfgs: self.cfgs,
+            mount_error: self.mount_error,
 
             public_path: self.public_path,
             internal_path: self.internal_path,
+            probes: self.probes,
 
             db: self.db,
             storage,
         }
     }
 
+    /// Let every app load its section of the settings
+    pub fn load_settings(&mut self, settings: &Settings) -> Result<(), SettingsError> {
+        for app in self.map.values_mut() {
+            app.load_settings(settings)?;
+        }
+        Ok(())
+    }
+
+    /// Full keys(ex. `auth.secret`) of the settings the apps consider secret
+    pub fn secret_settings(&self) -> Vec<String> {
+        self.map
+            .values()
+            .flat_map(|app| {
+                app.secret_settings()
+                    .iter()
+                    .map(move |key| format!("{}.{}", app.name(), key))
+            })
+            .collect()
+    }
+
     pub fn clap_defs(&self) -> Vec<Command> {
         let mut commands = Vec::new();
         for app in self.map.values() {
@@ -100,32 +228,99 @@ impl<D, S> Reactor<D, S> {
         }
         commands
     }
+
+    /// Sort the apps so that every app comes after its dependencies, keeping the mount order
+    /// otherwise.
+    ///
+    /// Fails if an app or a path is mounted twice, a dependency is not mounted or the
+    /// dependencies are circular.
+    fn order_apps(&mut self) -> Result<(), ReactorError> {
+        if let Some(err) = &self.mount_error {
+            return Err(err.clone());
+        }
+
+        let paths: HashMap<&'static str, &'static str> = self
+            .map
+            .iter()
+            .map(|(path, app)| (app.name(), *path))
+            .collect();
+
+        let mut ordered = IndexMap::with_capacity(self.map.len());
+        let mut visiting = Vec::new();
+        for path in self.map.keys().copied().collect::<Vec<_>>() {
+            if let Err(err) = self.visit_app(path, &paths, &mut visiting, &mut ordered) {
+                // Keep the apps which were already moved, so the reactor stays usable
+                ordered.extend(self.map.drain(..));
+                self.map = ordered;
+                return Err(err);
+            }
+        }
+
+        self.map = ordered;
+        Ok(())
+    }
+
+    fn visit_app(
+        &mut self,
+        path: &'static str,
+        paths: &HashMap<&'static str, &'static str>,
+        visiting: &mut Vec<&'static str>,
+        ordered: &mut IndexMap<&'static str, Box<dyn App>>,
+    ) -> Result<(), ReactorError> {
+        let app = match self.map.get(path) {
+            Some(app) => app,
+            // Already visited
+            None => return Ok(()),
+        };
+        let name = app.name();
+        let dependencies = app.dependencies();
+
+        if let Some(start) = visiting.iter().position(|visited| *visited == name) {
+            let mut cycle = visiting.split_off(start);
+            cycle.push(name);
+            return Err(ReactorError::CircularDependency(cycle));
+        }
+
+        visiting.push(name);
+        for dep in dependencies {
+            let dep_path = *paths.get(dep).ok_or(ReactorError::MissingDependency {
+                app: name,
+                dependency: dep,
+            })?;
+            self.visit_app(dep_path, paths, visiting, ordered)?;
+        }
+        visiting.pop();
+
+        if let Some(app) = self.map.shift_remove(path) {
+            ordered.insert(path, app);
+        }
+        Ok(())
+    }
 }
 
-impl Reactor<PgPool, Basteh> {
-    pub async fn run_migrations(&mut self) {
+impl Reactor<DbPool, Basteh> {
+    pub async fn run_migrations(&mut self) -> Result<(), ReactorError> {
+        self.order_apps()?;
         crate::migration::run_migrations(self.db.clone(), self.map.values_mut()).await;
+        Ok(())
     }
 
-    pub async fn run_command(mut self, subcommand: &str, args: &ArgMatches) {
-        let ext = self.get_extensions();
+    pub async fn run_command(
+        mut self,
+        subcommand: &str,
+        args: &ArgMatches,
+    ) -> Result<(), ReactorError> {
+        let ext = self.get_extensions()?;
         for app in self.map.values_mut() {
             if app.name() == subcommand {
                 app.clap_run(args, &ext).await
             }
         }
+        Ok(())
     }
 
-    pub fn get_extensions(&mut self) -> Extensions {
-        self.cfgs = self
-            .map
-            .values_mut()
-            .map(|app| {
-                let mut cfg = Configuration::new();
-                app.configure(&mut cfg);
-                cfg
-            })
-            .collect();
+    pub fn get_extensions(&mut self) -> Result<Extensions, ReactorError> {
+        let state = self.build_state()?;
 
         let mut ext = Extensions::new();
         for cfg in self.cfgs.iter() {
@@ -134,10 +329,14 @@ impl Reactor<PgPool, Basteh> {
 
         ext.insert(self.storage.clone());
         ext.insert(self.db.clone());
-        ext
+        ext.insert(state);
+        Ok(ext)
     }
 
-    pub fn into_router(mut self) -> Router {
+    /// Configure all the apps and build the shared state out of the values they registered
+    fn build_state(&mut self) -> Result<ReactorState, ReactorError> {
+        self.order_apps()?;
+
         self.cfgs = self
             .map
             .values_mut()
@@ -148,6 +347,55 @@ impl Reactor<PgPool, Basteh> {
             })
             .collect();
 
+        let mut values = Extensions::new();
+        for cfg in self.cfgs.iter_mut() {
+            values.extend(cfg.take_state());
+        }
+
+        Ok(ReactorState::new(
+            self.db.clone(),
+            self.storage.clone(),
+            values,
+        ))
+    }
+
+    /// Build the router, the apps' startup hooks and background tasks are not run, see
+    /// `Reactor::start`
+    pub fn into_router(mut self) -> Result<Router, ReactorError> {
+        self.build_router().map(|(router, _, _)| router)
+    }
+
+    /// Build the router, run the apps' startup hooks and spawn their background tasks.
+    ///
+    /// The returned handle should be shut down once the server has stopped.
+    pub async fn start(mut self) -> Result<(Router, ReactorHandle), ReactorError> {
+        let (router, state, tasks) = self.build_router()?;
+
+        for app in self.map.values_mut() {
+            app.on_startup(&state).await;
+        }
+
+        let (trigger, shutdown) = Shutdown::new();
+        let tasks = tasks
+            .into_iter()
+            .map(|task| tokio::spawn(task(state.clone(), shutdown.clone())))
+            .collect();
+
+        let handle = ReactorHandle {
+            apps: self.map,
+            state,
+            trigger,
+            tasks,
+        };
+
+        Ok((router, handle))
+    }
+
+    fn build_router(
+        &mut self,
+    ) -> Result<(Router, ReactorState, Vec<BackgroundTask>), ReactorError> {
+        let state = self.build_state()?;
+
         let mut router = Router::new();
 
         if self.public_path.is_some() {
@@ -161,23 +409,67 @@ impl Reactor<PgPool, Basteh> {
         }
 
         for cfg in self.cfgs.iter() {
-            router = cfg.configure_base_router(router);
+            router = cfg.configure_base_router(router, &state);
         }
 
-        router.layer(ReactorLayer(ReactorLayerInner {
-            db: self.db,
-            storage: self.storage,
-            state_fns: Arc::new(
-                self.cfgs
-                    .into_iter()
-                    .map(|v| v.into_global_state())
-                    .flatten()
-                    .collect(),
-            ),
-        }))
+        // Merged after the apps' middlewares, probes shouldn't go through auth and the like
+        let checks = self
+            .map
+            .values_mut()
+            .flat_map(|app| app.readiness_checks())
+            .collect();
+        let migrations = crate::migration::migration_ids(self.map.values_mut());
+        router = router.merge(self.probes.router(checks, migrations)?);
+
+        if self.probes.metrics_path.is_some() {
+            router = router.layer(middleware::from_fn_with_state(
+                self.app_prefixes(),
+                crate::prometheus::track,
+            ));
+        }
+
+        let tasks = self
+            .cfgs
+            .iter_mut()
+            .flat_map(|cfg| cfg.take_tasks())
+            .collect();
+
+        let router = router
+            .layer(ReactorLayer(ReactorLayerInner {
+                db: self.db.clone(),
+                storage: self.storage.clone(),
+                state: state.clone(),
+                state_fns: Arc::new(
+                    std::mem::take(&mut self.cfgs)
+                        .into_iter()
+                        .filter_map(|v| v.into_global_state())
+                        .collect(),
+                ),
+            }))
+            .layer(middleware::from_fn(crate::request_id::request_id))
+            .layer(crate::trace::layer())
+            .with_state(state.clone());
+
+        Ok((router, state, tasks))
+    }
+
+    fn app_prefixes(&self) -> crate::prometheus::AppPrefixes {
+        let roots = [&self.public_path, &self.internal_path];
+
+        Arc::new(
+            roots
+                .into_iter()
+                .flatten()
+                .flat_map(|root| {
+                    self.map
+                        .iter()
+                        .map(move |(path, app)| (format!("{}{}", root, path), app.name()))
+                })
+                .collect(),
+        )
     }
 
-    fn public_router(&mut self) -> Router {
+    fn public_router(&mut self) -> Router<ReactorState> {
         let mut router = Router::new();
         for (path, app) in self.map.iter_mut() {
             let prefix = format!("{}{}", self.public_path.as_ref().unwrap(), path);
@@ -192,7 +484,7 @@ impl Reactor<PgPool, Basteh> {
         router
     }
 
-    fn internal_router(&mut self) -> Router {
+    fn internal_router(&mut self) -> Router<ReactorState> {
         let mut router = Router::new();
         for (path, app) in self.map.iter_mut() {
             let prefix = format!("{}{}", self.internal_path.as_ref().unwrap(), path);
@@ -267,10 +559,43 @@ impl Reactor<PgPool, Basteh> {
     }
 }
 
+/// Returned by `Reactor::start`, owns the apps and their running background tasks
+pub struct ReactorHandle {
+    apps: IndexMap<&'static str, Box<dyn App>>,
+    state: ReactorState,
+    trigger: watch::Sender<bool>,
+    tasks: Vec<JoinHandle<()>>,
+}
+
+impl ReactorHandle {
+    pub fn state(&self) -> &ReactorState {
+        &self.state
+    }
+
+    /// Signal the background tasks to stop, wait for them and run the apps' shutdown hooks
+    pub async fn shutdown(mut self) {
+        // Fails only if there are no tasks listening
+        let _ = self.trigger.send(true);
+
+        for task in self.tasks {
+            if let Err(err) = task.await {
+                if err.is_panic() {
+                    std::panic::resume_unwind(err.into_panic());
+                }
+            }
+        }
+
+        for app in self.apps.values_mut().rev() {
+            app.on_shutdown(&self.state).await;
+        }
+    }
+}
+
 #[derive(Clone)]
 struct ReactorLayerInner {
-    db: PgPool,
+    db: DbPool,
     storage: Basteh,
+    state: ReactorState,
     state_fns: Arc<Vec<Box<dyn Fn(&mut Extensions) + Send + Sync>>>,
 }
 
@@ -313,8 +638,10 @@ where
             f(ext);
         }
 
+        // Kept for the handlers and middlewares still using `Extension` extractors
         ext.insert(self.data.storage.clone());
         ext.insert(self.data.db.clone());
+        ext.insert(self.data.state.clone());
         self.inner.call(req)
     }
 }
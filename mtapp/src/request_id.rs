@@ -0,0 +1,112 @@
+use std::{convert::Infallible, fmt, sync::Arc};
+
+use axum::{
+    async_trait,
+    body::{self, BoxBody, Bytes},
+    extract::FromRequestParts,
+    http::{header, request::Parts, HeaderName, HeaderValue, Request, Response},
+    middleware::Next,
+};
+use serde_json::Value;
+use uuid::Uuid;
+
+pub static REQUEST_ID_HEADER: HeaderName = HeaderName::from_static("x-request-id");
+
+/// Ids sent by the clients longer than this are replaced by a generated one
+const MAX_LENGTH: usize = 128;
+
+/// Id of the current request, taken from the `X-Request-Id` header or generated by the reactor.
+///
+/// It's sent back in the `X-Request-Id` header and the `request_id` field of json error bodies,
+/// and recorded on the request's log span.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct RequestId(Arc<str>);
+
+impl RequestId {
+    fn generate() -> Self {
+        Self(Uuid::new_v4().simple().to_string().into())
+    }
+
+    fn from_header(value: &HeaderValue) -> Option<Self> {
+        let value = value.to_str().ok()?;
+        if value.is_empty() || value.len() > MAX_LENGTH {
+            return None;
+        }
+        Some(Self(value.into()))
+    }
+
+    pub fn as_str(&self) -> &str {
+        &self.0
+    }
+}
+
+impl fmt::Display for RequestId {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(&self.0)
+    }
+}
+
+#[async_trait]
+impl<S: Send + Sync> FromRequestParts<S> for RequestId {
+    type Rejection = Infallible;
+
+    async fn from_request_parts(parts: &mut Parts, _: &S) -> Result<Self, Self::Rejection> {
+        // Only missing when the router isn't built by the reactor
+        Ok(parts
+            .extensions
+            .get::<RequestId>()
+            .cloned()
+            .unwrap_or_else(RequestId::generate))
+    }
+}
+
+pub(crate) async fn request_id<B>(mut req: Request<B>, next: Next<B>) -> Response<BoxBody> {
+    let id = req
+        .headers()
+        .get(&REQUEST_ID_HEADER)
+        .and_then(RequestId::from_header)
+        .unwrap_or_else(RequestId::generate);
+
+    tracing::Span::current().record("request_id", id.as_str());
+    req.extensions_mut().insert(id.clone());
+
+    let mut res = next.run(req).await;
+    res.headers_mut().insert(
+        REQUEST_ID_HEADER.clone(),
+        HeaderValue::from_str(id.as_str()).expect("Request ids are valid header values"),
+    );
+
+    if is_json_error(&res) {
+        let (parts, body) = res.into_parts();
+        let body = match hyper::body::to_bytes(body).await {
+            Ok(bytes) => with_request_id(bytes, &id),
+            Err(_) => Bytes::new(),
+        };
+        res = Response::from_parts(parts, body::boxed(body::Full::from(body)));
+        res.headers_mut().remove(header::CONTENT_LENGTH);
+    }
+
+    res
+}
+
+fn is_json_error(res: &Response<BoxBody>) -> bool {
+    let status = res.status();
+    let is_json = res
+        .headers()
+        .get(header::CONTENT_TYPE)
+        .and_then(|v| v.to_str().ok())
+        .map_or(false, |v| v.starts_with("application/json"));
+
+    (status.is_client_error() || status.is_server_error()) && is_json
+}
+
+/// Add the id to the error's body, bodies other than json objects are kept as is
+fn with_request_id(bytes: Bytes, id: &RequestId) -> Bytes {
+    match serde_json::from_slice::<Value>(&bytes) {
+        Ok(Value::Object(mut map)) => {
+            map.insert("request_id".into(), Value::String(id.to_string()));
+            serde_json::to_vec(&map).map(Bytes::from).unwrap_or(bytes)
+        }
+        _ => bytes,
+    }
+}
@@ -0,0 +1,171 @@
+//! Layered settings shared by the reactor and the apps.
+//!
+//! Values are read from the defaults, then a TOML/YAML/JSON file, then overridden by
+//! `<PREFIX>__SECTION__KEY` environment variables, then by explicit overrides(ex. CLI flags). Every
+//! app reads its own section, named after `App::name`, in `App::load_settings`. The hyphens of the
+//! section names are written as underscores in the environment variables, ex.
+//! `MYAPP__MTAPP_SESSION__TOKEN_KEY` for `mtapp-session.token_key`.
+
+use std::{env, error::Error, fmt, path::PathBuf};
+
+use config::{Config, Environment, File, Map};
+use serde::de::DeserializeOwned;
+use serde_json::Value;
+
+const REDACTED: &str = "<redacted>";
+
+#[derive(Debug)]
+pub enum SettingsError {
+    /// The sources couldn't be read or parsed
+    Load(config::ConfigError),
+
+    /// A value is missing or doesn't pass validation
+    Invalid { key: String, reason: String },
+}
+
+impl SettingsError {
+    pub fn invalid(key: impl Into<String>, reason: impl Into<String>) -> Self {
+        Self::Invalid {
+            key: key.into(),
+            reason: reason.into(),
+        }
+    }
+}
+
+impl fmt::Display for SettingsError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::Load(e) => write!(f, "Failed to load the settings: {}", e),
+            Self::Invalid { key, reason } => write!(f, "Invalid setting `{}`: {}", key, reason),
+        }
+    }
+}
+
+impl Error for SettingsError {}
+
+#[derive(Default)]
+pub struct SettingsBuilder {
+    file: Option<PathBuf>,
+    env_prefix: Option<String>,
+    defaults: Vec<(String, String)>,
+    overrides: Vec<(String, String)>,
+}
+
+impl SettingsBuilder {
+    /// Read the settings from this file, the format is guessed from its extension
+    pub fn file(mut self, path: impl Into<PathBuf>) -> Self {
+        self.file = Some(path.into());
+        self
+    }
+
+    /// Read the environment variables like `<PREFIX>__AUTH__SECRET`
+    pub fn env_prefix(mut self, prefix: impl Into<String>) -> Self {
+        self.env_prefix = Some(prefix.into());
+        self
+    }
+
+    /// Set a single value with the lowest precedence, any other source overrides it
+    pub fn set_default(mut self, key: impl Into<String>, value: impl Into<String>) -> Self {
+        self.defaults.push((key.into(), value.into()));
+        self
+    }
+
+    /// Override a single value(ex. `server.port`), later overrides win
+    pub fn set(mut self, key: impl Into<String>, value: impl Into<String>) -> Self {
+        self.overrides.push((key.into(), value.into()));
+        self
+    }
+
+    pub fn build(self) -> Result<Settings, SettingsError> {
+        let mut builder = Config::builder();
+
+        for (key, value) in self.defaults {
+            builder = builder
+                .set_default(key, value)
+                .map_err(SettingsError::Load)?;
+        }
+
+        if let Some(file) = self.file {
+            builder = builder.add_source(File::from(file));
+        }
+
+        if let Some(prefix) = self.env_prefix {
+            builder = builder.add_source(
+                Environment::with_prefix(&prefix)
+                    .prefix_separator("__")
+                    .separator("__")
+                    .source(Some(env_vars(&prefix))),
+            );
+        }
+
+        for (key, value) in self.overrides {
+            builder = builder
+                .set_override(key, value)
+                .map_err(SettingsError::Load)?;
+        }
+
+        builder.build().map(Settings).map_err(SettingsError::Load)
+    }
+}
+
+pub struct Settings(Config);
+
+impl Settings {
+    pub fn builder() -> SettingsBuilder {
+        SettingsBuilder::default()
+    }
+
+    /// Get a single value, `None` if it's not set
+    pub fn get<T: DeserializeOwned>(&self, key: &str) -> Result<Option<T>, SettingsError> {
+        match self.0.get::<T>(key) {
+            Ok(value) => Ok(Some(value)),
+            Err(config::ConfigError::NotFound(_)) => Ok(None),
+            Err(e) => Err(SettingsError::invalid(key, e.to_string())),
+        }
+    }
+
+    /// Get a whole section as a typed struct, falls back to its default if it's not set
+    pub fn section<T>(&self, name: &str) -> Result<T, SettingsError>
+    where
+        T: DeserializeOwned + Default,
+    {
+        self.get(name).map(Option::unwrap_or_default)
+    }
+
+    /// Pretty print all the settings, replacing the given keys(ex. `auth.secret`)
+    pub fn show(&self, secrets: &[String]) -> String {
+        let mut value = self
+            .0
+            .clone()
+            .try_deserialize::<Value>()
+            .unwrap_or(Value::Null);
+
+        for key in secrets {
+            let pointer = format!("/{}", key.replace('.', "/"));
+            if let Some(secret) = value.pointer_mut(&pointer) {
+                *secret = Value::String(REDACTED.into());
+            }
+        }
+
+        serde_json::to_string_pretty(&value).expect("Json values are serializable")
+    }
+}
+
+/// The variables with the prefix, with the underscores of their section names turned to hyphens
+/// since the shells don't allow hyphens in the names
+fn env_vars(prefix: &str) -> Map<String, String> {
+    let prefix = format!("{}__", prefix);
+
+    env::vars()
+        .filter_map(|(var, value)| {
+            let rest = var.strip_prefix(&prefix)?;
+            let var = match rest.split_once("__") {
+                Some((section, key)) => {
+                    format!("{}{}__{}", prefix, section.replace('_', "-"), key)
+                }
+                None => var,
+            };
+            Some((var, value))
+        })
+        .collect()
+}
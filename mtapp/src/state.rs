@@ -0,0 +1,55 @@
+use std::sync::Arc;
+
+use axum::{extract::FromRef, http::Extensions};
+use basteh::Basteh;
+
+use crate::db::DbPool;
+
+/// Shared state built once by the reactor and handed to every app's router.
+///
+/// Handlers can extract `State<DbPool>` or `State<Basteh>` directly, app specific values
+/// registered through `Configuration::state` can be extracted by implementing `FromRef`
+/// for them (see `ReactorState::get`).
+#[derive(Clone)]
+pub struct ReactorState(Arc<ReactorStateInner>);
+
+struct ReactorStateInner {
+    db: DbPool,
+    storage: Basteh,
+    values: Extensions,
+}
+
+impl ReactorState {
+    pub(crate) fn new(db: DbPool, storage: Basteh, values: Extensions) -> Self {
+        Self(Arc::new(ReactorStateInner {
+            db,
+            storage,
+            values,
+        }))
+    }
+
+    pub fn db(&self) -> &DbPool {
+        &self.0.db
+    }
+
+    pub fn storage(&self) -> &Basteh {
+        &self.0.storage
+    }
+
+    /// Get a value registered by one of the apps through `Configuration::state`
+    pub fn get<T: Send + Sync + 'static>(&self) -> Option<&T> {
+        self.0.values.get::<T>()
+    }
+}
+
+impl FromRef<ReactorState> for DbPool {
+    fn from_ref(state: &ReactorState) -> Self {
+        state.0.db.clone()
+    }
+}
+
+impl FromRef<ReactorState> for Basteh {
+    fn from_ref(state: &ReactorState) -> Self {
+        state.0.storage.clone()
+    }
+}
@@ -0,0 +1,36 @@
+use std::{future::Future, pin::Pin};
+
+use tokio::sync::watch;
+
+use crate::state::ReactorState;
+
+pub(crate) type BackgroundTask =
+    Box<dyn FnOnce(ReactorState, Shutdown) -> Pin<Box<dyn Future<Output = ()> + Send>> + Send>;
+
+/// Handed to background tasks, resolves when the reactor is shutting down.
+///
+/// Tasks are expected to finish their current unit of work and return once it's triggered,
+/// the reactor waits for them before running the apps' `on_shutdown` hooks.
+#[derive(Clone)]
+pub struct Shutdown(watch::Receiver<bool>);
+
+impl Shutdown {
+    pub(crate) fn new() -> (watch::Sender<bool>, Self) {
+        let (tx, rx) = watch::channel(false);
+        (tx, Self(rx))
+    }
+
+    pub fn is_triggered(&self) -> bool {
+        *self.0.borrow()
+    }
+
+    /// Wait until the shutdown is triggered
+    pub async fn wait(&mut self) {
+        while !*self.0.borrow_and_update() {
+            if self.0.changed().await.is_err() {
+                // Sender is gone, nothing will ever trigger it
+                return std::future::pending().await;
+            }
+        }
+    }
+}
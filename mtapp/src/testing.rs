@@ -0,0 +1,350 @@
+//! Helpers to run the apps in-process for integration tests, enabled by the `testing` feature.
+//!
+//! ```ignore
+//! let reactor = TestReactor::new(
+//!     Reactor::new()
+//!         .public_path("/api")
+//!         .mount_on("/users", UserApp::new()),
+//! )
+//! .await;
+//!
+//! let res = reactor.client().get("/api/users/me").await;
+//! assert_eq!(res.status(), StatusCode::UNAUTHORIZED);
+//!
+//! reactor.close().await;
+//! ```
+//!
+//! With postgres every `TestReactor` gets its own schema in the database pointed to by
+//! `TEST_DATABASE_URL`(or `DATABASE_URL`), which is dropped by `TestReactor::close`. The
+//! schemas left behind by a test that panicked before closing are dropped by the next
+//! `TestReactor` once they're an hour old. With sqlite an in-memory database is used instead.
+
+use std::net::SocketAddr;
+
+use axum::{
+    body::{Body, Bytes},
+    extract::ConnectInfo,
+    http::{header, HeaderMap, HeaderName, HeaderValue, Method, Request, StatusCode},
+    Router,
+};
+use basteh::Basteh;
+use basteh_memory::MemoryBackend;
+use serde::{de::DeserializeOwned, Serialize};
+use tower::ServiceExt;
+
+use crate::{db::DbPool, reactor::ReactorHandle, state::ReactorState, Reactor};
+
+/// A started reactor with its own database and storage, with all the migrations applied
+pub struct TestReactor {
+    router: Router,
+    handle: ReactorHandle,
+
+    #[cfg(feature = "postgres")]
+    schema: String,
+}
+
+impl TestReactor {
+    pub async fn new(reactor: Reactor<(), ()>) -> Self {
+        #[cfg(feature = "postgres")]
+        let (db, schema) = postgres::create_db().await;
+        #[cfg(all(feature = "sqlite", not(feature = "postgres")))]
+        let db = sqlite::create_db().await;
+
+        let storage = Basteh::build()
+            .provider(MemoryBackend::start_default())
+            .finish();
+
+        let mut reactor = reactor.db(db).storage(storage);
+        reactor
+            .run_migrations()
+            .await
+            .expect("Apps should be mounted with their dependencies");
+
+        let (router, handle) = reactor
+            .start()
+            .await
+            .expect("Apps should be mounted with their dependencies");
+
+        Self {
+            router,
+            handle,
+
+            #[cfg(feature = "postgres")]
+            schema,
+        }
+    }
+
+    pub fn db(&self) -> &DbPool {
+        self.handle.state().db()
+    }
+
+    pub fn storage(&self) -> &Basteh {
+        self.handle.state().storage()
+    }
+
+    pub fn state(&self) -> &ReactorState {
+        self.handle.state()
+    }
+
+    /// A client without any default headers
+    pub fn client(&self) -> TestClient {
+        TestClient {
+            router: self.router.clone(),
+            state: self.handle.state().clone(),
+            headers: HeaderMap::new(),
+            peer: None,
+        }
+    }
+
+    /// Shut the reactor down and clean up the database used by it
+    pub async fn close(self) {
+        let db = self.db().clone();
+        self.handle.shutdown().await;
+
+        #[cfg(feature = "postgres")]
+        postgres::drop_db(&db, &self.schema).await;
+
+        db.close().await;
+    }
+}
+
+/// Sends requests through the reactor's router, without binding to any address
+#[derive(Clone)]
+pub struct TestClient {
+    router: Router,
+    state: ReactorState,
+    headers: HeaderMap,
+    peer: Option<SocketAddr>,
+}
+
+impl TestClient {
+    pub fn state(&self) -> &ReactorState {
+        &self.state
+    }
+
+    /// Add a header to every request sent by this client
+    pub fn header(mut self, name: HeaderName, value: &str) -> Self {
+        self.headers.insert(
+            name,
+            HeaderValue::from_str(value).expect("Invalid header value"),
+        );
+        self
+    }
+
+    /// Send the requests from this address, as `ConnectInfo` would for a served router
+    pub fn peer(mut self, addr: SocketAddr) -> Self {
+        self.peer = Some(addr);
+        self
+    }
+
+    pub fn bearer(self, token: &str) -> Self {
+        self.header(header::AUTHORIZATION, &format!("Bearer {}", token))
+    }
+
+    pub async fn get(&self, uri: &str) -> TestResponse {
+        self.send(Method::GET, uri, Body::empty()).await
+    }
+
+    pub async fn delete(&self, uri: &str) -> TestResponse {
+        self.send(Method::DELETE, uri, Body::empty()).await
+    }
+
+    pub async fn post<T: Serialize>(&self, uri: &str, body: &T) -> TestResponse {
+        self.send_json(Method::POST, uri, body).await
+    }
+
+    pub async fn send_json<T: Serialize>(
+        &self,
+        method: Method,
+        uri: &str,
+        body: &T,
+    ) -> TestResponse {
+        let body = serde_json::to_vec(body).expect("Body should be serializable");
+
+        let mut req = self.build(method, uri, Body::from(body));
+        req.headers_mut().insert(
+            header::CONTENT_TYPE,
+            HeaderValue::from_static("application/json"),
+        );
+        self.request(req).await
+    }
+
+    pub async fn send(&self, method: Method, uri: &str, body: Body) -> TestResponse {
+        self.request(self.build(method, uri, body)).await
+    }
+
+    /// Send a raw request, the client's default headers are not added
+    pub async fn request(&self, req: Request<Body>) -> TestResponse {
+        let res = self
+            .router
+            .clone()
+            .oneshot(req)
+            .await
+            .expect("Router is infallible");
+
+        let (parts, body) = res.into_parts();
+        let body = hyper::body::to_bytes(body)
+            .await
+            .expect("Failed to read the response body");
+
+        TestResponse {
+            status: parts.status,
+            headers: parts.headers,
+            body,
+        }
+    }
+
+    fn build(&self, method: Method, uri: &str, body: Body) -> Request<Body> {
+        let mut req = Request::builder()
+            .method(method)
+            .uri(uri)
+            .body(body)
+            .expect("Invalid request");
+        req.headers_mut().extend(self.headers.clone());
+        if let Some(peer) = self.peer {
+            req.extensions_mut().insert(ConnectInfo(peer));
+        }
+        req
+    }
+}
+
+pub struct TestResponse {
+    status: StatusCode,
+    headers: HeaderMap,
+    body: Bytes,
+}
+
+impl TestResponse {
+    pub fn status(&self) -> StatusCode {
+        self.status
+    }
+
+    pub fn headers(&self) -> &HeaderMap {
+        &self.headers
+    }
+
+    pub fn bytes(&self) -> &Bytes {
+        &self.body
+    }
+
+    pub fn text(&self) -> String {
+        String::from_utf8_lossy(&self.body).into_owned()
+    }
+
+    pub fn json<T: DeserializeOwned>(&self) -> T {
+        serde_json::from_slice(&self.body)
+            .unwrap_or_else(|e| panic!("Invalid json response({}): {}", e, self.text()))
+    }
+}
+
+#[cfg(feature = "postgres")]
+mod postgres {
+    use std::{
+        env,
+        str::FromStr,
+        time::{SystemTime, UNIX_EPOCH},
+    };
+
+    use sqlx::{
+        postgres::{PgConnectOptions, PgPoolOptions},
+        ConnectOptions,
+    };
+
+    use crate::db::DbPool;
+
+    /// Schemas older than this are left behind by a failed test, not used by a running one
+    const STALE_SCHEMA_AGE: u64 = 60 * 60;
+
+    fn database_url() -> String {
+        env::var("TEST_DATABASE_URL")
+            .or_else(|_| env::var("DATABASE_URL"))
+            .expect("TEST_DATABASE_URL or DATABASE_URL should be in env to run the tests")
+    }
+
+    /// Create a fresh schema and a pool using it
+    pub(super) async fn create_db() -> (DbPool, String) {
+        let url = database_url();
+        let now = SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .expect("System time is before the unix epoch")
+            .as_secs();
+        let schema = format!("test_{}_{}", now, uuid::Uuid::new_v4().simple());
+
+        let options = PgConnectOptions::from_str(&url)
+            .expect("Invalid database url provided")
+            .disable_statement_logging()
+            .clone();
+
+        let pool = PgPoolOptions::new()
+            .max_connections(1)
+            .connect_with(options.clone())
+            .await
+            .expect("Database connection failed");
+        drop_stale_schemas(&pool, now).await;
+        sqlx::query(&format!("CREATE SCHEMA \"{}\"", schema))
+            .execute(&pool)
+            .await
+            .expect("Failed to create the test schema");
+        pool.close().await;
+
+        // Extensions are installed in public, so keep it in the search path
+        let pool = PgPoolOptions::new()
+            .connect_with(options.options([("search_path", format!("{},public", schema))]))
+            .await
+            .expect("Database connection failed");
+
+        (pool, schema)
+    }
+
+    /// Drop the schemas of the tests that didn't reach `TestReactor::close`
+    async fn drop_stale_schemas(pool: &DbPool, now: u64) {
+        let schemas: Vec<(String,)> =
+            sqlx::query_as("SELECT nspname FROM pg_namespace WHERE nspname LIKE 'test\\_%'")
+                .fetch_all(pool)
+                .await
+                .expect("Failed to list the test schemas");
+
+        for (schema,) in schemas {
+            let created_at = schema
+                .strip_prefix("test_")
+                .and_then(|rest| rest.split_once('_'))
+                .and_then(|(secs, _)| secs.parse::<u64>().ok());
+
+            if created_at
+                .is_some_and(|created_at| now.saturating_sub(created_at) > STALE_SCHEMA_AGE)
+            {
+                // Another test might be dropping it at the same time
+                sqlx::query(&format!("DROP SCHEMA IF EXISTS \"{}\" CASCADE", schema))
+                    .execute(pool)
+                    .await
+                    .expect("Failed to drop a stale test schema");
+            }
+        }
+    }
+
+    pub(super) async fn drop_db(pool: &DbPool, schema: &str) {
+        sqlx::query(&format!("DROP SCHEMA \"{}\" CASCADE", schema))
+            .execute(pool)
+            .await
+            .expect("Failed to drop the test schema");
+    }
+}
+
+#[cfg(all(feature = "sqlite", not(feature = "postgres")))]
+mod sqlite {
+    use sqlx::sqlite::SqlitePoolOptions;
+
+    use crate::db::DbPool;
+
+    pub(super) async fn create_db() -> DbPool {
+        // Every connection to an in-memory database gets its own database, so keep exactly one
+        // connection alive for the pool's lifetime
+        SqlitePoolOptions::new()
+            .max_connections(1)
+            .idle_timeout(None)
+            .max_lifetime(None)
+            .connect("sqlite::memory:")
+            .await
+            .expect("Failed to create the in-memory database")
+    }
+}
@@ -0,0 +1,61 @@
+use std::time::Duration;
+
+use axum::{
+    extract::MatchedPath,
+    http::{Request, Response},
+};
+use tower_http::{
+    classify::{ServerErrorsAsFailures, SharedClassifier},
+    trace::{MakeSpan, OnResponse, TraceLayer},
+};
+use tracing::{field::Empty, Span};
+
+pub(crate) type RequestTraceLayer =
+    TraceLayer<SharedClassifier<ServerErrorsAsFailures>, MakeRequestSpan, (), LogResponse>;
+
+/// Wraps every request in a `request` span, apps can fill in the empty fields(ex. `user_id`)
+/// through `Span::current().record`
+pub(crate) fn layer() -> RequestTraceLayer {
+    TraceLayer::new_for_http()
+        .make_span_with(MakeRequestSpan)
+        .on_request(())
+        .on_response(LogResponse)
+}
+
+#[derive(Clone)]
+pub(crate) struct MakeRequestSpan;
+
+impl<B> MakeSpan<B> for MakeRequestSpan {
+    fn make_span(&mut self, request: &Request<B>) -> Span {
+        let route = request
+            .extensions()
+            .get::<MatchedPath>()
+            .map(|path| path.as_str())
+            .unwrap_or_default();
+
+        tracing::info_span!(
+            "request",
+            method = %request.method(),
+            route,
+            uri = %request.uri(),
+            request_id = Empty,
+            user_id = Empty,
+            status = Empty,
+            latency_ms = Empty,
+        )
+    }
+}
+
+#[derive(Clone)]
+pub(crate) struct LogResponse;
+
+impl<B> OnResponse<B> for LogResponse {
+    fn on_response(self, response: &Response<B>, latency: Duration, span: &Span) {
+        let status = response.status().as_u16();
+        let latency_ms = latency.as_millis() as u64;
+
+        span.record("status", status);
+        span.record("latency_ms", latency_ms);
+        tracing::info!(status, latency_ms, "finished processing request");
+    }
+}
@@ -1,85 +1,138 @@
 use std::{
-    env,
+    env, fmt,
     net::{IpAddr, Ipv4Addr, SocketAddr},
+    process,
     str::FromStr,
 };
 
 use axum::Extension;
 use basteh::Basteh;
 use basteh_memory::MemoryBackend;
-use clap::{arg, Command};
+use clap::{arg, ArgAction, ArgMatches, Command};
+use serde::Deserialize;
 use serde_querystring_axum::{ParseMode, QueryStringConfig};
+#[cfg(all(feature = "sqlite", not(feature = "postgres")))]
+use sqlx::sqlite::{SqliteConnectOptions, SqlitePoolOptions};
+#[cfg(feature = "postgres")]
 use sqlx::{
     postgres::{PgConnectOptions, PgPoolOptions},
-    ConnectOptions, PgPool,
+    ConnectOptions,
 };
+use tracing_subscriber::EnvFilter;
 
-use mtapp::Reactor;
+use mtapp::{
+    db::DbPool,
+    settings::{Settings, SettingsError},
+    Reactor,
+};
 use mtapp_auth::{AuthApp, AuthConfig};
+use mtapp_client::{ClientApp, Provider as CP};
 use mtapp_grant::{GrantApp, Provider as GP};
 use mtapp_scope::ScopeApp;
 use mtapp_session::{Provider as SP, SessionApp};
 use mtapp_user::{Provider as UP, UserApp};
 use utoipa_swagger_ui::SwaggerUi;
 
+#[derive(Deserialize)]
+#[serde(default, deny_unknown_fields)]
+struct ServerSettings {
+    host: IpAddr,
+    port: u16,
+}
+
+impl Default for ServerSettings {
+    fn default() -> Self {
+        Self {
+            host: Ipv4Addr::new(127, 0, 0, 1).into(),
+            port: 3000,
+        }
+    }
+}
+
+#[derive(Default, Deserialize)]
+#[serde(rename_all = "lowercase")]
+enum LogFormat {
+    #[default]
+    Human,
+    Json,
+}
+
+#[derive(Deserialize)]
+#[serde(default, deny_unknown_fields)]
+struct LogSettings {
+    format: LogFormat,
+    filter: String,
+}
+
+impl Default for LogSettings {
+    fn default() -> Self {
+        Self {
+            format: LogFormat::Human,
+            filter: String::from("info"),
+        }
+    }
+}
+
 #[tokio::main]
 async fn main() {
     dotenvy::dotenv().ok();
-    env_logger::init();
 
-    let db_url = env::var("DATABASE_URL").expect("DATABASE_URL should be in env");
-    let secret = env::var("APP_SECRET").expect("APP_SECRET should be in env");
-    let host = env::var("APP_HOST").ok();
-    let port = env::var("APP_PORT").ok();
-
-    let db = get_db(&db_url).await;
-    let storage = get_storage();
-
-    let auth_app =
-        AuthApp::<UP, SP, GP>::with_config(AuthConfig::new("auth_blacklist", 10 * 60, secret));
+    let auth_app = AuthApp::<UP, SP, GP, CP, UP>::with_config(AuthConfig::new(
+        "auth_blacklist",
+        10 * 60,
+        String::new(),
+    ))
+    .depends_on(&["mtapp-user", "mtapp-session", "mtapp-grant", "mtapp-client"]);
     let scope_app = ScopeApp::new();
     let user_app = UserApp::new();
     let grant_app = GrantApp::new();
     let session_app = SessionApp::new();
+    let client_app = ClientApp::new();
 
     let mut app = Reactor::new()
         .public_path("/api/dev")
         .internal_path("/api/internals")
+        .health_path("/health")
+        .ready_path("/ready")
+        .version_path("/version", env!("CARGO_PKG_VERSION"))
+        .metrics_path("/metrics")
         .mount_on("/auth", auth_app)
         .mount_on("/scopes", scope_app)
         .mount_on("/users", user_app)
         .mount_on("/grants", grant_app)
         .mount_on("/sessions", session_app)
-        .storage(storage)
-        .db(db);
+        .mount_on("/clients", client_app);
 
     let m = get_clap_defs(&app).get_matches();
 
+    let settings = load_settings(&m).unwrap_or_else(exit_with);
+    init_logging(&settings).unwrap_or_else(exit_with);
+
+    if let Some(("config", _)) = m.subcommand() {
+        let mut secrets = app.secret_settings();
+        secrets.push(String::from("database.url"));
+        println!("{}", settings.show(&secrets));
+        return;
+    }
+
+    app.load_settings(&settings).unwrap_or_else(exit_with);
+    let (db_url, server) = get_main_settings(&settings).unwrap_or_else(exit_with);
+
+    let mut app = app.storage(get_storage()).db(get_db(&db_url).await);
+
     match m.subcommand() {
         Some(("migrate", _)) => {
-            app.run_migrations().await;
+            app.run_migrations().await.unwrap_or_else(exit_with);
         }
         Some((cmd, args)) => {
-            app.run_command(cmd, args).await;
+            app.run_command(cmd, args).await.unwrap_or_else(exit_with);
         }
         None => {
-            let host: IpAddr = m
-                .get_one("host")
-                .cloned()
-                .or(host)
-                .and_then(|v| v.parse().ok())
-                .unwrap_or(Ipv4Addr::new(127, 0, 0, 1).into());
-
-            let port: u16 = m
-                .get_one("port")
-                .cloned()
-                .or(port)
-                .and_then(|v| v.parse().ok())
-                .unwrap_or(3000);
+            let ServerSettings { host, port } = server;
 
             let api_docs = app.public_api_docs();
             let internal_api_docs = app.internal_api_docs();
-            let mut router = app.into_router();
+            let (mut router, handle) = app.start().await.unwrap_or_else(exit_with);
             router = router
                 .layer(Extension(QueryStringConfig::new(ParseMode::Brackets)))
                 .merge(SwaggerUi::new("/api/dev/docs").url("/api/dev/api-docs.json", api_docs))
@@ -91,9 +144,13 @@ async fn main() {
             log::info!("Running web server on: http://{}:{}", host, port);
 
             axum::Server::bind(&SocketAddr::new(host, port))
-                .serve(router.into_make_service())
+                .serve(router.into_make_service_with_connect_info::<SocketAddr>())
+                .with_graceful_shutdown(shutdown_signal())
                 .await
                 .expect("Failed to start the server");
+
+            log::info!("Waiting for the background tasks to finish");
+            handle.shutdown().await;
         }
     }
 }
@@ -102,16 +159,101 @@ fn get_clap_defs<D, S>(app: &Reactor<D, S>) -> Command {
     // Run the web server when there is no commands
     let mut clap_app = Command::new("myapp")
         .arg(arg!(--host <HOST> "Run on host"))
-        .arg(arg!(--port <PORT> "Run on port"));
+        .arg(arg!(--port <PORT> "Run on port"))
+        .arg(arg!(-c --config <FILE> "Read the settings from a toml/yaml/json file").global(true))
+        .arg(
+            arg!(--set <SETTING> "Override a setting, ex. auth.token_expiry=600")
+                .action(ArgAction::Append)
+                .global(true),
+        );
 
     // Register the migrate command
     clap_app = clap_app.subcommand(clap::Command::new("migrate").about("Apply all the migrations"));
 
+    // Register the config command
+    clap_app = clap_app.subcommand(
+        clap::Command::new("config")
+            .about("Inspect the settings")
+            .subcommand(Command::new("show").about("Print the settings, secrets redacted"))
+            .subcommand_required(true),
+    );
+
     // Register all subcommands from app
     clap_app.subcommands(app.clap_defs())
 }
 
-async fn get_db(db_url: &str) -> PgPool {
+/// Settings are read from the legacy env variables, then the file, then `MYAPP__SECTION__KEY` env
+/// variables, then CLI flags
+fn load_settings(m: &ArgMatches) -> Result<Settings, SettingsError> {
+    let mut builder = Settings::builder().env_prefix("MYAPP");
+
+    if let Some(file) = m.get_one::<String>("config") {
+        builder = builder.file(file);
+    }
+
+    // Kept for the old .env files, every other source takes precedence
+    for (var, key) in [
+        ("DATABASE_URL", "database.url"),
+        ("APP_SECRET", "auth.secret"),
+        ("APP_HOST", "server.host"),
+        ("APP_PORT", "server.port"),
+    ] {
+        if let Ok(value) = env::var(var) {
+            builder = builder.set_default(key, value);
+        }
+    }
+
+    if let Some(host) = m.get_one::<String>("host") {
+        builder = builder.set("server.host", host);
+    }
+    if let Some(port) = m.get_one::<String>("port") {
+        builder = builder.set("server.port", port);
+    }
+    for setting in m.get_many::<String>("set").into_iter().flatten() {
+        let (key, value) = setting.split_once('=').ok_or_else(|| {
+            SettingsError::invalid(setting, "overrides should be in the form of key=value")
+        })?;
+        builder = builder.set(key, value);
+    }
+
+    builder.build()
+}
+
+fn exit_with<T>(err: impl fmt::Display) -> T {
+    eprintln!("{}", err);
+    process::exit(1);
+}
+
+fn get_main_settings(settings: &Settings) -> Result<(String, ServerSettings), SettingsError> {
+    let db_url = settings
+        .get::<String>("database.url")?
+        .ok_or_else(|| SettingsError::invalid("database.url", "should be set"))?;
+    let server = settings.section("server")?;
+
+    Ok((db_url, server))
+}
+
+/// `RUST_LOG` takes precedence over `log.filter`, both use the `EnvFilter` syntax
+fn init_logging(settings: &Settings) -> Result<(), SettingsError> {
+    let log: LogSettings = settings.section("log")?;
+
+    let filter = match EnvFilter::try_from_default_env() {
+        Ok(filter) => filter,
+        Err(_) => EnvFilter::try_new(&log.filter)
+            .map_err(|e| SettingsError::invalid("log.filter", e.to_string()))?,
+    };
+
+    let subscriber = tracing_subscriber::fmt().with_env_filter(filter);
+    match log.format {
+        LogFormat::Human => subscriber.init(),
+        LogFormat::Json => subscriber.json().init(),
+    }
+
+    Ok(())
+}
+
+#[cfg(feature = "postgres")]
+async fn get_db(db_url: &str) -> DbPool {
     PgPoolOptions::new()
         .connect_with(
             PgConnectOptions::from_str(db_url)
@@ -123,6 +265,45 @@ async fn get_db(db_url: &str) -> PgPool {
         .expect("Database connection failed")
 }
 
+#[cfg(all(feature = "sqlite", not(feature = "postgres")))]
+async fn get_db(db_url: &str) -> DbPool {
+    SqlitePoolOptions::new()
+        .connect_with(
+            SqliteConnectOptions::from_str(db_url)
+                .expect("Invalid DATABASE_URL provided")
+                .create_if_missing(true),
+        )
+        .await
+        .expect("Database connection failed")
+}
+
+/// Resolves on ctrl-c or SIGTERM, the server stops accepting new connections and waits for the
+/// in-flight requests
+async fn shutdown_signal() {
+    let ctrl_c = async {
+        tokio::signal::ctrl_c()
+            .await
+            .expect("Failed to listen for ctrl-c");
+    };
+
+    #[cfg(unix)]
+    let terminate = async {
+        tokio::signal::unix::signal(tokio::signal::unix::SignalKind::terminate())
+            .expect("Failed to listen for SIGTERM")
+            .recv()
+            .await;
+    };
+    #[cfg(not(unix))]
+    let terminate = std::future::pending::<()>();
+
+    tokio::select! {
+        _ = ctrl_c => {},
+        _ = terminate => {},
+    }
+
+    log::info!("Shutting down the web server");
+}
+
 fn get_storage() -> Basteh {
     Basteh::build()
         .provider(MemoryBackend::start_default())
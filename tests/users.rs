@@ -0,0 +1,1302 @@
+use std::{
+    net::TcpListener,
+    sync::{Arc, Mutex},
+    time::{Duration, SystemTime, UNIX_EPOCH},
+};
+
+use axum::{
+    body::Body,
+    extract::State,
+    http::{header, Extensions, Method, StatusCode},
+    routing::{get, post},
+    Form, Json, Router, Server,
+};
+use base64::{engine::general_purpose::URL_SAFE_NO_PAD, Engine};
+use jsonwebtoken::{encode, Algorithm, EncodingKey, Header};
+use ring::{
+    digest::{digest, SHA256},
+    hmac,
+    rand::SystemRandom,
+    signature::{EcdsaKeyPair, Ed25519KeyPair, KeyPair, ECDSA_P256_SHA256_ASN1_SIGNING},
+};
+use serde::Deserialize;
+use serde_json::{json, Value};
+
+use mtapp::{
+    testing::{TestClient, TestReactor, TestResponse},
+    App, Reactor, RATE_LIMIT_REMAINING_HEADER, REQUEST_ID_HEADER,
+};
+use mtapp_auth::{
+    testing::AuthTestExt, AuthApp, AuthConfig, Claims, OidcProvider, SigningKey, API_KEY_HEADER,
+    CSRF_HEADER,
+};
+use mtapp_client::{ClientApp, Provider as CP};
+use mtapp_grant::{GrantApp, Provider as GP};
+use mtapp_scope::ScopeApp;
+use mtapp_session::{Provider as SP, SessionApp};
+use mtapp_user::{Provider as UP, User, UserApp};
+
+fn auth_config() -> AuthConfig {
+    AuthConfig::new("storage_scope", 24 * 60 * 60, String::from("secret"))
+        .with_sealing_key("sealing secret")
+        .relying_party("localhost", "mtapp", "http://localhost")
+}
+
+async fn reactor() -> TestReactor {
+    reactor_with(auth_config()).await
+}
+
+async fn reactor_with(config: AuthConfig) -> TestReactor {
+    reactor_with_sessions(config, SessionApp::new()).await
+}
+
+async fn reactor_with_sessions(config: AuthConfig, sessions: SessionApp) -> TestReactor {
+    TestReactor::new(
+        Reactor::new()
+            .public_path("/api")
+            .internal_path("/internal")
+            .mount_on("/auth", AuthApp::<UP, SP, GP, CP, UP>::with_config(config))
+            .mount_on("/scopes", ScopeApp::new())
+            .mount_on("/users", UserApp::new())
+            .mount_on("/grants", GrantApp::new())
+            .mount_on("/sessions", sessions.token_key("secret"))
+            .mount_on("/clients", ClientApp::new()),
+    )
+    .await
+}
+
+/// Sign up and log in as `testuser`, returns the refresh token
+async fn login(client: &TestClient) -> String {
+    let res = client
+        .post(
+            "/api/users/",
+            &json!({"username": "testuser", "password": "testpassword"}),
+        )
+        .await;
+    assert_eq!(res.status(), StatusCode::OK, "{}", res.text());
+
+    let res = client
+        .send(
+            Method::POST,
+            "/api/auth/login?flat=true",
+            Body::from("username=testuser&password=testpassword"),
+        )
+        .await;
+    assert_eq!(res.status(), StatusCode::OK, "{}", res.text());
+
+    res.json::<Value>()["refresh_token"]
+        .as_str()
+        .expect("Login should return a refresh token")
+        .to_owned()
+}
+
+async fn refresh(client: &TestClient, refresh_token: &str) -> TestResponse {
+    client
+        .clone()
+        .header(
+            header::COOKIE,
+            &format!("refresh-token={}; csrf-token=csrf", refresh_token),
+        )
+        .header(CSRF_HEADER.clone(), "csrf")
+        .send(Method::POST, "/api/auth/refresh?flat=true", Body::empty())
+        .await
+}
+
+#[tokio::test]
+async fn signup_and_get_me() {
+    let reactor = reactor().await;
+    let client = reactor.client();
+
+    let res = client
+        .post(
+            "/api/users/",
+            &json!({"username": "testuser", "password": "testpassword"}),
+        )
+        .await;
+    assert_eq!(res.status(), StatusCode::OK, "{}", res.text());
+
+    let user = User::get_by_username("testuser", reactor.db())
+        .await
+        .expect("User should be created");
+
+    let res = client.get("/api/users/me").await;
+    assert_eq!(res.status(), StatusCode::UNAUTHORIZED);
+
+    let res = client.as_user(user.id, &[]).get("/api/users/me").await;
+    assert_eq!(res.status(), StatusCode::OK, "{}", res.text());
+
+    reactor.close().await;
+}
+
+#[tokio::test]
+async fn internal_routes_need_admin_scope() {
+    let reactor = reactor().await;
+    let user_id = mtapp::Uuid::new_v4();
+
+    let res = reactor
+        .client()
+        .as_user(user_id, &[])
+        .get("/internal/users/")
+        .await;
+    assert_eq!(res.status(), StatusCode::FORBIDDEN);
+
+    let res = reactor
+        .client()
+        .as_user(user_id, &["admin"])
+        .get("/internal/users/")
+        .await;
+    assert_eq!(res.status(), StatusCode::OK, "{}", res.text());
+
+    reactor.close().await;
+}
+
+#[test]
+fn internal_docs_show_the_required_scopes() {
+    let docs = UserApp::new()
+        .internal_openapi("/internal/users")
+        .expect("UserApp has internal docs");
+    let docs = serde_json::to_value(docs).expect("Docs are serializable");
+
+    // Either of the scopes is enough
+    assert_eq!(
+        docs["paths"]["/"]["get"]["security"],
+        json!([{"jwt_token": ["superadmin"]}, {"jwt_token": ["admin"]}])
+    );
+}
+
+/// Run the `rotate-key` command and reload the keys, the app shares the reactor's key ring
+async fn rotate_key(app: &mut AuthApp<UP, SP, GP, CP, UP>, reactor: &TestReactor, args: &[&str]) {
+    let matches = app
+        .clap_def()
+        .expect("AuthApp has commands")
+        .get_matches_from([&["mtapp-auth", "rotate-key"], args].concat());
+    let mut ext = Extensions::new();
+    ext.insert(reactor.db().clone());
+
+    app.clap_run(&matches, &ext).await;
+    app.on_startup(reactor.state()).await;
+}
+
+#[tokio::test]
+async fn rotated_keys_are_accepted_until_retired() {
+    let config = auth_config();
+    let reactor = reactor_with(config.clone()).await;
+    let mut app = AuthApp::<UP, SP, GP, CP, UP>::with_config(config.clone());
+    let user_id = mtapp::Uuid::new_v4();
+
+    let configured = reactor.client().as_user(user_id, &["admin"]);
+    rotate_key(&mut app, &reactor, &["--algorithm", "EdDSA"]).await;
+
+    // New tokens are signed with the new key
+    let current = config.keys().current();
+    assert_eq!(current.algorithm(), Algorithm::EdDSA);
+    assert!(current.kid().is_some());
+
+    // The previous key is still in its grace period
+    let res = configured.get("/internal/users/").await;
+    assert_eq!(res.status(), StatusCode::OK, "{}", res.text());
+
+    let rotated = reactor.client().as_user(user_id, &["admin"]);
+    let res = rotated.get("/internal/users/").await;
+    assert_eq!(res.status(), StatusCode::OK, "{}", res.text());
+
+    // Keys which were never in the ring
+    let unknown = SigningKey::generate(Algorithm::EdDSA, "unknown").expect("Key is generated");
+    let token = Claims::new(
+        user_id,
+        mtapp::Uuid::new_v4(),
+        vec![String::from("admin")],
+        Duration::from_secs(60),
+    )
+    .generate_token(&unknown);
+    let res = reactor
+        .client()
+        .bearer(&token)
+        .get("/internal/users/")
+        .await;
+    assert_eq!(res.status(), StatusCode::UNAUTHORIZED);
+
+    // Retiring the rotated key right away, the configured one keeps its earlier grace period
+    rotate_key(
+        &mut app,
+        &reactor,
+        &["--algorithm", "EdDSA", "--grace", "0"],
+    )
+    .await;
+    let res = rotated.get("/internal/users/").await;
+    assert_eq!(res.status(), StatusCode::UNAUTHORIZED);
+    let res = configured.get("/internal/users/").await;
+    assert_eq!(res.status(), StatusCode::OK, "{}", res.text());
+
+    reactor.close().await;
+}
+
+#[tokio::test]
+async fn errors_carry_the_request_id() {
+    let reactor = reactor().await;
+
+    let res = reactor
+        .client()
+        .header(REQUEST_ID_HEADER.clone(), "support-ticket-1")
+        .get("/api/users/me")
+        .await;
+    assert_eq!(res.status(), StatusCode::UNAUTHORIZED);
+    assert_eq!(res.headers()[&REQUEST_ID_HEADER], "support-ticket-1");
+    assert_eq!(res.json::<Value>()["request_id"], "support-ticket-1");
+
+    // Generated when the client doesn't send one
+    let res = reactor.client().get("/api/users/me").await;
+    assert!(res.headers().contains_key(&REQUEST_ID_HEADER));
+
+    reactor.close().await;
+}
+
+#[tokio::test]
+async fn reused_refresh_tokens_revoke_the_session() {
+    let reactor = reactor().await;
+    let client = reactor
+        .client()
+        .header(header::USER_AGENT, "test")
+        .header(header::CONTENT_TYPE, "application/x-www-form-urlencoded");
+
+    let first = login(&client).await;
+
+    let res = refresh(&client, &first).await;
+    assert_eq!(res.status(), StatusCode::OK, "{}", res.text());
+    let second = res.json::<Value>()["refresh_token"]
+        .as_str()
+        .expect("Refresh should return a refresh token")
+        .to_owned();
+    assert_ne!(first, second);
+
+    // The first token is already rotated, using it again revokes the whole session
+    let res = refresh(&client, &first).await;
+    assert_eq!(res.status(), StatusCode::UNAUTHORIZED);
+
+    let res = refresh(&client, &second).await;
+    assert_eq!(res.status(), StatusCode::UNAUTHORIZED);
+
+    reactor.close().await;
+}
+
+#[tokio::test]
+async fn refresh_token_cookie_needs_the_csrf_header() {
+    let reactor = reactor().await;
+    let client = reactor
+        .client()
+        .header(header::USER_AGENT, "test")
+        .header(header::CONTENT_TYPE, "application/x-www-form-urlencoded");
+
+    let token = login(&client).await;
+    let cookie_only = client
+        .clone()
+        .header(header::COOKIE, &format!("refresh-token={}", token));
+    let wrong_header = client
+        .clone()
+        .header(
+            header::COOKIE,
+            &format!("refresh-token={}; csrf-token=csrf", token),
+        )
+        .header(CSRF_HEADER.clone(), "other");
+
+    for client in [&cookie_only, &wrong_header] {
+        let res = client
+            .send(Method::POST, "/api/auth/refresh", Body::empty())
+            .await;
+        assert_eq!(res.status(), StatusCode::FORBIDDEN);
+
+        let res = client
+            .send(Method::POST, "/api/auth/logout", Body::empty())
+            .await;
+        assert_eq!(res.status(), StatusCode::FORBIDDEN);
+    }
+
+    // Still logged in
+    let token = refreshed(&client, &token).await;
+
+    let res = client
+        .clone()
+        .header(
+            header::COOKIE,
+            &format!("refresh-token={}; csrf-token=csrf", token),
+        )
+        .header(CSRF_HEADER.clone(), "csrf")
+        .send(Method::POST, "/api/auth/logout", Body::empty())
+        .await;
+    assert_eq!(res.status(), StatusCode::OK, "{}", res.text());
+
+    let res = refresh(&client, &token).await;
+    assert_eq!(res.status(), StatusCode::UNAUTHORIZED);
+
+    reactor.close().await;
+}
+
+/// Refresh and return the new refresh token
+async fn refreshed(client: &TestClient, refresh_token: &str) -> String {
+    let res = refresh(client, refresh_token).await;
+    assert_eq!(res.status(), StatusCode::OK, "{}", res.text());
+    res.json::<Value>()["refresh_token"]
+        .as_str()
+        .expect("Refresh should return a refresh token")
+        .to_owned()
+}
+
+/// Move a timestamp of all the sessions back in time, instead of waiting for them to expire
+async fn backdate_sessions(reactor: &TestReactor, column: &str) {
+    let query = format!(
+        "UPDATE sessions SET {} = '2000-01-01 00:00:00+00:00'",
+        column
+    );
+    sqlx::query(&query)
+        .execute(reactor.db())
+        .await
+        .expect("Failed to backdate the sessions");
+}
+
+#[tokio::test]
+async fn idle_sessions_cant_be_refreshed() {
+    let sessions = SessionApp::new().max_idle(Duration::from_secs(60 * 60));
+    let reactor = reactor_with_sessions(auth_config(), sessions).await;
+    let client = reactor
+        .client()
+        .header(header::USER_AGENT, "test")
+        .header(header::CONTENT_TYPE, "application/x-www-form-urlencoded");
+
+    let token = refreshed(&client, &login(&client).await).await;
+
+    backdate_sessions(&reactor, "last_access_at").await;
+    let res = refresh(&client, &token).await;
+    assert_eq!(res.status(), StatusCode::UNAUTHORIZED);
+
+    reactor.close().await;
+}
+
+#[tokio::test]
+async fn old_sessions_cant_be_refreshed() {
+    let sessions = SessionApp::new().max_lifetime(Duration::from_secs(60 * 60));
+    let reactor = reactor_with_sessions(auth_config(), sessions).await;
+    let client = reactor
+        .client()
+        .header(header::USER_AGENT, "test")
+        .header(header::CONTENT_TYPE, "application/x-www-form-urlencoded");
+
+    // Refreshed recently, but it's still too old
+    let token = refreshed(&client, &login(&client).await).await;
+
+    backdate_sessions(&reactor, "created_at").await;
+    let res = refresh(&client, &token).await;
+    assert_eq!(res.status(), StatusCode::UNAUTHORIZED);
+
+    reactor.close().await;
+}
+
+#[tokio::test]
+async fn failed_logins_are_throttled() {
+    let reactor = reactor().await;
+    let client = reactor.client();
+
+    login(&client).await;
+    let attempt = |password: &str| {
+        client.send(
+            Method::POST,
+            "/api/auth/login",
+            Body::from(format!("username=testuser&password={}", password)),
+        )
+    };
+
+    // The first failures are free, then the username is blocked for a while
+    for _ in 0..4 {
+        let res = attempt("wrongpassword").await;
+        assert_eq!(res.status(), StatusCode::UNAUTHORIZED);
+    }
+    let res = attempt("testpassword").await;
+    assert_eq!(res.status(), StatusCode::TOO_MANY_REQUESTS);
+
+    let res = client
+        .clone()
+        .as_user(mtapp::Uuid::new_v4(), &["admin"])
+        .delete("/internal/auth/lockouts?username=testuser")
+        .await;
+    assert_eq!(res.status(), StatusCode::OK, "{}", res.text());
+
+    let res = attempt("testpassword").await;
+    assert_eq!(res.status(), StatusCode::OK, "{}", res.text());
+
+    reactor.close().await;
+}
+
+#[tokio::test]
+async fn signups_are_rate_limited() {
+    let reactor = reactor().await;
+    let client = reactor.client().peer(([203, 0, 113, 1], 4000).into());
+
+    // Counted before the body is checked
+    for remaining in (0..10).rev() {
+        let res = client.post("/api/users/", &json!({})).await;
+        assert_eq!(res.status(), StatusCode::UNPROCESSABLE_ENTITY);
+        assert_eq!(
+            res.headers()[&RATE_LIMIT_REMAINING_HEADER],
+            remaining.to_string().as_str()
+        );
+    }
+
+    let res = client.post("/api/users/", &json!({})).await;
+    assert_eq!(res.status(), StatusCode::TOO_MANY_REQUESTS);
+    assert!(res.headers().contains_key(header::RETRY_AFTER));
+
+    // The proxy headers aren't trusted by default
+    let res = client
+        .clone()
+        .header(
+            header::HeaderName::from_static("x-forwarded-for"),
+            "203.0.113.3",
+        )
+        .post("/api/users/", &json!({}))
+        .await;
+    assert_eq!(res.status(), StatusCode::TOO_MANY_REQUESTS);
+
+    // Other addresses have their own counters
+    let res = reactor
+        .client()
+        .peer(([203, 0, 113, 2], 4000).into())
+        .post("/api/users/", &json!({}))
+        .await;
+    assert_eq!(res.status(), StatusCode::UNPROCESSABLE_ENTITY);
+
+    reactor.close().await;
+}
+
+#[tokio::test]
+async fn oauth_token_endpoint() {
+    let reactor = reactor().await;
+    let client = reactor.client().header(header::USER_AGENT, "test");
+    let form = client
+        .clone()
+        .header(header::CONTENT_TYPE, "application/x-www-form-urlencoded");
+
+    let res = client
+        .post(
+            "/api/users/",
+            &json!({"username": "testuser", "password": "testpassword"}),
+        )
+        .await;
+    assert_eq!(res.status(), StatusCode::OK, "{}", res.text());
+
+    let res = form
+        .send(
+            Method::POST,
+            "/api/auth/token",
+            Body::from("grant_type=password&username=testuser&password=wrong"),
+        )
+        .await;
+    assert_eq!(res.status(), StatusCode::BAD_REQUEST);
+    assert_eq!(res.json::<Value>()["error"], "invalid_grant");
+
+    // Scopes which aren't granted can't be requested
+    let res = form
+        .send(
+            Method::POST,
+            "/api/auth/token",
+            Body::from(
+                "grant_type=password&username=testuser&password=testpassword\
+                &scope=admin",
+            ),
+        )
+        .await;
+    assert_eq!(res.json::<Value>()["error"], "invalid_scope");
+
+    let res = form
+        .send(
+            Method::POST,
+            "/api/auth/token",
+            Body::from("grant_type=password&username=testuser&password=testpassword"),
+        )
+        .await;
+    assert_eq!(res.status(), StatusCode::OK, "{}", res.text());
+    assert_eq!(res.headers()[header::CACHE_CONTROL], "no-store");
+    let refresh_token = res.json::<Value>()["refresh_token"]
+        .as_str()
+        .expect("Token should have a refresh token")
+        .to_owned();
+
+    let res = form
+        .send(
+            Method::POST,
+            "/api/auth/token",
+            Body::from(format!(
+                "grant_type=refresh_token&refresh_token={}",
+                refresh_token
+            )),
+        )
+        .await;
+    assert_eq!(res.status(), StatusCode::OK, "{}", res.text());
+    let refresh_token = res.json::<Value>()["refresh_token"]
+        .as_str()
+        .expect("Token should have a refresh token")
+        .to_owned();
+
+    let res = form
+        .send(
+            Method::POST,
+            "/api/auth/revoke",
+            Body::from(format!("token={}", refresh_token)),
+        )
+        .await;
+    assert_eq!(res.status(), StatusCode::OK, "{}", res.text());
+
+    let res = form
+        .send(
+            Method::POST,
+            "/api/auth/token",
+            Body::from(format!(
+                "grant_type=refresh_token&refresh_token={}",
+                refresh_token
+            )),
+        )
+        .await;
+    assert_eq!(res.json::<Value>()["error"], "invalid_grant");
+
+    reactor.close().await;
+}
+
+/// Run a refresh_token grant on the token endpoint
+async fn refresh_grant(form: &TestClient, refresh_token: &str) -> TestResponse {
+    form.send(
+        Method::POST,
+        "/api/auth/token",
+        Body::from(format!(
+            "grant_type=refresh_token&refresh_token={}",
+            refresh_token
+        )),
+    )
+    .await
+}
+
+#[tokio::test]
+async fn reused_refresh_tokens_revoke_the_oauth_session() {
+    let reactor = reactor().await;
+    let form = reactor
+        .client()
+        .header(header::USER_AGENT, "test")
+        .header(header::CONTENT_TYPE, "application/x-www-form-urlencoded");
+
+    let first = login(&form).await;
+
+    let res = refresh_grant(&form, &first).await;
+    assert_eq!(res.status(), StatusCode::OK, "{}", res.text());
+    let second = res.json::<Value>()["refresh_token"]
+        .as_str()
+        .expect("Token should have a refresh token")
+        .to_owned();
+
+    // Replaying the rotated token revokes the session, so the current token is rejected too
+    let res = refresh_grant(&form, &first).await;
+    assert_eq!(res.json::<Value>()["error"], "invalid_grant");
+
+    let res = refresh_grant(&form, &second).await;
+    assert_eq!(res.json::<Value>()["error"], "invalid_grant");
+
+    reactor.close().await;
+}
+
+#[tokio::test]
+async fn client_credentials_grant() {
+    let reactor = reactor().await;
+    let form = reactor
+        .client()
+        .header(header::CONTENT_TYPE, "application/x-www-form-urlencoded");
+
+    let res = reactor
+        .client()
+        .as_user(mtapp::Uuid::new_v4(), &["admin"])
+        .post(
+            "/internal/clients/",
+            &json!({"name": "worker", "scopes": ["admin"]}),
+        )
+        .await;
+    assert_eq!(res.status(), StatusCode::OK, "{}", res.text());
+    let body = res.json::<Value>();
+    let client_id = body["content"]["id"]
+        .as_str()
+        .expect("Client should have an id");
+    let client_secret = body["content"]["client_secret"]
+        .as_str()
+        .expect("The secret should be returned once");
+
+    let res = form
+        .send(
+            Method::POST,
+            "/api/auth/token",
+            Body::from(format!(
+                "grant_type=client_credentials&client_id={}&client_secret=wrong",
+                client_id
+            )),
+        )
+        .await;
+    assert_eq!(res.status(), StatusCode::UNAUTHORIZED);
+    assert_eq!(res.json::<Value>()["error"], "invalid_client");
+
+    let res = form
+        .send(
+            Method::POST,
+            "/api/auth/token",
+            Body::from(format!(
+                "grant_type=client_credentials&client_id={}&client_secret={}",
+                client_id, client_secret
+            )),
+        )
+        .await;
+    assert_eq!(res.status(), StatusCode::OK, "{}", res.text());
+    let token = res.json::<Value>();
+    assert_eq!(token["scope"], "admin");
+    assert!(token.get("refresh_token").is_none());
+
+    // The client can call the internal api with its own token
+    let access_token = token["access_token"].as_str().unwrap();
+    let client = reactor.client().bearer(access_token);
+    let res = client.get("/internal/users/").await;
+    assert_eq!(res.status(), StatusCode::OK, "{}", res.text());
+
+    // But not the routes of the users
+    let res = client.get("/api/users/me").await;
+    assert_eq!(res.status(), StatusCode::FORBIDDEN);
+    let res = client.get("/api/sessions/").await;
+    assert_eq!(res.status(), StatusCode::FORBIDDEN);
+
+    let res = client
+        .header(header::CONTENT_TYPE, "application/x-www-form-urlencoded")
+        .send(
+            Method::POST,
+            "/internal/auth/introspect",
+            Body::from(format!("token={}", access_token)),
+        )
+        .await;
+    assert_eq!(res.status(), StatusCode::OK, "{}", res.text());
+    let introspection = res.json::<Value>();
+    assert_eq!(introspection["active"], true);
+    assert_eq!(introspection["sub"], client_id);
+    assert_eq!(introspection["sub_type"], "client");
+
+    reactor.close().await;
+}
+
+#[tokio::test]
+async fn api_keys() {
+    let reactor = reactor().await;
+    let client = reactor.client();
+
+    let res = client
+        .post(
+            "/api/users/",
+            &json!({"username": "testuser", "password": "testpassword"}),
+        )
+        .await;
+    assert_eq!(res.status(), StatusCode::OK, "{}", res.text());
+    let user = User::get_by_username("testuser", reactor.db())
+        .await
+        .expect("User should be created");
+    let user_client = client.clone().as_user(user.id, &[]);
+
+    // Only the scopes the user has can be given to the key
+    let res = user_client
+        .post(
+            "/api/users/me/api-keys",
+            &json!({"name": "ci", "scopes": ["admin"]}),
+        )
+        .await;
+    assert_eq!(res.status(), StatusCode::FORBIDDEN);
+
+    let res = user_client
+        .post("/api/users/me/api-keys", &json!({"name": "ci"}))
+        .await;
+    assert_eq!(res.status(), StatusCode::OK, "{}", res.text());
+    let body = res.json::<Value>();
+    let key_id = body["content"]["id"]
+        .as_str()
+        .expect("Key should have an id");
+    let key = body["content"]["key"]
+        .as_str()
+        .expect("The key should be returned once");
+
+    let key_client = client.clone().header(API_KEY_HEADER.clone(), key);
+    let res = key_client.get("/api/users/me").await;
+    assert_eq!(res.status(), StatusCode::OK, "{}", res.text());
+
+    // Keys can't manage the keys
+    let res = key_client.get("/api/users/me/api-keys").await;
+    assert_eq!(res.status(), StatusCode::FORBIDDEN);
+
+    let res = user_client
+        .send(
+            Method::DELETE,
+            &format!("/api/users/me/api-keys/{}", key_id),
+            Body::empty(),
+        )
+        .await;
+    assert_eq!(res.status(), StatusCode::OK, "{}", res.text());
+
+    let res = key_client.get("/api/users/me").await;
+    assert_eq!(res.status(), StatusCode::UNAUTHORIZED);
+
+    reactor.close().await;
+}
+
+/// The TOTP code of the secret in an otpauth uri, `steps` after the current time step
+fn totp_code(uri: &str, steps: u64) -> String {
+    let secret = uri
+        .split(&['?', '&'][..])
+        .find_map(|param| param.strip_prefix("secret="))
+        .expect("The uri should have a secret");
+
+    // Base32 without padding
+    let (mut bits, mut len, mut key) = (0u32, 0, Vec::new());
+    for c in secret.bytes() {
+        let value = match c {
+            b'A'..=b'Z' => c - b'A',
+            b'2'..=b'7' => c - b'2' + 26,
+            _ => panic!("Invalid base32 secret: {}", secret),
+        };
+        bits = (bits << 5) | u32::from(value);
+        len += 5;
+        if len >= 8 {
+            len -= 8;
+            key.push((bits >> len) as u8);
+            bits &= (1 << len) - 1;
+        }
+    }
+
+    let step = SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .expect("UNIX_EPOCH is past")
+        .as_secs()
+        / 30
+        + steps;
+    let key = hmac::Key::new(hmac::HMAC_SHA1_FOR_LEGACY_USE_ONLY, &key);
+    let digest = hmac::sign(&key, &step.to_be_bytes());
+    let digest = digest.as_ref();
+    let offset = (digest[digest.len() - 1] & 0x0f) as usize;
+    let value = u32::from_be_bytes([
+        digest[offset] & 0x7f,
+        digest[offset + 1],
+        digest[offset + 2],
+        digest[offset + 3],
+    ]);
+    format!("{:06}", value % 1_000_000)
+}
+
+async fn enroll_totp(client: &TestClient) -> String {
+    let res = client.post("/api/users/me/totp", &json!({})).await;
+    assert_eq!(res.status(), StatusCode::OK, "{}", res.text());
+    let uri = res.json::<Value>()["content"]["otpauth_uri"]
+        .as_str()
+        .expect("Enrollment should return an otpauth uri")
+        .to_owned();
+    assert!(uri.starts_with("otpauth://totp/"), "{}", uri);
+    uri
+}
+
+/// Log in with the password and send the code with the challenge
+async fn mfa_login(client: &TestClient, code: &str) -> TestResponse {
+    let res = client
+        .send(
+            Method::POST,
+            "/api/auth/login?flat=true",
+            Body::from("username=testuser&password=testpassword"),
+        )
+        .await;
+    assert_eq!(res.status(), StatusCode::OK, "{}", res.text());
+    let body = res.json::<Value>();
+    assert_eq!(body["mfa_required"], true);
+    let challenge = body["challenge_token"]
+        .as_str()
+        .expect("Login should return a challenge");
+
+    client
+        .send(
+            Method::POST,
+            "/api/auth/login/mfa?flat=true",
+            Body::from(format!("challenge_token={}&code={}", challenge, code)),
+        )
+        .await
+}
+
+#[tokio::test]
+async fn totp_enrollment() {
+    let reactor = reactor().await;
+    let client = reactor.client();
+
+    login(&client).await;
+    let user = User::get_by_username("testuser", reactor.db())
+        .await
+        .expect("User should be created");
+    let user_client = client.clone().as_user(user.id, &[]);
+
+    enroll_totp(&user_client).await;
+    let res = user_client
+        .post("/api/users/me/totp/confirm", &json!({"code": "not-a-code"}))
+        .await;
+    assert_eq!(res.status(), StatusCode::FORBIDDEN);
+
+    // MFA isn't required until the enrollment is confirmed
+    let res = client
+        .send(
+            Method::POST,
+            "/api/auth/login?flat=true",
+            Body::from("username=testuser&password=testpassword"),
+        )
+        .await;
+    assert_eq!(res.status(), StatusCode::OK, "{}", res.text());
+    assert!(res.json::<Value>()["access_token"].is_string());
+
+    // Pending enrollments can be removed without a code
+    let res = user_client
+        .send_json(Method::DELETE, "/api/users/me/totp", &json!({"code": ""}))
+        .await;
+    assert_eq!(res.status(), StatusCode::OK, "{}", res.text());
+
+    let uri = enroll_totp(&user_client).await;
+    let res = user_client
+        .post(
+            "/api/users/me/totp/confirm",
+            &json!({"code": totp_code(&uri, 0)}),
+        )
+        .await;
+    assert_eq!(res.status(), StatusCode::OK, "{}", res.text());
+    let recovery_codes: Vec<String> =
+        serde_json::from_value(res.json::<Value>()["content"]["recovery_codes"].clone())
+            .expect("Confirming should return the recovery codes");
+
+    // The code of the confirmation is used, so the next one is sent
+    let res = mfa_login(&client, &totp_code(&uri, 1)).await;
+    assert_eq!(res.status(), StatusCode::OK, "{}", res.text());
+    assert!(res.json::<Value>()["access_token"].is_string());
+
+    // Recovery codes work once
+    let res = mfa_login(&client, &recovery_codes[0]).await;
+    assert_eq!(res.status(), StatusCode::OK, "{}", res.text());
+    let res = mfa_login(&client, &recovery_codes[0]).await;
+    assert_eq!(res.status(), StatusCode::UNAUTHORIZED);
+
+    let res = client
+        .send(
+            Method::POST,
+            "/api/auth/login/mfa",
+            Body::from("challenge_token=invalid&code=123456"),
+        )
+        .await;
+    assert_eq!(res.status(), StatusCode::UNAUTHORIZED);
+
+    reactor.close().await;
+}
+
+#[tokio::test]
+async fn passkey_ceremonies() {
+    let reactor = reactor().await;
+    let client = reactor.client();
+
+    login(&client).await;
+    let user = User::get_by_username("testuser", reactor.db())
+        .await
+        .expect("User should be created");
+    let user_client = client.clone().as_user(user.id, &[]);
+
+    let res = user_client
+        .post("/api/auth/webauthn/register/options", &json!({}))
+        .await;
+    assert_eq!(res.status(), StatusCode::OK, "{}", res.text());
+    let options = res.json::<Value>();
+    assert_eq!(options["content"]["rp"]["id"], "localhost");
+    assert_eq!(options["content"]["user"]["name"], "testuser");
+    assert!(options["content"]["challenge"].is_string());
+
+    // The client data of a challenge which was never issued
+    let res = user_client
+        .post(
+            "/api/auth/webauthn/register",
+            &json!({
+                "id": "",
+                "response": {
+                    "clientDataJSON": "eyJ0eXBlIjoid2ViYXV0aG4uY3JlYXRlIiwiY2hhbGxlbmdlIjoiYm05MExX\
+                        bHpjM1ZsWkEiLCJvcmlnaW4iOiJodHRwOi8vbG9jYWxob3N0In0",
+                    "attestationObject": ""
+                }
+            }),
+        )
+        .await;
+    assert_eq!(res.status(), StatusCode::UNAUTHORIZED);
+
+    let res = client
+        .post("/api/auth/webauthn/login/options", &json!({}))
+        .await;
+    assert_eq!(res.status(), StatusCode::OK, "{}", res.text());
+    assert_eq!(res.json::<Value>()["content"]["rpId"], "localhost");
+
+    let res = user_client.get("/api/users/me/passkeys").await;
+    assert_eq!(res.status(), StatusCode::OK, "{}", res.text());
+    assert_eq!(res.json::<Value>()["content"], json!([]));
+
+    reactor.close().await;
+}
+
+/// A software authenticator with an ES256 key, which always verifies the user
+struct Authenticator {
+    key_pair: EcdsaKeyPair,
+    credential_id: [u8; 16],
+}
+
+impl Authenticator {
+    fn new() -> Self {
+        let pkcs8 =
+            EcdsaKeyPair::generate_pkcs8(&ECDSA_P256_SHA256_ASN1_SIGNING, &SystemRandom::new())
+                .expect("Failed to generate a key");
+        Self {
+            key_pair: EcdsaKeyPair::from_pkcs8(&ECDSA_P256_SHA256_ASN1_SIGNING, pkcs8.as_ref())
+                .expect("The generated key is valid"),
+            credential_id: [7; 16],
+        }
+    }
+
+    fn id(&self) -> String {
+        URL_SAFE_NO_PAD.encode(self.credential_id)
+    }
+
+    fn client_data(typ: &str, challenge: &str) -> String {
+        json!({"type": typ, "challenge": challenge, "origin": "http://localhost"}).to_string()
+    }
+
+    fn authenticator_data(flags: u8, sign_count: u32) -> Vec<u8> {
+        let mut data = digest(&SHA256, b"localhost").as_ref().to_vec();
+        data.push(flags);
+        data.extend(sign_count.to_be_bytes());
+        data
+    }
+
+    /// The response of `navigator.credentials.create`, the CBOR is encoded by hand
+    fn register(&self, challenge: &str) -> Value {
+        // The COSE key {1: 2, 3: -7, -1: 1, -2: x, -3: y}
+        let point = self.key_pair.public_key().as_ref();
+        let mut key = vec![0xa5, 0x01, 0x02, 0x03, 0x26, 0x20, 0x01, 0x21, 0x58, 0x20];
+        key.extend(&point[1..33]);
+        key.extend([0x22, 0x58, 0x20]);
+        key.extend(&point[33..]);
+
+        // User present, verified and the attested credential, after an empty aaguid
+        let mut data = Self::authenticator_data(0x45, 0);
+        data.extend([0; 16]);
+        data.extend((self.credential_id.len() as u16).to_be_bytes());
+        data.extend(self.credential_id);
+        data.extend(key);
+
+        // {"fmt": "none", "attStmt": {}, "authData": data}
+        let mut object = vec![0xa3, 0x63];
+        object.extend(b"fmt");
+        object.push(0x64);
+        object.extend(b"none");
+        object.push(0x67);
+        object.extend(b"attStmt");
+        object.extend([0xa0, 0x68]);
+        object.extend(b"authData");
+        object.extend([0x58, data.len() as u8]);
+        object.extend(data);
+
+        json!({
+            "id": self.id(),
+            "response": {
+                "clientDataJSON":
+                    URL_SAFE_NO_PAD.encode(Self::client_data("webauthn.create", challenge)),
+                "attestationObject": URL_SAFE_NO_PAD.encode(object),
+            },
+            "name": "Test key",
+        })
+    }
+
+    /// The response of `navigator.credentials.get`
+    fn assert(&self, challenge: &str, flags: u8, sign_count: u32) -> Value {
+        let data = Self::authenticator_data(flags, sign_count);
+        let client_data = Self::client_data("webauthn.get", challenge);
+
+        let mut message = data.clone();
+        message.extend(digest(&SHA256, client_data.as_bytes()).as_ref());
+        let signature = self
+            .key_pair
+            .sign(&SystemRandom::new(), &message)
+            .expect("Failed to sign the assertion");
+
+        json!({
+            "id": self.id(),
+            "response": {
+                "clientDataJSON": URL_SAFE_NO_PAD.encode(client_data),
+                "authenticatorData": URL_SAFE_NO_PAD.encode(data),
+                "signature": URL_SAFE_NO_PAD.encode(signature),
+            },
+        })
+    }
+}
+
+async fn passkey_login(
+    client: &TestClient,
+    authenticator: &Authenticator,
+    flags: u8,
+    sign_count: u32,
+) -> TestResponse {
+    let res = client
+        .post("/api/auth/webauthn/login/options", &json!({}))
+        .await;
+    let challenge = res.json::<Value>()["content"]["challenge"]
+        .as_str()
+        .expect("Options should have a challenge")
+        .to_owned();
+
+    client
+        .post(
+            "/api/auth/webauthn/login?flat=true",
+            &authenticator.assert(&challenge, flags, sign_count),
+        )
+        .await
+}
+
+#[tokio::test]
+async fn passkey_register_and_login() {
+    let reactor = reactor().await;
+    let client = reactor.client();
+
+    login(&client).await;
+    let user = User::get_by_username("testuser", reactor.db())
+        .await
+        .expect("User should be created");
+    let user_client = client.clone().as_user(user.id, &[]);
+    let authenticator = Authenticator::new();
+
+    let res = user_client
+        .post("/api/auth/webauthn/register/options", &json!({}))
+        .await;
+    let challenge = res.json::<Value>()["content"]["challenge"]
+        .as_str()
+        .expect("Options should have a challenge")
+        .to_owned();
+    let res = user_client
+        .post(
+            "/api/auth/webauthn/register",
+            &authenticator.register(&challenge),
+        )
+        .await;
+    assert_eq!(res.status(), StatusCode::OK, "{}", res.text());
+
+    let res = user_client.get("/api/users/me/passkeys").await;
+    assert_eq!(res.json::<Value>()["content"][0]["name"], "Test key");
+
+    // The user is present, but not verified
+    let res = passkey_login(&client, &authenticator, 0x01, 1).await;
+    assert_eq!(res.status(), StatusCode::UNAUTHORIZED);
+
+    let res = passkey_login(&client, &authenticator, 0x05, 1).await;
+    assert_eq!(res.status(), StatusCode::OK, "{}", res.text());
+    assert!(res.json::<Value>()["access_token"].is_string());
+
+    // A counter which didn't go up means the key might be cloned
+    let res = passkey_login(&client, &authenticator, 0x05, 1).await;
+    assert_eq!(res.status(), StatusCode::UNAUTHORIZED);
+
+    let res = passkey_login(&client, &authenticator, 0x05, 2).await;
+    assert_eq!(res.status(), StatusCode::OK, "{}", res.text());
+
+    reactor.close().await;
+}
+
+/// A local OpenID Connect provider, the test plays the browser and sets the pending login
+struct MockProvider {
+    issuer: String,
+    key_pair: Vec<u8>,
+    // (nonce, code challenge) of the authorization
+    pending: Mutex<Option<(String, String)>>,
+}
+
+#[derive(Deserialize)]
+struct MockTokenRequest {
+    code: String,
+    client_id: String,
+    code_verifier: String,
+}
+
+async fn mock_oidc_provider() -> Arc<MockProvider> {
+    let listener = TcpListener::bind("127.0.0.1:0").expect("Failed to bind the mock provider");
+    let mock = Arc::new(MockProvider {
+        issuer: format!("http://{}", listener.local_addr().unwrap()),
+        key_pair: Ed25519KeyPair::generate_pkcs8(&SystemRandom::new())
+            .unwrap()
+            .as_ref()
+            .to_vec(),
+        pending: Mutex::new(None),
+    });
+
+    let router = Router::new()
+        .route(
+            "/.well-known/openid-configuration",
+            get(|State(mock): State<Arc<MockProvider>>| async move {
+                Json(json!({
+                    "issuer": mock.issuer,
+                    "authorization_endpoint": format!("{}/authorize", mock.issuer),
+                    "token_endpoint": format!("{}/token", mock.issuer),
+                    "jwks_uri": format!("{}/jwks", mock.issuer),
+                }))
+            }),
+        )
+        .route(
+            "/jwks",
+            get(|State(mock): State<Arc<MockProvider>>| async move {
+                let key_pair = Ed25519KeyPair::from_pkcs8(&mock.key_pair).unwrap();
+                Json(json!({"keys": [{
+                    "kty": "OKP",
+                    "crv": "Ed25519",
+                    "kid": "mock",
+                    "x": URL_SAFE_NO_PAD.encode(key_pair.public_key()),
+                }]}))
+            }),
+        )
+        .route("/token", post(mock_token))
+        .with_state(mock.clone());
+
+    let server = Server::from_tcp(listener)
+        .unwrap()
+        .serve(router.into_make_service());
+    tokio::spawn(server);
+
+    mock
+}
+
+async fn mock_token(
+    State(mock): State<Arc<MockProvider>>,
+    Form(form): Form<MockTokenRequest>,
+) -> Result<Json<Value>, StatusCode> {
+    let (nonce, challenge) = mock
+        .pending
+        .lock()
+        .unwrap()
+        .take()
+        .ok_or(StatusCode::BAD_REQUEST)?;
+    let verifier_hash = URL_SAFE_NO_PAD.encode(digest(&SHA256, form.code_verifier.as_bytes()));
+    if form.code != "mock-code" || verifier_hash != challenge {
+        return Err(StatusCode::BAD_REQUEST);
+    }
+
+    let now = SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .unwrap()
+        .as_secs();
+    let claims = json!({
+        "iss": mock.issuer,
+        "sub": "mock-subject",
+        "aud": form.client_id,
+        "iat": now,
+        "exp": now + 300,
+        "nonce": nonce,
+        "email": "mock@example.com",
+        "email_verified": true,
+        "preferred_username": "mockuser",
+    });
+    let mut header = Header::new(Algorithm::EdDSA);
+    header.kid = Some(String::from("mock"));
+    let id_token = encode(&header, &claims, &EncodingKey::from_ed_der(&mock.key_pair)).unwrap();
+
+    Ok(Json(json!({
+        "access_token": "mock-access-token",
+        "token_type": "Bearer",
+        "id_token": id_token,
+    })))
+}
+
+#[tokio::test]
+async fn oidc_login() {
+    let mock = mock_oidc_provider().await;
+    let reactor = reactor_with(auth_config().oidc_provider(OidcProvider::new(
+        "mock",
+        &mock.issuer,
+        "mtapp",
+        "client-secret",
+        "http://localhost/callback",
+    )))
+    .await;
+    let client = reactor.client();
+
+    let res = client
+        .send(
+            Method::POST,
+            "/api/auth/oidc/unknown/authorize",
+            Body::empty(),
+        )
+        .await;
+    assert_eq!(res.status(), StatusCode::NOT_FOUND);
+
+    let res = client
+        .send(Method::POST, "/api/auth/oidc/mock/authorize", Body::empty())
+        .await;
+    assert_eq!(res.status(), StatusCode::OK, "{}", res.text());
+    let url = res.json::<Value>()["content"]["authorization_url"]
+        .as_str()
+        .expect("Should return the authorization url")
+        .to_owned();
+    assert!(url.starts_with(&format!("{}/authorize?", mock.issuer)));
+    let cookie = res.headers()[header::SET_COOKIE].to_str().unwrap();
+    assert!(cookie.starts_with("oidc-state=") && cookie.contains("HttpOnly"));
+
+    // The generated values are base64url, so they aren't escaped
+    let param = |name: &str| {
+        url.split(['?', '&'])
+            .find_map(|pair| pair.strip_prefix(&format!("{}=", name)))
+            .expect("Should be in the authorization url")
+            .to_owned()
+    };
+    assert_eq!(param("code_challenge_method"), "S256");
+    *mock.pending.lock().unwrap() = Some((param("nonce"), param("code_challenge")));
+
+    let callback = format!("code=mock-code&state={}", param("state"));
+
+    // Only the browser which started the login can finish it
+    let res = client
+        .send(
+            Method::POST,
+            "/api/auth/oidc/mock/callback?flat=true",
+            Body::from(callback.clone()),
+        )
+        .await;
+    assert_eq!(res.status(), StatusCode::UNAUTHORIZED);
+
+    let res = client
+        .clone()
+        .header(header::COOKIE, &format!("oidc-state={}", param("state")))
+        .send(
+            Method::POST,
+            "/api/auth/oidc/mock/callback?flat=true",
+            Body::from(callback.clone()),
+        )
+        .await;
+    assert_eq!(res.status(), StatusCode::OK, "{}", res.text());
+    assert!(res.json::<Value>()["access_token"].is_string());
+
+    let user = User::get_by_username("mockuser", reactor.db())
+        .await
+        .expect("User should be made on the first login");
+    assert_eq!(user.email.as_deref(), Some("mock@example.com"));
+
+    // The state is consumed
+    let res = client
+        .clone()
+        .header(header::COOKIE, &format!("oidc-state={}", param("state")))
+        .send(
+            Method::POST,
+            "/api/auth/oidc/mock/callback?flat=true",
+            Body::from(callback),
+        )
+        .await;
+    assert_eq!(res.status(), StatusCode::UNAUTHORIZED);
+
+    let res = client
+        .clone()
+        .as_user(user.id, &[])
+        .get("/api/users/me/identities")
+        .await;
+    assert_eq!(res.status(), StatusCode::OK, "{}", res.text());
+    assert_eq!(res.json::<Value>()["content"][0]["subject"], "mock-subject");
+
+    reactor.close().await;
+}